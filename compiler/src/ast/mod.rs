@@ -10,6 +10,16 @@ pub struct Span {
     pub end: usize,
 }
 
+impl Span {
+    /// 1-based line and column of the span start
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        (line, col)
+    }
+}
+
 /// A complete qed program
 #[derive(Debug, Clone)]
 pub struct Program {
@@ -24,6 +34,10 @@ pub enum Item {
     Fact(Fact),
     Rule(Rule),
     Query(Query),
+    Explain(ExplainTemplate),
+    Constraint(Constraint),
+    /// Decision table; the parser also emits one `Item::Rule` per row
+    Table(Table),
 }
 
 /// Type definition: type Person = person(name: String, age: Int)
@@ -83,9 +97,69 @@ impl fmt::Display for Type {
 pub struct RelationDecl {
     pub name: String,
     pub signature: Type,
+    /// Keys declared with `key(0)`: argument positions that determine the rest
+    pub keys: Vec<Vec<usize>>,
+    pub span: Span,
+    /// Text of the `///` comments above the declaration
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl RelationDecl {
+    /// Combining algorithm given with `@combine("...")`
+    pub fn combine(&self) -> Option<&str> {
+        find_attribute(&self.attributes, "combine")
+            .and_then(|a| a.args.first())
+            .map(String::as_str)
+    }
+}
+
+/// How conflicting outcomes of a decision relation are resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    /// Any `Deny` outcome wins
+    DenyOverrides,
+    /// Any `Permit` (or `Allow`) outcome wins
+    PermitOverrides,
+    /// The first applicable clause in source order wins
+    FirstApplicable,
+}
+
+impl Combine {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "deny-overrides" => Some(Combine::DenyOverrides),
+            "permit-overrides" => Some(Combine::PermitOverrides),
+            "first-applicable" => Some(Combine::FirstApplicable),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Combine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Combine::DenyOverrides => "deny-overrides",
+            Combine::PermitOverrides => "permit-overrides",
+            Combine::FirstApplicable => "first-applicable",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Attribute on a rule or relation: @rule("owner-full-access")
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<String>,
     pub span: Span,
 }
 
+/// Find an attribute by name
+pub fn find_attribute<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attributes.iter().find(|a| a.name == name)
+}
+
 /// Fact: parent(alice, bob).
 #[derive(Debug, Clone)]
 pub struct Fact {
@@ -100,6 +174,301 @@ pub struct Rule {
     pub head: Atom,
     pub body: Vec<Goal>,
     pub span: Span,
+    /// Text of the `///` comments above the rule
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Rule {
+    /// Stable name given with `@rule("...")`
+    pub fn name(&self) -> Option<&str> {
+        find_attribute(&self.attributes, "rule")
+            .and_then(|a| a.args.first())
+            .map(String::as_str)
+    }
+
+    /// Label for explanations: the rule name, falling back to its doc comment
+    pub fn label(&self) -> Option<&str> {
+        self.name().or(self.doc.as_deref())
+    }
+
+    /// Names of the rules this rule defeats, from `@overrides("...")`
+    pub fn overrides(&self) -> Vec<&str> {
+        self.attributes
+            .iter()
+            .filter(|a| a.name == "overrides")
+            .flat_map(|a| a.args.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+/// Integrity constraint: :- has_role(U, Employee), has_role(U, Contractor).
+///
+/// The body must have no solutions; each solution is a violation.
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub body: Vec<Goal>,
+    pub span: Span,
+    /// Text of the `///` comments above the constraint
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Constraint {
+    /// Label for violation reports: `@constraint("...")` name, falling back to the doc comment
+    pub fn label(&self) -> Option<&str> {
+        find_attribute(&self.attributes, "constraint")
+            .and_then(|a| a.args.first())
+            .map(String::as_str)
+            .or(self.doc.as_deref())
+    }
+}
+
+/// Decision table, one rule per row:
+/// table classification(Emp, Level) {
+///     | service(Emp, Sal, Years) | Sal < 70000 | Years < 3 | Junior |
+/// }
+///
+/// The last head argument is the output; every other column is a condition.
+/// Conditions only compare bound variables, so a column such as the first
+/// one here has to bind the inputs: each row becomes a rule evaluated
+/// bottom-up, and nothing else produces `Sal` and `Years`.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub head: Atom,
+    pub rows: Vec<TableRow>,
+    pub span: Span,
+    /// Text of the `///` comments above the table
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+/// A row of a decision table: condition cells followed by the output cell
+#[derive(Debug, Clone)]
+pub struct TableRow {
+    pub cells: Vec<TableCell>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum TableCell {
+    /// `-`: no condition
+    Any(Span),
+    /// Conjunction of goals: Sal >= 70000, Sal < 100000
+    Goals(Vec<Goal>, Span),
+    /// A plain term, only valid as the output cell
+    Value(Term, Span),
+}
+
+impl TableCell {
+    pub fn span(&self) -> &Span {
+        match self {
+            TableCell::Any(span) | TableCell::Goals(_, span) | TableCell::Value(_, span) => span,
+        }
+    }
+}
+
+impl Table {
+    /// Desugar each row into a rule whose head carries the row's output
+    pub fn rules(&self) -> Result<Vec<Rule>, String> {
+        let Some((Term::Var(..), inputs)) = self.head.args.split_last() else {
+            return Err(format!(
+                "Table {} needs a variable as its last argument for the output column",
+                self.head.relation
+            ));
+        };
+
+        let mut rules = Vec::new();
+        let mut columns = None;
+        for (n, row) in self.rows.iter().enumerate() {
+            let location = format!(
+                "Table {} row {} at {}..{}",
+                self.head.relation,
+                n + 1,
+                row.span.start,
+                row.span.end
+            );
+            if *columns.get_or_insert(row.cells.len()) != row.cells.len() {
+                return Err(format!(
+                    "{} has {} cells, expected {}",
+                    location,
+                    row.cells.len(),
+                    columns.unwrap_or_default()
+                ));
+            }
+            let Some((output, conditions)) = row.cells.split_last() else {
+                return Err(format!("{} is empty", location));
+            };
+            let output = match output {
+                TableCell::Value(term, _) => term.clone(),
+                // A lowercase constructor reads like a relation call
+                TableCell::Goals(goals, span) => match goals.as_slice() {
+                    [Goal::Atom(atom)] => Term::Construct {
+                        constructor: atom.relation.clone(),
+                        args: atom.args.clone(),
+                        span: span.clone(),
+                    },
+                    _ => {
+                        return Err(format!(
+                            "{}: the last cell must be an output value",
+                            location
+                        ))
+                    }
+                },
+                TableCell::Any(_) => {
+                    return Err(format!("{}: the output cell cannot be `-`", location))
+                }
+            };
+
+            let mut body = Vec::new();
+            for cell in conditions {
+                match cell {
+                    TableCell::Any(_) => {}
+                    TableCell::Goals(goals, _) => body.extend(goals.iter().cloned()),
+                    TableCell::Value(_, span) => {
+                        return Err(format!(
+                            "{}: cell at {}..{} is a value, expected a condition or `-`",
+                            location, span.start, span.end
+                        ))
+                    }
+                }
+            }
+
+            // Head variables that no condition of this row mentions match anything
+            let mut mentioned = Vec::new();
+            for goal in &body {
+                goal_vars(goal, &mut mentioned);
+            }
+            let mut args: Vec<Term> = inputs
+                .iter()
+                .map(|arg| anonymize(arg, &mentioned))
+                .collect();
+            args.push(output);
+
+            rules.push(Rule {
+                head: Atom {
+                    relation: self.head.relation.clone(),
+                    args,
+                    span: self.head.span.clone(),
+                },
+                body,
+                span: row.span.clone(),
+                doc: Some(format!("{} table row {}", self.head.relation, n + 1)),
+                attributes: Vec::new(),
+            });
+        }
+        Ok(rules)
+    }
+}
+
+/// Variables mentioned by a goal
+pub fn goal_vars(goal: &Goal, vars: &mut Vec<String>) {
+    match goal {
+        Goal::Atom(atom) => atom.args.iter().for_each(|t| term_vars(t, vars)),
+        Goal::Unify(left, right, _) | Goal::Compare(_, left, right, _) => {
+            term_vars(left, vars);
+            term_vars(right, vars);
+        }
+        Goal::Match { subject, .. } => term_vars(subject, vars),
+    }
+}
+
+/// Variables mentioned by a term
+pub fn term_vars(term: &Term, vars: &mut Vec<String>) {
+    match term {
+        Term::Var(name, _) if !vars.contains(name) => vars.push(name.clone()),
+        Term::Construct { args, .. } => args.iter().for_each(|t| term_vars(t, vars)),
+        Term::BinOp { left, right, .. } => {
+            term_vars(left, vars);
+            term_vars(right, vars);
+        }
+        _ => {}
+    }
+}
+
+/// Replace variables outside `keep` with `_`
+fn anonymize(term: &Term, keep: &[String]) -> Term {
+    match term {
+        Term::Var(name, span) if !keep.contains(name) => Term::Var("_".to_string(), span.clone()),
+        Term::Construct {
+            constructor,
+            args,
+            span,
+        } => Term::Construct {
+            constructor: constructor.clone(),
+            args: args.iter().map(|a| anonymize(a, keep)).collect(),
+            span: span.clone(),
+        },
+        other => other.clone(),
+    }
+}
+
+/// Explanation template: explain has_role(U, R) as "{U.name} has role {R}"
+#[derive(Debug, Clone)]
+pub struct ExplainTemplate {
+    pub relation: String,
+    pub params: Vec<String>,
+    pub template: String,
+    pub template_span: Span,
+    pub span: Span,
+}
+
+/// A piece of an explanation template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplatePart {
+    /// Literal text
+    Text(String),
+    /// Placeholder: {U} or {U.name}
+    Hole { var: String, fields: Vec<String> },
+}
+
+impl ExplainTemplate {
+    /// Split the template into text and placeholders; `{{` and `}}` are literal braces
+    pub fn parts(&self) -> Result<Vec<TemplatePart>, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = self.template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut hole = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => hole.push(c),
+                            None => return Err(format!("unclosed placeholder {{{}", hole)),
+                        }
+                    }
+                    let mut path = hole.split('.').map(|s| s.trim().to_string());
+                    let var = path.next().unwrap_or_default();
+                    let fields: Vec<String> = path.collect();
+                    if var.is_empty() || fields.iter().any(String::is_empty) {
+                        return Err(format!("malformed placeholder {{{}}}", hole));
+                    }
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(TemplatePart::Hole { var, fields });
+                }
+                '}' => return Err("unmatched '}'".to_string()),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(parts)
+    }
 }
 
 /// An atom in a rule: parent(X, Y)
@@ -119,6 +488,13 @@ pub enum Goal {
     Unify(Term, Term, Span),
     /// Comparison: X < Y
     Compare(CompareOp, Term, Term, Span),
+    /// Regular-expression match: matches(Path, "^/projects/[^/]+/docs/")
+    Match {
+        subject: Term,
+        pattern: String,
+        pattern_span: Span,
+        span: Span,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -131,6 +507,20 @@ pub enum CompareOp {
     Ge, // >=
 }
 
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
 /// Terms that can appear in relations
 #[derive(Debug, Clone)]
 pub enum Term {
@@ -140,6 +530,16 @@ pub enum Term {
     Int(i64, Span),
     /// String literal: "hello"
     String(String, Span),
+    /// Date literal: 2026-12-31 (seconds since the epoch at UTC midnight)
+    Date(i64, Span),
+    /// Timestamp literal: 2026-12-31T10:00:00Z (seconds since the epoch)
+    Timestamp(i64, Span),
+    /// Duration literal: P90D, PT1H (seconds)
+    Duration(i64, Span),
+    /// The evaluation instant, supplied by the host: now()
+    Now(Span),
+    /// The UTC date of the evaluation instant: today()
+    Today(Span),
     /// Constructor application: person("Alice", 45)
     Construct {
         constructor: String,
@@ -170,6 +570,10 @@ impl Term {
             Term::Var(_, span) => span,
             Term::Int(_, span) => span,
             Term::String(_, span) => span,
+            Term::Date(_, span) => span,
+            Term::Timestamp(_, span) => span,
+            Term::Duration(_, span) => span,
+            Term::Now(span) | Term::Today(span) => span,
             Term::Construct { span, .. } => span,
             Term::BinOp { span, .. } => span,
         }
@@ -0,0 +1,449 @@
+/// Predicate dependency graph
+///
+/// A relation depends on every relation called in the body of one of its
+/// rules. The dependency is negative when the rule only applies while
+/// another rule does not: a rule defeated through `@overrides` depends
+/// negatively on the body of each rule that overrides it. Strongly connected
+/// components group mutually recursive relations; they are listed with the
+/// relations they depend on first, which is the order stratified evaluation
+/// needs. A negative dependency inside a component has no such order and is
+/// reported as a warning.
+use crate::ast::{Goal, Item, Program, Rule};
+use crate::ir::{IrGoal, IrProgram, IrRule};
+use crate::types::TypeWarning;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+
+/// Whether a dependency is on a relation holding or on it not holding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+/// `from` has a rule whose outcome depends on `to`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dependency {
+    pub from: String,
+    pub to: String,
+    pub polarity: Polarity,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Every relation, declared or mentioned by a rule
+    relations: BTreeSet<String>,
+    edges: BTreeSet<Dependency>,
+    /// Strongly connected components, dependencies first
+    components: Vec<Vec<String>>,
+    component: HashMap<String, usize>,
+}
+
+impl DependencyGraph {
+    pub fn build(program: &Program) -> Self {
+        let mut relations = BTreeSet::new();
+        let mut rules: Vec<&Rule> = Vec::new();
+        for item in &program.items {
+            match item {
+                Item::RelationDecl(rel) => {
+                    relations.insert(rel.name.clone());
+                }
+                Item::Rule(rule) => rules.push(rule),
+                _ => {}
+            }
+        }
+
+        let mut edges = BTreeSet::new();
+        for rule in &rules {
+            relations.insert(rule.head.relation.clone());
+            for callee in body_relations(rule) {
+                relations.insert(callee.to_string());
+                edges.insert(Dependency {
+                    from: rule.head.relation.clone(),
+                    to: callee.to_string(),
+                    polarity: Polarity::Positive,
+                });
+            }
+        }
+
+        // A defeated rule holds only while the rules overriding it fail
+        let named: HashMap<&str, &Rule> = rules
+            .iter()
+            .filter_map(|rule| Some((rule.name()?, *rule)))
+            .collect();
+        for rule in &rules {
+            for target in rule.overrides() {
+                let Some(defeated) = named.get(target) else {
+                    continue;
+                };
+                for callee in body_relations(rule) {
+                    edges.insert(Dependency {
+                        from: defeated.head.relation.clone(),
+                        to: callee.to_string(),
+                        polarity: Polarity::Negative,
+                    });
+                }
+            }
+        }
+
+        Self::new(relations, edges)
+    }
+
+    /// The same graph over lowered relations, which passes like magic sets add to
+    pub fn from_ir(program: &IrProgram) -> Self {
+        let mut relations = BTreeSet::new();
+        let mut edges = BTreeSet::new();
+        let rules: Vec<&IrRule> = program.relations.iter().flat_map(|r| &r.rules).collect();
+        let calls = |rule: &IrRule| -> Vec<String> {
+            rule.body
+                .iter()
+                .filter_map(|goal| match goal {
+                    IrGoal::Call { relation, .. } => Some(relation.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        for rel in &program.relations {
+            relations.insert(rel.name.clone());
+            for rule in &rel.rules {
+                for callee in calls(rule) {
+                    relations.insert(callee.clone());
+                    edges.insert(Dependency {
+                        from: rel.name.clone(),
+                        to: callee,
+                        polarity: Polarity::Positive,
+                    });
+                }
+            }
+        }
+        for rule in &rules {
+            for target in &rule.overrides {
+                for defeated in rules.iter().filter(|r| r.name.as_ref() == Some(target)) {
+                    for callee in calls(rule) {
+                        edges.insert(Dependency {
+                            from: defeated.head.relation.clone(),
+                            to: callee,
+                            polarity: Polarity::Negative,
+                        });
+                    }
+                }
+            }
+        }
+        Self::new(relations, edges)
+    }
+
+    fn new(relations: BTreeSet<String>, edges: BTreeSet<Dependency>) -> Self {
+        let mut graph = DependencyGraph {
+            relations,
+            edges,
+            components: Vec::new(),
+            component: HashMap::new(),
+        };
+        graph.components = graph.tarjan();
+        for (n, scc) in graph.components.iter().enumerate() {
+            for relation in scc {
+                graph.component.insert(relation.clone(), n);
+            }
+        }
+        graph
+    }
+
+    pub fn relations(&self) -> impl Iterator<Item = &str> {
+        self.relations.iter().map(String::as_str)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Dependency> {
+        self.edges.iter()
+    }
+
+    /// Relations `relation` depends on, with the polarity of each dependency
+    pub fn dependencies<'g>(
+        &'g self,
+        relation: &'g str,
+    ) -> impl Iterator<Item = (&'g str, Polarity)> + 'g {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.from == relation)
+            .map(|edge| (edge.to.as_str(), edge.polarity))
+    }
+
+    /// Strongly connected components, each after the components it depends on
+    pub fn components(&self) -> &[Vec<String>] {
+        &self.components
+    }
+
+    /// Index of the component containing `relation`
+    pub fn component_of(&self, relation: &str) -> Option<usize> {
+        self.component.get(relation).copied()
+    }
+
+    /// Whether two relations are mutually recursive (or the same relation)
+    pub fn same_component(&self, a: &str, b: &str) -> bool {
+        match (self.component_of(a), self.component_of(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Whether `relation` can depend on itself, directly or through others
+    pub fn is_recursive(&self, relation: &str) -> bool {
+        match self.component_of(relation) {
+            Some(n) => {
+                self.components[n].len() > 1
+                    || self.dependencies(relation).any(|(to, _)| to == relation)
+            }
+            None => false,
+        }
+    }
+
+    /// Negative dependencies inside a recursive component, which rule out
+    /// a stratified evaluation
+    pub fn negative_cycles(&self) -> impl Iterator<Item = &Dependency> {
+        self.edges.iter().filter(|edge| {
+            edge.polarity == Polarity::Negative && self.same_component(&edge.from, &edge.to)
+        })
+    }
+
+    /// The graph in Graphviz format; negative dependencies are dashed
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph dependencies {\n");
+        for (n, scc) in self.components.iter().enumerate() {
+            if scc.len() > 1 {
+                let _ = writeln!(out, "  subgraph cluster_{} {{", n);
+                for relation in scc {
+                    let _ = writeln!(out, "    \"{}\";", relation);
+                }
+                out.push_str("  }\n");
+            }
+        }
+        for relation in &self.relations {
+            let _ = writeln!(out, "  \"{}\";", relation);
+        }
+        for edge in &self.edges {
+            let style = match edge.polarity {
+                Polarity::Positive => "",
+                Polarity::Negative => " [style=dashed]",
+            };
+            let _ = writeln!(out, "  \"{}\" -> \"{}\"{};", edge.from, edge.to, style);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Tarjan's algorithm; components come out after everything they reach
+    fn tarjan(&self) -> Vec<Vec<String>> {
+        struct Tarjan<'g> {
+            calls: BTreeMap<&'g str, Vec<&'g str>>,
+            index: HashMap<&'g str, usize>,
+            low: HashMap<&'g str, usize>,
+            stack: Vec<&'g str>,
+            on_stack: HashSet<&'g str>,
+            components: Vec<Vec<String>>,
+        }
+
+        impl<'g> Tarjan<'g> {
+            fn visit(&mut self, node: &'g str) {
+                let index = self.index.len();
+                self.index.insert(node, index);
+                self.low.insert(node, index);
+                self.stack.push(node);
+                self.on_stack.insert(node);
+                let callees = self.calls.get(node).cloned().unwrap_or_default();
+                for next in callees {
+                    if !self.index.contains_key(next) {
+                        self.visit(next);
+                        let low = self.low[node].min(self.low[next]);
+                        self.low.insert(node, low);
+                    } else if self.on_stack.contains(next) {
+                        let low = self.low[node].min(self.index[next]);
+                        self.low.insert(node, low);
+                    }
+                }
+                if self.low[node] == index {
+                    let mut component = Vec::new();
+                    while let Some(member) = self.stack.pop() {
+                        self.on_stack.remove(member);
+                        component.push(member.to_string());
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let mut calls: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for edge in &self.edges {
+            calls.entry(&edge.from).or_default().push(&edge.to);
+        }
+        let mut tarjan = Tarjan {
+            calls,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            components: Vec::new(),
+        };
+        for relation in &self.relations {
+            if !tarjan.index.contains_key(relation.as_str()) {
+                tarjan.visit(relation);
+            }
+        }
+        tarjan.components
+    }
+}
+
+/// Warnings for overrides whose outcome depends on the rule they defeat
+pub fn check_negative_cycles(program: &Program) -> Vec<TypeWarning> {
+    let graph = DependencyGraph::build(program);
+    let cycles: Vec<&Dependency> = graph.negative_cycles().collect();
+    if cycles.is_empty() {
+        return Vec::new();
+    }
+    let rules: Vec<&Rule> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Rule(rule) => Some(rule),
+            _ => None,
+        })
+        .collect();
+    let named: HashMap<&str, &Rule> = rules
+        .iter()
+        .filter_map(|rule| Some((rule.name()?, *rule)))
+        .collect();
+
+    let mut warnings = Vec::new();
+    for rule in &rules {
+        for target in rule.overrides() {
+            let Some(defeated) = named.get(target) else {
+                continue;
+            };
+            let relation = &defeated.head.relation;
+            let cyclic = body_relations(rule).any(|callee| {
+                cycles
+                    .iter()
+                    .any(|edge| edge.from == *relation && edge.to == callee)
+            });
+            if cyclic {
+                warnings.push(TypeWarning::NegativeCycle {
+                    rule: target.to_string(),
+                    relation: relation.clone(),
+                    span: rule.span.clone(),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+fn body_relations(rule: &Rule) -> impl Iterator<Item = &str> {
+    rule.body.iter().filter_map(|goal| match goal {
+        Goal::Atom(atom) => Some(atom.relation.as_str()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn graph(source: &str) -> DependencyGraph {
+        let program = parser::parse(source).expect("Parse failed");
+        DependencyGraph::build(&program)
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let graph = graph(
+            r#"
+            rel zero: Int
+            rel even: Int
+            rel odd: Int
+            rel edge: Int × Int
+            rel step: Int × Int
+            rel reachable: Int × Int
+            even(N) :- zero(N).
+            even(N) :- odd(M), N = M + 1.
+            odd(N) :- even(M), N = M + 1.
+            reachable(X, Y) :- edge(X, Y).
+            reachable(X, Z) :- step(X, Z).
+            step(X, Z) :- edge(X, Y), reachable(Y, Z).
+        "#,
+        );
+        assert!(graph.is_recursive("even") && graph.is_recursive("odd"));
+        assert!(graph.same_component("even", "odd"));
+        assert!(graph.is_recursive("reachable") && graph.is_recursive("step"));
+        assert!(!graph.is_recursive("zero") && !graph.is_recursive("edge"));
+
+        // Components come after the components they depend on
+        let position = |name: &str| graph.component_of(name).unwrap();
+        assert!(position("zero") < position("even"));
+        assert!(position("edge") < position("reachable"));
+        assert_eq!(
+            graph.components()[position("even")],
+            vec!["even".to_string(), "odd".to_string()]
+        );
+        assert_eq!(graph.negative_cycles().count(), 0);
+    }
+
+    #[test]
+    fn test_overrides_are_negative() {
+        let graph = graph(
+            r#"
+            rel contractor: String
+            rel employee: String
+            rel access: String × Int
+            @rule("employees")
+            access(U, 1) :- employee(U).
+            @rule("no-contractors")
+            @overrides("employees")
+            access(U, 0) :- contractor(U).
+        "#,
+        );
+        let deps: Vec<_> = graph.dependencies("access").collect();
+        assert_eq!(
+            deps,
+            vec![
+                ("contractor", Polarity::Positive),
+                ("contractor", Polarity::Negative),
+                ("employee", Polarity::Positive),
+            ]
+        );
+        assert!(!graph.is_recursive("access"));
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"access\" -> \"contractor\" [style=dashed];"));
+        assert!(dot.contains("\"access\" -> \"employee\";"));
+    }
+
+    #[test]
+    fn test_negative_cycle_warning() {
+        let source = r#"
+            rel node: Int
+            rel edge: Int × Int
+            rel safe: Int × Int
+            rel flagged: Int
+            @rule("default")
+            safe(X, 1) :- node(X).
+            @rule("unsafe")
+            @overrides("default")
+            safe(X, 0) :- edge(X, Y), flagged(Y).
+            flagged(Y) :- safe(Y, 1).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let graph = DependencyGraph::build(&program);
+        assert!(graph.same_component("safe", "flagged"));
+        assert_eq!(graph.negative_cycles().count(), 1);
+        let warnings = check_negative_cycles(&program);
+        let messages: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec!["Rule default of safe is overridden by a rule that depends on safe in turn"]
+        );
+        assert_eq!(warnings[0].code(), "W0303");
+    }
+}
@@ -0,0 +1,2285 @@
+/// Query explanation for qed
+///
+/// Answers queries against a type-checked program with a depth-bounded
+/// top-down resolution engine and records a proof tree for every answer.
+/// Calls to recursive relations are tabled, so left recursion and repeated
+/// subgoals terminate, and the total number of resolution steps is capped.
+/// This is the reference semantics behind `qedc explain`; compiled
+/// programs do not depend on it.
+use crate::ast::{
+    BinOp, Combine, CompareOp, Constraint, Fact, Goal, Item, Program, Query, Rule, Span,
+    TemplatePart, Term, TypeDef, TypeDefKind,
+};
+use crate::deps::DependencyGraph;
+use crate::types::TypeError;
+use qed_runtime::temporal;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Resolution depth at which a branch is abandoned
+const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Number of answers reported before the search stops
+const DEFAULT_MAX_ANSWERS: usize = 16;
+
+/// Clauses tried before the whole search is abandoned
+const DEFAULT_MAX_STEPS: usize = 100_000;
+
+/// Runtime values manipulated by the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+    /// Calendar values, all in seconds since the epoch (durations in seconds)
+    Date(i64),
+    Timestamp(i64),
+    Duration(i64),
+    /// Constructor application; nullary variants have no arguments
+    Cons(String, Vec<Value>),
+    /// Unbound logic variable
+    Var(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Date(d) => write!(f, "{}", temporal::format_date(*d)),
+            Value::Timestamp(t) => write!(f, "{}", temporal::format_timestamp(*t)),
+            Value::Duration(d) => write!(f, "{}", temporal::format_duration(*d)),
+            Value::Cons(name, args) if args.is_empty() => write!(f, "{}", name),
+            Value::Cons(name, args) => write!(f, "{}({})", name, join(args)),
+            Value::Var(id) => write!(f, "_G{}", id),
+        }
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One step of a proof
+#[derive(Debug, Clone)]
+pub enum ProofNode {
+    /// Goal proved by a stored fact
+    Fact {
+        relation: String,
+        args: Vec<Value>,
+        span: Span,
+        /// The goal rendered with its relation's explanation template
+        text: Option<String>,
+    },
+    /// Goal proved by a rule; children prove the body goals in source order
+    Rule {
+        relation: String,
+        args: Vec<Value>,
+        rule_id: usize,
+        /// `@rule` name or doc comment of the rule
+        label: Option<String>,
+        span: Span,
+        /// The goal rendered with its relation's explanation template
+        text: Option<String>,
+        children: Vec<ProofNode>,
+    },
+    /// Unification that succeeded
+    Unify { left: Value, right: Value },
+    /// Comparison that held
+    Compare {
+        op: CompareOp,
+        left: Value,
+        right: Value,
+    },
+    /// Regex match, recording which pattern matched
+    Match { subject: Value, pattern: String },
+    /// Winning derivation of a decision, with the derivations it defeated
+    Decision {
+        winner: Box<ProofNode>,
+        /// Combining algorithm that chose the winner, if the relation has one
+        algorithm: Option<Combine>,
+        /// Defeated derivations, each with the algorithm that defeated it
+        /// (None when it lost to an `@overrides` annotation)
+        overridden: Vec<(ProofNode, Option<Combine>)>,
+    },
+}
+
+impl ProofNode {
+    /// Apply the final bindings so the tree shows ground values
+    fn resolve(&self, b: &Bindings) -> ProofNode {
+        let all = |args: &[Value]| args.iter().map(|v| b.resolve(v)).collect();
+        match self {
+            ProofNode::Fact {
+                relation,
+                args,
+                span,
+                text,
+            } => ProofNode::Fact {
+                relation: relation.clone(),
+                args: all(args),
+                span: span.clone(),
+                text: text.clone(),
+            },
+            ProofNode::Rule {
+                relation,
+                args,
+                rule_id,
+                label,
+                span,
+                text,
+                children,
+            } => ProofNode::Rule {
+                relation: relation.clone(),
+                args: all(args),
+                rule_id: *rule_id,
+                label: label.clone(),
+                span: span.clone(),
+                text: text.clone(),
+                children: children.iter().map(|c| c.resolve(b)).collect(),
+            },
+            ProofNode::Unify { left, right } => ProofNode::Unify {
+                left: b.resolve(left),
+                right: b.resolve(right),
+            },
+            ProofNode::Compare { op, left, right } => ProofNode::Compare {
+                op: *op,
+                left: b.resolve(left),
+                right: b.resolve(right),
+            },
+            ProofNode::Match { subject, pattern } => ProofNode::Match {
+                subject: b.resolve(subject),
+                pattern: pattern.clone(),
+            },
+            ProofNode::Decision {
+                winner,
+                algorithm,
+                overridden,
+            } => ProofNode::Decision {
+                winner: Box::new(winner.resolve(b)),
+                algorithm: *algorithm,
+                overridden: overridden
+                    .iter()
+                    .map(|(n, by)| (n.resolve(b), *by))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Number of nodes in the tree
+    fn size(&self) -> usize {
+        match self {
+            ProofNode::Rule { children, .. } => {
+                1 + children.iter().map(ProofNode::size).sum::<usize>()
+            }
+            ProofNode::Decision {
+                winner, overridden, ..
+            } => 1 + winner.size() + overridden.iter().map(|(n, _)| n.size()).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    /// Rule that produced this proof, if any
+    fn rule_id(&self) -> Option<usize> {
+        match self {
+            ProofNode::Rule { rule_id, .. } => Some(*rule_id),
+            ProofNode::Decision { winner, .. } => winner.rule_id(),
+            _ => None,
+        }
+    }
+
+    /// One-line summary of a fact or rule step, without its children
+    fn headline(&self) -> String {
+        match self {
+            ProofNode::Fact {
+                relation,
+                args,
+                text,
+                ..
+            } => format!("{}  [fact]", describe(relation, args, text.as_deref())),
+            ProofNode::Rule {
+                relation,
+                args,
+                rule_id,
+                label,
+                text,
+                ..
+            } => format!(
+                "{}  [{}]",
+                describe(relation, args, text.as_deref()),
+                rule_tag(*rule_id, label.as_deref())
+            ),
+            ProofNode::Decision { winner, .. } => winner.headline(),
+            other => other.to_string().trim_end().to_string(),
+        }
+    }
+
+    fn write_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            ProofNode::Fact {
+                relation,
+                args,
+                text,
+                ..
+            } => {
+                writeln!(
+                    f,
+                    "{}{}  [fact]",
+                    indent,
+                    describe(relation, args, text.as_deref())
+                )
+            }
+            ProofNode::Rule {
+                relation,
+                args,
+                rule_id,
+                label,
+                text,
+                children,
+                ..
+            } => {
+                writeln!(
+                    f,
+                    "{}{}  [{}]",
+                    indent,
+                    describe(relation, args, text.as_deref()),
+                    rule_tag(*rule_id, label.as_deref())
+                )?;
+                for child in children {
+                    child.write_tree(f, depth + 1)?;
+                }
+                Ok(())
+            }
+            ProofNode::Unify { left, right } => writeln!(f, "{}{} = {}", indent, left, right),
+            ProofNode::Compare { op, left, right } => {
+                writeln!(f, "{}{} {} {}", indent, left, op, right)
+            }
+            ProofNode::Match { subject, pattern } => {
+                writeln!(f, "{}{} matches {:?}", indent, subject, pattern)
+            }
+            ProofNode::Decision {
+                winner,
+                algorithm,
+                overridden,
+            } => {
+                winner.write_tree(f, depth)?;
+                write_verdict(f, &indent, *algorithm, overridden)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProofNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+/// Say which algorithm chose a decision and what it overrode
+fn write_verdict(
+    f: &mut impl fmt::Write,
+    indent: &str,
+    algorithm: Option<Combine>,
+    overridden: &[(ProofNode, Option<Combine>)],
+) -> fmt::Result {
+    if let Some(algorithm) = algorithm {
+        writeln!(f, "{}  ⇒ decided by {}", indent, algorithm)?;
+    }
+    for (node, by) in overridden {
+        let reason = match by {
+            Some(algorithm) => algorithm.to_string(),
+            None => "@overrides".to_string(),
+        };
+        writeln!(
+            f,
+            "{}  ✗ overrides {} ({})",
+            indent,
+            node.headline(),
+            reason
+        )?;
+    }
+    Ok(())
+}
+
+/// Templated text for a goal, falling back to `relation(args)`
+fn describe(relation: &str, args: &[Value], text: Option<&str>) -> String {
+    match text {
+        Some(text) => text.to_string(),
+        None => format!("{}({})", relation, join(args)),
+    }
+}
+
+fn rule_tag(rule_id: usize, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("rule {}: {}", rule_id, label),
+        None => format!("rule {}", rule_id),
+    }
+}
+
+/// A rule whose head matched a failed query goal but whose body did not
+/// hold, or whose answer lost to another in a decision
+#[derive(Debug, Clone)]
+pub struct RuleAttempt {
+    pub relation: String,
+    pub rule_id: usize,
+    pub label: Option<String>,
+    pub span: Span,
+    /// The body held, but the decision overrode its answer
+    pub overridden: bool,
+}
+
+/// The search limit that cut a search short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// A branch went deeper than the resolution depth limit
+    Depth,
+    /// The whole search tried more clauses than the step limit
+    Steps,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Depth => write!(f, "depth limit"),
+            Limit::Steps => write!(f, "step limit"),
+        }
+    }
+}
+
+/// A single answer to a query with the proof of each query goal
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub bindings: Vec<(String, Value)>,
+    pub proofs: Vec<ProofNode>,
+}
+
+/// All answers found for a query
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub answers: Vec<Answer>,
+    /// The limit the search reached, if any, so that answers may be missing
+    pub truncated: Option<Limit>,
+    /// For a query with no answers, the rules that were tried and failed
+    pub attempts: Vec<RuleAttempt>,
+    /// For a query with no answers, decisions that were reached instead
+    pub decisions: Vec<ProofNode>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (true, Some(limit)) = (self.answers.is_empty(), self.truncated) {
+            // A search cut short proves nothing either way
+            return writeln!(
+                f,
+                "Unknown: the search reached its {} before finding a proof",
+                limit
+            );
+        }
+        if self.answers.is_empty() {
+            writeln!(f, "No: the query is not provable")?;
+            let mut relation = None;
+            for attempt in &self.attempts {
+                if relation != Some(&attempt.relation) {
+                    writeln!(f, "  Rules considered for {}:", attempt.relation)?;
+                    relation = Some(&attempt.relation);
+                }
+                let tag = rule_tag(attempt.rule_id, attempt.label.as_deref());
+                if attempt.overridden {
+                    writeln!(f, "    ⊘ {} (overridden)", tag)?;
+                } else {
+                    writeln!(f, "    ✗ {}", tag)?;
+                }
+            }
+            if !self.decisions.is_empty() {
+                writeln!(f, "  Decided instead:")?;
+                for decision in &self.decisions {
+                    decision.write_tree(f, 2)?;
+                }
+            }
+        }
+        for (i, answer) in self.answers.iter().enumerate() {
+            if answer.bindings.is_empty() {
+                writeln!(f, "Answer {}: yes", i + 1)?;
+            } else {
+                let bindings: Vec<_> = answer
+                    .bindings
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                writeln!(f, "Answer {}: {}", i + 1, bindings.join(", "))?;
+            }
+            for proof in &answer.proofs {
+                proof.write_tree(f, 1)?;
+            }
+        }
+        if let Some(limit) = self.truncated {
+            writeln!(f, "(search {} reached; some answers may be missing)", limit)?;
+        }
+        Ok(())
+    }
+}
+
+/// A solution to the body of an integrity constraint
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub label: Option<String>,
+    pub span: Span,
+    pub answer: Answer,
+}
+
+impl Violation {
+    /// Report the violation with the bindings and the source location of
+    /// every fact and rule that produced it
+    pub fn render(&self, source: &str) -> String {
+        let (line, col) = self.span.line_col(source);
+        let mut text = format!("integrity constraint at {}:{} violated", line, col);
+        if let Some(label) = &self.label {
+            text.push_str(&format!(": {}", label));
+        }
+        text.push('\n');
+        if !self.answer.bindings.is_empty() {
+            let bindings: Vec<_> = self
+                .answer
+                .bindings
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect();
+            text.push_str(&format!("  with {}\n", bindings.join(", ")));
+        }
+        for proof in &self.answer.proofs {
+            write_located(proof, source, 1, &mut text);
+        }
+        text
+    }
+
+    /// The violation as a type error at the constraint, pointing at the
+    /// facts behind it
+    pub fn to_error(&self) -> TypeError {
+        let mut facts = Vec::new();
+        for proof in &self.answer.proofs {
+            collect_facts(proof, &mut facts);
+        }
+        TypeError::ConstraintViolation {
+            label: self.label.clone(),
+            bindings: self
+                .answer
+                .bindings
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect(),
+            facts,
+            span: self.span.clone(),
+        }
+    }
+}
+
+/// The stored facts a proof rests on, with what each one says
+fn collect_facts(node: &ProofNode, out: &mut Vec<(Span, String)>) {
+    match node {
+        ProofNode::Fact {
+            relation,
+            args,
+            span,
+            text,
+        } => {
+            // A fact may support more than one step of the proof
+            let fact = (span.clone(), describe(relation, args, text.as_deref()));
+            if !out.contains(&fact) {
+                out.push(fact);
+            }
+        }
+        ProofNode::Rule { children, .. } => {
+            children.iter().for_each(|child| collect_facts(child, out));
+        }
+        ProofNode::Decision { winner, .. } => collect_facts(winner, out),
+        _ => {}
+    }
+}
+
+/// Like `ProofNode::write_tree`, with the source location of facts and rules
+fn write_located(node: &ProofNode, source: &str, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match node {
+        ProofNode::Fact {
+            relation,
+            args,
+            span,
+            text,
+        } => {
+            let (line, col) = span.line_col(source);
+            out.push_str(&format!(
+                "{}{}  [fact at {}:{}]\n",
+                indent,
+                describe(relation, args, text.as_deref()),
+                line,
+                col
+            ));
+        }
+        ProofNode::Rule {
+            relation,
+            args,
+            rule_id,
+            label,
+            span,
+            text,
+            children,
+        } => {
+            let (line, col) = span.line_col(source);
+            out.push_str(&format!(
+                "{}{}  [{} at {}:{}]\n",
+                indent,
+                describe(relation, args, text.as_deref()),
+                rule_tag(*rule_id, label.as_deref()),
+                line,
+                col
+            ));
+            for child in children {
+                write_located(child, source, depth + 1, out);
+            }
+        }
+        ProofNode::Decision {
+            winner,
+            algorithm,
+            overridden,
+        } => {
+            write_located(winner, source, depth, out);
+            let _ = write_verdict(out, &indent, *algorithm, overridden);
+        }
+        other => out.push_str(&format!("{}{}", indent, other)),
+    }
+}
+
+/// A clause that can prove a goal for a relation
+#[derive(Clone, Copy)]
+enum Clause<'a> {
+    Fact(&'a Fact),
+    Rule(usize, &'a Rule),
+}
+
+/// Variable bindings with a trail for undoing them on backtracking, and the
+/// answer tables of the search they belong to
+struct Bindings {
+    slots: Vec<Option<Value>>,
+    trail: Vec<usize>,
+    /// The limit the search reached; the step limit ends it outright, so it
+    /// wins over a depth limit reached before
+    truncated: Option<Limit>,
+    /// Clauses tried so far
+    steps: usize,
+    /// Answers to tabled calls, by call variant
+    tables: HashMap<String, Table>,
+    /// Tabled calls being evaluated, outermost first
+    active: Vec<Frame>,
+    /// Answers added to any table, to tell when a fixpoint is reached
+    added: usize,
+}
+
+/// Ground answers found so far for one tabled call
+#[derive(Default)]
+struct Table {
+    /// Arguments, proof, and the proof's size in nodes
+    answers: Vec<(Vec<Value>, ProofNode, usize)>,
+    keys: HashSet<String>,
+    /// Every answer is in; calls read the table instead of resolving
+    complete: bool,
+}
+
+/// A tabled call under evaluation
+struct Frame {
+    key: String,
+    /// A repeated call read this table, so it may be short of answers
+    looped: bool,
+    /// Oldest frame whose table this call or a call below it read
+    low: usize,
+}
+
+impl Bindings {
+    fn new() -> Self {
+        Bindings {
+            slots: Vec::new(),
+            trail: Vec::new(),
+            truncated: None,
+            steps: 0,
+            tables: HashMap::new(),
+            active: Vec::new(),
+            added: 0,
+        }
+    }
+
+    fn fresh(&mut self) -> Value {
+        self.slots.push(None);
+        Value::Var(self.slots.len() - 1)
+    }
+
+    /// Follow variable bindings until reaching an unbound variable or a non-variable
+    fn walk(&self, value: &Value) -> Value {
+        let mut current = value.clone();
+        while let Value::Var(id) = current {
+            match &self.slots[id] {
+                Some(bound) => current = bound.clone(),
+                None => break,
+            }
+        }
+        current
+    }
+
+    /// Substitute bindings everywhere inside a value
+    fn resolve(&self, value: &Value) -> Value {
+        match self.walk(value) {
+            Value::Cons(name, args) => {
+                Value::Cons(name, args.iter().map(|a| self.resolve(a)).collect())
+            }
+            other => other,
+        }
+    }
+
+    fn bind(&mut self, id: usize, value: Value) {
+        self.slots[id] = Some(value);
+        self.trail.push(id);
+    }
+
+    fn mark(&self) -> usize {
+        self.trail.len()
+    }
+
+    fn undo(&mut self, mark: usize) {
+        while self.trail.len() > mark {
+            if let Some(id) = self.trail.pop() {
+                self.slots[id] = None;
+            }
+        }
+    }
+
+    fn unify(&mut self, left: &Value, right: &Value) -> bool {
+        match (self.walk(left), self.walk(right)) {
+            (Value::Var(a), Value::Var(b)) if a == b => true,
+            (Value::Var(id), other) | (other, Value::Var(id)) => {
+                self.bind(id, other);
+                true
+            }
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Date(a), Value::Date(b))
+            | (Value::Timestamp(a), Value::Timestamp(b))
+            | (Value::Duration(a), Value::Duration(b)) => a == b,
+            (Value::Cons(c1, args1), Value::Cons(c2, args2)) => {
+                c1 == c2
+                    && args1.len() == args2.len()
+                    && args1
+                        .iter()
+                        .zip(args2.iter())
+                        .all(|(a, b)| self.unify(a, b))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Continuation receiving the bindings and the proofs of the goals solved so far.
+/// Returning false stops the search.
+type GoalsCont<'k> = dyn FnMut(&mut Bindings, Vec<ProofNode>) -> bool + 'k;
+
+/// Continuation receiving the proof of a single solved atom
+type AtomCont<'k> = dyn FnMut(&mut Bindings, ProofNode) -> bool + 'k;
+
+/// How a relation with `@combine` or `@overrides` picks among derivations
+#[derive(Debug, Clone)]
+struct Policy {
+    combine: Option<Combine>,
+    /// Argument positions identifying a request: the first key, or all but the last
+    request: Vec<usize>,
+}
+
+/// One derivation of a decision relation, before combining
+struct Candidate {
+    args: Vec<Value>,
+    node: ProofNode,
+}
+
+/// Positions in a relation's clauses of the facts with each argument value
+#[derive(Default)]
+struct FactIndex {
+    /// Ground facts by the value of each argument, keyed by its debug form
+    by_arg: Vec<HashMap<String, Vec<usize>>>,
+    /// Rules, and facts that only evaluate during a search; tried on every call
+    always: Vec<usize>,
+}
+
+/// Top-down resolution engine over a parsed program
+pub struct Explainer<'a> {
+    clauses: HashMap<&'a str, Vec<Clause<'a>>>,
+    /// Clauses of each relation by fact argument, for calls with bound arguments
+    fact_index: HashMap<&'a str, FactIndex>,
+    max_depth: usize,
+    max_answers: usize,
+    max_steps: usize,
+    /// Relations that can call themselves, whose calls are tabled
+    recursive: HashSet<String>,
+    /// Instant `now()` evaluates to; goals using it fail when unset
+    now: Option<i64>,
+    /// Declaration index of each sum-type variant, which defines its ordering
+    variant_order: HashMap<&'a str, usize>,
+    /// Decision relations: outcomes are combined per request
+    policies: HashMap<&'a str, Policy>,
+    /// Rules each rule defeats through `@overrides`, transitively
+    overrides: HashMap<usize, HashSet<usize>>,
+    /// Integrity constraints, in source order
+    constraints: Vec<&'a Constraint>,
+    /// Explanation template of each relation: parameter names and parts
+    templates: HashMap<&'a str, (&'a [String], Vec<TemplatePart>)>,
+    /// Field names of each product constructor, for `{U.name}` placeholders
+    fields: HashMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> Explainer<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let mut clauses: HashMap<&'a str, Vec<Clause<'a>>> = HashMap::new();
+        let mut rule_id = 0;
+        let mut variant_order = HashMap::new();
+        let mut templates = HashMap::new();
+        let mut fields = HashMap::new();
+        let mut constraints = Vec::new();
+        let mut policies = HashMap::new();
+        let mut rule_names = HashMap::new();
+        let mut direct: Vec<(usize, &'a Rule)> = Vec::new();
+
+        // Clauses are tried in source order, with rules numbered by position
+        for item in &program.items {
+            match item {
+                Item::Fact(fact) => clauses
+                    .entry(fact.relation.as_str())
+                    .or_default()
+                    .push(Clause::Fact(fact)),
+                Item::TypeDef(TypeDef {
+                    def: TypeDefKind::Sum { variants },
+                    ..
+                }) => {
+                    for (index, variant) in variants.iter().enumerate() {
+                        variant_order.insert(variant.as_str(), index);
+                    }
+                }
+                Item::TypeDef(TypeDef {
+                    def:
+                        TypeDefKind::Product {
+                            constructor,
+                            fields: defs,
+                        },
+                    ..
+                }) => {
+                    fields.insert(
+                        constructor.as_str(),
+                        defs.iter().map(|f| f.name.as_str()).collect(),
+                    );
+                }
+                Item::Constraint(constraint) => constraints.push(constraint),
+                Item::Explain(template) => {
+                    // Malformed templates are rejected by the type checker
+                    if let Ok(parts) = template.parts() {
+                        templates.insert(
+                            template.relation.as_str(),
+                            (template.params.as_slice(), parts),
+                        );
+                    }
+                }
+                Item::Rule(rule) => {
+                    clauses
+                        .entry(rule.head.relation.as_str())
+                        .or_default()
+                        .push(Clause::Rule(rule_id, rule));
+                    if let Some(name) = rule.name() {
+                        rule_names.insert(name, rule_id);
+                    }
+                    if !rule.overrides().is_empty() {
+                        direct.push((rule_id, rule));
+                    }
+                    rule_id += 1;
+                }
+                Item::RelationDecl(rel) => {
+                    let combine = rel.combine().and_then(Combine::from_name);
+                    let arity = match &rel.signature {
+                        crate::ast::Type::Product(types) => types.len(),
+                        _ => 1,
+                    };
+                    let request = match rel.keys.first() {
+                        Some(key) => key.clone(),
+                        None => (0..arity.saturating_sub(1)).collect(),
+                    };
+                    policies.insert(rel.name.as_str(), Policy { combine, request });
+                }
+                _ => {}
+            }
+        }
+
+        // Close `@overrides` transitively: if A beats B and B beats C, A beats C
+        let mut overrides: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (id, rule) in &direct {
+            let mut stack: Vec<&str> = rule.overrides();
+            let beaten = overrides.entry(*id).or_default();
+            while let Some(name) = stack.pop() {
+                let Some(&target) = rule_names.get(name) else {
+                    continue;
+                };
+                if beaten.insert(target) {
+                    if let Some((_, next)) = direct.iter().find(|(i, _)| *i == target) {
+                        stack.extend(next.overrides());
+                    }
+                }
+            }
+        }
+
+        // Only relations that combine or override need decision semantics
+        let overridden_relations: HashSet<&str> = direct
+            .iter()
+            .map(|(_, rule)| rule.head.relation.as_str())
+            .collect();
+        policies
+            .retain(|name, policy| policy.combine.is_some() || overridden_relations.contains(name));
+
+        let graph = DependencyGraph::build(program);
+        let recursive = graph
+            .relations()
+            .filter(|name| graph.is_recursive(name))
+            .map(str::to_string)
+            .collect();
+
+        let mut explainer = Explainer {
+            clauses,
+            fact_index: HashMap::new(),
+            policies,
+            overrides,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_answers: DEFAULT_MAX_ANSWERS,
+            max_steps: DEFAULT_MAX_STEPS,
+            recursive,
+            now: None,
+            variant_order,
+            constraints,
+            templates,
+            fields,
+        };
+        explainer.fact_index = explainer.index_facts();
+        explainer
+    }
+
+    /// Index the ground facts of every relation by each argument's value
+    fn index_facts(&self) -> HashMap<&'a str, FactIndex> {
+        let mut b = Bindings::new();
+        let empty = HashMap::new();
+        self.clauses
+            .iter()
+            .map(|(&relation, clauses)| {
+                let mut index = FactIndex::default();
+                for (i, clause) in clauses.iter().enumerate() {
+                    let values = match clause {
+                        Clause::Fact(fact) => self
+                            .instantiate_all(&fact.args, &empty, &mut b)
+                            .filter(|values| values.iter().all(is_ground)),
+                        Clause::Rule(..) => None,
+                    };
+                    let Some(values) = values else {
+                        index.always.push(i);
+                        continue;
+                    };
+                    if index.by_arg.len() < values.len() {
+                        index.by_arg.resize_with(values.len(), HashMap::new);
+                    }
+                    for (pos, value) in values.iter().enumerate() {
+                        index.by_arg[pos]
+                            .entry(format!("{:?}", value))
+                            .or_default()
+                            .push(i);
+                    }
+                }
+                (relation, index)
+            })
+            .collect()
+    }
+
+    /// The clauses a call can match, in source order: with an argument
+    /// bound, only the facts agreeing on the most selective one
+    fn candidates(&self, relation: &str, args: &[Value], b: &Bindings) -> Option<Vec<usize>> {
+        let index = self.fact_index.get(relation)?;
+        let facts = args
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, arg)| {
+                let value = b.resolve(arg);
+                is_ground(&value).then(|| {
+                    index
+                        .by_arg
+                        .get(pos)
+                        .and_then(|values| values.get(&format!("{:?}", value)))
+                        .map_or(&[][..], Vec::as_slice)
+                })
+            })
+            .min_by_key(|facts| facts.len())?;
+        let mut tried = index.always.clone();
+        tried.extend_from_slice(facts);
+        tried.sort_unstable();
+        Some(tried)
+    }
+
+    pub fn with_limits(mut self, max_depth: usize, max_answers: usize) -> Self {
+        self.max_depth = max_depth;
+        self.max_answers = max_answers;
+        self
+    }
+
+    /// Cap the number of clauses tried in one search
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Fix the instant that `now()` evaluates to (seconds since the epoch)
+    pub fn with_now(mut self, now: i64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Find answers to a query, each with a proof tree per goal
+    pub fn explain(&self, query: &Query) -> Explanation {
+        self.explain_goals(&query.goals)
+    }
+
+    /// Solve every integrity constraint against the facts; each answer is a violation
+    ///
+    /// Goals using `now()` fail unless `with_now` was called, so time-dependent
+    /// constraints are left to the load-time check.
+    pub fn violations(&self) -> Vec<Violation> {
+        self.check_constraints().0
+    }
+
+    /// The violations, and the constraints whose search reached the depth
+    /// or step limit, with that limit, so that some of their violations may
+    /// be missing
+    pub fn check_constraints(&self) -> (Vec<Violation>, Vec<(&'a Constraint, Limit)>) {
+        let mut violations = Vec::new();
+        let mut truncated = Vec::new();
+        for &constraint in &self.constraints {
+            let explanation = self.explain_goals(&constraint.body);
+            if let Some(limit) = explanation.truncated {
+                truncated.push((constraint, limit));
+            }
+            for answer in explanation.answers {
+                violations.push(Violation {
+                    label: constraint.label().map(str::to_string),
+                    span: constraint.span.clone(),
+                    answer,
+                });
+            }
+        }
+        (violations, truncated)
+    }
+
+    fn explain_goals(&self, goals: &[Goal]) -> Explanation {
+        let mut b = Bindings::new();
+        let mut names = Vec::new();
+        for goal in goals {
+            collect_goal_vars(goal, &mut names);
+        }
+        let env: HashMap<String, Value> = names.iter().map(|n| (n.clone(), b.fresh())).collect();
+
+        let mut answers = Vec::new();
+        let max_answers = self.max_answers;
+        self.solve(goals, &env, &mut b, 0, Vec::new(), &mut |b, proofs| {
+            answers.push(Answer {
+                bindings: names
+                    .iter()
+                    .map(|n| (n.clone(), b.resolve(&env[n])))
+                    .collect(),
+                proofs: proofs.iter().map(|p| self.narrate(p.resolve(b))).collect(),
+            });
+            answers.len() < max_answers
+        });
+
+        let (attempts, decisions) = if answers.is_empty() {
+            let decisions = self.reached_decisions(goals);
+            let mut attempts = self.attempted_rules(goals);
+            for attempt in &mut attempts {
+                attempt.overridden = decisions.iter().any(|decision| match decision {
+                    ProofNode::Decision { overridden, .. } => overridden
+                        .iter()
+                        .any(|(node, _)| node.rule_id() == Some(attempt.rule_id)),
+                    _ => false,
+                });
+            }
+            (attempts, decisions)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        Explanation {
+            answers,
+            truncated: b.truncated,
+            attempts,
+            decisions,
+        }
+    }
+
+    /// For failed calls to decision relations, the decisions that were made
+    /// for the same request; they explain e.g. why a permit was not granted
+    fn reached_decisions(&self, goals: &[Goal]) -> Vec<ProofNode> {
+        let mut decisions = Vec::new();
+        for goal in goals {
+            let Goal::Atom(atom) = goal else { continue };
+            if !self.policies.contains_key(atom.relation.as_str()) {
+                continue;
+            }
+            let mut b = Bindings::new();
+            let env = HashMap::new();
+            let Some(args) = self.instantiate_all(&atom.args, &env, &mut b) else {
+                continue;
+            };
+            for (_, node) in self.decide(&atom.relation, &args, &mut b, 0) {
+                decisions.push(self.narrate(node));
+            }
+        }
+        decisions
+    }
+
+    /// Fill in templated text for every relation goal in a resolved proof
+    fn narrate(&self, mut node: ProofNode) -> ProofNode {
+        match &mut node {
+            ProofNode::Fact {
+                relation,
+                args,
+                text,
+                ..
+            } => *text = self.render(relation, args),
+            ProofNode::Rule {
+                relation,
+                args,
+                text,
+                children,
+                ..
+            } => {
+                *text = self.render(relation, args);
+                *children = std::mem::take(children)
+                    .into_iter()
+                    .map(|c| self.narrate(c))
+                    .collect();
+            }
+            ProofNode::Decision {
+                winner, overridden, ..
+            } => {
+                **winner = self.narrate((**winner).clone());
+                *overridden = std::mem::take(overridden)
+                    .into_iter()
+                    .map(|(c, by)| (self.narrate(c), by))
+                    .collect();
+            }
+            _ => {}
+        }
+        node
+    }
+
+    /// Render a goal with its relation's template, if it has one
+    fn render(&self, relation: &str, args: &[Value]) -> Option<String> {
+        let (params, parts) = self.templates.get(relation)?;
+        let mut text = String::new();
+        for part in parts {
+            match part {
+                TemplatePart::Text(t) => text.push_str(t),
+                TemplatePart::Hole { var, fields } => {
+                    let index = params.iter().position(|p| p == var)?;
+                    let mut value = args.get(index)?;
+                    for field in fields {
+                        let Value::Cons(constructor, values) = value else {
+                            return None;
+                        };
+                        let position = self
+                            .fields
+                            .get(constructor.as_str())?
+                            .iter()
+                            .position(|f| f == field)?;
+                        value = values.get(position)?;
+                    }
+                    match value {
+                        // Strings read better without quotes in prose
+                        Value::Str(s) => text.push_str(s),
+                        other => text.push_str(&other.to_string()),
+                    }
+                }
+            }
+        }
+        Some(text)
+    }
+
+    /// Rules whose heads match the query's atoms; when the query fails these
+    /// are the rules that were tried and whose bodies did not hold
+    fn attempted_rules(&self, goals: &[Goal]) -> Vec<RuleAttempt> {
+        let mut attempts = Vec::new();
+        for goal in goals {
+            let Goal::Atom(atom) = goal else { continue };
+            let mut b = Bindings::new();
+            let env = HashMap::new();
+            let Some(args) = self.instantiate_all(&atom.args, &env, &mut b) else {
+                continue;
+            };
+            for clause in self
+                .clauses
+                .get(atom.relation.as_str())
+                .into_iter()
+                .flatten()
+            {
+                let Clause::Rule(rule_id, rule) = clause else {
+                    continue;
+                };
+                let mark = b.mark();
+                let rule_env = self.rename(rule, &mut b);
+                let matches = self
+                    .instantiate_all(&rule.head.args, &rule_env, &mut b)
+                    .is_some_and(|head| self.unify_all(&args, &head, &mut b));
+                b.undo(mark);
+                if matches {
+                    attempts.push(RuleAttempt {
+                        relation: atom.relation.clone(),
+                        rule_id: *rule_id,
+                        label: rule.label().map(str::to_string),
+                        span: rule.span.clone(),
+                        overridden: false,
+                    });
+                }
+            }
+        }
+        attempts
+    }
+
+    /// Solve a conjunction of goals left to right
+    fn solve(
+        &self,
+        goals: &[Goal],
+        env: &HashMap<String, Value>,
+        b: &mut Bindings,
+        depth: usize,
+        acc: Vec<ProofNode>,
+        k: &mut GoalsCont,
+    ) -> bool {
+        let Some((goal, rest)) = goals.split_first() else {
+            return k(b, acc);
+        };
+
+        // Solve the first goal, then continue with the rest of the conjunction
+        let mut next = |b: &mut Bindings, node: ProofNode| {
+            let mut acc = acc.clone();
+            acc.push(node);
+            self.solve(rest, env, b, depth, acc, k)
+        };
+
+        match goal {
+            Goal::Atom(atom) => {
+                let Some(args) = self.instantiate_all(&atom.args, env, b) else {
+                    return true;
+                };
+                self.solve_atom(&atom.relation, &args, b, depth, &mut next)
+            }
+            Goal::Unify(left, right, _) => {
+                let (Some(left), Some(right)) = (
+                    self.instantiate(left, env, b),
+                    self.instantiate(right, env, b),
+                ) else {
+                    return true;
+                };
+                let mark = b.mark();
+                let keep_going =
+                    !b.unify(&left, &right) || next(b, ProofNode::Unify { left, right });
+                b.undo(mark);
+                keep_going
+            }
+            Goal::Compare(op, left, right, _) => {
+                let (Some(left), Some(right)) = (
+                    self.instantiate(left, env, b),
+                    self.instantiate(right, env, b),
+                ) else {
+                    return true;
+                };
+                let (left, right) = (b.resolve(&left), b.resolve(&right));
+                if self.compare(*op, &left, &right) {
+                    next(
+                        b,
+                        ProofNode::Compare {
+                            op: *op,
+                            left,
+                            right,
+                        },
+                    )
+                } else {
+                    true
+                }
+            }
+            Goal::Match {
+                subject, pattern, ..
+            } => {
+                let Some(subject) = self.instantiate(subject, env, b) else {
+                    return true;
+                };
+                match b.resolve(&subject) {
+                    Value::Str(s) if qed_runtime::regex_match(&s, pattern) => next(
+                        b,
+                        ProofNode::Match {
+                            subject: Value::Str(s),
+                            pattern: pattern.clone(),
+                        },
+                    ),
+                    _ => true,
+                }
+            }
+        }
+    }
+
+    /// Solve a single relation call against its facts and rules
+    fn solve_atom(
+        &self,
+        relation: &str,
+        args: &[Value],
+        b: &mut Bindings,
+        depth: usize,
+        k: &mut AtomCont,
+    ) -> bool {
+        if depth >= self.max_depth {
+            b.truncated.get_or_insert(Limit::Depth);
+            return true;
+        }
+        if self.policies.contains_key(relation) {
+            return self.solve_decision(relation, args, b, depth, k);
+        }
+        if self.recursive.contains(relation) {
+            return self.solve_tabled(relation, args, b, depth, k);
+        }
+        self.solve_clauses(relation, args, b, depth, k)
+    }
+
+    /// Solve a call to a recursive relation through its answer table
+    ///
+    /// The first call of a variant evaluates the clauses; a repeated call
+    /// below it reads the answers found so far instead of recursing, and
+    /// the first call runs its clauses again until no table grows. Each
+    /// answer is passed on once, with the first proof found for it.
+    fn solve_tabled(
+        &self,
+        relation: &str,
+        args: &[Value],
+        b: &mut Bindings,
+        depth: usize,
+        k: &mut AtomCont,
+    ) -> bool {
+        let key = variant_key(
+            relation,
+            &args.iter().map(|v| b.resolve(v)).collect::<Vec<_>>(),
+        );
+        let complete = b.tables.get(&key).is_some_and(|table| table.complete);
+        if let Some(pos) = b.active.iter().position(|frame| frame.key == key) {
+            b.active[pos].looped = true;
+            if let Some(top) = b.active.last_mut() {
+                top.low = top.low.min(pos);
+            }
+            return self.consume(&key, args, b, k);
+        }
+        if complete {
+            return self.consume(&key, args, b, k);
+        }
+
+        let pos = b.active.len();
+        b.active.push(Frame {
+            key: key.clone(),
+            looped: false,
+            low: pos,
+        });
+        b.tables.entry(key.clone()).or_default();
+        let mut passed = HashSet::new();
+        loop {
+            b.active[pos].looped = false;
+            let added = b.added;
+            let keep_going = self.solve_clauses(relation, args, b, depth, &mut |b, node| {
+                let answer: Vec<Value> = args.iter().map(|v| b.resolve(v)).collect();
+                let answer_key = variant_key(relation, &answer);
+                if answer.iter().all(is_ground) {
+                    let proof = node.resolve(b);
+                    let table = b.tables.entry(key.clone()).or_default();
+                    if table.keys.insert(answer_key.clone()) {
+                        let size = proof.size();
+                        table.answers.push((answer, proof, size));
+                        b.added += 1;
+                    }
+                }
+                !passed.insert(answer_key) || k(b, node)
+            });
+            if !keep_going {
+                b.active.truncate(pos);
+                return false;
+            }
+            if !b.active[pos].looped || b.added == added || b.truncated.is_some() {
+                break;
+            }
+        }
+
+        // A call that read an older call's table completes with that call
+        let Some(frame) = b.active.pop() else {
+            return true;
+        };
+        if frame.low == pos {
+            if let Some(table) = b.tables.get_mut(&key) {
+                table.complete = true;
+            }
+        } else if let Some(parent) = b.active.last_mut() {
+            parent.low = parent.low.min(frame.low);
+        }
+        true
+    }
+
+    /// Answer a call from the answers its table has so far
+    ///
+    /// Copying a proof counts one step per node, so that deep proofs read
+    /// over and over stay within the step limit.
+    fn consume(&self, key: &str, args: &[Value], b: &mut Bindings, k: &mut AtomCont) -> bool {
+        let count = b.tables[key].answers.len();
+        for i in 0..count {
+            if b.steps >= self.max_steps {
+                b.truncated = Some(Limit::Steps);
+                return true;
+            }
+            let (answer, node, size) = b.tables[key].answers[i].clone();
+            b.steps += size;
+            let mark = b.mark();
+            let keep_going = !self.unify_all(args, &answer, b) || k(b, node);
+            b.undo(mark);
+            if !keep_going {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Solve a call to a decision relation: only winning derivations succeed
+    fn solve_decision(
+        &self,
+        relation: &str,
+        args: &[Value],
+        b: &mut Bindings,
+        depth: usize,
+        k: &mut AtomCont,
+    ) -> bool {
+        for (winner, node) in self.decide(relation, args, b, depth) {
+            let mark = b.mark();
+            let keep_going = !self.unify_all(args, &winner, b) || k(b, node);
+            b.undo(mark);
+            if !keep_going {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Derive every outcome for the requests matching `args`, then keep the
+    /// winners per request; returns each winner's arguments and decision proof
+    fn decide(
+        &self,
+        relation: &str,
+        args: &[Value],
+        b: &mut Bindings,
+        depth: usize,
+    ) -> Vec<(Vec<Value>, ProofNode)> {
+        let policy = &self.policies[relation];
+
+        // Leave the outcome open so conflicting derivations are all seen
+        let general: Vec<Value> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                if policy.request.contains(&i) {
+                    arg.clone()
+                } else {
+                    b.fresh()
+                }
+            })
+            .collect();
+        let mut candidates = Vec::new();
+        self.solve_clauses(relation, &general, b, depth, &mut |b, node| {
+            candidates.push(Candidate {
+                args: general.iter().map(|v| b.resolve(v)).collect(),
+                node: node.resolve(b),
+            });
+            true
+        });
+
+        // Group derivations by request, keeping first-seen order
+        let mut groups: Vec<(Vec<String>, Vec<Candidate>)> = Vec::new();
+        for candidate in candidates {
+            let request: Vec<String> = policy
+                .request
+                .iter()
+                .map(|&i| candidate.args[i].to_string())
+                .collect();
+            match groups.iter_mut().find(|(r, _)| *r == request) {
+                Some((_, group)) => group.push(candidate),
+                None => groups.push((request, vec![candidate])),
+            }
+        }
+
+        let mut decisions = Vec::new();
+        for (_, group) in groups {
+            decisions.extend(self.combine(policy.combine, group));
+        }
+        decisions
+    }
+
+    /// Pick the winning derivations of one request
+    fn combine(
+        &self,
+        algorithm: Option<Combine>,
+        group: Vec<Candidate>,
+    ) -> Vec<(Vec<Value>, ProofNode)> {
+        // `@overrides` first: drop derivations beaten by another that applies
+        let rules: Vec<Option<usize>> = group.iter().map(|c| c.node.rule_id()).collect();
+        let beaten = |rule: Option<usize>| {
+            rule.is_some_and(|r| {
+                rules.iter().flatten().any(|other| {
+                    self.overrides
+                        .get(other)
+                        .is_some_and(|beats| beats.contains(&r))
+                })
+            })
+        };
+        let (alive, beaten): (Vec<Candidate>, Vec<Candidate>) =
+            group.into_iter().partition(|c| !beaten(c.node.rule_id()));
+        let mut overridden: Vec<(ProofNode, Option<Combine>)> =
+            beaten.into_iter().map(|c| (c.node, None)).collect();
+
+        let winners: Vec<Candidate> = match algorithm {
+            None => alive,
+            Some(algorithm) => {
+                let outcome = |c: &Candidate| c.args.last().cloned();
+                let decisive = match algorithm {
+                    Combine::FirstApplicable => alive.first().and_then(outcome),
+                    Combine::DenyOverrides => alive
+                        .iter()
+                        .find(|c| is_effect(&c.args, &["Deny"]))
+                        .or(alive.first())
+                        .and_then(outcome),
+                    Combine::PermitOverrides => alive
+                        .iter()
+                        .find(|c| is_effect(&c.args, &["Permit", "Allow"]))
+                        .or(alive.first())
+                        .and_then(outcome),
+                };
+                // One answer per request; derivations of other outcomes lose
+                let mut winner = None;
+                for candidate in alive {
+                    if winner.is_none() && outcome(&candidate) == decisive {
+                        winner = Some(candidate);
+                    } else if outcome(&candidate) != decisive
+                        || algorithm == Combine::FirstApplicable
+                    {
+                        overridden.push((candidate.node, Some(algorithm)));
+                    }
+                }
+                winner.into_iter().collect()
+            }
+        };
+
+        winners
+            .into_iter()
+            .map(|winner| {
+                let node = if overridden.is_empty() && algorithm.is_none() {
+                    winner.node
+                } else {
+                    ProofNode::Decision {
+                        winner: Box::new(winner.node),
+                        algorithm,
+                        overridden: overridden.clone(),
+                    }
+                };
+                (winner.args, node)
+            })
+            .collect()
+    }
+
+    /// Solve a relation call against every fact and rule, in source order
+    fn solve_clauses(
+        &self,
+        relation: &str,
+        args: &[Value],
+        b: &mut Bindings,
+        depth: usize,
+        k: &mut AtomCont,
+    ) -> bool {
+        let Some(clauses) = self.clauses.get(relation) else {
+            return true;
+        };
+
+        let tried = self
+            .candidates(relation, args, b)
+            .unwrap_or_else(|| (0..clauses.len()).collect());
+        for clause in tried.iter().map(|&i| &clauses[i]) {
+            if b.steps >= self.max_steps {
+                b.truncated = Some(Limit::Steps);
+                return true;
+            }
+            b.steps += 1;
+            let mark = b.mark();
+            let keep_going = match *clause {
+                Clause::Fact(fact) => {
+                    let empty = HashMap::new();
+                    match self.instantiate_all(&fact.args, &empty, b) {
+                        Some(values) if self.unify_all(args, &values, b) => {
+                            let node = ProofNode::Fact {
+                                relation: relation.to_string(),
+                                args: args.to_vec(),
+                                span: fact.span.clone(),
+                                text: None,
+                            };
+                            k(b, node)
+                        }
+                        _ => true,
+                    }
+                }
+                Clause::Rule(rule_id, rule) => {
+                    let env = self.rename(rule, b);
+                    match self.instantiate_all(&rule.head.args, &env, b) {
+                        Some(head) if self.unify_all(args, &head, b) => self.solve(
+                            &rule.body,
+                            &env,
+                            b,
+                            depth + 1,
+                            Vec::new(),
+                            &mut |b, children| {
+                                let node = ProofNode::Rule {
+                                    relation: relation.to_string(),
+                                    args: args.to_vec(),
+                                    rule_id,
+                                    label: rule.label().map(str::to_string),
+                                    span: rule.span.clone(),
+                                    text: None,
+                                    children,
+                                };
+                                k(b, node)
+                            },
+                        ),
+                        _ => true,
+                    }
+                }
+            };
+            b.undo(mark);
+            if !keep_going {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Give every variable of a rule a fresh logic variable
+    fn rename(&self, rule: &Rule, b: &mut Bindings) -> HashMap<String, Value> {
+        let mut names = Vec::new();
+        for arg in &rule.head.args {
+            collect_term_vars(arg, &mut names);
+        }
+        for goal in &rule.body {
+            collect_goal_vars(goal, &mut names);
+        }
+        names.into_iter().map(|n| (n, b.fresh())).collect()
+    }
+
+    fn unify_all(&self, left: &[Value], right: &[Value], b: &mut Bindings) -> bool {
+        left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| b.unify(l, r))
+    }
+
+    fn instantiate_all(
+        &self,
+        terms: &[Term],
+        env: &HashMap<String, Value>,
+        b: &mut Bindings,
+    ) -> Option<Vec<Value>> {
+        terms.iter().map(|t| self.instantiate(t, env, b)).collect()
+    }
+
+    /// Turn a term into a value; arithmetic fails unless its operands are bound
+    fn instantiate(
+        &self,
+        term: &Term,
+        env: &HashMap<String, Value>,
+        b: &mut Bindings,
+    ) -> Option<Value> {
+        match term {
+            Term::Var(name, _) => match env.get(name) {
+                Some(value) => Some(value.clone()),
+                None => Some(b.fresh()),
+            },
+            Term::Int(n, _) => Some(Value::Int(*n)),
+            Term::String(s, _) => Some(Value::Str(s.clone())),
+            Term::Date(d, _) => Some(Value::Date(*d)),
+            Term::Timestamp(t, _) => Some(Value::Timestamp(*t)),
+            Term::Duration(d, _) => Some(Value::Duration(*d)),
+            Term::Now(_) => self.now.map(Value::Timestamp),
+            Term::Today(_) => self.now.map(|now| {
+                Value::Date(now.div_euclid(temporal::SECONDS_PER_DAY) * temporal::SECONDS_PER_DAY)
+            }),
+            Term::Construct {
+                constructor, args, ..
+            } => Some(Value::Cons(
+                constructor.clone(),
+                self.instantiate_all(args, env, b)?,
+            )),
+            Term::BinOp {
+                op, left, right, ..
+            } => {
+                let left = self.instantiate(left, env, b)?;
+                let right = self.instantiate(right, env, b)?;
+                arith(*op, &b.walk(&left), &b.walk(&right))
+            }
+        }
+    }
+
+    /// Compare two ground values; ordering is defined on like scalars and on
+    /// variants of the same enum, by declaration order
+    fn compare(&self, op: CompareOp, left: &Value, right: &Value) -> bool {
+        let ordering = match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Date(b))
+            | (Value::Timestamp(a), Value::Timestamp(b))
+            | (Value::Duration(a), Value::Duration(b)) => Some(a.cmp(b)),
+            (Value::Cons(a, a_args), Value::Cons(b, b_args))
+                if a_args.is_empty() && b_args.is_empty() =>
+            {
+                match (
+                    self.variant_order.get(a.as_str()),
+                    self.variant_order.get(b.as_str()),
+                ) {
+                    (Some(a), Some(b)) => Some(a.cmp(b)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match op {
+            CompareOp::Eq => left == right,
+            CompareOp::Ne => left != right,
+            CompareOp::Lt => ordering == Some(Ordering::Less),
+            CompareOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            CompareOp::Gt => ordering == Some(Ordering::Greater),
+            CompareOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// A call's relation and arguments, with unbound variables numbered by
+/// first occurrence so that calls differing only in variables share a key
+fn variant_key(relation: &str, args: &[Value]) -> String {
+    fn write(value: &Value, vars: &mut Vec<usize>, out: &mut String) {
+        match value {
+            Value::Var(id) => {
+                let n = vars.iter().position(|v| v == id).unwrap_or_else(|| {
+                    vars.push(*id);
+                    vars.len() - 1
+                });
+                out.push_str(&format!("_{}", n));
+            }
+            Value::Cons(name, args) => {
+                out.push_str(name);
+                out.push('(');
+                for arg in args {
+                    write(arg, vars, out);
+                    out.push(',');
+                }
+                out.push(')');
+            }
+            other => out.push_str(&format!("{:?}", other)),
+        }
+    }
+    let mut out = format!("{}(", relation);
+    let mut vars = Vec::new();
+    for arg in args {
+        write(arg, &mut vars, &mut out);
+        out.push(',');
+    }
+    out.push(')');
+    out
+}
+
+fn is_ground(value: &Value) -> bool {
+    match value {
+        Value::Var(_) => false,
+        Value::Cons(_, args) => args.iter().all(is_ground),
+        _ => true,
+    }
+}
+
+/// Whether the outcome (last argument) is one of the named nullary variants
+fn is_effect(args: &[Value], names: &[&str]) -> bool {
+    matches!(args.last(), Some(Value::Cons(name, fields)) if fields.is_empty() && names.contains(&name.as_str()))
+}
+
+/// Evaluate arithmetic on ground values, following the type checker's rules
+fn arith(op: BinOp, left: &Value, right: &Value) -> Option<Value> {
+    let int = |l: i64, r: i64| match op {
+        BinOp::Add => l.checked_add(r),
+        BinOp::Sub => l.checked_sub(r),
+        BinOp::Mul => l.checked_mul(r),
+        BinOp::Div => l.checked_div(r),
+        BinOp::Mod => l.checked_rem(r),
+    };
+    match (op, left, right) {
+        (_, Value::Int(l), Value::Int(r)) => int(*l, *r).map(Value::Int),
+        (BinOp::Add | BinOp::Sub, Value::Date(d), Value::Duration(s))
+        | (BinOp::Add, Value::Duration(s), Value::Date(d)) => int(*d, *s).map(Value::Date),
+        (BinOp::Add | BinOp::Sub, Value::Timestamp(t), Value::Duration(s))
+        | (BinOp::Add, Value::Duration(s), Value::Timestamp(t)) => {
+            int(*t, *s).map(Value::Timestamp)
+        }
+        (BinOp::Sub, Value::Date(l), Value::Date(r))
+        | (BinOp::Sub, Value::Timestamp(l), Value::Timestamp(r))
+        | (BinOp::Add | BinOp::Sub, Value::Duration(l), Value::Duration(r))
+        | (BinOp::Mul | BinOp::Div, Value::Duration(l), Value::Int(r))
+        | (BinOp::Mul, Value::Int(l), Value::Duration(r)) => int(*l, *r).map(Value::Duration),
+        _ => None,
+    }
+}
+
+fn collect_term_vars(term: &Term, names: &mut Vec<String>) {
+    match term {
+        Term::Var(name, _) => {
+            if name != "_" && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        Term::Construct { args, .. } => {
+            for arg in args {
+                collect_term_vars(arg, names);
+            }
+        }
+        Term::BinOp { left, right, .. } => {
+            collect_term_vars(left, names);
+            collect_term_vars(right, names);
+        }
+        Term::Int(..)
+        | Term::String(..)
+        | Term::Date(..)
+        | Term::Timestamp(..)
+        | Term::Duration(..)
+        | Term::Now(..)
+        | Term::Today(..) => {}
+    }
+}
+
+fn collect_goal_vars(goal: &Goal, names: &mut Vec<String>) {
+    match goal {
+        Goal::Atom(atom) => {
+            for arg in &atom.args {
+                collect_term_vars(arg, names);
+            }
+        }
+        Goal::Unify(left, right, _) | Goal::Compare(_, left, right, _) => {
+            collect_term_vars(left, names);
+            collect_term_vars(right, names);
+        }
+        Goal::Match { subject, .. } => collect_term_vars(subject, names),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn explain(source: &str, query: &str) -> Explanation {
+        let program = parser::parse(source).expect("Parse failed");
+        let query = parser::parse_query_for(&program, query).expect("Query parse failed");
+        Explainer::new(&program).explain(&query)
+    }
+
+    const FAMILY: &str = r#"
+        type Person = person(name: String, age: Int)
+        rel parent: Person × Person
+        rel ancestor: Person × Person
+        parent(person("Alice", 70), person("Bob", 45)).
+        parent(person("Bob", 45), person("Carol", 20)).
+        ancestor(X, Y) :- parent(X, Y).
+        ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+    "#;
+
+    #[test]
+    fn test_explain_recursive_proof() {
+        let explanation = explain(
+            FAMILY,
+            r#"ancestor(person("Alice", 70), person("Carol", 20))"#,
+        );
+        assert_eq!(explanation.answers.len(), 1);
+
+        match &explanation.answers[0].proofs[0] {
+            ProofNode::Rule {
+                rule_id, children, ..
+            } => {
+                assert_eq!(*rule_id, 1);
+                assert_eq!(children.len(), 2);
+                assert!(matches!(children[0], ProofNode::Fact { .. }));
+            }
+            other => panic!("Expected rule proof, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_explain_bindings() {
+        let explanation = explain(FAMILY, r#"?- ancestor(person("Alice", 70), Q)."#);
+        let found: Vec<String> = explanation
+            .answers
+            .iter()
+            .map(|a| a.bindings[0].1.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec![r#"person("Bob", 45)"#, r#"person("Carol", 20)"#]
+        );
+    }
+
+    #[test]
+    fn test_explain_failure() {
+        let explanation = explain(FAMILY, r#"ancestor(person("Carol", 20), Q)"#);
+        assert!(explanation.answers.is_empty());
+        assert!(explanation.to_string().contains("not provable"));
+    }
+
+    #[test]
+    fn test_explain_depth_limit() {
+        let source = r#"
+            rel edge: Int × Int
+            rel reach: Int × Int
+            edge(1, 2).
+            edge(2, 3).
+            edge(3, 4).
+            reach(X, Y) :- edge(X, Y).
+            reach(X, Z) :- edge(X, Y), reach(Y, Z).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let query = parser::parse_query("reach(1, Q)").expect("Query parse failed");
+
+        let explanation = Explainer::new(&program).with_limits(3, 16).explain(&query);
+        assert_eq!(explanation.truncated, Some(Limit::Depth));
+        assert_eq!(explanation.answers.len(), 2);
+        assert!(explanation.to_string().contains("depth limit reached"));
+    }
+
+    #[test]
+    fn test_explain_left_recursion() {
+        // Left recursion over a cycle never bottoms out without tabling
+        let source = r#"
+            rel edge: Int × Int
+            rel reach: Int × Int
+            edge(1, 2).
+            edge(2, 1).
+            reach(X, Z) :- reach(X, Y), edge(Y, Z).
+            reach(X, Y) :- edge(X, Y).
+        "#;
+        let explanation = explain(source, "reach(1, Q)");
+        assert_eq!(explanation.truncated, None);
+        let found: Vec<String> = explanation
+            .answers
+            .iter()
+            .map(|a| a.bindings[0].1.to_string())
+            .collect();
+        assert_eq!(found, vec!["2", "1"]);
+    }
+
+    #[test]
+    fn test_explain_doubly_recursive() {
+        let source = r#"
+            rel e: Int × Int
+            rel q: Int × Int
+            e(1, 2).
+            e(2, 3).
+            e(3, 1).
+            e(4, 5).
+            q(X, Z) :- q(X, Y), q(Y, Z).
+            q(X, Z) :- e(X, Z).
+        "#;
+        let explanation = explain(source, "q(A, 3)");
+        assert_eq!(explanation.truncated, None);
+        let mut found: Vec<String> = explanation
+            .answers
+            .iter()
+            .map(|a| a.bindings[0].1.to_string())
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["1", "2", "3"]);
+        assert!(explain(source, "q(4, 1)").answers.is_empty());
+    }
+
+    #[test]
+    fn test_explain_self_recursive() {
+        let source = r#"
+            rel e: Int × Int
+            rel q: Int × Int
+            e(1, 2).
+            q(X, Z) :- q(X, Z).
+            q(X, Z) :- e(X, Z).
+        "#;
+        let explanation = explain(source, "q(A, B)");
+        assert_eq!(explanation.truncated, None);
+        assert_eq!(explanation.answers.len(), 1);
+        assert!(explain(source, "q(2, 1)").answers.is_empty());
+    }
+
+    #[test]
+    fn test_explain_step_limit() {
+        // Every round of the table finds a new, larger answer
+        let source = r#"
+            rel count: Int
+            count(0).
+            count(N) :- count(M), N = M + 1.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let query = parser::parse_query("count(N), N < 0").expect("Query parse failed");
+
+        let explanation = Explainer::new(&program)
+            .with_max_steps(1000)
+            .explain(&query);
+        assert_eq!(explanation.truncated, Some(Limit::Steps));
+        assert!(explanation.answers.is_empty());
+        // Running out of steps proves nothing, so the answer is not "No"
+        assert_eq!(
+            explanation.to_string(),
+            "Unknown: the search reached its step limit before finding a proof\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_indexes_bound_facts() {
+        let mut source = String::from(
+            r#"
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+        "#,
+        );
+        for i in 0..5000 {
+            source.push_str(&format!("parent({}, {}).\n", i, i + 1));
+        }
+        let program = parser::parse(&source).expect("Parse failed");
+        let query = parser::parse_query("ancestor(0, 20)").expect("Query parse failed");
+
+        // Each call binds the parent, so it tries one fact instead of all 5000
+        let explanation = Explainer::new(&program)
+            .with_limits(64, 1)
+            .with_max_steps(1000)
+            .explain(&query);
+        assert_eq!(explanation.truncated, None);
+        assert_eq!(explanation.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_explain_answer_limit() {
+        let program = parser::parse(FAMILY).expect("Parse failed");
+        let query = parser::parse_query("ancestor(X, Y)").expect("Query parse failed");
+
+        let all = Explainer::new(&program).explain(&query);
+        assert_eq!(all.answers.len(), 3);
+        let first = Explainer::new(&program).with_limits(64, 1).explain(&query);
+        assert_eq!(first.answers.len(), 1);
+        assert_eq!(first.truncated, None);
+    }
+
+    #[test]
+    fn test_explain_unifies_structures() {
+        // The shared variable is bound by the first goal and checked by the second
+        let explanation = explain(
+            FAMILY,
+            r#"parent(person(N, 70), P), parent(P, person(C, A))"#,
+        );
+        assert_eq!(explanation.answers.len(), 1);
+        let bindings: Vec<String> = explanation.answers[0]
+            .bindings
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![
+                r#"N = "Alice""#,
+                r#"P = person("Bob", 45)"#,
+                r#"C = "Carol""#,
+                "A = 20",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_records_matched_pattern() {
+        let source = r#"
+            rel path: String
+            rel doc_path: String
+            path("/projects/qed/docs/intro").
+            path("/projects/qed/src/main.rs").
+            doc_path(P) :- path(P), matches(P, "^/projects/[^/]+/docs/").
+        "#;
+        let explanation = explain(source, "doc_path(P)");
+        assert_eq!(explanation.answers.len(), 1);
+
+        let rendered = explanation.to_string();
+        assert!(rendered.contains(r#"P = "/projects/qed/docs/intro""#));
+        assert!(rendered.contains(r#"matches "^/projects/[^/]+/docs/""#));
+    }
+
+    #[test]
+    fn test_explain_matches_escaped_pattern() {
+        let source = r#"
+            rel path: String
+            rel dotted: String
+            path("/a.b").
+            path("/axb").
+            dotted(P) :- path(P), matches(P, "^/a\\.b$").
+        "#;
+        let explanation = explain(source, "dotted(P)");
+        assert_eq!(explanation.answers.len(), 1);
+
+        let rendered = explanation.to_string();
+        assert!(rendered.contains(r#"P = "/a.b""#));
+        // The pattern is shown as it was written
+        assert!(rendered.contains(r#"matches "^/a\\.b$""#));
+    }
+
+    #[test]
+    fn test_explain_matches_unknown_escapes() {
+        let source = r#"
+            rel ticket: String
+            rel numbered: String
+            ticket("T-42").
+            ticket("T-xy").
+            numbered(T) :- ticket(T), matches(T, "^T-\d+$").
+        "#;
+        let explanation = explain(source, "numbered(T)");
+        assert_eq!(explanation.answers.len(), 1);
+        assert!(explanation.to_string().contains(r#"T = "T-42""#));
+    }
+
+    #[test]
+    fn test_explain_matches_word_boundary() {
+        let source = r#"
+            rel title: String
+            rel about_rust: String
+            title("learning rust today").
+            title("trusting the process").
+            about_rust(T) :- title(T), matches(T, "\brust\b").
+        "#;
+        let explanation = explain(source, "about_rust(T)");
+        assert_eq!(explanation.answers.len(), 1);
+        assert!(explanation
+            .to_string()
+            .contains(r#"T = "learning rust today""#));
+    }
+
+    #[test]
+    fn test_explain_temporal_now() {
+        let source = r#"
+            rel contract: String × Timestamp
+            rel expiring: String
+            contract("acme", 2026-03-01T00:00:00Z).
+            contract("globex", 2027-01-01T00:00:00Z).
+            expiring(C) :- contract(C, End), End - P90D <= now().
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let query = parser::parse_query("expiring(C)").expect("Query parse failed");
+        let now = qed_runtime::temporal::parse_timestamp("2026-01-15T00:00:00Z").unwrap();
+
+        let explanation = Explainer::new(&program).with_now(now).explain(&query);
+        let rendered = explanation.to_string();
+        assert_eq!(explanation.answers.len(), 1);
+        assert!(rendered.contains(r#"C = "acme""#));
+        assert!(rendered.contains("2026-03-01T00:00:00Z"));
+
+        // Without a supplied instant, goals on now() are not provable
+        assert!(Explainer::new(&program).explain(&query).answers.is_empty());
+
+        // today() is the date of the instant, so a Date valid until today holds all day
+        let source = r#"
+            rel offer: String × Date
+            rel valid: String
+            offer("spring", 2026-01-15).
+            offer("winter", 2026-01-14).
+            valid(O) :- offer(O, Until), Until >= today().
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let query = parser::parse_query("valid(O)").expect("Query parse failed");
+        let now = qed_runtime::temporal::parse_timestamp("2026-01-15T23:00:00Z").unwrap();
+        let explanation = Explainer::new(&program).with_now(now).explain(&query);
+        assert_eq!(explanation.answers.len(), 1);
+        assert!(explanation.to_string().contains(r#"O = "spring""#));
+        assert!(explanation.to_string().contains("2026-01-15 >= 2026-01-15"));
+    }
+
+    #[test]
+    fn test_explain_enum_ordering() {
+        let source = r#"
+            type Level = | Public | Internal | Confidential | Secret
+            rel doc: String × Level
+            rel shareable: String
+            doc("readme", Public).
+            doc("roadmap", Internal).
+            doc("payroll", Secret).
+            shareable(D) :- doc(D, L), L <= Internal, L != Public.
+        "#;
+        let explanation = explain(source, "shareable(D)");
+        assert_eq!(explanation.answers.len(), 1);
+        let rendered = explanation.to_string();
+        assert!(rendered.contains(r#"D = "roadmap""#));
+        assert!(rendered.contains("Internal <= Internal"));
+    }
+
+    #[test]
+    fn test_explain_rule_labels() {
+        let source = r#"
+            type Level = | Public | Secret
+            rel level: String × Level
+            rel owner: String × String
+            rel can_read: String × String
+            level("readme", Public).
+            level("payroll", Secret).
+            owner("payroll", "carol").
+            /// Owners can read their documents
+            can_read(U, D) :- owner(D, U).
+            @rule("public-read")
+            can_read(U, D) :- level(D, Public), owner(_, U).
+        "#;
+        let explanation = explain(source, r#"can_read("carol", "payroll")"#);
+        assert!(explanation
+            .to_string()
+            .contains("[rule 0: Owners can read their documents]"));
+
+        // Denials list every rule whose head matched
+        let denial = explain(source, r#"can_read("bob", "payroll")"#).to_string();
+        assert!(denial.contains("Rules considered for can_read:"));
+        assert!(denial.contains("✗ rule 0: Owners can read their documents"));
+        assert!(denial.contains("✗ rule 1: public-read"));
+    }
+
+    #[test]
+    fn test_explain_templates() {
+        let source = r#"
+            type User = user(name: String, dept: String)
+            rel has_role: User × String
+            rel can_approve: User
+            explain has_role(U, R) as "{U.name} has role {R}"
+            has_role(user("alice", "finance"), "manager").
+            can_approve(U) :- has_role(U, "manager").
+        "#;
+        let text = explain(source, "can_approve(X)").to_string();
+        assert!(text.contains("alice has role manager  [fact]"));
+        // Relations without a template use the generic form
+        assert!(text.contains("can_approve(user(\"alice\", \"finance\"))  [rule 0]"));
+    }
+
+    #[test]
+    fn test_constraint_violations() {
+        let source = r#"
+            type Role = | Employee | Contractor
+            rel has_role: String × Role
+            has_role("alice", Employee).
+            has_role("bob", Employee).
+            has_role("bob", Contractor).
+            /// Employees cannot also be contractors
+            :- has_role(U, Employee), has_role(U, Contractor).
+            :- has_role(_, Contractor), now() > 2026-01-01T00:00:00Z.
+        "#;
+        let program = parser::parse(source).unwrap();
+        crate::types::TypeChecker::new()
+            .check_program(&program)
+            .unwrap();
+
+        // Without an instant the time-dependent constraint cannot fire
+        let violations = Explainer::new(&program).violations();
+        assert_eq!(violations.len(), 1);
+        let report = violations[0].render(source);
+        assert!(report.contains("violated: Employees cannot also be contractors"));
+        assert!(report.contains("with U = \"bob\""));
+        assert!(report.contains("has_role(\"bob\", Contractor)  [fact at 6:13]"));
+
+        let later = temporal::parse_date("2026-06-01").unwrap();
+        assert_eq!(
+            Explainer::new(&program).with_now(later).violations().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_constraint_search_truncated() {
+        let source = r#"
+            rel edge: Int × Int
+            rel reach: Int × Int
+            edge(1, 2).
+            edge(2, 3).
+            edge(3, 4).
+            reach(X, Y) :- edge(X, Y).
+            reach(X, Z) :- edge(X, Y), reach(Y, Z).
+            :- reach(1, 4).
+        "#;
+        let program = parser::parse(source).unwrap();
+
+        // The chain is too long for the limit, which must not pass for "no violation"
+        let (violations, truncated) = Explainer::new(&program)
+            .with_limits(2, 16)
+            .check_constraints();
+        assert!(violations.is_empty());
+        assert_eq!(truncated.len(), 1);
+        assert_eq!(truncated[0].1, Limit::Depth);
+
+        let (violations, truncated) = Explainer::new(&program).check_constraints();
+        assert_eq!(violations.len(), 1);
+        assert!(truncated.is_empty());
+    }
+
+    #[test]
+    fn test_explain_combining_decisions() {
+        let source = r#"
+            type Effect = | Permit | Deny
+            rel employee: String
+            rel contractor: String
+            rel manager: String
+            @combine("deny-overrides")
+            rel decision: String × Effect
+            employee("bob").
+            contractor("bob").
+            manager("carol").
+            contractor("carol").
+            @rule("employees-permitted")
+            decision(U, Permit) :- employee(U).
+            @rule("deny-contractors")
+            decision(U, Deny) :- contractor(U).
+            @rule("manager-exception")
+            @overrides("deny-contractors")
+            decision(U, Permit) :- manager(U).
+        "#;
+
+        let bob = explain(source, r#"decision("bob", E)"#);
+        assert_eq!(bob.answers.len(), 1);
+        assert_eq!(bob.answers[0].bindings[0].1.to_string(), "Deny");
+        let text = bob.to_string();
+        assert!(text.contains("[rule 1: deny-contractors]"));
+        assert!(text.contains("⇒ decided by deny-overrides"));
+        assert!(text.contains(
+            "✗ overrides decision(\"bob\", Permit)  [rule 0: employees-permitted] (deny-overrides)"
+        ));
+
+        // The denied permit is explained by the decision that was made instead
+        let denied = explain(source, r#"decision("bob", Permit)"#).to_string();
+        assert!(denied.contains("Decided instead:"));
+        assert!(denied.contains("decision(\"bob\", Deny)  [rule 1: deny-contractors]"));
+        // The permit's body held; it lost to the deny rather than failing
+        assert!(denied.contains("⊘ rule 0: employees-permitted (overridden)"));
+        assert!(!denied.contains("✗ rule 0"));
+
+        // A more specific rule overrides the deny it names
+        let carol = explain(source, r#"decision("carol", E)"#);
+        assert_eq!(carol.answers[0].bindings[0].1.to_string(), "Permit");
+        assert!(carol
+            .to_string()
+            .contains("[rule 1: deny-contractors] (@overrides)"));
+
+        let first = source.replace("deny-overrides\")", "first-applicable\")");
+        let bob = explain(&first, r#"decision("bob", E)"#);
+        assert_eq!(bob.answers[0].bindings[0].1.to_string(), "Permit");
+    }
+}
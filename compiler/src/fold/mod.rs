@@ -0,0 +1,358 @@
+/// Constant folding
+///
+/// Arithmetic on literals is evaluated at compile time, wherever it appears:
+/// facts need it to be stored as values, and rules, queries and constraints
+/// then compute less at run time. Evaluation follows the type checker's
+/// rules for temporal values, and a division by zero or an overflow is a
+/// compile error. Once lowered, comparisons between constants are decided,
+/// variants by declaration order and temporal values by time: one that
+/// always holds is dropped, a rule or constraint with one that never holds
+/// can never apply, and neither can a query.
+use crate::ast::{BinOp, Goal, Item, Program, Term};
+use crate::ir::{CompareOp, IrGoal, IrProgram, IrTerm, IrTypeDef, IrValue, TypeLayout};
+use crate::types::TypeError;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// The program with its constant arithmetic evaluated, and the errors
+/// evaluating it; terms that fail to evaluate are left as written
+pub fn fold_program(program: &Program) -> (Program, Vec<TypeError>) {
+    let mut errors = Vec::new();
+    let mut folded = program.clone();
+    for item in &mut folded.items {
+        match item {
+            Item::Fact(fact) => fold_terms(&mut fact.args, &mut errors),
+            Item::Rule(rule) => {
+                fold_terms(&mut rule.head.args, &mut errors);
+                fold_goals(&mut rule.body, &mut errors);
+            }
+            Item::Query(query) => fold_goals(&mut query.goals, &mut errors),
+            Item::Constraint(constraint) => fold_goals(&mut constraint.body, &mut errors),
+            _ => {}
+        }
+    }
+    (folded, errors)
+}
+
+fn fold_terms(terms: &mut [Term], errors: &mut Vec<TypeError>) {
+    for term in terms {
+        *term = fold_term(term, errors);
+    }
+}
+
+fn fold_goals(goals: &mut [Goal], errors: &mut Vec<TypeError>) {
+    for goal in goals {
+        match goal {
+            Goal::Atom(atom) => fold_terms(&mut atom.args, errors),
+            Goal::Unify(left, right, _) | Goal::Compare(_, left, right, _) => {
+                *left = fold_term(left, errors);
+                *right = fold_term(right, errors);
+            }
+            Goal::Match { subject, .. } => *subject = fold_term(subject, errors),
+        }
+    }
+}
+
+/// Evaluate the arithmetic on literals in a term
+pub fn fold_term(term: &Term, errors: &mut Vec<TypeError>) -> Term {
+    match term {
+        Term::BinOp {
+            op,
+            left,
+            right,
+            span,
+        } => {
+            let left = fold_term(left, errors);
+            let right = fold_term(right, errors);
+            match eval(*op, &left, &right) {
+                Some(Ok(value)) => return value.with_span(span),
+                Some(Err(error)) => errors.push(error.with_span(span)),
+                None => {}
+            }
+            Term::BinOp {
+                op: *op,
+                left: Box::new(left),
+                right: Box::new(right),
+                span: span.clone(),
+            }
+        }
+        Term::Construct {
+            constructor,
+            args,
+            span,
+        } => Term::Construct {
+            constructor: constructor.clone(),
+            args: args.iter().map(|arg| fold_term(arg, errors)).collect(),
+            span: span.clone(),
+        },
+        _ => term.clone(),
+    }
+}
+
+/// A literal result, before it is given the span of its expression
+enum Literal {
+    Int(i64),
+    Date(i64),
+    Timestamp(i64),
+    Duration(i64),
+}
+
+impl Literal {
+    fn with_span(self, span: &crate::ast::Span) -> Term {
+        let span = span.clone();
+        match self {
+            Literal::Int(n) => Term::Int(n, span),
+            Literal::Date(d) => Term::Date(d, span),
+            Literal::Timestamp(t) => Term::Timestamp(t, span),
+            Literal::Duration(d) => Term::Duration(d, span),
+        }
+    }
+}
+
+enum ArithError {
+    DivisionByZero,
+    Overflow,
+}
+
+impl ArithError {
+    fn with_span(self, span: &crate::ast::Span) -> TypeError {
+        let span = span.clone();
+        match self {
+            ArithError::DivisionByZero => TypeError::DivisionByZero { span },
+            ArithError::Overflow => TypeError::ArithmeticOverflow { span },
+        }
+    }
+}
+
+/// Evaluate an operator on two literals; None unless both are literals the
+/// operator is defined for
+fn eval(op: BinOp, left: &Term, right: &Term) -> Option<Result<Literal, ArithError>> {
+    let int = |l: i64, r: i64| {
+        if matches!(op, BinOp::Div | BinOp::Mod) && r == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        let result = match op {
+            BinOp::Add => l.checked_add(r),
+            BinOp::Sub => l.checked_sub(r),
+            BinOp::Mul => l.checked_mul(r),
+            BinOp::Div => l.checked_div(r),
+            BinOp::Mod => l.checked_rem(r),
+        };
+        result.ok_or(ArithError::Overflow)
+    };
+    let result = match (op, left, right) {
+        (_, Term::Int(l, _), Term::Int(r, _)) => int(*l, *r).map(Literal::Int),
+        (BinOp::Add | BinOp::Sub, Term::Date(d, _), Term::Duration(s, _))
+        | (BinOp::Add, Term::Duration(s, _), Term::Date(d, _)) => int(*d, *s).map(Literal::Date),
+        (BinOp::Add | BinOp::Sub, Term::Timestamp(t, _), Term::Duration(s, _))
+        | (BinOp::Add, Term::Duration(s, _), Term::Timestamp(t, _)) => {
+            int(*t, *s).map(Literal::Timestamp)
+        }
+        (BinOp::Sub, Term::Date(l, _), Term::Date(r, _))
+        | (BinOp::Sub, Term::Timestamp(l, _), Term::Timestamp(r, _))
+        | (BinOp::Add | BinOp::Sub, Term::Duration(l, _), Term::Duration(r, _))
+        | (BinOp::Mul | BinOp::Div, Term::Duration(l, _), Term::Int(r, _))
+        | (BinOp::Mul, Term::Int(l, _), Term::Duration(r, _)) => int(*l, *r).map(Literal::Duration),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Decide comparisons between constants in lowered rules, queries and
+/// constraints
+pub fn simplify(program: &mut IrProgram) {
+    let order = variant_order(&program.types);
+    for rel in &mut program.relations {
+        rel.rules
+            .retain_mut(|rule| simplify_goals(&mut rule.body, &order));
+    }
+    program
+        .constraints
+        .retain_mut(|constraint| simplify_goals(&mut constraint.goals, &order));
+    for query in &mut program.queries {
+        if !simplify_goals(&mut query.goals, &order) {
+            // Nothing is left to run: a plan that never returns answers no
+            query.goals.clear();
+            query.plan = Some(Vec::new());
+        }
+    }
+}
+
+/// Declaration index of each variant, by type and variant name
+fn variant_order(types: &[IrTypeDef]) -> HashMap<(&str, &str), usize> {
+    let mut order = HashMap::new();
+    for def in types {
+        if let TypeLayout::Enum { variants, .. } = &def.layout {
+            for (index, variant) in variants.iter().enumerate() {
+                order.insert((def.name.as_str(), variant.as_str()), index);
+            }
+        }
+    }
+    order
+}
+
+/// Drop comparisons that always hold, returning false if one never does
+fn simplify_goals(goals: &mut Vec<IrGoal>, order: &HashMap<(&str, &str), usize>) -> bool {
+    if goals.iter().any(|goal| decide(goal, order) == Some(false)) {
+        return false;
+    }
+    goals.retain(|goal| decide(goal, order).is_none());
+    true
+}
+
+/// The outcome of a comparison between constants; temporal values are
+/// lowered to integers, so they compare like them
+fn decide(goal: &IrGoal, order: &HashMap<(&str, &str), usize>) -> Option<bool> {
+    let IrGoal::Compare {
+        op,
+        left: IrTerm::Value(left),
+        right: IrTerm::Value(right),
+        ..
+    } = goal
+    else {
+        return None;
+    };
+    let ordering = match (left, right) {
+        (IrValue::Int(a), IrValue::Int(b)) => Some(a.cmp(b)),
+        (IrValue::String(a), IrValue::String(b)) => Some(a.cmp(b)),
+        (
+            IrValue::Variant {
+                type_name: a_type,
+                variant: a,
+            },
+            IrValue::Variant {
+                type_name: b_type,
+                variant: b,
+            },
+        ) if a_type == b_type => {
+            let a = order.get(&(a_type.as_str(), a.as_str()));
+            let b = order.get(&(b_type.as_str(), b.as_str()));
+            a.zip(b).map(|(a, b)| a.cmp(b))
+        }
+        _ => None,
+    };
+    match op {
+        CompareOp::Eq => equal(left, right),
+        CompareOp::Ne => equal(left, right).map(|equal| !equal),
+        CompareOp::Lt => ordering.map(|o| o == Ordering::Less),
+        CompareOp::Le => ordering.map(|o| o != Ordering::Greater),
+        CompareOp::Gt => ordering.map(|o| o == Ordering::Greater),
+        CompareOp::Ge => ordering.map(|o| o != Ordering::Less),
+    }
+}
+
+fn equal(left: &IrValue, right: &IrValue) -> Option<bool> {
+    match (left, right) {
+        (IrValue::Int(a), IrValue::Int(b)) => Some(a == b),
+        (IrValue::String(a), IrValue::String(b)) => Some(a == b),
+        (IrValue::Variant { variant: a, .. }, IrValue::Variant { variant: b, .. }) => Some(a == b),
+        (
+            IrValue::Struct {
+                type_name: a,
+                fields: a_fields,
+            },
+            IrValue::Struct {
+                type_name: b,
+                fields: b_fields,
+            },
+        ) if a == b && a_fields.len() == b_fields.len() => a_fields
+            .iter()
+            .zip(b_fields)
+            .try_fold(true, |all, (a, b)| Some(all && equal(a, b)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_fold_arithmetic() {
+        let program = parser::parse(
+            r#"
+            rel limit: Int × Duration
+            limit(2 * 3 + 1, P1D * 2).
+            "#,
+        )
+        .expect("Parse failed");
+        let (folded, errors) = fold_program(&program);
+        assert!(errors.is_empty());
+        let Item::Fact(fact) = &folded.items[1] else {
+            panic!("expected a fact");
+        };
+        assert!(matches!(fact.args[0], Term::Int(7, _)));
+        assert!(matches!(fact.args[1], Term::Duration(172800, _)));
+    }
+
+    #[test]
+    fn test_fold_errors() {
+        let program = parser::parse(
+            r#"
+            rel r: Int
+            r(1 / (2 - 2)).
+            r(9223372036854775807 + 1).
+            r(X) :- r(Y), X = Y + 4 % 0.
+            "#,
+        )
+        .expect("Parse failed");
+        let (_, errors) = fold_program(&program);
+        let codes: Vec<_> = errors.iter().map(|e| e.code()).collect();
+        assert_eq!(codes, vec!["E0208", "E0209", "E0208"]);
+    }
+
+    #[test]
+    fn test_simplify_constant_comparisons() {
+        let program = parser::parse(
+            r#"
+            rel r: Int
+            rel s: Int
+            s(X) :- r(X), 2 + 2 == 4.
+            s(X) :- r(X), 1 > 2.
+            ?- s(1), "a" < "b".
+            ?- s(1), 3 != 3.
+            "#,
+        )
+        .expect("Parse failed");
+        let ir = crate::ir::lower_to_ir(&program);
+        let s = ir.relations.iter().find(|r| r.name == "s").unwrap();
+        assert_eq!(s.rules.len(), 1);
+        assert_eq!(s.rules[0].body.len(), 1);
+        assert_eq!(ir.queries[0].goals.len(), 1);
+        // A query that cannot hold runs nothing and answers no
+        assert!(ir.queries[1].goals.is_empty());
+        assert_eq!(ir.queries[1].plan.as_deref().map(<[_]>::len), Some(0));
+    }
+
+    #[test]
+    fn test_simplify_variant_and_temporal_comparisons() {
+        let program = parser::parse(
+            r#"
+            type Level = Low | Mid | High
+            rel r: Level
+            rel s: Level
+            s(X) :- r(X), Low < High.
+            s(X) :- r(X), High <= Mid.
+            s(X) :- r(X), 2026-01-01 < 2026-02-01.
+            s(X) :- r(X), P1D <= PT1H.
+            ?- 3 > 4.
+            ?- Mid >= Low, 2026-01-01T10:00:00Z > 2026-01-01T09:00:00Z.
+            ?- s(Low), 2026-03-01 < 2026-02-01.
+            "#,
+        )
+        .expect("Parse failed");
+        let ir = crate::ir::lower_to_ir(&program);
+        let s = ir.relations.iter().find(|r| r.name == "s").unwrap();
+        // Variants compare by declaration order, temporal values by time
+        let bodies: Vec<usize> = s.rules.iter().map(|rule| rule.body.len()).collect();
+        assert_eq!(bodies, vec![1, 1]);
+        let never: Vec<bool> = ir
+            .queries
+            .iter()
+            .map(|query| query.goals.is_empty() && query.plan.as_ref().is_some_and(Vec::is_empty))
+            .collect();
+        assert_eq!(never, vec![true, false, true]);
+        assert!(ir.queries[1].goals.is_empty());
+    }
+}
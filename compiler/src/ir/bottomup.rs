@@ -0,0 +1,576 @@
+/// Planning of semi-naive bottom-up evaluation
+///
+/// A component of the dependency graph can be materialized when all of its
+/// rules are range-restricted and everything it depends on can be
+/// materialized too, positively, or is only ever called with its arguments
+/// bound. Facts and rules without a recursive call seed the first round.
+/// Each round then runs every recursive rule once per recursive call,
+/// reading that call from the previous round's delta and all other calls
+/// from the full relations, until a round finds no new tuple. Components
+/// are materialized when a clause that queries or constraints reach has to
+/// enumerate their tuples, or when they are recursive and reached. The others
+/// answer calls top-down, with their arguments bound, and run their bodies
+/// the way queries and constraints run: as loops over the materialized
+/// relations they call and filters calling the rest.
+use super::*;
+use crate::deps::Polarity;
+
+pub(super) fn plan(program: &mut IrProgram) {
+    program.fixpoints = fixpoints(program);
+    let materialized: HashSet<String> = program
+        .fixpoints
+        .iter()
+        .flat_map(|f| f.relations.iter().cloned())
+        .collect();
+    let loops = |goals: &[IrGoal]| {
+        range_restricted(goals, &[]).then(|| {
+            nest(
+                goals,
+                0,
+                None,
+                &mut HashSet::new(),
+                &materialized,
+                &query_holds(),
+            )
+        })
+    };
+    for query in &mut program.queries {
+        // A query decided at compile time to fail keeps its empty plan
+        if query.goals.is_empty() && query.plan.as_ref().is_some_and(Vec::is_empty) {
+            continue;
+        }
+        query.plan = loops(&query.goals);
+    }
+    for constraint in &mut program.constraints {
+        constraint.plan = loops(&constraint.goals);
+    }
+}
+
+fn query_holds() -> IrInstruction {
+    IrInstruction::Return { value: None }
+}
+
+fn fixpoints(program: &IrProgram) -> Vec<IrFixpoint> {
+    let relations: HashMap<&str, &IrRelation> = program
+        .relations
+        .iter()
+        .map(|rel| (rel.name.as_str(), rel))
+        .collect();
+    let graph = &program.dependencies;
+    let components = graph.components();
+
+    // Components come after their dependencies, so those are decided first
+    let mut eligible = vec![false; components.len()];
+    for (n, scc) in components.iter().enumerate() {
+        eligible[n] = scc.iter().all(|name| {
+            let Some(rel) = relations.get(name.as_str()) else {
+                return false;
+            };
+            let mut scanned = HashSet::new();
+            for rule in &rel.rules {
+                enumerated(&rule.body, HashSet::new(), &mut scanned);
+            }
+            plannable(rel)
+                && graph.dependencies(name).all(|(to, polarity)| {
+                    polarity == Polarity::Positive
+                        && (graph
+                            .component_of(to)
+                            .is_some_and(|m| m == n || eligible[m])
+                            || !scanned.contains(to))
+                })
+        });
+    }
+
+    let reached = reached(program);
+
+    // Materialize the relations some reached clause has to enumerate, and
+    // the recursive components queries and constraints reach. Callers come
+    // after their callees, so each component is decided once everything
+    // calling it is: a rule evaluated bottom-up starts with nothing bound,
+    // one evaluated top-down with its head.
+    let mut scanned = HashSet::new();
+    for goals in program
+        .queries
+        .iter()
+        .map(|q| &q.goals)
+        .chain(program.constraints.iter().map(|c| &c.goals))
+    {
+        enumerated(goals, HashSet::new(), &mut scanned);
+    }
+    let mut needed = vec![false; components.len()];
+    for (n, scc) in components.iter().enumerate().rev() {
+        needed[n] = eligible[n]
+            && scc.iter().any(|name| {
+                scanned.contains(name)
+                    || reached.contains(name.as_str()) && graph.is_recursive(name)
+            });
+        for rule in scc
+            .iter()
+            .filter(|name| reached.contains(name.as_str()))
+            .filter_map(|name| relations.get(name.as_str()))
+            .flat_map(|rel| &rel.rules)
+        {
+            let mut bound = HashSet::new();
+            if !needed[n] {
+                rule.head.args.iter().for_each(|arg| vars(arg, &mut bound));
+            }
+            enumerated(&rule.body, bound, &mut scanned);
+        }
+    }
+    let materialized: HashSet<String> = components
+        .iter()
+        .zip(&needed)
+        .filter(|(_, needed)| **needed)
+        .flat_map(|(scc, _)| scc.iter().cloned())
+        .collect();
+
+    components
+        .iter()
+        .zip(&needed)
+        .filter(|(_, needed)| **needed)
+        .map(|(scc, _)| {
+            let members: Vec<&IrRelation> =
+                scc.iter().map(|name| relations[name.as_str()]).collect();
+            fixpoint(&members, &materialized)
+        })
+        .collect()
+}
+
+/// Add the relations that goals, run in order after the variables in
+/// `bound`, call with an argument unbound
+fn enumerated(goals: &[IrGoal], mut bound: HashSet<VarId>, out: &mut HashSet<String>) {
+    for goal in goals {
+        if let IrGoal::Call { relation, args, .. } = goal {
+            if !args.iter().all(|arg| is_bound(arg, &bound)) {
+                out.insert(relation.clone());
+            }
+        }
+        bind(goal, &mut bound);
+    }
+}
+
+/// The first relation that goals, run in order after the variables in
+/// `bound`, call with an argument unbound although it is not materialized
+pub(crate) fn unbound_call<'g>(
+    goals: &'g [IrGoal],
+    mut bound: HashSet<VarId>,
+    materialized: &HashSet<String>,
+) -> Option<&'g str> {
+    for goal in goals {
+        if let IrGoal::Call { relation, args, .. } = goal {
+            if !materialized.contains(relation) && !args.iter().all(|arg| is_bound(arg, &bound)) {
+                return Some(relation);
+            }
+        }
+        bind(goal, &mut bound);
+    }
+    None
+}
+
+/// Relations that goals call, directly or through rules
+/// Relations that queries and constraints can end up calling
+pub(super) fn reached(program: &IrProgram) -> HashSet<&str> {
+    reachable(
+        &program.dependencies,
+        program
+            .queries
+            .iter()
+            .map(|q| &q.goals)
+            .chain(program.constraints.iter().map(|c| &c.goals))
+            .flatten(),
+    )
+}
+
+fn reachable<'a>(
+    graph: &'a DependencyGraph,
+    goals: impl Iterator<Item = &'a IrGoal>,
+) -> HashSet<&'a str> {
+    let mut reached = HashSet::new();
+    let mut stack: Vec<&str> = goals
+        .filter_map(|goal| match goal {
+            IrGoal::Call { relation, .. } => Some(relation.as_str()),
+            _ => None,
+        })
+        .collect();
+    while let Some(name) = stack.pop() {
+        if reached.insert(name) {
+            stack.extend(graph.dependencies(name).map(|(to, _)| to));
+        }
+    }
+    reached
+}
+
+fn fixpoint(members: &[&IrRelation], materialized: &HashSet<String>) -> IrFixpoint {
+    let names: Vec<String> = members.iter().map(|rel| rel.name.clone()).collect();
+    let mut init = Vec::new();
+    let mut step = Vec::new();
+
+    for rel in members {
+        if !rel.facts.is_empty() {
+            init.push(IrInstruction::InsertFacts {
+                relation: rel.name.clone(),
+            });
+        }
+    }
+
+    let mut rules: Vec<&IrRule> = members.iter().flat_map(|rel| &rel.rules).collect();
+    rules.sort_by_key(|rule| rule.id);
+    for rule in rules {
+        let recursive: Vec<usize> = rule
+            .body
+            .iter()
+            .enumerate()
+            .filter_map(|(i, goal)| match goal {
+                IrGoal::Call { relation, .. } if names.contains(relation) => Some(i),
+                _ => None,
+            })
+            .collect();
+        if recursive.is_empty() {
+            init.push(clause(rule, None, materialized));
+        }
+        for delta in recursive {
+            step.push(clause(rule, Some(delta), materialized));
+        }
+    }
+
+    init.push(IrInstruction::Swap {
+        relations: names.clone(),
+    });
+    if !step.is_empty() {
+        step.push(IrInstruction::Swap {
+            relations: names.clone(),
+        });
+    }
+    IrFixpoint {
+        relations: names,
+        init,
+        step,
+    }
+}
+
+/// One rule as nested loops, reading the goal at `delta` from the delta
+fn clause(rule: &IrRule, delta: Option<usize>, materialized: &HashSet<String>) -> IrInstruction {
+    IrInstruction::Clause {
+        rule: rule.id,
+        vars: rule.vars.clone(),
+        body: nest(
+            &rule.body,
+            0,
+            delta,
+            &mut HashSet::new(),
+            materialized,
+            &IrInstruction::DeltaInsert {
+                relation: rule.head.relation.clone(),
+                args: rule.head.args.clone(),
+            },
+        ),
+    }
+}
+
+/// Goals from `i` on as nested loops and filters around `last`; calls to
+/// relations that are not materialized are filters
+pub(crate) fn nest(
+    goals: &[IrGoal],
+    i: usize,
+    delta: Option<usize>,
+    bound: &mut HashSet<VarId>,
+    materialized: &HashSet<String>,
+    last: &IrInstruction,
+) -> Vec<IrInstruction> {
+    let Some(goal) = goals.get(i) else {
+        return vec![last.clone()];
+    };
+    match goal {
+        IrGoal::Call { relation, args, .. } if materialized.contains(relation) => {
+            let key: Vec<usize> = (0..args.len())
+                .filter(|&k| is_bound(&args[k], bound))
+                .collect();
+            for arg in args {
+                vars(arg, bound);
+            }
+            let version = if delta == Some(i) {
+                Version::Delta
+            } else {
+                Version::Full
+            };
+            let body = nest(goals, i + 1, delta, bound, materialized, last);
+            let (relation, args) = (relation.clone(), args.clone());
+            vec![if key.is_empty() {
+                IrInstruction::Scan {
+                    relation,
+                    version,
+                    args,
+                    body,
+                }
+            } else {
+                IrInstruction::Join {
+                    relation,
+                    version,
+                    key,
+                    args,
+                    body,
+                }
+            }]
+        }
+        _ => {
+            bind(goal, bound);
+            let mut body = vec![IrInstruction::Filter(goal.clone())];
+            body.extend(nest(goals, i + 1, delta, bound, materialized, last));
+            body
+        }
+    }
+}
+
+/// Whether every rule of a relation can run left to right as loops and
+/// filters, with its head bound at the end
+pub(super) fn plannable(rel: &IrRelation) -> bool {
+    rel.combine.is_none()
+        && rel
+            .rules
+            .iter()
+            .all(|rule| rule.overrides.is_empty() && range_restricted(&rule.body, &rule.head.args))
+}
+
+/// Whether goals bind everything they test and `head` as they run in order
+fn range_restricted(body: &[IrGoal], head: &[IrTerm]) -> bool {
+    let mut bound = HashSet::new();
+    for goal in body {
+        if !ready(goal, &bound) {
+            return false;
+        }
+        bind(goal, &mut bound);
+    }
+    head.iter().all(|arg| is_bound(arg, &bound))
+}
+
+/// Whether a goal can run once the variables in `bound` are: a unification
+/// needs a bound side or a constructor on both, a test needs its operands
+pub(super) fn ready(goal: &IrGoal, bound: &HashSet<VarId>) -> bool {
+    match goal {
+        IrGoal::Call { .. } => true,
+        IrGoal::Unify { left, right } => {
+            let constructor = |term: &IrTerm| match term {
+                IrTerm::Construct { constructor, .. } => Some(constructor.clone()),
+                _ => None,
+            };
+            let pairs = constructor(left).is_some() && constructor(left) == constructor(right);
+            pairs || is_bound(left, bound) || is_bound(right, bound)
+        }
+        IrGoal::Compare { left, right, .. } => is_bound(left, bound) && is_bound(right, bound),
+        IrGoal::Match { subject, .. } => is_bound(subject, bound),
+    }
+}
+
+/// Add the variables a goal binds when it succeeds
+pub(super) fn bind(goal: &IrGoal, bound: &mut HashSet<VarId>) {
+    match goal {
+        IrGoal::Call { args, .. } => args.iter().for_each(|arg| vars(arg, bound)),
+        IrGoal::Unify { left, right } => {
+            vars(left, bound);
+            vars(right, bound);
+        }
+        IrGoal::Compare { .. } | IrGoal::Match { .. } => {}
+    }
+}
+
+pub(super) fn is_bound(term: &IrTerm, bound: &HashSet<VarId>) -> bool {
+    match term {
+        IrTerm::Var(id) => bound.contains(id),
+        IrTerm::Value(_) | IrTerm::Now | IrTerm::Today => true,
+        IrTerm::Construct { args, .. } => args.iter().all(|arg| is_bound(arg, bound)),
+        IrTerm::BinOp { left, right, .. } => is_bound(left, bound) && is_bound(right, bound),
+        IrTerm::FieldAccess { .. } => false,
+    }
+}
+
+pub(super) fn vars(term: &IrTerm, out: &mut HashSet<VarId>) {
+    match term {
+        IrTerm::Var(id) => {
+            out.insert(*id);
+        }
+        IrTerm::Construct { args, .. } => args.iter().for_each(|arg| vars(arg, out)),
+        IrTerm::BinOp { left, right, .. } => {
+            vars(left, out);
+            vars(right, out);
+        }
+        IrTerm::FieldAccess { base, .. } => vars(base, out),
+        IrTerm::Value(_) | IrTerm::Now | IrTerm::Today => {}
+    }
+}
+
+/// Every variable a goal mentions
+pub(super) fn goal_vars(goal: &IrGoal, out: &mut HashSet<VarId>) {
+    match goal {
+        IrGoal::Call { args, .. } => args.iter().for_each(|arg| vars(arg, out)),
+        IrGoal::Unify { left, right } | IrGoal::Compare { left, right, .. } => {
+            vars(left, out);
+            vars(right, out);
+        }
+        IrGoal::Match { subject, .. } => vars(subject, out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn fixpoints(source: &str) -> Vec<IrFixpoint> {
+        let program = parser::parse(source).expect("Parse failed");
+        lower_to_ir(&program).fixpoints
+    }
+
+    #[test]
+    fn test_plan_transitive_closure() {
+        let plans = fixpoints(
+            r#"
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            rel unrelated: Int
+            parent(1, 2).
+            parent(2, 3).
+            unrelated(7).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            ?- ancestor(1, 3).
+        "#,
+        );
+        // parent is read by the recursive component, unrelated is not needed
+        let names: Vec<_> = plans.iter().map(|f| f.relations.clone()).collect();
+        assert_eq!(names, vec![vec!["parent"], vec!["ancestor"]]);
+
+        let parent = &plans[0];
+        assert!(
+            matches!(&parent.init[0], IrInstruction::InsertFacts { relation } if relation == "parent")
+        );
+        assert!(parent.step.is_empty());
+
+        let ancestor = &plans[1];
+        assert_eq!(ancestor.init.len(), 2);
+        assert!(matches!(ancestor.init[1], IrInstruction::Swap { .. }));
+        // parent(X, Y) is scanned in full, then ancestor(Y, Z) joins on Y from the delta
+        let IrInstruction::Clause { body, .. } = &ancestor.step[0] else {
+            panic!("expected a clause");
+        };
+        let IrInstruction::Scan {
+            relation,
+            version: Version::Full,
+            body,
+            ..
+        } = &body[0]
+        else {
+            panic!("expected a scan of parent");
+        };
+        assert_eq!(relation, "parent");
+        let IrInstruction::Join {
+            relation,
+            version: Version::Delta,
+            key,
+            body,
+            ..
+        } = &body[0]
+        else {
+            panic!("expected a join with the delta");
+        };
+        assert_eq!((relation.as_str(), key.as_slice()), ("ancestor", &[0][..]));
+        assert!(
+            matches!(&body[0], IrInstruction::DeltaInsert { relation, .. } if relation == "ancestor")
+        );
+    }
+
+    #[test]
+    fn test_plan_one_round_per_recursive_call() {
+        let plans = fixpoints(
+            r#"
+            rel edge: Int × Int
+            rel path: Int × Int
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), path(Y, Z), X != Z.
+            ?- path(1, 2).
+        "#,
+        );
+        let path = plans.last().unwrap();
+        // Two delta variants and the swap
+        assert_eq!(path.step.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_query_loops() {
+        let program = parser::parse(
+            r#"
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            ?- ancestor(1, Q).
+            ?- parent(1, Q), Q > 2.
+        "#,
+        )
+        .expect("Parse failed");
+        let ir = lower_to_ir(&program);
+        // The query joins on its constant and succeeds on the first tuple
+        let Some(plan) = &ir.queries[0].plan else {
+            panic!("expected a planned query");
+        };
+        let IrInstruction::Join { key, body, .. } = &plan[0] else {
+            panic!("expected a join");
+        };
+        assert_eq!(key, &[0]);
+        assert!(matches!(body[0], IrInstruction::Return { value: None }));
+        // parent is materialized as a dependency of ancestor
+        assert!(ir.queries[1].plan.is_some());
+    }
+
+    #[test]
+    fn test_plan_joins_over_recursion() {
+        let source = r#"
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            rel kin: Int × Int
+            rel elder: Int
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            kin(X, Y) :- ancestor(A, X), ancestor(A, Y), X != Y.
+            elder(X) :- ancestor(X, Y), ancestor(Y, Z).
+            ?- kin(2, Q).
+            ?- elder(1).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+        // kin is enumerated by the query, elder only checked top-down
+        let names: Vec<_> = ir.fixpoints.iter().map(|f| f.relations.clone()).collect();
+        assert_eq!(names, vec![vec!["parent"], vec!["ancestor"], vec!["kin"]]);
+        let kin = &ir.fixpoints[2];
+        let IrInstruction::Clause { body, .. } = &kin.init[0] else {
+            panic!("expected a clause");
+        };
+        assert!(matches!(&body[0], IrInstruction::Scan { relation, .. } if relation == "ancestor"));
+        assert!(ir.unbound_calls().is_empty());
+
+        // elder's body binds Y and Z by looping over ancestor
+        let elder = ir.relations.iter().find(|r| r.name == "elder").unwrap();
+        let body = elder.rules[0].top_down(&ir.materialized());
+        let IrInstruction::Join { relation, key, .. } = &body[0] else {
+            panic!("expected a join");
+        };
+        assert_eq!((relation.as_str(), key.as_slice()), ("ancestor", &[0][..]));
+    }
+
+    #[test]
+    fn test_plan_skips_policies() {
+        let plans = fixpoints(
+            r#"
+            type Effect = | Permit | Deny
+            rel manager: String
+            rel reports: String × String
+            @combine("deny-overrides")
+            rel decision: String × Effect
+            reports(A, C) :- reports(A, B), reports(B, C).
+            decision(U, Permit) :- manager(U).
+            ?- reports("ann", C), decision(C, Permit).
+        "#,
+        );
+        let names: Vec<_> = plans.iter().flat_map(|f| f.relations.clone()).collect();
+        assert_eq!(names, vec!["reports"]);
+    }
+}
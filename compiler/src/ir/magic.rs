@@ -0,0 +1,362 @@
+/// Magic-sets specialization of recursive relations
+///
+/// Bottom-up evaluation computes a whole recursive relation even when a
+/// query only asks about some of its tuples. When a query calls a recursive
+/// relation with constant arguments, this pass copies the relation under an
+/// adornment marking each argument bound (`b`) or free (`f`), such as
+/// `ancestor.bf`. Each rule of the copy is guarded by a magic relation,
+/// `magic.ancestor.bf`, holding the bound arguments worth asking about: the
+/// query seeds it, and every recursive call in a rule body adds the
+/// arguments it passes on. Before such a call, a supplementary relation
+/// keeps the bindings of the body so far, so the magic rule for the call
+/// and the rest of the body do not join them twice. The query then calls
+/// the copy, which only derives tuples reachable from its constants.
+/// Generated names contain dots, which source relation names cannot.
+use super::bottomup::{bind, goal_vars, is_bound, vars};
+use super::*;
+use std::collections::BTreeMap;
+
+/// Specialize the recursive relations that queries call with constants,
+/// then plan the program again
+pub fn rewrite(program: &mut IrProgram) {
+    let recursive: HashSet<String> = program
+        .fixpoints
+        .iter()
+        .flat_map(|fixpoint| &fixpoint.relations)
+        .filter(|name| program.dependencies.is_recursive(name))
+        .cloned()
+        .collect();
+    let mut magic = Magic {
+        relations: program
+            .relations
+            .iter()
+            .map(|rel| (rel.name.as_str(), rel))
+            .collect(),
+        graph: &program.dependencies,
+        added: BTreeMap::new(),
+        pending: Vec::new(),
+    };
+
+    for query in &mut program.queries {
+        for goal in &mut query.goals {
+            let IrGoal::Call { relation, args, .. } = goal else {
+                continue;
+            };
+            if !recursive.contains(relation) || !magic.typed(relation) {
+                continue;
+            }
+            let adornment: Vec<bool> = args
+                .iter()
+                .map(|arg| matches!(arg, IrTerm::Value(_)))
+                .collect();
+            if !adornment.contains(&true) {
+                continue;
+            }
+            let seed = args
+                .iter()
+                .filter_map(|arg| match arg {
+                    IrTerm::Value(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect();
+            let adorned = magic.adorn(relation, &adornment);
+            if let Some(rel) = magic.added.get_mut(&magic_name(&adorned)) {
+                rel.facts.push(IrFact { args: seed });
+            }
+            *relation = adorned;
+        }
+    }
+
+    while let Some((name, adornment)) = magic.pending.pop() {
+        let source = magic.relations[name.as_str()];
+        for rule in &source.rules {
+            magic.adorn_rule(rule, &adornment);
+        }
+    }
+
+    let added = std::mem::take(&mut magic.added);
+    program.relations.extend(added.into_values());
+    plan(program);
+}
+
+struct Magic<'p> {
+    relations: HashMap<&'p str, &'p IrRelation>,
+    graph: &'p DependencyGraph,
+    /// Adorned, magic and supplementary relations, by name
+    added: BTreeMap<String, IrRelation>,
+    /// Adorned relations whose rules are still to be written
+    pending: Vec<(String, Vec<bool>)>,
+}
+
+impl Magic<'_> {
+    /// Whether the types of every rule variable in the relation's component
+    /// are known, for the signatures of supplementary relations
+    fn typed(&self, relation: &str) -> bool {
+        let Some(n) = self.graph.component_of(relation) else {
+            return false;
+        };
+        self.graph.components()[n].iter().all(|name| {
+            self.relations.get(name.as_str()).is_some_and(|rel| {
+                rel.rules
+                    .iter()
+                    .all(|rule| rule.vars.iter().all(|var| var.ty.is_some()))
+            })
+        })
+    }
+
+    /// The copy of `relation` for calls binding the arguments in `adornment`
+    fn adorn(&mut self, relation: &str, adornment: &[bool]) -> String {
+        let pattern: String = adornment
+            .iter()
+            .map(|&bound| if bound { 'b' } else { 'f' })
+            .collect();
+        let name = format!("{}.{}", relation, pattern);
+        if self.added.contains_key(&name) {
+            return name;
+        }
+
+        let source = self.relations[relation];
+        let types = match &source.signature {
+            Type::Product(types) => types.clone(),
+            ty => vec![ty.clone()],
+        };
+        let bound_types = types
+            .into_iter()
+            .zip(adornment)
+            .filter(|(_, &bound)| bound)
+            .map(|(ty, _)| ty)
+            .collect();
+        self.added.insert(
+            magic_name(&name),
+            empty_relation(magic_name(&name), product(bound_types)),
+        );
+        self.added.insert(
+            name.clone(),
+            IrRelation {
+                facts: source.facts.clone(),
+                keys: source.keys.clone(),
+                ..empty_relation(name.clone(), source.signature.clone())
+            },
+        );
+        self.pending
+            .push((relation.to_string(), adornment.to_vec()));
+        name
+    }
+
+    /// Add a rule's adorned copy, with magic rules for the recursive calls
+    /// of its body
+    fn adorn_rule(&mut self, rule: &IrRule, adornment: &[bool]) {
+        let head = self.adorn(&rule.head.relation, adornment);
+        let bound_args: Vec<IrTerm> = select(&rule.head.args, adornment);
+        let mut bound = HashSet::new();
+        for arg in &bound_args {
+            vars(arg, &mut bound);
+        }
+        let mut prefix = vec![call(magic_name(&head), bound_args)];
+
+        for (i, goal) in rule.body.iter().enumerate() {
+            match goal {
+                IrGoal::Call { relation, args, .. }
+                    if self.graph.same_component(relation, &rule.head.relation)
+                        && args.iter().any(|arg| is_bound(arg, &bound)) =>
+                {
+                    let passed: Vec<bool> = args.iter().map(|arg| is_bound(arg, &bound)).collect();
+                    if prefix.len() > 1 {
+                        let name = format!("sup.{}.{}.{}", head, rule.id, i);
+                        prefix = vec![self.supplementary(name, rule, i, &bound, prefix)];
+                    }
+                    let callee = self.adorn(relation, &passed);
+                    let magic = IrAtom {
+                        relation: magic_name(&callee),
+                        args: select(args, &passed),
+                    };
+                    self.add_rule(derived(rule, magic, prefix.clone()));
+                    prefix.push(call(callee, args.clone()));
+                }
+                _ => prefix.push(goal.clone()),
+            }
+            bind(goal, &mut bound);
+        }
+
+        let atom = IrAtom {
+            relation: head,
+            args: rule.head.args.clone(),
+        };
+        self.add_rule(IrRule {
+            label: rule.label.clone(),
+            ..derived(rule, atom, prefix)
+        });
+    }
+
+    /// A relation of the bindings made by `prefix` that the goals from
+    /// `from` on and the head use, returning the call that reads it back
+    fn supplementary(
+        &mut self,
+        name: String,
+        rule: &IrRule,
+        from: usize,
+        bound: &HashSet<VarId>,
+        prefix: Vec<IrGoal>,
+    ) -> IrGoal {
+        let mut used = HashSet::new();
+        for arg in &rule.head.args {
+            vars(arg, &mut used);
+        }
+        for goal in &rule.body[from..] {
+            goal_vars(goal, &mut used);
+        }
+        let kept: Vec<&IrVar> = rule
+            .vars
+            .iter()
+            .filter(|var| bound.contains(&var.id) && used.contains(&var.id))
+            .collect();
+        let signature = product(kept.iter().filter_map(|var| var.ty.clone()).collect());
+        let args: Vec<IrTerm> = kept.iter().map(|var| IrTerm::Var(var.id)).collect();
+
+        self.added
+            .insert(name.clone(), empty_relation(name.clone(), signature));
+        let atom = IrAtom {
+            relation: name.clone(),
+            args: args.clone(),
+        };
+        self.add_rule(derived(rule, atom, prefix));
+        call(name, args)
+    }
+
+    fn add_rule(&mut self, rule: IrRule) {
+        if let Some(rel) = self.added.get_mut(&rule.head.relation) {
+            rel.rules.push(rule);
+        }
+    }
+}
+
+fn magic_name(adorned: &str) -> String {
+    format!("magic.{}", adorned)
+}
+
+fn empty_relation(name: String, signature: Type) -> IrRelation {
+    IrRelation {
+        name,
+        signature,
+        facts: vec![],
+        rules: vec![],
+        modes: vec![],
+        keys: vec![],
+        combine: None,
+        needs_tabling: false,
+    }
+}
+
+fn product(mut types: Vec<Type>) -> Type {
+    if types.len() == 1 {
+        types.remove(0)
+    } else {
+        Type::Product(types)
+    }
+}
+
+/// The arguments at the positions marked in `adornment`
+fn select(args: &[IrTerm], adornment: &[bool]) -> Vec<IrTerm> {
+    args.iter()
+        .zip(adornment)
+        .filter(|(_, &bound)| bound)
+        .map(|(arg, _)| arg.clone())
+        .collect()
+}
+
+fn call(relation: String, args: Vec<IrTerm>) -> IrGoal {
+    IrGoal::Call {
+        relation,
+        mode_index: 0,
+        args,
+    }
+}
+
+/// A rule generated from `rule`, sharing its number and variables
+fn derived(rule: &IrRule, head: IrAtom, body: Vec<IrGoal>) -> IrRule {
+    IrRule {
+        id: rule.id,
+        label: None,
+        name: None,
+        overrides: vec![],
+        head,
+        body,
+        vars: rule.vars.clone(),
+        needs_tabling: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn rewritten(source: &str) -> IrProgram {
+        let program = parser::parse(source).expect("Parse failed");
+        let mut ir = lower_to_ir(&program);
+        rewrite(&mut ir);
+        ir
+    }
+
+    fn find<'a>(ir: &'a IrProgram, name: &str) -> &'a IrRelation {
+        ir.relations
+            .iter()
+            .find(|rel| rel.name == name)
+            .unwrap_or_else(|| panic!("no relation {}", name))
+    }
+
+    const ANCESTOR: &str = r#"
+        rel parent: Int × Int
+        rel ancestor: Int × Int
+        parent(1, 2).
+        parent(2, 3).
+        ancestor(X, Y) :- parent(X, Y).
+        ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+    "#;
+
+    #[test]
+    fn test_magic_specializes_bound_query() {
+        let ir = rewritten(&format!("{}\n?- ancestor(1, Q).", ANCESTOR));
+        let IrGoal::Call { relation, .. } = &ir.queries[0].goals[0] else {
+            panic!("expected a call");
+        };
+        assert_eq!(relation, "ancestor.bf");
+
+        // The query seeds the magic relation, which the recursive call extends
+        let magic = find(&ir, "magic.ancestor.bf");
+        assert!(matches!(&magic.facts[0].args[..], [IrValue::Int(1)]));
+        assert_eq!(magic.rules.len(), 1);
+        let IrGoal::Call { relation, .. } = &magic.rules[0].body[0] else {
+            panic!("expected a supplementary call");
+        };
+        assert!(relation.starts_with("sup.ancestor.bf."));
+
+        // Every adorned rule starts from the magic set
+        let adorned = find(&ir, "ancestor.bf");
+        assert_eq!(adorned.rules.len(), 2);
+        assert!(matches!(&adorned.rules[0].body[0],
+            IrGoal::Call { relation, .. } if relation == "magic.ancestor.bf"));
+
+        // Only the specialized component is evaluated
+        let materialized: Vec<_> = ir
+            .fixpoints
+            .iter()
+            .flat_map(|f| f.relations.clone())
+            .collect();
+        assert!(!materialized.contains(&"ancestor".to_string()));
+        assert!(materialized.contains(&"ancestor.bf".to_string()));
+        assert!(ir.queries[0].plan.is_some());
+    }
+
+    #[test]
+    fn test_magic_skips_free_query() {
+        let ir = rewritten(&format!("{}\n?- ancestor(X, Y).", ANCESTOR));
+        assert!(ir.relations.iter().all(|rel| !rel.name.contains('.')));
+        let materialized: Vec<_> = ir
+            .fixpoints
+            .iter()
+            .flat_map(|f| f.relations.clone())
+            .collect();
+        assert!(materialized.contains(&"ancestor".to_string()));
+    }
+}
@@ -6,8 +6,17 @@
 /// - Tabling/memoization points
 /// - Mode information (input/output patterns)
 /// - Memory allocation sites
-use crate::ast::Type;
-use std::collections::HashMap;
+use crate::ast::{Combine, Goal, Span, Type};
+use crate::deps::DependencyGraph;
+use crate::types::{TypeChecker, VariantTypes};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+mod bottomup;
+pub mod magic;
+mod order;
+mod text;
+pub mod verify;
 
 /// A compiled qed program in IR form
 #[derive(Debug, Clone)]
@@ -15,6 +24,12 @@ pub struct IrProgram {
     pub types: Vec<IrTypeDef>,
     pub relations: Vec<IrRelation>,
     pub queries: Vec<IrQuery>,
+    /// Integrity constraints, checked when the program starts
+    pub constraints: Vec<IrConstraint>,
+    /// Which relations each relation's rules depend on
+    pub dependencies: DependencyGraph,
+    /// Components evaluated bottom-up, dependencies first
+    pub fixpoints: Vec<IrFixpoint>,
 }
 
 /// IR type definition with memory layout info
@@ -28,6 +43,8 @@ pub struct IrTypeDef {
 pub enum TypeLayout {
     /// Product type with known fields
     Struct {
+        /// Name values of the type are written with: `person` in `person("Ann", 45)`
+        constructor: String,
         fields: Vec<(String, Type)>,
         size_bytes: usize,
         align_bytes: usize,
@@ -48,21 +65,128 @@ pub struct IrRelation {
     pub facts: Vec<IrFact>,
     pub rules: Vec<IrRule>,
     pub modes: Vec<IrMode>,
+    /// Declared keys: argument positions that determine the whole tuple
+    pub keys: Vec<Vec<usize>>,
+    /// Combining algorithm for a decision relation
+    pub combine: Option<Combine>,
+    /// Part of a recursive component of the dependency graph
+    pub needs_tabling: bool,
+}
+
+/// How many solutions a call can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Determinism {
+    /// Exactly one solution
+    Det,
+    /// At most one solution
+    Semidet,
+    /// At least one solution
+    Multi,
+    /// Any number of solutions
+    Nondet,
+}
+
+impl Determinism {
+    pub fn new(can_fail: bool, can_multi: bool) -> Self {
+        match (can_fail, can_multi) {
+            (false, false) => Determinism::Det,
+            (true, false) => Determinism::Semidet,
+            (false, true) => Determinism::Multi,
+            (true, true) => Determinism::Nondet,
+        }
+    }
+
+    /// Whether a call can have no solution
+    pub fn can_fail(self) -> bool {
+        matches!(self, Determinism::Semidet | Determinism::Nondet)
+    }
+
+    /// Whether a call can have more than one solution
+    pub fn can_multi(self) -> bool {
+        matches!(self, Determinism::Multi | Determinism::Nondet)
+    }
+
+    /// Whether every call that meets `self` also meets `other`
+    pub fn within(self, other: Determinism) -> bool {
+        (!self.can_fail() || other.can_fail()) && (!self.can_multi() || other.can_multi())
+    }
+}
+
+impl fmt::Display for Determinism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Determinism::Det => "det",
+            Determinism::Semidet => "semidet",
+            Determinism::Multi => "multi",
+            Determinism::Nondet => "nondet",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Determinism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "det" => Ok(Determinism::Det),
+            "semidet" => Ok(Determinism::Semidet),
+            "multi" => Ok(Determinism::Multi),
+            "nondet" => Ok(Determinism::Nondet),
+            _ => Err(format!("Unknown determinism {}", s)),
+        }
+    }
+}
+
+impl IrRelation {
+    /// Whether answers compete per request: the relation combines outcomes
+    /// or some of its rules override others
+    pub fn is_decision(&self) -> bool {
+        self.combine.is_some() || self.rules.iter().any(|rule| !rule.overrides.is_empty())
+    }
+
+    /// Key whose positions are all bound, if any; a lookup by it finds at most one tuple
+    pub fn bound_key(&self, bound: &[bool]) -> Option<&[usize]> {
+        self.keys
+            .iter()
+            .find(|key| key.iter().all(|&i| bound.get(i).copied().unwrap_or(false)))
+            .map(Vec::as_slice)
+    }
+
+    /// Determinism of a call with the given argument positions bound
+    pub fn lookup_determinism(&self, bound: &[bool]) -> Determinism {
+        if self.bound_key(bound).is_some() {
+            Determinism::Semidet
+        } else {
+            Determinism::Nondet
+        }
+    }
 }
 
 /// Mode specifies input/output pattern for a relation
 #[derive(Debug, Clone)]
 pub struct IrMode {
     pub pattern: Vec<ModeAnnotation>,
+    /// Inferred number of solutions of a call in this mode
+    pub determinism: Determinism,
     pub implementation: IrPredicate,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ModeAnnotation {
     Input,  // Ground at call time
     Output, // Computed by predicate
 }
 
+impl fmt::Display for ModeAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModeAnnotation::Input => write!(f, "in"),
+            ModeAnnotation::Output => write!(f, "out"),
+        }
+    }
+}
+
 /// A fact in IR form (ground terms only)
 #[derive(Debug, Clone)]
 pub struct IrFact {
@@ -72,11 +196,31 @@ pub struct IrFact {
 /// Rule in IR form with explicit unification
 #[derive(Debug, Clone)]
 pub struct IrRule {
+    /// Position of the rule in the source, shared with proof trees
+    pub id: usize,
+    /// `@rule` name or doc comment, shown in explanations
+    pub label: Option<String>,
+    /// `@rule` name, which `@overrides` refers to
+    pub name: Option<String>,
+    /// Names of the rules this rule defeats when both apply
+    pub overrides: Vec<String>,
     pub head: IrAtom,
     pub body: Vec<IrGoal>,
+    /// Named variables, shared by the head and the body
+    pub vars: Vec<IrVar>,
+    /// The body calls a relation in the head's recursive component
     pub needs_tabling: bool,
 }
 
+/// A named variable of a rule, query or constraint
+#[derive(Debug, Clone)]
+pub struct IrVar {
+    pub id: VarId,
+    pub name: String,
+    /// Type inferred by the checker; None if the clause does not type check
+    pub ty: Option<Type>,
+}
+
 #[derive(Debug, Clone)]
 pub struct IrAtom {
     pub relation: String,
@@ -93,12 +237,15 @@ pub enum IrGoal {
     },
     /// Unify two terms
     Unify { left: IrTerm, right: IrTerm },
-    /// Comparison
+    /// Comparison; `ty` is the operand type when lowering can see it
     Compare {
         op: CompareOp,
         left: IrTerm,
         right: IrTerm,
+        ty: Option<Type>,
     },
+    /// Regex match against a pattern validated by the type checker
+    Match { subject: IrTerm, pattern: String },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -118,6 +265,12 @@ pub enum IrTerm {
     Var(VarId),
     /// Constant value
     Value(IrValue),
+    /// Constructor pattern with variables among its arguments
+    Construct {
+        type_name: String,
+        constructor: String,
+        args: Vec<IrTerm>,
+    },
     /// Field access
     FieldAccess { base: Box<IrTerm>, field: String },
     /// Binary operation
@@ -126,6 +279,10 @@ pub enum IrTerm {
         left: Box<IrTerm>,
         right: Box<IrTerm>,
     },
+    /// Evaluation instant supplied by the host (seconds since the epoch)
+    Now,
+    /// UTC midnight of the evaluation instant
+    Today,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -215,6 +372,55 @@ pub enum IrInstruction {
     },
     /// Return from predicate
     Return { value: Option<VarId> },
+    /// Add a relation's facts to its new tuples
+    InsertFacts { relation: String },
+    /// Evaluate one rule: its variables are scoped to `body`
+    Clause {
+        rule: usize,
+        vars: Vec<IrVar>,
+        body: Vec<IrInstruction>,
+    },
+    /// Run `body` for every tuple of a relation that matches `args`
+    Scan {
+        relation: String,
+        version: Version,
+        args: Vec<IrTerm>,
+        body: Vec<IrInstruction>,
+    },
+    /// Like `Scan`, probing an index on the arguments at `key`, which are bound
+    Join {
+        relation: String,
+        version: Version,
+        key: Vec<usize>,
+        args: Vec<IrTerm>,
+        body: Vec<IrInstruction>,
+    },
+    /// Skip to the next tuple unless a goal holds, binding what it determines
+    Filter(IrGoal),
+    /// Add a derived tuple to the relation's new tuples unless it is known
+    DeltaInsert { relation: String, args: Vec<IrTerm> },
+    /// End a round: new tuples join the full relations and become the deltas
+    Swap { relations: Vec<String> },
+}
+
+/// Which tuples of a relation an instruction reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// Every tuple derived so far
+    Full,
+    /// Tuples derived in the previous round
+    Delta,
+}
+
+/// Semi-naive bottom-up evaluation of one component of the dependency graph
+#[derive(Debug, Clone)]
+pub struct IrFixpoint {
+    pub relations: Vec<String>,
+    /// Facts and non-recursive rules, run once and ending in a `Swap`
+    pub init: Vec<IrInstruction>,
+    /// One round, repeated while its `Swap` finds new tuples; empty if the
+    /// component is not recursive
+    pub step: Vec<IrInstruction>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -224,20 +430,226 @@ pub struct BlockId(pub usize);
 #[derive(Debug, Clone)]
 pub struct IrQuery {
     pub goals: Vec<IrGoal>,
+    /// Named variables, whose values are the answer bindings
+    pub vars: Vec<IrVar>,
+    /// Loops over materialized relations and filters calling the others,
+    /// ending in `Return` when the query holds; None if its goals cannot
+    /// run in order
+    pub plan: Option<Vec<IrInstruction>>,
+}
+
+/// Integrity constraint: the goals must have no solution
+#[derive(Debug, Clone)]
+pub struct IrConstraint {
+    /// Position among the source's constraints, which runtime messages
+    /// number them by, even when simplification drops some, if the source
+    /// text is not at hand
+    pub id: usize,
+    pub goals: Vec<IrGoal>,
+    pub vars: Vec<IrVar>,
+    pub label: Option<String>,
+    /// The constraint in the source, for runtime messages to point at
+    pub span: Span,
+    /// Loops over materialized relations and filters calling the others,
+    /// ending in `Return` when the constraint is violated; None if its
+    /// goals cannot run in order
+    pub plan: Option<Vec<IrInstruction>>,
+}
+
+/// A clause calling a relation evaluated top-down without binding all of
+/// its arguments: only materialized relations can enumerate tuples
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnboundCall {
+    pub clause: Clause,
+    pub relation: String,
+}
+
+/// A rule, query or constraint of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clause {
+    /// A rule of a relation, by id
+    Rule { relation: String, id: usize },
+    /// A query, by position
+    Query(usize),
+    /// A constraint, by its position among the source's constraints
+    Constraint(usize),
+}
+
+impl fmt::Display for Clause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Clause::Rule { relation, id } => write!(f, "rule {} of {}", id, relation),
+            Clause::Query(i) => write!(f, "query {}", i),
+            Clause::Constraint(i) => write!(f, "constraint {}", i),
+        }
+    }
+}
+
+impl fmt::Display for UnboundCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} calls {} with unbound arguments, but {} is not evaluated bottom-up, \
+             so its tuples cannot be enumerated",
+            self.clause, self.relation, self.relation
+        )
+    }
+}
+
+impl IrProgram {
+    /// Relations evaluated bottom-up into tuple sets
+    pub fn materialized(&self) -> HashSet<String> {
+        self.fixpoints
+            .iter()
+            .flat_map(|fixpoint| fixpoint.relations.iter().cloned())
+            .collect()
+    }
+
+    /// Calls that would need a relation evaluated top-down to enumerate its
+    /// tuples. A rule evaluated top-down is called with its head bound; a
+    /// query or constraint without a plan can only call relations. Rules no
+    /// query or constraint reaches never run, so they are not checked.
+    pub fn unbound_calls(&self) -> Vec<UnboundCall> {
+        let materialized = self.materialized();
+        let reached = bottomup::reached(self);
+        let mut found = Vec::new();
+        for rel in self
+            .relations
+            .iter()
+            .filter(|rel| !materialized.contains(&rel.name) && reached.contains(rel.name.as_str()))
+        {
+            for rule in &rel.rules {
+                let mut bound = HashSet::new();
+                rule.head
+                    .args
+                    .iter()
+                    .for_each(|arg| bottomup::vars(arg, &mut bound));
+                if let Some(relation) = bottomup::unbound_call(&rule.body, bound, &materialized) {
+                    found.push(UnboundCall {
+                        clause: Clause::Rule {
+                            relation: rel.name.clone(),
+                            id: rule.id,
+                        },
+                        relation: relation.to_string(),
+                    });
+                }
+            }
+        }
+        let none = HashSet::new();
+        let clauses = self
+            .queries
+            .iter()
+            .enumerate()
+            .map(|(i, q)| (Clause::Query(i), &q.goals, q.plan.is_some()))
+            .chain(
+                self.constraints
+                    .iter()
+                    .map(|c| (Clause::Constraint(c.id), &c.goals, c.plan.is_some())),
+            );
+        for (clause, goals, planned) in clauses {
+            let loops = if planned { &materialized } else { &none };
+            if let Some(relation) = bottomup::unbound_call(goals, HashSet::new(), loops) {
+                found.push(UnboundCall {
+                    clause,
+                    relation: relation.to_string(),
+                });
+            }
+        }
+        found
+    }
+}
+
+impl IrRule {
+    /// The body as loops over the materialized relations and filters,
+    /// ending in `Return`, for a top-down call that binds the head
+    pub fn top_down(&self, materialized: &HashSet<String>) -> Vec<IrInstruction> {
+        let mut bound = HashSet::new();
+        self.head
+            .args
+            .iter()
+            .for_each(|arg| bottomup::vars(arg, &mut bound));
+        bottomup::nest(
+            &self.body,
+            0,
+            None,
+            &mut bound,
+            materialized,
+            &IrInstruction::Return { value: None },
+        )
+    }
 }
 
 /// IR Lowering context
 pub struct IrLowering {
     var_counter: usize,
-    relations: HashMap<String, IrRelation>,
+    /// Relations in declaration order
+    relations: Vec<IrRelation>,
+    /// Position of each relation in `relations`, by name
+    relation_index: HashMap<String, usize>,
+    /// Result type of every constructor and sum variant; a variant name
+    /// that several types declare maps to the last, unless `scope_variants`
+    /// says otherwise
+    constructor_types: HashMap<String, String>,
+    /// Constructors that are sum-type variants
+    variants: HashSet<String>,
+    /// Variables of the rule, query or constraint being lowered, by name
+    scope: HashMap<String, VarId>,
+    scope_vars: Vec<IrVar>,
+    /// Inferred type of each variable of the clause being lowered, by name
+    scope_types: HashMap<String, Type>,
+    /// Inferred type of each shared variant name in the clause or fact
+    /// being lowered, by span
+    scope_variants: VariantTypes,
+    /// Declarations of the program, for the types of clause variables
+    checker: TypeChecker,
 }
 
 impl IrLowering {
     pub fn new() -> Self {
         IrLowering {
             var_counter: 0,
-            relations: HashMap::new(),
+            relations: Vec::new(),
+            relation_index: HashMap::new(),
+            constructor_types: HashMap::new(),
+            variants: HashSet::new(),
+            scope: HashMap::new(),
+            scope_vars: Vec::new(),
+            scope_types: HashMap::new(),
+            scope_variants: VariantTypes::new(),
+            checker: TypeChecker::new(),
+        }
+    }
+
+    /// Start a clause: its variables are shared by its head and body only
+    fn enter_scope(&mut self, head: Option<&crate::ast::Atom>, body: &[Goal]) {
+        self.scope.clear();
+        self.scope_vars.clear();
+        let types = self.checker.resolve_clause(head, body).unwrap_or_default();
+        self.scope_types = types.vars;
+        self.scope_variants = types.variants;
+    }
+
+    /// The type a constructor or variant name belongs to where it occurs
+    fn constructor_type(&self, constructor: &str, span: &Span) -> String {
+        match self.scope_variants.get(&(span.start, span.end)) {
+            Some(ty) => ty.clone(),
+            None => self.constructor_types[constructor].clone(),
+        }
+    }
+
+    /// Finish a clause, returning its variables with their inferred types
+    fn leave_scope(&mut self) -> Vec<IrVar> {
+        let mut vars = std::mem::take(&mut self.scope_vars);
+        for var in &mut vars {
+            var.ty = self.scope_types.get(&var.name).cloned();
         }
+        self.scope.clear();
+        vars
+    }
+
+    fn relation_mut(&mut self, name: &str) -> Option<&mut IrRelation> {
+        let &i = self.relation_index.get(name)?;
+        self.relations.get_mut(i)
     }
 
     fn fresh_var(&mut self) -> VarId {
@@ -251,9 +663,30 @@ impl IrLowering {
         let mut types = Vec::new();
         let mut queries = Vec::new();
 
+        // Arithmetic on constants is evaluated first; the checker reported any errors
+        let (program, _) = crate::fold::fold_program(program);
+        let program = &program;
+
+        // The program has been checked already; this only collects declarations
+        self.checker = TypeChecker::new();
+        let _ = self.checker.check_program(program);
+
         // First pass: collect type definitions
         for item in &program.items {
             if let crate::ast::Item::TypeDef(typedef) = item {
+                match &typedef.def {
+                    crate::ast::TypeDefKind::Product { constructor, .. } => {
+                        self.constructor_types
+                            .insert(constructor.clone(), typedef.name.clone());
+                    }
+                    crate::ast::TypeDefKind::Sum { variants } => {
+                        for variant in variants {
+                            self.constructor_types
+                                .insert(variant.clone(), typedef.name.clone());
+                            self.variants.insert(variant.clone());
+                        }
+                    }
+                }
                 types.push(self.lower_type_def(typedef));
             }
         }
@@ -261,31 +694,40 @@ impl IrLowering {
         // Second pass: initialize relations from declarations
         for item in &program.items {
             if let crate::ast::Item::RelationDecl(rel) = item {
-                self.relations.insert(
-                    rel.name.clone(),
-                    IrRelation {
-                        name: rel.name.clone(),
-                        signature: rel.signature.clone(),
-                        facts: vec![],
-                        rules: vec![],
-                        modes: vec![],
-                    },
-                );
+                let next = self.relations.len();
+                let i = *self.relation_index.entry(rel.name.clone()).or_insert(next);
+                let relation = IrRelation {
+                    name: rel.name.clone(),
+                    signature: rel.signature.clone(),
+                    facts: vec![],
+                    rules: vec![],
+                    modes: vec![],
+                    keys: rel.keys.clone(),
+                    combine: rel.combine().and_then(Combine::from_name),
+                    needs_tabling: false,
+                };
+                if i == next {
+                    self.relations.push(relation);
+                } else {
+                    self.relations[i] = relation;
+                }
             }
         }
 
-        // Third pass: collect facts and rules
+        // Third pass: collect facts and rules, numbering rules in source order
+        let mut rule_id = 0;
         for item in &program.items {
             match item {
                 crate::ast::Item::Fact(fact) => {
                     let lowered = self.lower_fact(fact);
-                    if let Some(rel) = self.relations.get_mut(&fact.relation) {
+                    if let Some(rel) = self.relation_mut(&fact.relation) {
                         rel.facts.push(lowered);
                     }
                 }
                 crate::ast::Item::Rule(rule) => {
-                    let lowered = self.lower_rule(rule);
-                    if let Some(rel) = self.relations.get_mut(&rule.head.relation) {
+                    let lowered = self.lower_rule(rule_id, rule);
+                    rule_id += 1;
+                    if let Some(rel) = self.relation_mut(&rule.head.relation) {
                         rel.rules.push(lowered);
                     }
                 }
@@ -293,24 +735,83 @@ impl IrLowering {
             }
         }
 
-        // Fourth pass: collect queries
+        // Fourth pass: collect queries and constraints
+        let mut constraints = Vec::new();
         for item in &program.items {
-            if let crate::ast::Item::Query(query) = item {
-                queries.push(self.lower_query(query));
+            match item {
+                crate::ast::Item::Query(query) => queries.push(self.lower_query(query)),
+                crate::ast::Item::Constraint(constraint) => {
+                    self.enter_scope(None, &constraint.body);
+                    let goals = constraint.body.iter().map(|g| self.lower_goal(g)).collect();
+                    constraints.push(IrConstraint {
+                        id: constraints.len(),
+                        goals,
+                        vars: self.leave_scope(),
+                        label: constraint.label().map(str::to_string),
+                        span: constraint.span.clone(),
+                        plan: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Fifth pass: a mode for every call pattern, with its inferred determinism
+        let modes = crate::modes::infer(program);
+        for (name, pattern, determinism) in modes.iter() {
+            let Some(signature) = self.relation_mut(name).map(|r| r.signature.clone()) else {
+                continue;
+            };
+            let types = match signature {
+                Type::Product(types) => types,
+                single => vec![single],
+            };
+            let params = pattern
+                .iter()
+                .zip(types)
+                .map(|(&mode, ty)| IrParam {
+                    var: self.fresh_var(),
+                    ty,
+                    mode,
+                })
+                .collect();
+            let mode = IrMode {
+                pattern: pattern.to_vec(),
+                determinism,
+                implementation: IrPredicate {
+                    params,
+                    body: Vec::new(),
+                },
+            };
+            if let Some(rel) = self.relation_mut(name) {
+                rel.modes.push(mode);
             }
         }
 
-        IrProgram {
+        let mut ir = IrProgram {
             types,
-            relations: self.relations.values().cloned().collect(),
+            relations: self.relations.clone(),
             queries,
-        }
+            constraints,
+            dependencies: DependencyGraph::default(),
+            fixpoints: Vec::new(),
+        };
+
+        // Sixth pass: decide comparisons between constants
+        crate::fold::simplify(&mut ir);
+
+        // Seventh pass: order rule bodies by estimated cost
+        order::reorder(&mut ir);
+
+        // Eighth pass: find recursion and plan bottom-up evaluation
+        plan(&mut ir);
+        ir
     }
 
     fn lower_type_def(&self, typedef: &crate::ast::TypeDef) -> IrTypeDef {
         let layout = match &typedef.def {
             crate::ast::TypeDefKind::Product {
-                constructor: _,
+                constructor,
                 fields,
             } => {
                 // Calculate struct layout (simplified - assuming 8-byte alignment)
@@ -325,6 +826,7 @@ impl IrLowering {
                     .collect();
 
                 TypeLayout::Struct {
+                    constructor: constructor.clone(),
                     fields: field_types,
                     size_bytes: size,
                     align_bytes: 8,
@@ -346,7 +848,7 @@ impl IrLowering {
     fn type_size(ty: &crate::ast::Type) -> usize {
         match ty {
             crate::ast::Type::Named(name) => match name.as_str() {
-                "Int" => 8,
+                "Int" | "Date" | "Timestamp" | "Duration" => 8,
                 "String" => 16, // ptr + len
                 _ => 8,         // pointer to struct
             },
@@ -356,21 +858,36 @@ impl IrLowering {
         }
     }
 
-    fn lower_fact(&self, fact: &crate::ast::Fact) -> IrFact {
+    fn lower_fact(&mut self, fact: &crate::ast::Fact) -> IrFact {
+        self.scope_variants = self.checker.fact_variants(fact);
         IrFact {
-            args: fact.args.iter().map(Self::lower_term_to_value).collect(),
+            args: fact
+                .args
+                .iter()
+                .map(|t| self.lower_term_to_value(t))
+                .collect(),
         }
     }
 
-    fn lower_term_to_value(term: &crate::ast::Term) -> IrValue {
+    fn lower_term_to_value(&self, term: &crate::ast::Term) -> IrValue {
         match term {
             crate::ast::Term::Int(n, _) => IrValue::Int(*n),
             crate::ast::Term::String(s, _) => IrValue::String(s.clone()),
+            // Temporal values are all represented as seconds
+            crate::ast::Term::Date(n, _)
+            | crate::ast::Term::Timestamp(n, _)
+            | crate::ast::Term::Duration(n, _) => IrValue::Int(*n),
+            crate::ast::Term::Construct {
+                constructor, span, ..
+            } if self.variants.contains(constructor) => IrValue::Variant {
+                type_name: self.constructor_type(constructor, span),
+                variant: constructor.clone(),
+            },
             crate::ast::Term::Construct {
                 constructor, args, ..
             } => IrValue::Struct {
                 type_name: constructor.clone(),
-                fields: args.iter().map(Self::lower_term_to_value).collect(),
+                fields: args.iter().map(|a| self.lower_term_to_value(a)).collect(),
             },
             crate::ast::Term::Var(name, _) => {
                 // Type checker should have caught this
@@ -379,26 +896,34 @@ impl IrLowering {
                     name
                 )
             }
+            crate::ast::Term::Now(_) | crate::ast::Term::Today(_) => {
+                // Type checker should have caught this
+                unreachable!(
+                    "now() and today() should not appear in facts - this is a type checker bug"
+                )
+            }
             crate::ast::Term::BinOp { .. } => {
                 // Type checker should have caught this
                 unreachable!(
-                    "Binary ops in facts should be pre-evaluated - this is a type checker bug"
+                    "Arithmetic in facts is folded before lowering - this is a type checker bug"
                 )
             }
         }
     }
 
-    fn lower_rule(&mut self, rule: &crate::ast::Rule) -> IrRule {
-        // Check if this rule needs tabling (recursive)
-        let needs_tabling = rule
-            .body
-            .iter()
-            .any(|g| matches!(g, crate::ast::Goal::Atom(a) if a.relation == rule.head.relation));
-
+    fn lower_rule(&mut self, id: usize, rule: &crate::ast::Rule) -> IrRule {
+        self.enter_scope(Some(&rule.head), &rule.body);
+        let head = self.lower_atom(&rule.head);
+        let body = rule.body.iter().map(|g| self.lower_goal(g)).collect();
         IrRule {
-            head: self.lower_atom(&rule.head),
-            body: rule.body.iter().map(|g| self.lower_goal(g)).collect(),
-            needs_tabling,
+            id,
+            label: rule.label().map(str::to_string),
+            name: rule.name().map(str::to_string),
+            overrides: rule.overrides().into_iter().map(str::to_string).collect(),
+            head,
+            body,
+            vars: self.leave_scope(),
+            needs_tabling: false,
         }
     }
 
@@ -411,18 +936,36 @@ impl IrLowering {
 
     fn lower_term(&mut self, term: &crate::ast::Term) -> IrTerm {
         match term {
-            crate::ast::Term::Var(name, _) => {
-                // For now, create a fresh var - in real impl would track var mappings
-                IrTerm::Var(self.fresh_var())
-            }
+            // Every `_` is a distinct variable
+            crate::ast::Term::Var(name, _) if name == "_" => IrTerm::Var(self.fresh_var()),
+            crate::ast::Term::Var(name, _) => match self.scope.get(name) {
+                Some(&id) => IrTerm::Var(id),
+                None => {
+                    let id = self.fresh_var();
+                    self.scope.insert(name.clone(), id);
+                    self.scope_vars.push(IrVar {
+                        id,
+                        name: name.clone(),
+                        ty: None,
+                    });
+                    IrTerm::Var(id)
+                }
+            },
             crate::ast::Term::Int(n, _) => IrTerm::Value(IrValue::Int(*n)),
             crate::ast::Term::String(s, _) => IrTerm::Value(IrValue::String(s.clone())),
+            crate::ast::Term::Date(n, _)
+            | crate::ast::Term::Timestamp(n, _)
+            | crate::ast::Term::Duration(n, _) => IrTerm::Value(IrValue::Int(*n)),
+            crate::ast::Term::Now(_) => IrTerm::Now,
+            crate::ast::Term::Today(_) => IrTerm::Today,
             crate::ast::Term::Construct {
                 constructor, args, ..
-            } => IrTerm::Value(IrValue::Struct {
-                type_name: constructor.clone(),
-                fields: args.iter().map(Self::lower_term_to_value).collect(),
-            }),
+            } if !is_ground(term) => IrTerm::Construct {
+                type_name: self.constructor_types[constructor].clone(),
+                constructor: constructor.clone(),
+                args: args.iter().map(|arg| self.lower_term(arg)).collect(),
+            },
+            crate::ast::Term::Construct { .. } => IrTerm::Value(self.lower_term_to_value(term)),
             crate::ast::Term::BinOp {
                 op, left, right, ..
             } => IrTerm::BinOp {
@@ -458,10 +1001,38 @@ impl IrLowering {
                 op: self.lower_compare_op(*op),
                 left: self.lower_term(left),
                 right: self.lower_term(right),
+                ty: self.operand_type(left).or_else(|| self.operand_type(right)),
+            },
+            crate::ast::Goal::Match {
+                subject, pattern, ..
+            } => IrGoal::Match {
+                subject: self.lower_term(subject),
+                pattern: pattern.clone(),
             },
         }
     }
 
+    /// Type of a compared term, from its literal or the clause's variable types
+    fn operand_type(&self, term: &crate::ast::Term) -> Option<Type> {
+        let name = match term {
+            crate::ast::Term::Int(..) => "Int",
+            crate::ast::Term::String(..) => "String",
+            crate::ast::Term::Date(..) | crate::ast::Term::Today(_) => "Date",
+            crate::ast::Term::Timestamp(..) | crate::ast::Term::Now(_) => "Timestamp",
+            crate::ast::Term::Duration(..) => "Duration",
+            crate::ast::Term::Construct {
+                constructor, span, ..
+            } if self.constructor_types.contains_key(constructor) => {
+                return Some(Type::Named(self.constructor_type(constructor, span)))
+            }
+            crate::ast::Term::Construct { .. } => return None,
+            crate::ast::Term::Var(name, _) => return self.scope_types.get(name).cloned(),
+            // Arithmetic is on Int and temporal values, all compared as scalars
+            crate::ast::Term::BinOp { .. } => return None,
+        };
+        Some(Type::Named(name.to_string()))
+    }
+
     fn lower_compare_op(&self, op: crate::ast::CompareOp) -> CompareOp {
         match op {
             crate::ast::CompareOp::Eq => CompareOp::Eq,
@@ -474,8 +1045,12 @@ impl IrLowering {
     }
 
     fn lower_query(&mut self, query: &crate::ast::Query) -> IrQuery {
+        self.enter_scope(None, &query.goals);
+        let goals = query.goals.iter().map(|g| self.lower_goal(g)).collect();
         IrQuery {
-            goals: query.goals.iter().map(|g| self.lower_goal(g)).collect(),
+            goals,
+            vars: self.leave_scope(),
+            plan: None,
         }
     }
 }
@@ -492,6 +1067,35 @@ pub fn lower_to_ir(program: &crate::ast::Program) -> IrProgram {
     lowering.lower(program)
 }
 
+/// Derive the dependency graph, tabling points and bottom-up plans from the
+/// relations; passes that rewrite relations run this again
+pub fn plan(ir: &mut IrProgram) {
+    ir.dependencies = DependencyGraph::from_ir(ir);
+    let graph = &ir.dependencies;
+    for rel in &mut ir.relations {
+        rel.needs_tabling = graph.is_recursive(&rel.name);
+        for rule in &mut rel.rules {
+            rule.needs_tabling = rule.body.iter().any(|goal| {
+                matches!(goal, IrGoal::Call { relation, .. }
+                    if graph.same_component(relation, &rule.head.relation))
+            });
+        }
+    }
+    bottomup::plan(ir);
+}
+
+/// Whether a term is a constant, so it can be lowered to an `IrValue`
+fn is_ground(term: &crate::ast::Term) -> bool {
+    match term {
+        crate::ast::Term::Var(..)
+        | crate::ast::Term::Now(_)
+        | crate::ast::Term::Today(_)
+        | crate::ast::Term::BinOp { .. } => false,
+        crate::ast::Term::Construct { args, .. } => args.iter().all(is_ground),
+        _ => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -503,6 +1107,9 @@ mod tests {
             types: vec![],
             relations: vec![],
             queries: vec![],
+            constraints: vec![],
+            dependencies: DependencyGraph::default(),
+            fixpoints: vec![],
         };
         assert_eq!(ir.types.len(), 0);
     }
@@ -558,6 +1165,95 @@ mod tests {
         assert!(!ancestor_rel.rules[0].needs_tabling);
     }
 
+    #[test]
+    fn test_lower_rule_shares_variables() {
+        let source = r#"
+            type Person = person(name: String, age: Int)
+            rel parent: Person × Person
+            rel ancestor: Person × Person
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            ?- ancestor(A, _), parent(_, A).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+
+        let rule = &ir
+            .relations
+            .iter()
+            .find(|r| r.name == "ancestor")
+            .unwrap()
+            .rules[0];
+        let names: Vec<_> = rule.vars.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["X", "Z", "Y"]);
+        let person = Some(Type::Named("Person".to_string()));
+        assert!(rule.vars.iter().all(|v| v.ty == person));
+
+        let var = |term: &IrTerm| match term {
+            IrTerm::Var(id) => *id,
+            other => panic!("expected a variable, got {:?}", other),
+        };
+        let call_args = |goal: &IrGoal| match goal {
+            IrGoal::Call { args, .. } => args.iter().map(var).collect::<Vec<_>>(),
+            other => panic!("expected a call, got {:?}", other),
+        };
+        let (x, z, y) = (rule.vars[0].id, rule.vars[1].id, rule.vars[2].id);
+        assert_eq!(var(&rule.head.args[0]), x);
+        assert_eq!(var(&rule.head.args[1]), z);
+        assert_eq!(call_args(&rule.body[0]), vec![x, y]);
+        assert_eq!(call_args(&rule.body[1]), vec![y, z]);
+
+        // Each `_` is its own variable and is not listed
+        let query = &ir.queries[0];
+        assert_eq!(query.vars.len(), 1);
+        let (first, second) = (call_args(&query.goals[0]), call_args(&query.goals[1]));
+        assert_eq!(first[0], second[1]);
+        assert_ne!(first[1], second[0]);
+    }
+
+    #[test]
+    fn test_lower_constructor_pattern() {
+        let source = r#"
+            type Employee = employee(name: String, salary: Int)
+            type Level = | Junior | Senior
+            rel staff: Employee
+            rel classification: Employee × Level
+            classification(employee(_, Sal), Junior) :- staff(employee(_, Sal)), Sal < 100.
+            classification(E, Senior) :- staff(E), employee(_, Sal) = E, Sal >= 100.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+        let rules = &ir
+            .relations
+            .iter()
+            .find(|r| r.name == "classification")
+            .unwrap()
+            .rules;
+
+        let sal = rules[0].vars[0].id;
+        let IrTerm::Construct {
+            type_name,
+            constructor,
+            args,
+        } = &rules[0].head.args[0]
+        else {
+            panic!("expected a constructor pattern");
+        };
+        assert_eq!(
+            (type_name.as_str(), constructor.as_str()),
+            ("Employee", "employee")
+        );
+        assert!(matches!(args[1], IrTerm::Var(id) if id == sal));
+        assert!(matches!(
+            rules[0].head.args[1],
+            IrTerm::Value(IrValue::Variant { .. })
+        ));
+
+        let IrGoal::Unify { left, .. } = &rules[1].body[1] else {
+            panic!("expected an equality");
+        };
+        assert!(matches!(left, IrTerm::Construct { .. }));
+    }
+
     #[test]
     fn test_lower_recursive_rule() {
         let source = r#"
@@ -574,6 +1270,66 @@ mod tests {
         assert!(ancestor_rel.rules[0].needs_tabling); // Recursive!
     }
 
+    #[test]
+    fn test_lower_mutual_recursion() {
+        let source = r#"
+            rel zero: Int
+            rel even: Int
+            rel odd: Int
+            rel parity: Int
+            even(N) :- zero(N).
+            even(N) :- odd(M), N = M + 1.
+            odd(N) :- even(M), N = M + 1.
+            parity(N) :- even(N).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+
+        let relation = |name: &str| ir.relations.iter().find(|r| r.name == name).unwrap();
+        assert!(relation("even").needs_tabling && relation("odd").needs_tabling);
+        assert!(!relation("parity").needs_tabling && !relation("zero").needs_tabling);
+        // Only the rules that go around the cycle are recursive
+        assert!(!relation("even").rules[0].needs_tabling);
+        assert!(relation("even").rules[1].needs_tabling);
+        assert!(relation("odd").rules[0].needs_tabling);
+        assert!(ir.dependencies.same_component("even", "odd"));
+    }
+
+    #[test]
+    fn test_lower_modes() {
+        let source = r#"
+            rel edge: Int × Int key(0)
+            rel path: Int × Int
+            edge(1, 2).
+            edge(2, 3).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+
+        let path = ir.relations.iter().find(|r| r.name == "path").unwrap();
+        let modes: Vec<_> = path
+            .modes
+            .iter()
+            .map(|m| (m.pattern.clone(), m.determinism))
+            .collect();
+        use ModeAnnotation::{Input, Output};
+        assert_eq!(
+            modes,
+            vec![
+                (vec![Input, Input], Determinism::Semidet),
+                (vec![Input, Output], Determinism::Nondet),
+                (vec![Output, Output], Determinism::Multi),
+            ]
+        );
+        // The recursive call reaches edge with its key bound
+        let edge = ir.relations.iter().find(|r| r.name == "edge").unwrap();
+        let lookup = edge.modes.iter().find(|m| m.pattern == [Input, Output]);
+        assert_eq!(lookup.map(|m| m.determinism), Some(Determinism::Semidet));
+        assert_eq!(edge.modes[0].implementation.params.len(), 2);
+    }
+
     #[test]
     fn test_lower_query() {
         let source = r#"
@@ -587,4 +1343,44 @@ mod tests {
         assert_eq!(ir.queries.len(), 1);
         assert_eq!(ir.queries[0].goals.len(), 1);
     }
+
+    #[test]
+    fn test_lower_matches() {
+        let source = r#"
+            rel path: String
+            ?- path(P), matches(P, "^/docs/").
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+
+        match &ir.queries[0].goals[1] {
+            IrGoal::Match { pattern, .. } => assert_eq!(pattern, "^/docs/"),
+            other => panic!("Expected match goal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lower_variant_compare() {
+        let source = r#"
+            type Action = | Read | Write | Delete
+            rel allowed: Action
+            allowed(A) :- A != Delete.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+
+        let rule = &ir.relations[0].rules[0];
+        match &rule.body[0] {
+            IrGoal::Compare {
+                right: IrTerm::Value(IrValue::Variant { type_name, variant }),
+                ty: Some(Type::Named(ty)),
+                ..
+            } => {
+                assert_eq!(type_name, "Action");
+                assert_eq!(variant, "Delete");
+                assert_eq!(ty, "Action");
+            }
+            other => panic!("Expected variant comparison, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,294 @@
+/// Cost-based ordering of rule bodies
+///
+/// Rule bodies are written for reading, not for evaluation. This pass picks
+/// the order goals run in: filters and unifications as soon as their
+/// variables are bound, and otherwise the call expected to produce the
+/// fewest tuples. Estimates come from the facts known at compile time: a
+/// call binding a declared key yields one tuple, and each bound argument
+/// divides a relation by its number of distinct values in that column, down
+/// to one tuple.
+/// Derived relations are assumed large. Relations that cannot be evaluated
+/// bottom-up only answer calls with every argument bound, so like filters
+/// they wait until their arguments are. A goal never moves before a
+/// unification it follows in the source and shares a variable with, which
+/// keeps top-down evaluation of the reordered rule sound. Only the IR is
+/// reordered; explanations come from the source rules.
+use super::bottomup::{bind, goal_vars, is_bound, plannable, ready};
+use super::*;
+
+/// Tuples assumed for a relation with rules, on top of its facts
+const DERIVED_TUPLES: f64 = 1000.0;
+
+/// Fraction of a derived relation kept by each bound argument
+const DERIVED_SELECTIVITY: f64 = 0.1;
+
+pub(super) fn reorder(program: &mut IrProgram) {
+    let statistics = Statistics::new(&program.relations);
+    for rel in &mut program.relations {
+        for rule in &mut rel.rules {
+            rule.body = statistics.order(&rule.body);
+        }
+    }
+}
+
+/// What is known at compile time about the size of each relation
+struct Statistics {
+    tuples: HashMap<String, f64>,
+    /// Distinct values per argument, for relations made of facts only
+    distinct: HashMap<String, Vec<f64>>,
+    keys: HashMap<String, Vec<Vec<usize>>>,
+    /// Relations answered top-down only, which need every argument bound
+    bound_only: HashSet<String>,
+}
+
+impl Statistics {
+    fn new(relations: &[IrRelation]) -> Self {
+        let mut statistics = Statistics {
+            tuples: HashMap::new(),
+            distinct: HashMap::new(),
+            keys: HashMap::new(),
+            bound_only: HashSet::new(),
+        };
+        for rel in relations {
+            let facts = rel.facts.len() as f64;
+            if rel.rules.is_empty() {
+                statistics.tuples.insert(rel.name.clone(), facts);
+                let arity = rel.facts.first().map_or(0, |fact| fact.args.len());
+                let distinct = (0..arity)
+                    .map(|i| {
+                        let values: HashSet<String> = rel
+                            .facts
+                            .iter()
+                            .map(|fact| format!("{:?}", fact.args[i]))
+                            .collect();
+                        values.len() as f64
+                    })
+                    .collect();
+                statistics.distinct.insert(rel.name.clone(), distinct);
+            } else {
+                statistics
+                    .tuples
+                    .insert(rel.name.clone(), facts + DERIVED_TUPLES);
+            }
+            statistics.keys.insert(rel.name.clone(), rel.keys.clone());
+            if !plannable(rel) {
+                statistics.bound_only.insert(rel.name.clone());
+            }
+        }
+        statistics
+    }
+
+    /// Expected tuples of a call, given the arguments bound before it
+    fn estimate(&self, relation: &str, args: &[IrTerm], bound: &HashSet<VarId>) -> f64 {
+        let tuples = self.tuples.get(relation).copied().unwrap_or(DERIVED_TUPLES);
+        let given: Vec<usize> = (0..args.len())
+            .filter(|&i| is_bound(&args[i], bound))
+            .collect();
+        let keyed = self
+            .keys
+            .get(relation)
+            .is_some_and(|keys| keys.iter().any(|key| key.iter().all(|k| given.contains(k))));
+        if keyed {
+            return tuples.min(1.0);
+        }
+        match self.distinct.get(relation) {
+            Some(distinct) => {
+                let divisor: f64 = given.iter().filter_map(|&i| distinct.get(i)).product();
+                (tuples / divisor).max(tuples.min(1.0))
+            }
+            None => tuples * DERIVED_SELECTIVITY.powi(given.len() as i32),
+        }
+    }
+
+    fn order(&self, body: &[IrGoal]) -> Vec<IrGoal> {
+        // Goals that must stay after an earlier unification sharing a variable
+        let mut after: Vec<Vec<usize>> = vec![Vec::new(); body.len()];
+        for (i, goal) in body.iter().enumerate() {
+            let IrGoal::Unify { .. } = goal else {
+                continue;
+            };
+            let mut shared = HashSet::new();
+            goal_vars(goal, &mut shared);
+            for (j, later) in body.iter().enumerate().skip(i + 1) {
+                let mut mentioned = HashSet::new();
+                goal_vars(later, &mut mentioned);
+                if !shared.is_disjoint(&mentioned) {
+                    after[j].push(i);
+                }
+            }
+        }
+
+        let mut done = vec![false; body.len()];
+        let mut bound = HashSet::new();
+        let mut ordered = Vec::with_capacity(body.len());
+        while ordered.len() < body.len() {
+            let candidates: Vec<usize> = (0..body.len())
+                .filter(|&i| !done[i] && after[i].iter().all(|&j| done[j]))
+                .collect();
+            let filter = candidates
+                .iter()
+                .copied()
+                .find(|&i| !matches!(body[i], IrGoal::Call { .. }) && ready(&body[i], &bound));
+            let call = candidates
+                .iter()
+                .copied()
+                .filter_map(|i| match &body[i] {
+                    IrGoal::Call { relation, args, .. }
+                        if !self.bound_only.contains(relation)
+                            || args.iter().all(|arg| is_bound(arg, &bound)) =>
+                    {
+                        Some((i, self.estimate(relation, args, &bound)))
+                    }
+                    _ => None,
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _)| i);
+            // Goals that can never be ready keep their place after the rest
+            let next = filter.or(call).unwrap_or(candidates[0]);
+            done[next] = true;
+            bind(&body[next], &mut bound);
+            ordered.push(body[next].clone());
+        }
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn bodies(source: &str, relation: &str) -> Vec<Vec<String>> {
+        let program = parser::parse(source).expect("Parse failed");
+        let ir = lower_to_ir(&program);
+        let rel = ir.relations.iter().find(|r| r.name == relation).unwrap();
+        rel.rules
+            .iter()
+            .map(|rule| {
+                rule.body
+                    .iter()
+                    .map(|goal| match goal {
+                        IrGoal::Call { relation, .. } => relation.clone(),
+                        IrGoal::Unify { .. } => "=".to_string(),
+                        IrGoal::Compare { .. } => "compare".to_string(),
+                        IrGoal::Match { .. } => "matches".to_string(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_order_smallest_relation_first() {
+        let order = bodies(
+            r#"
+            rel big: Int × Int
+            rel small: Int
+            rel out: Int × Int
+            big(1, 10).
+            big(2, 20).
+            big(3, 30).
+            small(2).
+            out(X, Y) :- big(X, Y), small(X), Y > 5.
+        "#,
+            "out",
+        );
+        // small binds X, then big is probed on it; the comparison waits for Y
+        assert_eq!(order, vec![vec!["small", "big", "compare"]]);
+    }
+
+    #[test]
+    fn test_order_filters_after_binders() {
+        let order = bodies(
+            r#"
+            type Employee = employee(id: Int, salary: Int)
+            type Department = dept(name: String, budget: Int)
+            rel works_in: Employee × Department key(0)
+            rel over_budget: Department
+            works_in(employee(1, 80000), dept("Engineering", 500000)).
+            works_in(employee(2, 90000), dept("Sales", 300000)).
+            over_budget(Dept) :-
+                works_in(employee(_, Sal1), Dept),
+                works_in(employee(_, Sal2), Dept),
+                dept(_, Budget) = Dept,
+                Sal1 + Sal2 > Budget.
+        "#,
+            "over_budget",
+        );
+        // The budget is destructured as soon as Dept is known
+        assert_eq!(order, vec![vec!["works_in", "=", "works_in", "compare"]]);
+    }
+
+    #[test]
+    fn test_order_keeps_unification_before_its_uses() {
+        let order = bodies(
+            r#"
+            rel p: Int
+            rel q: Int
+            rel r: Int
+            p(1).
+            p(2).
+            p(3).
+            q(3).
+            r(X) :- p(X), Y = X + 1, q(Y).
+        "#,
+            "r",
+        );
+        // q is the smallest relation, but Y is only known once p has bound X
+        let body = &order[0];
+        let unify = body.iter().position(|goal| goal == "=").unwrap();
+        let call = body.iter().position(|goal| goal == "q").unwrap();
+        assert!(unify < call, "{:?}", body);
+    }
+
+    #[test]
+    fn test_order_divides_by_every_bound_argument() {
+        let facts: Vec<String> = (0..100)
+            .map(|i| format!("t({}, {}, {}).", i / 10, i % 10, i))
+            .collect();
+        let order = bodies(
+            &format!(
+                r#"
+            rel s: Int × Int
+            rel t: Int × Int × Int
+            rel u: Int
+            rel out: Int × Int
+            s(1, 2).
+            {}
+            u(1). u(2). u(3). u(4). u(5).
+            out(X, Z) :- u(Z), t(X, Y, Z), s(X, Y).
+        "#,
+                facts.join("\n")
+            ),
+            "out",
+        );
+        // With X and Y bound, t's 100 facts over 10 × 10 values give one
+        // tuple, fewer than the five of u
+        assert_eq!(order, vec![vec!["s", "t", "u"]]);
+    }
+
+    #[test]
+    fn test_order_waits_to_call_combined_relation() {
+        let users: Vec<String> = (0..1500).map(|i| format!("user({}).", i)).collect();
+        let order = bodies(
+            &format!(
+                r#"
+            type Effect = Permit | Deny
+            rel user: Int
+            rel grant: Int
+            @combine("deny-overrides")
+            rel po: Int × Effect
+            rel ok: Int
+            {}
+            grant(3).
+            po(U, Permit) :- grant(U).
+            ok(U) :- user(U), po(U, Permit).
+        "#,
+                users.join("\n")
+            ),
+            "ok",
+        );
+        // po is the cheaper call, but it only answers once U is bound
+        assert_eq!(order, vec![vec!["user", "po"]]);
+    }
+}
@@ -0,0 +1,1451 @@
+/// Textual form of the IR
+///
+/// `IrProgram` and its parts print as indented text, one declaration, goal
+/// or instruction per line, with blocks in braces; `IrProgram` parses back
+/// from the same text. Variables print as `%N`, constants as in source,
+/// struct values as `Type{..}`, variants as `Type::Variant` and constructor
+/// patterns as `Type::ctor(..)`. The dependency graph is not printed:
+/// parsing recomputes it from the rules. Lines starting with `;` are
+/// comments, for hand-written IR.
+use super::*;
+use std::str::FromStr;
+
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Writer::render(|w| w.program(self)))
+    }
+}
+
+impl fmt::Display for IrRelation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Writer::render(|w| w.relation(self)))
+    }
+}
+
+impl fmt::Display for IrRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Writer::render(|w| w.rule(self)))
+    }
+}
+
+impl fmt::Display for IrInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Writer::render(|w| w.instruction(self)))
+    }
+}
+
+impl fmt::Display for IrGoal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrGoal::Call {
+                relation,
+                mode_index,
+                args,
+            } => write!(f, "call {}({})", callee(relation, *mode_index), list(args)),
+            IrGoal::Unify { left, right } => write!(f, "unify {} = {}", left, right),
+            IrGoal::Compare {
+                op,
+                left,
+                right,
+                ty,
+            } => {
+                write!(f, "compare {} {} {}", left, op, right)?;
+                match ty {
+                    Some(ty) => write!(f, ": {}", TypeText(ty)),
+                    None => Ok(()),
+                }
+            }
+            IrGoal::Match { subject, pattern } => write!(f, "match {} {:?}", subject, pattern),
+        }
+    }
+}
+
+impl fmt::Display for IrTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrTerm::Var(var) => write!(f, "{}", var),
+            IrTerm::Value(value) => write!(f, "{}", value),
+            IrTerm::Construct {
+                type_name,
+                constructor,
+                args,
+            } => write!(f, "{}::{}({})", type_name, constructor, list(args)),
+            IrTerm::FieldAccess { base, field } => write!(f, "{}.{}", base, field),
+            IrTerm::BinOp { op, left, right } => write!(f, "({} {} {})", left, op, right),
+            IrTerm::Now => write!(f, "now()"),
+            IrTerm::Today => write!(f, "today()"),
+        }
+    }
+}
+
+impl fmt::Display for IrValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrValue::Int(n) => write!(f, "{}", n),
+            IrValue::String(s) => write!(f, "{:?}", s),
+            IrValue::Struct { type_name, fields } => write!(f, "{}{{{}}}", type_name, list(fields)),
+            IrValue::Variant { type_name, variant } => write!(f, "{}::{}", type_name, variant),
+        }
+    }
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for VarId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Full => write!(f, "full"),
+            Version::Delta => write!(f, "delta"),
+        }
+    }
+}
+
+/// A type as it prints in IR, where the empty product is `()`
+struct TypeText<'a>(&'a Type);
+
+impl fmt::Display for TypeText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Type::Product(types) if types.is_empty() => write!(f, "()"),
+            ty => write!(f, "{}", ty),
+        }
+    }
+}
+
+pub(super) fn list<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A relation and its mode, which is omitted when it is the first
+fn callee(relation: &str, mode: usize) -> String {
+    if mode == 0 {
+        relation.to_string()
+    } else {
+        format!("{}/{}", relation, mode)
+    }
+}
+
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn vars_line(vars: &[IrVar]) -> String {
+    let vars: Vec<String> = vars
+        .iter()
+        .map(|var| {
+            let name = if is_ident(&var.name) {
+                var.name.clone()
+            } else {
+                format!("{:?}", var.name)
+            };
+            match &var.ty {
+                Some(ty) => format!("{} {}: {}", var.id, name, TypeText(ty)),
+                None => format!("{} {}", var.id, name),
+            }
+        })
+        .collect();
+    format!("vars {}", vars.join(", "))
+}
+
+/// Indented text, built line by line
+struct Writer {
+    out: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn render(write: impl FnOnce(&mut Writer)) -> String {
+        let mut writer = Writer {
+            out: String::new(),
+            depth: 0,
+        };
+        write(&mut writer);
+        writer.out
+    }
+
+    fn line(&mut self, text: impl fmt::Display) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(&text.to_string());
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, header: impl fmt::Display, body: impl FnOnce(&mut Writer)) {
+        self.line(format_args!("{} {{", header));
+        self.depth += 1;
+        body(self);
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn program(&mut self, program: &IrProgram) {
+        for def in &program.types {
+            self.type_def(def);
+        }
+        let mut first = program.types.is_empty();
+        let mut separate = |w: &mut Writer| {
+            if !std::mem::take(&mut first) {
+                w.out.push('\n');
+            }
+        };
+        for rel in &program.relations {
+            separate(self);
+            self.relation(rel);
+        }
+        for query in &program.queries {
+            separate(self);
+            self.query(query);
+        }
+        for constraint in &program.constraints {
+            separate(self);
+            self.constraint(constraint);
+        }
+        for fixpoint in &program.fixpoints {
+            separate(self);
+            self.fixpoint(fixpoint);
+        }
+    }
+
+    fn type_def(&mut self, def: &IrTypeDef) {
+        match &def.layout {
+            TypeLayout::Struct {
+                constructor,
+                fields,
+                size_bytes,
+                align_bytes,
+            } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, ty)| format!("{}: {}", name, TypeText(ty)))
+                    .collect();
+                self.line(format_args!(
+                    "type {} = struct {} {{ {} }} size {} align {}",
+                    def.name,
+                    constructor,
+                    fields.join(", "),
+                    size_bytes,
+                    align_bytes
+                ));
+            }
+            TypeLayout::Enum {
+                variants,
+                tag_size,
+                max_variant_size,
+            } => self.line(format_args!(
+                "type {} = enum {{ {} }} tag {} size {}",
+                def.name,
+                variants.join(", "),
+                tag_size,
+                max_variant_size
+            )),
+        }
+    }
+
+    fn relation(&mut self, rel: &IrRelation) {
+        let header = format!("relation {}: {}", rel.name, TypeText(&rel.signature));
+        self.block(header, |w| {
+            for key in &rel.keys {
+                w.line(format_args!("key [{}]", list(key)));
+            }
+            if let Some(combine) = rel.combine {
+                w.line(format_args!("combine \"{}\"", combine));
+            }
+            if rel.needs_tabling {
+                w.line("tabled");
+            }
+            for fact in &rel.facts {
+                w.line(format_args!("fact ({})", list(&fact.args)));
+            }
+            for rule in &rel.rules {
+                w.rule(rule);
+            }
+            for mode in &rel.modes {
+                w.mode(mode);
+            }
+        });
+    }
+
+    fn rule(&mut self, rule: &IrRule) {
+        self.block(format_args!("rule {}", rule.id), |w| {
+            if let Some(label) = &rule.label {
+                w.line(format_args!("label {:?}", label));
+            }
+            if let Some(name) = &rule.name {
+                w.line(format_args!("name {:?}", name));
+            }
+            if !rule.overrides.is_empty() {
+                let names: Vec<String> =
+                    rule.overrides.iter().map(|n| format!("{:?}", n)).collect();
+                w.line(format_args!("overrides {}", names.join(", ")));
+            }
+            if rule.needs_tabling {
+                w.line("tabled");
+            }
+            w.vars(&rule.vars);
+            w.line(format_args!(
+                "head {}({})",
+                rule.head.relation,
+                list(&rule.head.args)
+            ));
+            for goal in &rule.body {
+                w.line(goal);
+            }
+        });
+    }
+
+    fn mode(&mut self, mode: &IrMode) {
+        let header = format!("mode ({}) {}", list(&mode.pattern), mode.determinism);
+        self.block(header, |w| {
+            for param in &mode.implementation.params {
+                w.line(format_args!(
+                    "param {}: {} {}",
+                    param.var,
+                    TypeText(&param.ty),
+                    param.mode
+                ));
+            }
+            w.instructions(&mode.implementation.body);
+        });
+    }
+
+    fn vars(&mut self, vars: &[IrVar]) {
+        if !vars.is_empty() {
+            self.line(vars_line(vars));
+        }
+    }
+
+    fn query(&mut self, query: &IrQuery) {
+        self.block("query", |w| {
+            w.vars(&query.vars);
+            for goal in &query.goals {
+                w.line(goal);
+            }
+            if let Some(plan) = &query.plan {
+                w.block("plan", |w| w.instructions(plan));
+            }
+        });
+    }
+
+    fn constraint(&mut self, constraint: &IrConstraint) {
+        let header = format!(
+            "constraint {} at {}..{}",
+            constraint.id, constraint.span.start, constraint.span.end
+        );
+        self.block(header, |w| {
+            if let Some(label) = &constraint.label {
+                w.line(format_args!("label {:?}", label));
+            }
+            w.vars(&constraint.vars);
+            for goal in &constraint.goals {
+                w.line(goal);
+            }
+            if let Some(plan) = &constraint.plan {
+                w.block("plan", |w| w.instructions(plan));
+            }
+        });
+    }
+
+    fn fixpoint(&mut self, fixpoint: &IrFixpoint) {
+        let header = format!("fixpoint [{}]", list(&fixpoint.relations));
+        self.block(header, |w| {
+            w.block("init", |w| w.instructions(&fixpoint.init));
+            if !fixpoint.step.is_empty() {
+                w.block("step", |w| w.instructions(&fixpoint.step));
+            }
+        });
+    }
+
+    fn instructions(&mut self, instructions: &[IrInstruction]) {
+        for instruction in instructions {
+            self.instruction(instruction);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &IrInstruction) {
+        match instruction {
+            IrInstruction::Alloc { dest, ty } => {
+                self.line(format_args!("alloc {}: {}", dest, TypeText(ty)))
+            }
+            IrInstruction::Load { dest, src, offset } => {
+                self.line(format_args!("load {} = {}[{}]", dest, src, offset))
+            }
+            IrInstruction::Store { dest, src, offset } => {
+                self.line(format_args!("store {}[{}] = {}", dest, offset, src))
+            }
+            IrInstruction::Unify { left, right } => {
+                self.line(format_args!("unify {}, {}", left, right))
+            }
+            IrInstruction::Call {
+                dest,
+                relation,
+                mode,
+                args,
+            } => {
+                let call = format!("{}({})", callee(relation, *mode), list(args));
+                match dest {
+                    Some(dest) => self.line(format_args!("call {} = {}", dest, call)),
+                    None => self.line(format_args!("call {}", call)),
+                }
+            }
+            IrInstruction::TableLookup { dest, table, key } => {
+                self.line(format_args!("table_lookup {} = {}[{}]", dest, table, key))
+            }
+            IrInstruction::TableInsert { table, key, value } => {
+                self.line(format_args!("table_insert {}[{}] = {}", table, key, value))
+            }
+            IrInstruction::Branch {
+                cond,
+                true_block,
+                false_block,
+            } => self.line(format_args!(
+                "branch {}, {}, {}",
+                cond, true_block, false_block
+            )),
+            IrInstruction::Return { value: Some(value) } => {
+                self.line(format_args!("return {}", value))
+            }
+            IrInstruction::Return { value: None } => self.line("return"),
+            IrInstruction::InsertFacts { relation } => {
+                self.line(format_args!("insert_facts {}", relation))
+            }
+            IrInstruction::Clause { rule, vars, body } => {
+                self.block(format_args!("clause {}", rule), |w| {
+                    w.vars(vars);
+                    w.instructions(body);
+                })
+            }
+            IrInstruction::Scan {
+                relation,
+                version,
+                args,
+                body,
+            } => self.block(
+                format_args!("scan {} {}({})", version, relation, list(args)),
+                |w| w.instructions(body),
+            ),
+            IrInstruction::Join {
+                relation,
+                version,
+                key,
+                args,
+                body,
+            } => self.block(
+                format_args!(
+                    "join {} {}({}) on [{}]",
+                    version,
+                    relation,
+                    list(args),
+                    list(key)
+                ),
+                |w| w.instructions(body),
+            ),
+            IrInstruction::Filter(goal) => self.line(format_args!("filter {}", goal)),
+            IrInstruction::DeltaInsert { relation, args } => {
+                self.line(format_args!("delta_insert {}({})", relation, list(args)))
+            }
+            IrInstruction::Swap { relations } => {
+                self.line(format_args!("swap [{}]", list(relations)))
+            }
+        }
+    }
+}
+
+impl FromStr for IrProgram {
+    type Err = String;
+
+    /// Parse the textual IR, recomputing the dependency graph
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let mut program = IrProgram {
+            types: Vec::new(),
+            relations: Vec::new(),
+            queries: Vec::new(),
+            constraints: Vec::new(),
+            dependencies: DependencyGraph::default(),
+            fixpoints: Vec::new(),
+        };
+        while parser.peek().is_some() {
+            match parser.keyword()?.as_str() {
+                "type" => program.types.push(parser.type_def()?),
+                "relation" => program.relations.push(parser.relation()?),
+                "query" => program.queries.push(parser.query()?),
+                "constraint" => program.constraints.push(parser.constraint()?),
+                "fixpoint" => program.fixpoints.push(parser.fixpoint()?),
+                other => return Err(parser.error(format!("unexpected '{}'", other))),
+            }
+        }
+        program.dependencies = DependencyGraph::from_ir(&program);
+        Ok(program)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Var(usize),
+    Int(i64),
+    Str(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Var(n) => write!(f, "'%{}'", n),
+            Token::Int(n) => write!(f, "'{}'", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Punct(p) => write!(f, "'{}'", p),
+        }
+    }
+}
+
+const PUNCTUATION: &[&str] = &[
+    "::", "<=", ">=", "==", "!=", "(", ")", "{", "}", "[", "]", ",", ":", "=", "<", ">", "+", "-",
+    "*", "/", "%", "×", ".",
+];
+
+/// Split IR text into tokens, each with its line number
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line_no = n + 1;
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            if c == ';' {
+                break;
+            }
+            let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let next = rest[c.len_utf8()..].chars().next();
+            let (token, len) = if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .unwrap_or(rest.len());
+                (Token::Ident(rest[..len].to_string()), len)
+            } else if c.is_ascii_digit() || (c == '-' && next.is_some_and(|d| d.is_ascii_digit())) {
+                let len = 1 + digits(&rest[1..]);
+                let n = rest[..len]
+                    .parse()
+                    .map_err(|_| format!("line {}: bad integer {}", line_no, &rest[..len]))?;
+                (Token::Int(n), len)
+            } else if c == '%' && next.is_some_and(|d| d.is_ascii_digit()) {
+                let len = 1 + digits(&rest[1..]);
+                (Token::Var(rest[1..len].parse().unwrap_or(usize::MAX)), len)
+            } else if c == '"' {
+                let (s, len) = unquote(rest)
+                    .ok_or_else(|| format!("line {}: unterminated string", line_no))?;
+                (Token::Str(s), len)
+            } else if let Some(p) = PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+                (Token::Punct(p), p.len())
+            } else {
+                return Err(format!("line {}: unexpected character '{}'", line_no, c));
+            };
+            tokens.push((token, line_no));
+            rest = &rest[len..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// The string a quoted literal at the start of `text` denotes, and the
+/// literal's length; escapes are those `{:?}` writes
+fn unquote(text: &str) -> Option<(String, usize)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, i + 1)),
+            '\\' => {
+                let (_, escape) = chars.next()?;
+                value.push(match escape {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    'u' => {
+                        let mut hex = String::new();
+                        chars.next().filter(|&(_, c)| c == '{')?;
+                        for (_, c) in chars.by_ref() {
+                            if c == '}' {
+                                break;
+                            }
+                            hex.push(c);
+                        }
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    other => other,
+                });
+            }
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    /// An error on the line of the token at `pos`
+    fn error_at(&self, pos: usize, message: impl fmt::Display) -> String {
+        match self.tokens.get(pos).or(self.tokens.last()) {
+            Some((_, line)) => format!("line {}: {}", line, message),
+            None => message.to_string(),
+        }
+    }
+
+    /// An error in what was just read
+    fn error(&self, message: impl fmt::Display) -> String {
+        self.error_at(self.pos.saturating_sub(1), message)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, String> {
+        match self.tokens.get(self.pos) {
+            Some((token, _)) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => Err(self.error(format!("expected {}, found end of input", expected))),
+        }
+    }
+
+    fn unexpected<T>(&mut self, expected: &str) -> Result<T, String> {
+        let message = match self.peek() {
+            Some(token) => format!("expected {}, found {}", expected, token),
+            None => format!("expected {}, found end of input", expected),
+        };
+        Err(self.error_at(self.pos, message))
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", punct))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => self.unexpected("a name"),
+        }
+    }
+
+    fn keyword(&mut self) -> Result<String, String> {
+        self.ident()
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", keyword))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Str(s)) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => self.unexpected("a string"),
+        }
+    }
+
+    fn index(&mut self) -> Result<usize, String> {
+        match self.peek() {
+            Some(&Token::Int(n)) if n >= 0 => {
+                self.pos += 1;
+                Ok(n as usize)
+            }
+            _ => self.unexpected("a number"),
+        }
+    }
+
+    fn var(&mut self) -> Result<VarId, String> {
+        match self.peek() {
+            Some(&Token::Var(n)) => {
+                self.pos += 1;
+                Ok(VarId(n))
+            }
+            _ => self.unexpected("a variable"),
+        }
+    }
+
+    /// Items separated by commas up to `close`, after the opening bracket
+    fn list<T>(
+        &mut self,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let mut items = Vec::new();
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// A braced block of lines, each starting with a keyword
+    fn block(
+        &mut self,
+        mut line: impl FnMut(&mut Self, &str) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.expect("{")?;
+        while !self.eat("}") {
+            let keyword = self.keyword()?;
+            line(self, &keyword)?;
+        }
+        Ok(())
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        let mut types = vec![self.type_factor()?];
+        while self.eat("×") {
+            types.push(self.type_factor()?);
+        }
+        Ok(if types.len() == 1 {
+            types.remove(0)
+        } else {
+            Type::Product(types)
+        })
+    }
+
+    fn type_factor(&mut self) -> Result<Type, String> {
+        if self.eat("(") {
+            self.expect(")")?;
+            return Ok(Type::Product(Vec::new()));
+        }
+        let name = self.ident()?;
+        match name.as_str() {
+            "List" | "Option" => {
+                self.expect("<")?;
+                let inner = Box::new(self.ty()?);
+                self.expect(">")?;
+                Ok(if name == "List" {
+                    Type::List(inner)
+                } else {
+                    Type::Option(inner)
+                })
+            }
+            _ => Ok(Type::Named(name)),
+        }
+    }
+
+    fn type_def(&mut self) -> Result<IrTypeDef, String> {
+        let name = self.ident()?;
+        self.expect("=")?;
+        let layout = match self.keyword()?.as_str() {
+            "struct" => {
+                let constructor = self.ident()?;
+                self.expect("{")?;
+                let fields = self.list("}", |p| {
+                    let field = p.ident()?;
+                    p.expect(":")?;
+                    Ok((field, p.ty()?))
+                })?;
+                self.expect_keyword("size")?;
+                let size_bytes = self.index()?;
+                self.expect_keyword("align")?;
+                TypeLayout::Struct {
+                    constructor,
+                    fields,
+                    size_bytes,
+                    align_bytes: self.index()?,
+                }
+            }
+            "enum" => {
+                self.expect("{")?;
+                let variants = self.list("}", Self::ident)?;
+                self.expect_keyword("tag")?;
+                let tag_size = self.index()?;
+                self.expect_keyword("size")?;
+                TypeLayout::Enum {
+                    variants,
+                    tag_size,
+                    max_variant_size: self.index()?,
+                }
+            }
+            other => return Err(self.error(format!("unknown layout '{}'", other))),
+        };
+        Ok(IrTypeDef { name, layout })
+    }
+
+    fn relation(&mut self) -> Result<IrRelation, String> {
+        let name = self.ident()?;
+        self.expect(":")?;
+        let mut rel = IrRelation {
+            name,
+            signature: self.ty()?,
+            facts: Vec::new(),
+            rules: Vec::new(),
+            modes: Vec::new(),
+            keys: Vec::new(),
+            combine: None,
+            needs_tabling: false,
+        };
+        self.block(|p, keyword| {
+            match keyword {
+                "key" => {
+                    p.expect("[")?;
+                    rel.keys.push(p.list("]", Self::index)?);
+                }
+                "combine" => {
+                    let name = p.string()?;
+                    let combine = Combine::from_name(&name)
+                        .ok_or_else(|| p.error(format!("unknown combining algorithm {}", name)))?;
+                    rel.combine = Some(combine);
+                }
+                "tabled" => rel.needs_tabling = true,
+                "fact" => {
+                    p.expect("(")?;
+                    let args = p.list(")", Self::value)?;
+                    rel.facts.push(IrFact { args });
+                }
+                "rule" => rel.rules.push(p.rule()?),
+                "mode" => rel.modes.push(p.mode()?),
+                other => return Err(p.error(format!("unexpected '{}' in relation", other))),
+            }
+            Ok(())
+        })?;
+        Ok(rel)
+    }
+
+    fn rule(&mut self) -> Result<IrRule, String> {
+        let mut rule = IrRule {
+            id: self.index()?,
+            label: None,
+            name: None,
+            overrides: Vec::new(),
+            head: IrAtom {
+                relation: String::new(),
+                args: Vec::new(),
+            },
+            body: Vec::new(),
+            vars: Vec::new(),
+            needs_tabling: false,
+        };
+        let mut head = None;
+        self.block(|p, keyword| {
+            match keyword {
+                "label" => rule.label = Some(p.string()?),
+                "name" => rule.name = Some(p.string()?),
+                "overrides" => {
+                    rule.overrides.push(p.string()?);
+                    while p.eat(",") {
+                        rule.overrides.push(p.string()?);
+                    }
+                }
+                "tabled" => rule.needs_tabling = true,
+                "vars" => rule.vars = p.vars()?,
+                "head" => {
+                    let relation = p.ident()?;
+                    p.expect("(")?;
+                    let args = p.list(")", Self::term)?;
+                    head = Some(IrAtom { relation, args });
+                }
+                goal => rule.body.push(p.goal(goal)?),
+            }
+            Ok(())
+        })?;
+        rule.head = head.ok_or_else(|| self.error(format!("rule {} has no head", rule.id)))?;
+        Ok(rule)
+    }
+
+    fn mode(&mut self) -> Result<IrMode, String> {
+        self.expect("(")?;
+        let pattern = self.list(")", Self::mode_annotation)?;
+        let determinism = self.keyword()?;
+        let determinism = determinism.parse().map_err(|e| self.error(e))?;
+        let mut implementation = IrPredicate {
+            params: Vec::new(),
+            body: Vec::new(),
+        };
+        self.block(|p, keyword| {
+            if keyword == "param" {
+                let var = p.var()?;
+                p.expect(":")?;
+                let ty = p.ty()?;
+                let mode = p.mode_annotation()?;
+                implementation.params.push(IrParam { var, ty, mode });
+            } else {
+                implementation.body.push(p.instruction(keyword)?);
+            }
+            Ok(())
+        })?;
+        Ok(IrMode {
+            pattern,
+            determinism,
+            implementation,
+        })
+    }
+
+    fn mode_annotation(&mut self) -> Result<ModeAnnotation, String> {
+        match self.keyword()?.as_str() {
+            "in" => Ok(ModeAnnotation::Input),
+            "out" => Ok(ModeAnnotation::Output),
+            other => Err(self.error(format!("unknown mode '{}'", other))),
+        }
+    }
+
+    fn vars(&mut self) -> Result<Vec<IrVar>, String> {
+        let mut vars = Vec::new();
+        loop {
+            let id = self.var()?;
+            let name = match self.peek() {
+                Some(Token::Str(_)) => self.string()?,
+                _ => self.ident()?,
+            };
+            let ty = if self.eat(":") {
+                Some(self.ty()?)
+            } else {
+                None
+            };
+            vars.push(IrVar { id, name, ty });
+            if !self.eat(",") {
+                return Ok(vars);
+            }
+        }
+    }
+
+    fn query(&mut self) -> Result<IrQuery, String> {
+        let mut query = IrQuery {
+            goals: Vec::new(),
+            vars: Vec::new(),
+            plan: None,
+        };
+        self.block(|p, keyword| {
+            match keyword {
+                "vars" => query.vars = p.vars()?,
+                "plan" => query.plan = Some(p.instructions()?),
+                goal => query.goals.push(p.goal(goal)?),
+            }
+            Ok(())
+        })?;
+        Ok(query)
+    }
+
+    fn constraint(&mut self) -> Result<IrConstraint, String> {
+        let id = self.index()?;
+        self.expect_keyword("at")?;
+        let start = self.index()?;
+        self.expect(".")?;
+        self.expect(".")?;
+        let mut constraint = IrConstraint {
+            id,
+            goals: Vec::new(),
+            vars: Vec::new(),
+            label: None,
+            span: Span {
+                start,
+                end: self.index()?,
+            },
+            plan: None,
+        };
+        self.block(|p, keyword| {
+            match keyword {
+                "label" => constraint.label = Some(p.string()?),
+                "vars" => constraint.vars = p.vars()?,
+                "plan" => constraint.plan = Some(p.instructions()?),
+                goal => constraint.goals.push(p.goal(goal)?),
+            }
+            Ok(())
+        })?;
+        Ok(constraint)
+    }
+
+    fn fixpoint(&mut self) -> Result<IrFixpoint, String> {
+        self.expect("[")?;
+        let mut fixpoint = IrFixpoint {
+            relations: self.list("]", Self::ident)?,
+            init: Vec::new(),
+            step: Vec::new(),
+        };
+        self.block(|p, keyword| {
+            match keyword {
+                "init" => fixpoint.init = p.instructions()?,
+                "step" => fixpoint.step = p.instructions()?,
+                other => return Err(p.error(format!("unexpected '{}' in fixpoint", other))),
+            }
+            Ok(())
+        })?;
+        Ok(fixpoint)
+    }
+
+    fn goal(&mut self, keyword: &str) -> Result<IrGoal, String> {
+        match keyword {
+            "call" => {
+                let (relation, mode_index) = self.callee()?;
+                self.expect("(")?;
+                Ok(IrGoal::Call {
+                    relation,
+                    mode_index,
+                    args: self.list(")", Self::term)?,
+                })
+            }
+            "unify" => {
+                let left = self.term()?;
+                self.expect("=")?;
+                Ok(IrGoal::Unify {
+                    left,
+                    right: self.term()?,
+                })
+            }
+            "compare" => {
+                let left = self.term()?;
+                let op = match self.next("a comparison")? {
+                    Token::Punct("==") => CompareOp::Eq,
+                    Token::Punct("!=") => CompareOp::Ne,
+                    Token::Punct("<") => CompareOp::Lt,
+                    Token::Punct("<=") => CompareOp::Le,
+                    Token::Punct(">") => CompareOp::Gt,
+                    Token::Punct(">=") => CompareOp::Ge,
+                    _ => {
+                        self.pos -= 1;
+                        return self.unexpected("a comparison");
+                    }
+                };
+                let right = self.term()?;
+                let ty = if self.eat(":") {
+                    Some(self.ty()?)
+                } else {
+                    None
+                };
+                Ok(IrGoal::Compare {
+                    op,
+                    left,
+                    right,
+                    ty,
+                })
+            }
+            "match" => Ok(IrGoal::Match {
+                subject: self.term()?,
+                pattern: self.string()?,
+            }),
+            other => Err(self.error(format!("unknown goal '{}'", other))),
+        }
+    }
+
+    fn callee(&mut self) -> Result<(String, usize), String> {
+        let relation = self.ident()?;
+        let mode = if self.eat("/") { self.index()? } else { 0 };
+        Ok((relation, mode))
+    }
+
+    fn term(&mut self) -> Result<IrTerm, String> {
+        let mut term = self.primary()?;
+        while self.eat(".") {
+            term = IrTerm::FieldAccess {
+                base: Box::new(term),
+                field: self.ident()?,
+            };
+        }
+        Ok(term)
+    }
+
+    fn primary(&mut self) -> Result<IrTerm, String> {
+        match self.peek() {
+            Some(Token::Var(_)) => Ok(IrTerm::Var(self.var()?)),
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let left = Box::new(self.term()?);
+                let op = match self.next("an operator")? {
+                    Token::Punct("+") => BinOp::Add,
+                    Token::Punct("-") => BinOp::Sub,
+                    Token::Punct("*") => BinOp::Mul,
+                    Token::Punct("/") => BinOp::Div,
+                    Token::Punct("%") => BinOp::Mod,
+                    _ => {
+                        self.pos -= 1;
+                        return self.unexpected("an operator");
+                    }
+                };
+                let right = Box::new(self.term()?);
+                self.expect(")")?;
+                Ok(IrTerm::BinOp { op, left, right })
+            }
+            Some(Token::Ident(name)) if name == "now" || name == "today" => {
+                let term = if name == "now" {
+                    IrTerm::Now
+                } else {
+                    IrTerm::Today
+                };
+                self.pos += 1;
+                self.expect("(")?;
+                self.expect(")")?;
+                Ok(term)
+            }
+            Some(Token::Ident(_))
+                if self.tokens.get(self.pos + 1).map(|t| &t.0) == Some(&Token::Punct("::")) =>
+            {
+                let type_name = self.ident()?;
+                self.expect("::")?;
+                let name = self.ident()?;
+                if self.eat("(") {
+                    Ok(IrTerm::Construct {
+                        type_name,
+                        constructor: name,
+                        args: self.list(")", Self::term)?,
+                    })
+                } else {
+                    Ok(IrTerm::Value(IrValue::Variant {
+                        type_name,
+                        variant: name,
+                    }))
+                }
+            }
+            _ => Ok(IrTerm::Value(self.value()?)),
+        }
+    }
+
+    fn value(&mut self) -> Result<IrValue, String> {
+        match self.next("a value")? {
+            Token::Int(n) => Ok(IrValue::Int(n)),
+            Token::Str(s) => Ok(IrValue::String(s)),
+            Token::Ident(type_name) => {
+                if self.eat("::") {
+                    return Ok(IrValue::Variant {
+                        type_name,
+                        variant: self.ident()?,
+                    });
+                }
+                self.expect("{")?;
+                Ok(IrValue::Struct {
+                    type_name,
+                    fields: self.list("}", Self::value)?,
+                })
+            }
+            _ => {
+                self.pos -= 1;
+                self.unexpected("a value")
+            }
+        }
+    }
+
+    fn instructions(&mut self) -> Result<Vec<IrInstruction>, String> {
+        let mut instructions = Vec::new();
+        self.block(|p, keyword| {
+            instructions.push(p.instruction(keyword)?);
+            Ok(())
+        })?;
+        Ok(instructions)
+    }
+
+    fn instruction(&mut self, keyword: &str) -> Result<IrInstruction, String> {
+        let instruction = match keyword {
+            "alloc" => {
+                let dest = self.var()?;
+                self.expect(":")?;
+                IrInstruction::Alloc {
+                    dest,
+                    ty: self.ty()?,
+                }
+            }
+            "load" => {
+                let dest = self.var()?;
+                self.expect("=")?;
+                let src = self.var()?;
+                self.expect("[")?;
+                let offset = self.index()?;
+                self.expect("]")?;
+                IrInstruction::Load { dest, src, offset }
+            }
+            "store" => {
+                let dest = self.var()?;
+                self.expect("[")?;
+                let offset = self.index()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                IrInstruction::Store {
+                    dest,
+                    src: self.var()?,
+                    offset,
+                }
+            }
+            "unify" => {
+                let left = self.var()?;
+                self.expect(",")?;
+                IrInstruction::Unify {
+                    left,
+                    right: self.var()?,
+                }
+            }
+            "call" => {
+                let dest = match self.peek() {
+                    Some(Token::Var(_)) => {
+                        let dest = self.var()?;
+                        self.expect("=")?;
+                        Some(dest)
+                    }
+                    _ => None,
+                };
+                let (relation, mode) = self.callee()?;
+                self.expect("(")?;
+                IrInstruction::Call {
+                    dest,
+                    relation,
+                    mode,
+                    args: self.list(")", Self::var)?,
+                }
+            }
+            "table_lookup" => {
+                let dest = self.var()?;
+                self.expect("=")?;
+                let table = self.ident()?;
+                self.expect("[")?;
+                let key = self.var()?;
+                self.expect("]")?;
+                IrInstruction::TableLookup { dest, table, key }
+            }
+            "table_insert" => {
+                let table = self.ident()?;
+                self.expect("[")?;
+                let key = self.var()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                IrInstruction::TableInsert {
+                    table,
+                    key,
+                    value: self.var()?,
+                }
+            }
+            "branch" => {
+                let cond = self.var()?;
+                self.expect(",")?;
+                let true_block = self.block_id()?;
+                self.expect(",")?;
+                IrInstruction::Branch {
+                    cond,
+                    true_block,
+                    false_block: self.block_id()?,
+                }
+            }
+            "return" => IrInstruction::Return {
+                value: match self.peek() {
+                    Some(Token::Var(_)) => Some(self.var()?),
+                    _ => None,
+                },
+            },
+            "insert_facts" => IrInstruction::InsertFacts {
+                relation: self.ident()?,
+            },
+            "clause" => {
+                let rule = self.index()?;
+                let mut vars = Vec::new();
+                let mut body = Vec::new();
+                self.block(|p, keyword| {
+                    if keyword == "vars" {
+                        vars = p.vars()?;
+                    } else {
+                        body.push(p.instruction(keyword)?);
+                    }
+                    Ok(())
+                })?;
+                IrInstruction::Clause { rule, vars, body }
+            }
+            "scan" => {
+                let version = self.version()?;
+                let relation = self.ident()?;
+                self.expect("(")?;
+                let args = self.list(")", Self::term)?;
+                IrInstruction::Scan {
+                    relation,
+                    version,
+                    args,
+                    body: self.instructions()?,
+                }
+            }
+            "join" => {
+                let version = self.version()?;
+                let relation = self.ident()?;
+                self.expect("(")?;
+                let args = self.list(")", Self::term)?;
+                self.expect_keyword("on")?;
+                self.expect("[")?;
+                let key = self.list("]", Self::index)?;
+                IrInstruction::Join {
+                    relation,
+                    version,
+                    key,
+                    args,
+                    body: self.instructions()?,
+                }
+            }
+            "filter" => {
+                let keyword = self.keyword()?;
+                IrInstruction::Filter(self.goal(&keyword)?)
+            }
+            "delta_insert" => {
+                let relation = self.ident()?;
+                self.expect("(")?;
+                IrInstruction::DeltaInsert {
+                    relation,
+                    args: self.list(")", Self::term)?,
+                }
+            }
+            "swap" => {
+                self.expect("[")?;
+                IrInstruction::Swap {
+                    relations: self.list("]", Self::ident)?,
+                }
+            }
+            other => return Err(self.error(format!("unknown instruction '{}'", other))),
+        };
+        Ok(instruction)
+    }
+
+    fn version(&mut self) -> Result<Version, String> {
+        match self.keyword()?.as_str() {
+            "full" => Ok(Version::Full),
+            "delta" => Ok(Version::Delta),
+            other => Err(self.error(format!("unknown version '{}'", other))),
+        }
+    }
+
+    fn block_id(&mut self) -> Result<BlockId, String> {
+        let name = self.ident()?;
+        name.strip_prefix("bb")
+            .and_then(|n| n.parse().ok())
+            .map(BlockId)
+            .ok_or_else(|| self.error(format!("expected a block, found '{}'", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn lowered(source: &str) -> IrProgram {
+        let program = parser::parse(source).expect("Parse failed");
+        let mut ir = lower_to_ir(&program);
+        magic::rewrite(&mut ir);
+        ir
+    }
+
+    #[test]
+    fn test_ir_text_round_trip() {
+        let ir = lowered(
+            r#"
+            type Person = person(name: String, born: Int)
+            type Level = Junior | Senior
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            rel level: Person × Level key(0)
+            rel senior: String
+            parent(1, 2).
+            parent(2, 3).
+            level(person("Ann", 1950), Senior).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            senior(N) :- level(person(N, B), Senior), B + 10 < 1990.
+            @constraint("no-self-parent")
+            :- parent(X, X).
+            ?- ancestor(1, Q).
+            ?- senior(N).
+            ?- Senior < Junior.
+            "#,
+        );
+        let text = ir.to_string();
+        let parsed: IrProgram = text.parse().unwrap_or_else(|e| panic!("{}\n{}", e, text));
+        assert_eq!(parsed.to_string(), text);
+        assert!(text.contains("fixpoint [ancestor.bf]"));
+        assert!(text.contains("fact (person{\"Ann\", 1950}, Level::Senior)"));
+        assert!(text.contains("type Person = struct person { name: String, born: Int }"));
+        assert!(text.contains("constraint 0 at "));
+        assert!(parsed.dependencies.is_recursive("ancestor.bf"));
+    }
+
+    #[test]
+    fn test_ir_text_errors_name_the_line() {
+        let error = "relation r: Int {\n  fact (1)\n  bogus\n}"
+            .parse::<IrProgram>()
+            .unwrap_err();
+        assert_eq!(error, "line 3: unexpected 'bogus' in relation");
+        let error = "query {\n  compare %0 ~ 1\n}"
+            .parse::<IrProgram>()
+            .unwrap_err();
+        assert!(error.starts_with("line 2:"), "{}", error);
+    }
+}
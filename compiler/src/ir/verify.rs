@@ -0,0 +1,594 @@
+/// IR verifier
+///
+/// Code generation trusts its input: every called relation exists and is
+/// called with its arity, every variable is declared by the rule, query,
+/// constraint or clause using it, mode patterns agree with the relation's
+/// signature, and bottom-up loops only read materialized relations. When a
+/// pass breaks one of these, the result is LLVM that clang rejects far from
+/// the cause. This pass checks them and names the relation and rule at
+/// fault. Predicates have no labelled blocks, so a branch target is a
+/// position in its predicate's body.
+use super::*;
+
+/// A broken invariant, and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The relation and rule, mode, query, constraint or fixpoint at fault
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Check the invariants code generation relies on
+pub fn check(program: &IrProgram) -> Vec<Violation> {
+    let mut verifier = Verifier {
+        relations: HashMap::new(),
+        materialized: program
+            .fixpoints
+            .iter()
+            .flat_map(|fixpoint| &fixpoint.relations)
+            .map(String::as_str)
+            .collect(),
+        rules: program
+            .relations
+            .iter()
+            .flat_map(|rel| rel.rules.iter().map(|rule| rule.id))
+            .collect(),
+        location: String::new(),
+        wildcards: HashMap::new(),
+        violations: Vec::new(),
+    };
+
+    for rel in &program.relations {
+        if verifier.relations.insert(&rel.name, rel).is_some() {
+            verifier.location = format!("relation {}", rel.name);
+            verifier.report("declared more than once");
+        }
+    }
+    for rel in &program.relations {
+        verifier.relation(rel);
+    }
+    for (i, query) in program.queries.iter().enumerate() {
+        verifier.location = format!("query {}", i);
+        let scope = declared(&query.vars);
+        verifier.goals(&query.goals, &scope);
+        verifier.wildcards();
+        if let Some(plan) = &query.plan {
+            verifier.instructions(plan, &Context::Plan, &scope);
+            verifier.wildcards();
+        }
+    }
+    for constraint in &program.constraints {
+        verifier.location = match &constraint.label {
+            Some(label) => format!("constraint {}", label),
+            None => format!("constraint {}", constraint.id),
+        };
+        let scope = declared(&constraint.vars);
+        verifier.goals(&constraint.goals, &scope);
+        verifier.wildcards();
+        if let Some(plan) = &constraint.plan {
+            verifier.instructions(plan, &Context::Plan, &scope);
+            verifier.wildcards();
+        }
+    }
+    for (i, fixpoint) in program.fixpoints.iter().enumerate() {
+        verifier.location = format!("fixpoint {} [{}]", i, fixpoint.relations.join(", "));
+        for name in &fixpoint.relations {
+            if !verifier.relations.contains_key(name.as_str()) {
+                verifier.report(format!("unknown relation {}", name));
+            }
+        }
+        let context = Context::Fixpoint(&fixpoint.relations);
+        verifier.instructions(&fixpoint.init, &context, &HashSet::new());
+        verifier.instructions(&fixpoint.step, &context, &HashSet::new());
+    }
+    verifier.violations
+}
+
+fn declared(vars: &[IrVar]) -> HashSet<VarId> {
+    vars.iter().map(|var| var.id).collect()
+}
+
+/// The argument types of a relation's signature
+fn columns(signature: &Type) -> &[Type] {
+    match signature {
+        Type::Product(types) => types,
+        ty => std::slice::from_ref(ty),
+    }
+}
+
+/// Where an instruction list runs, which decides the instructions it may use
+enum Context<'p> {
+    /// The body of a mode, `len` instructions long
+    Predicate { len: usize },
+    /// The evaluation of a component, which derives only its own relations
+    Fixpoint(&'p [String]),
+    /// A query's search of materialized relations
+    Plan,
+}
+
+struct Verifier<'p> {
+    relations: HashMap<&'p str, &'p IrRelation>,
+    materialized: HashSet<&'p str>,
+    /// Numbers of the rules, which clauses refer to
+    rules: HashSet<usize>,
+    location: String,
+    /// Occurrences of undeclared variables in the current clause
+    wildcards: HashMap<VarId, usize>,
+    violations: Vec<Violation>,
+}
+
+impl<'p> Verifier<'p> {
+    fn report(&mut self, message: impl Into<String>) {
+        self.violations.push(Violation {
+            location: self.location.clone(),
+            message: message.into(),
+        });
+    }
+
+    fn relation(&mut self, rel: &'p IrRelation) {
+        let arity = columns(&rel.signature).len();
+        self.location = format!("relation {}", rel.name);
+        for (i, fact) in rel.facts.iter().enumerate() {
+            if fact.args.len() != arity {
+                self.report(format!(
+                    "fact {} has {} arguments, expected {}",
+                    i,
+                    fact.args.len(),
+                    arity
+                ));
+            }
+        }
+        for key in &rel.keys {
+            if key.iter().any(|&k| k >= arity) {
+                self.report(format!("key {:?} is out of range", key));
+            }
+        }
+
+        for rule in &rel.rules {
+            self.location = match &rule.name {
+                Some(name) => format!("relation {}, rule {}", rel.name, name),
+                None => format!("relation {}, rule {}", rel.name, rule.id),
+            };
+            if rule.head.relation != rel.name {
+                self.report(format!("head is for relation {}", rule.head.relation));
+            }
+            if rule.head.args.len() != arity {
+                self.report(format!(
+                    "head has {} arguments, expected {}",
+                    rule.head.args.len(),
+                    arity
+                ));
+            }
+            let scope = declared(&rule.vars);
+            for arg in &rule.head.args {
+                self.term(arg, &scope);
+            }
+            self.goals(&rule.body, &scope);
+            self.wildcards();
+        }
+
+        let mut patterns = HashSet::new();
+        for mode in &rel.modes {
+            let pattern = format!("({})", super::text::list(&mode.pattern));
+            self.location = format!("relation {}, mode {}", rel.name, pattern);
+            if !patterns.insert(mode.pattern.clone()) {
+                self.report("declared more than once");
+            }
+            if mode.pattern.len() != arity {
+                self.report(format!(
+                    "pattern has {} positions, expected {}",
+                    mode.pattern.len(),
+                    arity
+                ));
+            }
+            let params = &mode.implementation.params;
+            if params.len() != mode.pattern.len() {
+                self.report(format!(
+                    "{} parameters for a pattern of {}",
+                    params.len(),
+                    mode.pattern.len()
+                ));
+            }
+            for (i, param) in params.iter().enumerate() {
+                if mode.pattern.get(i).is_some_and(|&m| m != param.mode) {
+                    self.report(format!(
+                        "parameter {} is {}, not {}",
+                        i, param.mode, mode.pattern[i]
+                    ));
+                }
+                if let Some(ty) = columns(&rel.signature).get(i) {
+                    if *ty != param.ty {
+                        self.report(format!("parameter {} is {}, not {}", i, param.ty, ty));
+                    }
+                }
+            }
+            let body = &mode.implementation.body;
+            let mut scope: HashSet<VarId> = params.iter().map(|param| param.var).collect();
+            scope.extend(body.iter().filter_map(defined));
+            self.instructions(body, &Context::Predicate { len: body.len() }, &scope);
+        }
+    }
+
+    fn goals(&mut self, goals: &[IrGoal], scope: &HashSet<VarId>) {
+        for goal in goals {
+            self.goal(goal, scope);
+        }
+    }
+
+    fn goal(&mut self, goal: &IrGoal, scope: &HashSet<VarId>) {
+        match goal {
+            IrGoal::Call {
+                relation,
+                mode_index,
+                args,
+            } => {
+                self.call(relation, args.len());
+                if let Some(rel) = self.relations.get(relation.as_str()) {
+                    if *mode_index > 0 && *mode_index >= rel.modes.len() {
+                        self.report(format!("{} has no mode {}", relation, mode_index));
+                    }
+                }
+                for arg in args {
+                    self.term(arg, scope);
+                }
+            }
+            IrGoal::Unify { left, right } | IrGoal::Compare { left, right, .. } => {
+                self.term(left, scope);
+                self.term(right, scope);
+            }
+            IrGoal::Match { subject, .. } => self.term(subject, scope),
+        }
+    }
+
+    /// Check that a relation exists and takes `args` arguments
+    fn call(&mut self, relation: &str, args: usize) {
+        let Some(rel) = self.relations.get(relation) else {
+            self.report(format!("unknown relation {}", relation));
+            return;
+        };
+        let arity = columns(&rel.signature).len();
+        if args != arity {
+            self.report(format!(
+                "{} takes {} arguments, given {}",
+                relation, arity, args
+            ));
+        }
+    }
+
+    fn term(&mut self, term: &IrTerm, scope: &HashSet<VarId>) {
+        match term {
+            IrTerm::Var(var) if !scope.contains(var) => {
+                *self.wildcards.entry(*var).or_default() += 1;
+            }
+            IrTerm::Var(_) => {}
+            IrTerm::Construct { args, .. } => {
+                for arg in args {
+                    self.term(arg, scope);
+                }
+            }
+            IrTerm::FieldAccess { base, .. } => self.term(base, scope),
+            IrTerm::BinOp { left, right, .. } => {
+                self.term(left, scope);
+                self.term(right, scope);
+            }
+            IrTerm::Value(_) | IrTerm::Now | IrTerm::Today => {}
+        }
+    }
+
+    /// Report undeclared variables of the clause just checked that occur
+    /// more than once; each `_` is a fresh variable used once
+    fn wildcards(&mut self) {
+        let mut repeated: Vec<VarId> = std::mem::take(&mut self.wildcards)
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(var, _)| var)
+            .collect();
+        repeated.sort_by_key(|var| var.0);
+        for var in repeated {
+            self.report(format!("{} is not declared", var));
+        }
+    }
+
+    fn var(&mut self, var: VarId, scope: &HashSet<VarId>) {
+        if !scope.contains(&var) {
+            self.report(format!("{} is not declared", var));
+        }
+    }
+
+    fn instructions(
+        &mut self,
+        instructions: &[IrInstruction],
+        context: &Context,
+        scope: &HashSet<VarId>,
+    ) {
+        for instruction in instructions {
+            self.instruction(instruction, context, scope);
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        instruction: &IrInstruction,
+        context: &Context,
+        scope: &HashSet<VarId>,
+    ) {
+        let predicate = matches!(context, Context::Predicate { .. });
+        match instruction {
+            IrInstruction::Alloc { .. }
+            | IrInstruction::Load { .. }
+            | IrInstruction::Store { .. }
+            | IrInstruction::Unify { .. }
+            | IrInstruction::Call { .. }
+            | IrInstruction::TableLookup { .. }
+            | IrInstruction::TableInsert { .. }
+            | IrInstruction::Branch { .. }
+                if !predicate =>
+            {
+                self.report(format!(
+                    "`{}` outside a mode",
+                    instruction.to_string().trim()
+                ));
+            }
+            IrInstruction::InsertFacts { .. }
+            | IrInstruction::Clause { .. }
+            | IrInstruction::Scan { .. }
+            | IrInstruction::Join { .. }
+            | IrInstruction::Filter(_)
+            | IrInstruction::DeltaInsert { .. }
+            | IrInstruction::Swap { .. }
+                if predicate =>
+            {
+                let text = instruction.to_string();
+                let first = text
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim_end_matches(" {");
+                self.report(format!("`{}` in a mode", first));
+            }
+
+            IrInstruction::Store { dest, src, .. } => {
+                self.var(*dest, scope);
+                self.var(*src, scope);
+            }
+            IrInstruction::Load { src, .. } => self.var(*src, scope),
+            IrInstruction::Unify { left, right } => {
+                self.var(*left, scope);
+                self.var(*right, scope);
+            }
+            IrInstruction::Call {
+                relation,
+                mode,
+                args,
+                ..
+            } => {
+                match self.relations.get(relation.as_str()) {
+                    Some(rel) if *mode >= rel.modes.len() => {
+                        self.report(format!("{} has no mode {}", relation, mode))
+                    }
+                    Some(_) => {}
+                    None => self.report(format!("unknown relation {}", relation)),
+                }
+                for arg in args {
+                    self.var(*arg, scope);
+                }
+            }
+            IrInstruction::TableLookup { key, .. } => self.var(*key, scope),
+            IrInstruction::TableInsert { key, value, .. } => {
+                self.var(*key, scope);
+                self.var(*value, scope);
+            }
+            IrInstruction::Branch {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                self.var(*cond, scope);
+                if let Context::Predicate { len } = context {
+                    for block in [true_block, false_block] {
+                        if block.0 >= *len {
+                            self.report(format!("branch to {}, past the end of the body", block));
+                        }
+                    }
+                }
+            }
+            IrInstruction::Alloc { .. } => {}
+
+            IrInstruction::Return { value } => match context {
+                Context::Fixpoint(_) => self.report("`return` in a fixpoint"),
+                Context::Plan if value.is_some() => self.report("a plan returns no value"),
+                Context::Plan => {}
+                Context::Predicate { .. } => {
+                    if let Some(value) = value {
+                        self.var(*value, scope);
+                    }
+                }
+            },
+            IrInstruction::InsertFacts { relation } => self.derives(relation, context),
+            IrInstruction::DeltaInsert { relation, args } => {
+                self.call(relation, args.len());
+                for arg in args {
+                    self.term(arg, scope);
+                }
+                self.derives(relation, context);
+            }
+            IrInstruction::Swap { relations } => {
+                for relation in relations {
+                    self.derives(relation, context);
+                }
+            }
+            IrInstruction::Clause { rule, vars, body } => {
+                if !self.rules.contains(rule) {
+                    self.report(format!("clause for unknown rule {}", rule));
+                }
+                self.instructions(body, context, &declared(vars));
+                self.wildcards();
+            }
+            IrInstruction::Scan {
+                relation,
+                args,
+                body,
+                ..
+            }
+            | IrInstruction::Join {
+                relation,
+                args,
+                body,
+                ..
+            } => {
+                self.call(relation, args.len());
+                if !self.materialized.contains(relation.as_str()) {
+                    self.report(format!(
+                        "{} is read bottom-up but not materialized",
+                        relation
+                    ));
+                }
+                if let IrInstruction::Join { key, .. } = instruction {
+                    if key.is_empty() || key.iter().any(|&k| k >= args.len()) {
+                        self.report(format!("join on {} has a bad key {:?}", relation, key));
+                    }
+                }
+                for arg in args {
+                    self.term(arg, scope);
+                }
+                self.instructions(body, context, scope);
+            }
+            IrInstruction::Filter(goal) => self.goal(goal, scope),
+        }
+    }
+
+    /// Check that an instruction deriving `relation` is in its fixpoint
+    fn derives(&mut self, relation: &str, context: &Context) {
+        match context {
+            Context::Fixpoint(relations) if relations.iter().any(|r| r == relation) => {}
+            Context::Fixpoint(_) => {
+                self.report(format!("{} is derived outside its fixpoint", relation))
+            }
+            _ => self.report(format!("{} is derived outside a fixpoint", relation)),
+        }
+    }
+}
+
+/// The variable an instruction of a predicate defines
+fn defined(instruction: &IrInstruction) -> Option<VarId> {
+    match instruction {
+        IrInstruction::Alloc { dest, .. }
+        | IrInstruction::Load { dest, .. }
+        | IrInstruction::TableLookup { dest, .. } => Some(*dest),
+        IrInstruction::Call { dest, .. } => *dest,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn messages(ir: &IrProgram) -> Vec<String> {
+        check(ir).iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_verify_accepts_lowered_programs() {
+        let program = parser::parse(
+            r#"
+            type Level = Junior | Senior
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            rel level: Int × Level key(0)
+            parent(1, 2).
+            level(1, Senior).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            ?- ancestor(1, Q), level(Q, Junior).
+            "#,
+        )
+        .expect("Parse failed");
+        let mut ir = lower_to_ir(&program);
+        magic::rewrite(&mut ir);
+        assert_eq!(messages(&ir), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_verify_reports_rules_by_name() {
+        let ir: IrProgram = r#"
+            relation edge: Int × Int {
+              fact (1, 2, 3)
+              rule 0 {
+                name "shortcut"
+                vars %0 X: Int
+                head edge(%0, %1)
+                call path(%1)
+                call edge(%0)
+              }
+              mode (in) semidet {
+                param %5: Int out
+              }
+            }
+        "#
+        .parse()
+        .expect("IR should parse");
+        assert_eq!(
+            messages(&ir),
+            vec![
+                "relation edge: fact 0 has 3 arguments, expected 2",
+                "relation edge, rule shortcut: unknown relation path",
+                "relation edge, rule shortcut: edge takes 2 arguments, given 1",
+                "relation edge, rule shortcut: %1 is not declared",
+                "relation edge, mode (in): pattern has 1 positions, expected 2",
+                "relation edge, mode (in): parameter 0 is out, not in",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_checks_instructions() {
+        let ir: IrProgram = r#"
+            relation r: Int {
+              mode (in) semidet {
+                param %0: Int in
+                branch %0, bb0, bb2
+              }
+            }
+
+            relation s: Int {
+            }
+
+            query {
+              plan {
+                scan full s(%3, %4) {
+                  filter compare %3 > 0
+                  return
+                }
+              }
+            }
+
+            fixpoint [r] {
+              init {
+                delta_insert s(1)
+                return
+              }
+            }
+        "#
+        .parse()
+        .expect("IR should parse");
+        assert_eq!(
+            messages(&ir),
+            vec![
+                "relation r, mode (in): branch to bb2, past the end of the body",
+                "query 0: s takes 1 arguments, given 2",
+                "query 0: s is read bottom-up but not materialized",
+                "query 0: %3 is not declared",
+                "fixpoint 0 [r]: s is derived outside its fixpoint",
+                "fixpoint 0 [r]: `return` in a fixpoint",
+            ]
+        );
+    }
+}
@@ -8,21 +8,78 @@
 
 pub mod ast;
 pub mod codegen;
+pub mod deps;
+pub mod explain;
+pub mod fold;
 pub mod ir;
+pub mod modes;
 pub mod parser;
+pub mod tables;
+pub mod termination;
 pub mod types;
 
 pub use ast::Program;
 pub use codegen::CodeGen;
+pub use explain::Explainer;
 pub use parser::parse;
-pub use types::TypeChecker;
+pub use types::{TypeChecker, TypeError, TypeErrors, TypeWarning};
 
 use std::fs;
 use std::path::Path;
 use std::process::Command;
 
+/// What `compile_file` produces and which IR passes it runs
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Write LLVM IR instead of an executable
+    pub emit_llvm: bool,
+    /// Write the textual IR instead of an executable
+    pub emit_ir: bool,
+    /// Write the dependency graph, in Graphviz format, instead of an executable
+    pub emit_deps: bool,
+    /// Specialize recursive relations to the constants of queries
+    pub magic_sets: bool,
+    /// Check the IR's invariants before code generation, as debug builds do
+    pub verify_ir: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            emit_llvm: false,
+            emit_ir: false,
+            emit_deps: false,
+            magic_sets: true,
+            verify_ir: false,
+        }
+    }
+}
+
+/// Lower a checked program to IR and run the passes the options enable
+///
+/// The result is verified in debug builds and when `verify_ir` is set; a
+/// violation is a compiler bug, reported by relation and rule.
+pub fn lower_program(program: &Program, options: &CompileOptions) -> Result<ir::IrProgram, String> {
+    let mut ir = ir::lower_to_ir(program);
+    if options.magic_sets {
+        ir::magic::rewrite(&mut ir);
+    }
+    if options.verify_ir || cfg!(debug_assertions) {
+        let violations = ir::verify::check(&ir);
+        if !violations.is_empty() {
+            let report: Vec<_> = violations.iter().map(|v| format!("\n  {}", v)).collect();
+            return Err(format!("IR verification failed:{}", report.concat()));
+        }
+    }
+    Ok(ir)
+}
+
 /// Compile a .qed source file to an executable
-pub fn compile_file(source_path: &Path, output_path: &Path, emit_llvm: bool) -> Result<(), String> {
+pub fn compile_file(
+    source_path: &Path,
+    output_path: &Path,
+    options: &CompileOptions,
+) -> Result<(), String> {
     // Read source file
     let source = fs::read_to_string(source_path)
         .map_err(|e| format!("Failed to read source file: {}", e))?;
@@ -34,14 +91,43 @@ pub fn compile_file(source_path: &Path, output_path: &Path, emit_llvm: bool) ->
     let mut type_checker = TypeChecker::new();
     type_checker
         .check_program(&program)
-        .map_err(|e| format!("Type error: {}", e))?;
+        .map_err(|e| format!("Type error: {}", e.describe(&source)))?;
+    let print_warning = |warning: &TypeWarning| {
+        let (line, col) = warning.span().line_col(&source);
+        eprintln!(
+            "warning[{}] at {}:{}: {}",
+            warning.code(),
+            line,
+            col,
+            warning
+        );
+    };
+    type_checker.warnings().iter().for_each(print_warning);
+
+    // Reject facts that violate integrity constraints
+    check_constraints(&source, &program)?
+        .iter()
+        .for_each(print_warning);
+
+    if options.emit_deps {
+        fs::write(output_path, deps::DependencyGraph::build(&program).to_dot())
+            .map_err(|e| format!("Failed to write dependency graph: {}", e))?;
+        return Ok(());
+    }
+
+    let lowered = lower_program(&program, options)?;
+    if options.emit_ir {
+        fs::write(output_path, lowered.to_string())
+            .map_err(|e| format!("Failed to write IR file: {}", e))?;
+        return Ok(());
+    }
 
     // Generate LLVM IR
-    let mut codegen = CodeGen::new();
-    let ir = codegen.codegen_program(&program)?;
+    let mut codegen = CodeGen::new().with_source(&source);
+    let ir = codegen.codegen_ir(&lowered)?;
 
     // If emit_llvm is true, just write the IR and stop
-    if emit_llvm {
+    if options.emit_llvm {
         fs::write(output_path, ir).map_err(|e| format!("Failed to write IR file: {}", e))?;
         return Ok(());
     }
@@ -89,8 +175,159 @@ pub fn compile_to_ir(source: &str) -> Result<String, String> {
     let mut type_checker = TypeChecker::new();
     type_checker
         .check_program(&program)
-        .map_err(|e| format!("Type error: {}", e))?;
+        .map_err(|e| format!("Type error: {}", e.describe(source)))?;
+
+    check_constraints(source, &program)?;
+
+    let mut codegen = CodeGen::new().with_source(source);
+    codegen.codegen_ir(&lower_program(&program, &CompileOptions::default())?)
+}
+
+/// Errors and warnings found by `check_source`
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    pub errors: Vec<TypeError>,
+    pub warnings: Vec<TypeWarning>,
+    /// Determinism of each relation mode, inferred when there are no errors
+    pub modes: modes::Modes,
+}
+
+/// Parse and type-check a source program, returning every error and warning
+///
+/// Diagnostics are data with codes, spans and notes, for editors and other
+/// tools; a parse error is returned as a message. A program that type
+/// checks is also lowered, to report the calls compiled code cannot make,
+/// and its facts are checked against its integrity constraints.
+pub fn check_source(source: &str) -> Result<Diagnostics, String> {
+    let program = parse(source).map_err(|e| format!("Parse error: {}", e))?;
+    let mut type_checker = TypeChecker::new();
+    let typed = type_checker.check_program(&program);
+    let mut diagnostics = Diagnostics {
+        warnings: type_checker.warnings().to_vec(),
+        ..Diagnostics::default()
+    };
+    match typed {
+        Ok(()) => {
+            diagnostics.errors = check_calls(&program);
+            let (violations, truncated) = Explainer::new(&program).check_constraints();
+            diagnostics
+                .errors
+                .extend(violations.iter().map(explain::Violation::to_error));
+            diagnostics
+                .warnings
+                .extend(unchecked_constraints(&truncated));
+            diagnostics.modes = modes::infer(&program);
+        }
+        Err(TypeErrors(errors)) => diagnostics.errors = errors,
+    }
+    Ok(diagnostics)
+}
+
+/// Calls compiled code cannot make, as spanned errors
+///
+/// A relation with `@combine`, or one that cannot be materialized for
+/// another reason, only answers calls whose arguments are bound. The
+/// explainer searches such relations anyway, so without this `check` would
+/// accept programs that `compile` rejects.
+fn check_calls(program: &Program) -> Vec<TypeError> {
+    let Ok(lowered) = lower_program(program, &CompileOptions::default()) else {
+        return Vec::new();
+    };
+    lowered
+        .unbound_calls()
+        .into_iter()
+        .filter_map(|call| {
+            let (goals, span) = clause_source(program, &call.clause)?;
+            // Point at the call itself when it is written in the source
+            let span = goals
+                .iter()
+                .find_map(|goal| match goal {
+                    ast::Goal::Atom(atom) if atom.relation == call.relation => Some(&atom.span),
+                    _ => None,
+                })
+                .unwrap_or(span);
+            Some(TypeError::UnboundCall {
+                relation: call.relation.clone(),
+                clause: call.clause.to_string(),
+                span: span.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Body and span of a lowered rule, query or constraint in the source
+fn clause_source<'p>(
+    program: &'p Program,
+    clause: &ir::Clause,
+) -> Option<(&'p [ast::Goal], &'p ast::Span)> {
+    let items = program.items.iter();
+    match clause {
+        ir::Clause::Rule { id, .. } => items
+            .filter_map(|item| match item {
+                ast::Item::Rule(rule) => Some((rule.body.as_slice(), &rule.span)),
+                _ => None,
+            })
+            .nth(*id),
+        ir::Clause::Query(i) => items
+            .filter_map(|item| match item {
+                ast::Item::Query(query) => Some((query.goals.as_slice(), &query.span)),
+                _ => None,
+            })
+            .nth(*i),
+        ir::Clause::Constraint(id) => items
+            .filter_map(|item| match item {
+                ast::Item::Constraint(c) => Some((c.body.as_slice(), &c.span)),
+                _ => None,
+            })
+            .nth(*id),
+    }
+}
+
+/// Check the program's facts against its integrity constraints
+///
+/// Every violation is reported with its bindings and the facts behind it.
+/// Constraints whose search was cut off are returned as warnings.
+pub fn check_constraints(source: &str, program: &Program) -> Result<Vec<TypeWarning>, String> {
+    let (violations, truncated) = Explainer::new(program).check_constraints();
+    if violations.is_empty() {
+        return Ok(unchecked_constraints(&truncated));
+    }
+    let reports: Vec<_> = violations.iter().map(|v| v.render(source)).collect();
+    Err(format!("Constraint error: {}", reports.join("")))
+}
+
+/// Warnings for constraints whose search was cut off
+fn unchecked_constraints(truncated: &[(&ast::Constraint, explain::Limit)]) -> Vec<TypeWarning> {
+    truncated
+        .iter()
+        .map(|(constraint, limit)| TypeWarning::UncheckedConstraint {
+            limit: limit.to_string(),
+            span: constraint.span.clone(),
+        })
+        .collect()
+}
+
+/// Answer a query against a source program and render the proof trees
+///
+/// `now` is the instant `now()` evaluates to, in seconds since the epoch.
+pub fn explain_query(source: &str, query: &str, now: i64) -> Result<String, String> {
+    let program = parse(source).map_err(|e| format!("Parse error: {}", e))?;
+
+    let mut type_checker = TypeChecker::new();
+    type_checker
+        .check_program(&program)
+        .map_err(|e| format!("Type error: {}", e.describe(source)))?;
+
+    let parsed =
+        parser::parse_query_for(&program, query).map_err(|e| format!("Parse error: {}", e))?;
+    // The query's spans point into its own text, not the program
+    type_checker.check_query(&parsed).map_err(|e| {
+        let text = parser::query_text(query);
+        format!("Type error in query {}: {}", text, e.describe(&text))
+    })?;
 
-    let mut codegen = CodeGen::new();
-    codegen.codegen_program(&program)
+    Ok(Explainer::new(&program)
+        .with_now(now)
+        .explain(&parsed)
+        .to_string())
 }
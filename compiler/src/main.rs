@@ -25,6 +25,22 @@ enum Commands {
         #[arg(long)]
         emit_llvm: bool,
 
+        /// Emit the textual IR of the middle end instead of binary
+        #[arg(long, conflicts_with = "emit_llvm")]
+        emit_ir: bool,
+
+        /// Emit the relation dependency graph in Graphviz format instead of binary
+        #[arg(long, conflicts_with_all = ["emit_llvm", "emit_ir"])]
+        emit_deps: bool,
+
+        /// Evaluate recursive relations in full, without specializing them to queries
+        #[arg(long)]
+        no_magic: bool,
+
+        /// Check the IR's invariants before code generation (always on in debug builds)
+        #[arg(long)]
+        verify_ir: bool,
+
         /// Optimization level (0-3)
         #[arg(short = 'O', default_value = "2")]
         opt_level: u8,
@@ -34,6 +50,14 @@ enum Commands {
     Check {
         /// Input .qed file
         input: PathBuf,
+
+        /// Fail if there are any warnings
+        #[arg(long)]
+        deny_warnings: bool,
+
+        /// Show the inferred determinism of every relation mode
+        #[arg(long)]
+        verbose: bool,
     },
 
     /// Show proof tree for a query
@@ -43,6 +67,10 @@ enum Commands {
 
         /// Query to explain
         query: String,
+
+        /// Instant for now() (ISO date or timestamp); defaults to QED_NOW, then the clock
+        #[arg(long)]
+        now: Option<String>,
     },
 
     /// Start interactive REPL
@@ -60,18 +88,33 @@ fn main() -> Result<()> {
             input,
             output,
             emit_llvm,
+            emit_ir,
+            emit_deps,
+            no_magic,
+            verify_ir,
             opt_level,
         } => {
             println!("Compiling {:?}...", input);
-            compile_program(&input, output.as_ref(), emit_llvm, opt_level)?;
+            let options = qedc::CompileOptions {
+                emit_llvm,
+                emit_ir,
+                emit_deps,
+                magic_sets: !no_magic,
+                verify_ir,
+            };
+            compile_program(&input, output.as_ref(), &options, opt_level)?;
         }
-        Commands::Check { input } => {
+        Commands::Check {
+            input,
+            deny_warnings,
+            verbose,
+        } => {
             println!("Checking {:?}...", input);
-            check_program(&input)?;
+            check_program(&input, deny_warnings, verbose)?;
         }
-        Commands::Explain { input, query } => {
+        Commands::Explain { input, query, now } => {
             println!("Explaining query '{}' in {:?}...", query, input);
-            explain_query(&input, &query)?;
+            explain_query(&input, &query, now)?;
         }
         Commands::Repl { input } => {
             println!("Starting REPL...");
@@ -85,29 +128,70 @@ fn main() -> Result<()> {
 fn compile_program(
     input: &PathBuf,
     output: Option<&PathBuf>,
-    emit_llvm: bool,
+    options: &qedc::CompileOptions,
     _opt_level: u8,
 ) -> Result<()> {
     let output_path = output.cloned().unwrap_or_else(|| input.with_extension(""));
 
-    qedc::compile_file(input, &output_path, emit_llvm).map_err(|e| anyhow::anyhow!(e))?;
+    qedc::compile_file(input, &output_path, options).map_err(|e| anyhow::anyhow!(e))?;
 
     println!("Successfully compiled {:?} -> {:?}", input, output_path);
     Ok(())
 }
 
-fn check_program(input: &PathBuf) -> Result<()> {
-    // TODO: Implement type checking
-    println!("Type checking not yet implemented");
-    println!("  Input: {:?}", input);
+fn check_program(input: &PathBuf, deny_warnings: bool, verbose: bool) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let source = std::fs::read_to_string(input)?;
+    let diagnostics = qedc::check_source(&source).map_err(|e| anyhow::anyhow!(e))?;
+
+    let path = input.display().to_string();
+    let color = std::io::stderr().is_terminal();
+    for warning in &diagnostics.warnings {
+        eprint!("{}", warning.render(&path, &source, color));
+    }
+    for error in &diagnostics.errors {
+        eprint!("{}", error.render(&path, &source, color));
+    }
+
+    let (errors, warnings) = (diagnostics.errors.len(), diagnostics.warnings.len());
+    if errors > 0 {
+        anyhow::bail!("{} type error(s) in {}", errors, path);
+    }
+    if deny_warnings && warnings > 0 {
+        anyhow::bail!(
+            "{} warning(s) in {} (denied by --deny-warnings)",
+            warnings,
+            path
+        );
+    }
+    if verbose {
+        println!("Modes:");
+        for (relation, pattern, determinism) in diagnostics.modes.iter() {
+            println!(
+                "  {}: {}",
+                qedc::modes::describe(relation, pattern),
+                determinism
+            );
+        }
+    }
+    println!("No errors found ({} warning(s))", warnings);
     Ok(())
 }
 
-fn explain_query(input: &PathBuf, query: &str) -> Result<()> {
-    // TODO: Implement query explanation
-    println!("Query explanation not yet implemented");
-    println!("  Input: {:?}", input);
-    println!("  Query: {}", query);
+fn explain_query(input: &PathBuf, query: &str, now: Option<String>) -> Result<()> {
+    use qed_runtime::temporal;
+
+    // The clock is read once here so every rule sees the same instant
+    let now = match now.or_else(|| std::env::var("QED_NOW").ok()) {
+        Some(text) => temporal::parse_instant(&text)
+            .ok_or_else(|| anyhow::anyhow!("Invalid --now value: {}", text))?,
+        None => temporal::wall_clock(),
+    };
+
+    let source = std::fs::read_to_string(input)?;
+    let explanation = qedc::explain_query(&source, query, now).map_err(|e| anyhow::anyhow!(e))?;
+    print!("{}", explanation);
     Ok(())
 }
 
@@ -0,0 +1,602 @@
+/// Determinism inference per relation mode
+///
+/// A mode says which arguments are bound when a relation is called. For
+/// every mode that is declared or reached from a query, constraint or rule
+/// body, the analysis infers whether a call can fail and whether it can
+/// have more than one answer. Facts, keys and clauses whose heads have
+/// different constants at bound positions limit the answers; goals compose
+/// left to right. Recursive relations are solved by starting every mode at
+/// `semidet`: a mode stops failing only once a clause that cannot fail is
+/// found, and gains answers as soon as a clause can give several, until
+/// nothing changes.
+use crate::ast::*;
+use crate::ir::{Determinism, ModeAnnotation};
+use crate::types::TypeError;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Which arguments are bound by the caller
+pub type Pattern = Vec<ModeAnnotation>;
+
+/// Inferred determinism of every relation mode that is declared or used
+#[derive(Debug, Clone, Default)]
+pub struct Modes {
+    relations: BTreeMap<String, BTreeMap<Pattern, Determinism>>,
+}
+
+impl Modes {
+    pub fn get(&self, relation: &str, pattern: &[ModeAnnotation]) -> Option<Determinism> {
+        self.relations.get(relation)?.get(pattern).copied()
+    }
+
+    /// Every mode, ordered by relation name and then pattern
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[ModeAnnotation], Determinism)> {
+        self.relations.iter().flat_map(|(name, modes)| {
+            modes
+                .iter()
+                .map(move |(pattern, &det)| (name.as_str(), pattern.as_slice(), det))
+        })
+    }
+}
+
+/// A call pattern as written in `@mode`: ancestor(in, out)
+pub fn describe(relation: &str, pattern: &[ModeAnnotation]) -> String {
+    let modes: Vec<_> = pattern.iter().map(|m| m.to_string()).collect();
+    format!("{}({})", relation, modes.join(", "))
+}
+
+/// A determinism promised by the author with `@mode(in, out, det)`
+#[derive(Debug, Clone)]
+pub struct Declared {
+    pub pattern: Pattern,
+    pub determinism: Determinism,
+    pub span: Span,
+}
+
+/// The `@mode` declarations of a relation
+pub fn declarations(rel: &RelationDecl) -> Result<Vec<Declared>, TypeError> {
+    let arity = arity(&rel.signature);
+    let mut declared = Vec::new();
+    for attribute in rel.attributes.iter().filter(|a| a.name == "mode") {
+        let invalid = |reason: String| TypeError::InvalidMode {
+            relation: rel.name.clone(),
+            reason,
+            span: attribute.span.clone(),
+        };
+        let Some((last, modes)) = attribute.args.split_last() else {
+            return Err(invalid(
+                "expected argument modes followed by a determinism".to_string(),
+            ));
+        };
+        if modes.len() != arity {
+            return Err(invalid(format!(
+                "{} argument mode(s) given, expected {}",
+                modes.len(),
+                arity
+            )));
+        }
+        let pattern = modes
+            .iter()
+            .map(|mode| match mode.as_str() {
+                "in" => Ok(ModeAnnotation::Input),
+                "out" => Ok(ModeAnnotation::Output),
+                other => Err(invalid(format!("unknown argument mode {}", other))),
+            })
+            .collect::<Result<_, _>>()?;
+        let determinism = last
+            .parse()
+            .map_err(|_| invalid(format!("unknown determinism {}", last)))?;
+        declared.push(Declared {
+            pattern,
+            determinism,
+            span: attribute.span.clone(),
+        });
+    }
+    Ok(declared)
+}
+
+/// Declared modes whose inferred determinism breaks the declaration
+pub fn check_declarations(program: &Program, modes: &Modes) -> Vec<TypeError> {
+    let mut errors = Vec::new();
+    for item in &program.items {
+        let Item::RelationDecl(rel) = item else {
+            continue;
+        };
+        for declared in declarations(rel).unwrap_or_default() {
+            let Some(inferred) = modes.get(&rel.name, &declared.pattern) else {
+                continue;
+            };
+            if !inferred.within(declared.determinism) {
+                errors.push(TypeError::ModeMismatch {
+                    mode: describe(&rel.name, &declared.pattern),
+                    declared: declared.determinism,
+                    inferred,
+                    span: declared.span,
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Infer the determinism of every declared, queried or called mode
+pub fn infer(program: &Program) -> Modes {
+    let mut analysis = Analysis::new(program);
+    for item in &program.items {
+        if let Item::RelationDecl(rel) = item {
+            let arity = arity(&rel.signature);
+            analysis.lookup(&rel.name, vec![ModeAnnotation::Input; arity]);
+            analysis.lookup(&rel.name, vec![ModeAnnotation::Output; arity]);
+            for declared in declarations(rel).unwrap_or_default() {
+                analysis.lookup(&rel.name, declared.pattern);
+            }
+        }
+    }
+
+    loop {
+        analysis.changed = false;
+        for item in &program.items {
+            match item {
+                Item::Query(query) => {
+                    analysis.clause(None, &query.goals);
+                }
+                Item::Constraint(constraint) => {
+                    analysis.clause(None, &constraint.body);
+                }
+                _ => {}
+            }
+        }
+        let requested: Vec<(String, Pattern)> = analysis
+            .table
+            .iter()
+            .flat_map(|(name, modes)| modes.keys().map(move |p| (name.clone(), p.clone())))
+            .collect();
+        for (name, pattern) in requested {
+            let det = analysis.relation(&name, &pattern);
+            let old = analysis.table[&name][&pattern];
+            let new = Determinism::new(
+                old.can_fail() && det.can_fail(),
+                old.can_multi() || det.can_multi(),
+            );
+            if new != old {
+                analysis.table.get_mut(&name).unwrap().insert(pattern, new);
+                analysis.changed = true;
+            }
+        }
+        if !analysis.changed {
+            break;
+        }
+    }
+
+    Modes {
+        relations: analysis.table,
+    }
+}
+
+fn arity(signature: &Type) -> usize {
+    match signature {
+        Type::Product(types) => types.len(),
+        _ => 1,
+    }
+}
+
+struct Analysis<'a> {
+    keys: HashMap<&'a str, &'a [Vec<usize>]>,
+    /// Facts of each relation without duplicates
+    facts: HashMap<&'a str, Vec<&'a [Term]>>,
+    rules: HashMap<&'a str, Vec<&'a Rule>>,
+    /// Sum type variants, which a pattern can fail to match
+    variants: HashSet<&'a str>,
+    /// Determinism found so far for each requested mode
+    table: BTreeMap<String, BTreeMap<Pattern, Determinism>>,
+    changed: bool,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(program: &'a Program) -> Self {
+        let mut analysis = Analysis {
+            keys: HashMap::new(),
+            facts: HashMap::new(),
+            rules: HashMap::new(),
+            variants: HashSet::new(),
+            table: BTreeMap::new(),
+            changed: false,
+        };
+        let mut seen = HashSet::new();
+        for item in &program.items {
+            match item {
+                Item::RelationDecl(rel) => {
+                    analysis.keys.insert(&rel.name, &rel.keys);
+                    analysis.facts.entry(&rel.name).or_default();
+                    analysis.rules.entry(&rel.name).or_default();
+                }
+                Item::TypeDef(TypeDef {
+                    def: TypeDefKind::Sum { variants },
+                    ..
+                }) => analysis
+                    .variants
+                    .extend(variants.iter().map(String::as_str)),
+                _ => {}
+            }
+        }
+        for item in &program.items {
+            match item {
+                Item::Fact(fact) => {
+                    let key: Vec<_> = fact.args.iter().map(ground_key).collect();
+                    if let Some(facts) = analysis.facts.get_mut(fact.relation.as_str()) {
+                        if seen.insert((fact.relation.as_str(), key)) {
+                            facts.push(&fact.args);
+                        }
+                    }
+                }
+                Item::Rule(rule) => {
+                    if let Some(rules) = analysis.rules.get_mut(rule.head.relation.as_str()) {
+                        rules.push(rule);
+                    }
+                }
+                _ => {}
+            }
+        }
+        analysis
+    }
+
+    /// Current determinism of a mode, requesting it if it is new
+    ///
+    /// A new mode may fail until one of its clauses is shown not to, so a
+    /// recursive call cannot vouch for itself.
+    fn lookup(&mut self, relation: &str, pattern: Pattern) -> Determinism {
+        if !self.rules.contains_key(relation) {
+            return Determinism::Nondet;
+        }
+        let modes = self.table.entry(relation.to_string()).or_default();
+        *modes.entry(pattern).or_insert_with(|| {
+            self.changed = true;
+            Determinism::Semidet
+        })
+    }
+
+    /// Determinism of a relation from its clauses, given its callees so far
+    fn relation(&mut self, name: &str, pattern: &[ModeAnnotation]) -> Determinism {
+        let inputs: Vec<usize> = (0..pattern.len())
+            .filter(|&i| pattern[i] == ModeAnnotation::Input)
+            .collect();
+        let project = |args: &[Term]| -> Vec<Option<String>> {
+            inputs
+                .iter()
+                .map(|&i| args.get(i).and_then(ground_key))
+                .collect()
+        };
+
+        // Facts are ground, so two facts overlap exactly when their projections agree
+        let facts: Vec<_> = self.facts[name].iter().map(|args| project(args)).collect();
+        let mut can_fail = facts.is_empty() || !inputs.is_empty();
+        let mut seen = HashSet::new();
+        let mut overlapping = !facts.iter().all(|p| seen.insert(p));
+        let mut can_multi = false;
+
+        let rules = self.rules[name].clone();
+        let heads: Vec<_> = rules.iter().map(|r| project(&r.head.args)).collect();
+        for (i, rule) in rules.iter().enumerate() {
+            let (fails, multi) = self.clause(Some((&rule.head.args, pattern)), &rule.body);
+            can_fail &= fails;
+            can_multi |= multi;
+            overlapping |= heads[i + 1..]
+                .iter()
+                .chain(&facts)
+                .any(|other| !exclusive(&heads[i], other));
+        }
+
+        let outputs = inputs.len() < pattern.len();
+        let key_bound = self.keys[name].iter().any(|key| {
+            key.iter()
+                .all(|&i| pattern.get(i) == Some(&ModeAnnotation::Input))
+        });
+        Determinism::new(
+            can_fail,
+            outputs && !key_bound && (can_multi || overlapping),
+        )
+    }
+
+    /// Whether a clause can fail, and whether it can give several answers
+    fn clause(
+        &mut self,
+        head: Option<(&[Term], &[ModeAnnotation])>,
+        body: &[Goal],
+    ) -> (bool, bool) {
+        let mut bound = HashSet::new();
+        // Variables that can take more than one value
+        let mut varying = HashSet::new();
+        let mut can_fail = false;
+
+        if let Some((args, pattern)) = head {
+            for (arg, mode) in args.iter().zip(pattern) {
+                if *mode == ModeAnnotation::Input {
+                    can_fail |= self.matches(arg, &mut bound);
+                }
+            }
+        }
+
+        for goal in body {
+            let before = bound.clone();
+            let mut multi = false;
+            let inputs = match goal {
+                Goal::Atom(atom) => {
+                    let pattern: Pattern = atom
+                        .args
+                        .iter()
+                        .map(|arg| {
+                            if ground(arg, &bound) {
+                                ModeAnnotation::Input
+                            } else {
+                                ModeAnnotation::Output
+                            }
+                        })
+                        .collect();
+                    let det = self.lookup(&atom.relation, pattern.clone());
+                    can_fail |= det.can_fail();
+                    multi = det.can_multi();
+                    for (arg, mode) in atom.args.iter().zip(&pattern) {
+                        if *mode == ModeAnnotation::Output {
+                            can_fail |= self.matches(arg, &mut bound);
+                        }
+                    }
+                    atom.args.iter().collect()
+                }
+                Goal::Unify(left, right, _) => {
+                    match (ground(left, &bound), ground(right, &bound)) {
+                        (false, true) => can_fail |= self.matches(left, &mut bound),
+                        (true, false) => can_fail |= self.matches(right, &mut bound),
+                        _ => {
+                            can_fail = true;
+                            self.matches(left, &mut bound);
+                            self.matches(right, &mut bound);
+                        }
+                    }
+                    vec![left, right]
+                }
+                Goal::Compare(..) | Goal::Match { .. } => {
+                    can_fail = true;
+                    Vec::new()
+                }
+            };
+            // What a goal binds varies if the goal has several answers or
+            // depends on something that varies
+            if multi || inputs.iter().any(|arg| mentions(arg, &varying)) {
+                varying.extend(bound.difference(&before).cloned());
+            }
+        }
+
+        // An output left as `_` stands for every value
+        let can_multi = head.is_some_and(|(args, pattern)| {
+            args.iter().zip(pattern).any(|(arg, mode)| {
+                *mode == ModeAnnotation::Output && (mentions(arg, &varying) || !ground(arg, &bound))
+            })
+        });
+        (can_fail, can_multi)
+    }
+
+    /// Bind the variables of a pattern matched against a ground value, and
+    /// say whether the match can fail
+    fn matches(&self, term: &Term, bound: &mut HashSet<String>) -> bool {
+        match term {
+            Term::Var(name, _) if name == "_" => false,
+            Term::Var(name, _) => !bound.insert(name.clone()),
+            Term::Construct {
+                constructor, args, ..
+            } => {
+                let mut refutable = self.variants.contains(constructor.as_str());
+                for arg in args {
+                    refutable |= self.matches(arg, bound);
+                }
+                refutable
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Whether two clauses have different constants at some bound position
+fn exclusive(a: &[Option<String>], b: &[Option<String>]) -> bool {
+    a.iter()
+        .zip(b)
+        .any(|pair| matches!(pair, (Some(x), Some(y)) if x != y))
+}
+
+/// A canonical text for a constant term, ignoring spans
+fn ground_key(term: &Term) -> Option<String> {
+    match term {
+        Term::Int(n, _) => Some(n.to_string()),
+        Term::String(s, _) => Some(format!("{:?}", s)),
+        Term::Date(n, _) => Some(format!("date {}", n)),
+        Term::Timestamp(n, _) => Some(format!("timestamp {}", n)),
+        Term::Duration(n, _) => Some(format!("duration {}", n)),
+        Term::Construct {
+            constructor, args, ..
+        } => {
+            let args = args.iter().map(ground_key).collect::<Option<Vec<_>>>()?;
+            Some(format!("{}({})", constructor, args.join(", ")))
+        }
+        Term::Var(..) | Term::Now(_) | Term::Today(_) | Term::BinOp { .. } => None,
+    }
+}
+
+fn ground(term: &Term, bound: &HashSet<String>) -> bool {
+    match term {
+        Term::Var(name, _) => bound.contains(name),
+        Term::Construct { args, .. } => args.iter().all(|a| ground(a, bound)),
+        Term::BinOp { left, right, .. } => ground(left, bound) && ground(right, bound),
+        _ => true,
+    }
+}
+
+fn mentions(term: &Term, vars: &HashSet<String>) -> bool {
+    match term {
+        Term::Var(name, _) => vars.contains(name),
+        Term::Construct { args, .. } => args.iter().any(|a| mentions(a, vars)),
+        Term::BinOp { left, right, .. } => mentions(left, vars) || mentions(right, vars),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::ModeAnnotation::{Input, Output};
+    use crate::parser;
+
+    fn modes(source: &str) -> Modes {
+        infer(&parser::parse(source).expect("Parse failed"))
+    }
+
+    #[test]
+    fn test_facts_and_keys() {
+        let modes = modes(
+            r#"
+            rel salary: String × Int key(0)
+            rel parent: String × String
+            rel origin: String
+            salary("alice", 10).
+            salary("bob", 20).
+            parent("alice", "bob").
+            parent("alice", "carol").
+            origin("alice").
+            ?- salary("alice", S), parent("alice", C), parent(C, "alice").
+        "#,
+        );
+        assert_eq!(
+            modes.get("salary", &[Input, Output]),
+            Some(Determinism::Semidet)
+        );
+        assert_eq!(
+            modes.get("salary", &[Output, Output]),
+            Some(Determinism::Multi)
+        );
+        assert_eq!(
+            modes.get("parent", &[Input, Output]),
+            Some(Determinism::Nondet)
+        );
+        assert_eq!(
+            modes.get("parent", &[Input, Input]),
+            Some(Determinism::Semidet)
+        );
+        assert_eq!(modes.get("origin", &[Output]), Some(Determinism::Det));
+    }
+
+    #[test]
+    fn test_rules_and_recursion() {
+        let modes = modes(
+            r#"
+            rel edge: Int × Int
+            rel path: Int × Int
+            rel next: Int × Int
+            rel config: String
+            rel setting: Int × String
+            edge(1, 2).
+            edge(2, 3).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+            next(X, Y) :- Y = X + 1.
+            config("fast").
+            setting(0, S) :- config(S).
+            setting(1, "fixed").
+            ?- path(1, Y), next(Y, Z), setting(0, S).
+        "#,
+        );
+        assert_eq!(
+            modes.get("path", &[Input, Output]),
+            Some(Determinism::Nondet)
+        );
+        assert_eq!(modes.get("next", &[Input, Output]), Some(Determinism::Det));
+        // Different constants at the bound position make the clauses exclusive
+        assert_eq!(
+            modes.get("setting", &[Input, Output]),
+            Some(Determinism::Semidet)
+        );
+        assert_eq!(
+            modes.get("setting", &[Output, Output]),
+            Some(Determinism::Multi)
+        );
+    }
+
+    #[test]
+    fn test_recursive_modes_can_fail() {
+        let modes = modes(
+            r#"
+            rel parent: Int × Int
+            rel ancestor: Int × Int
+            rel p: Int
+            rel count: Int
+            parent(1, 2).
+            parent(2, 3).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            p(X) :- p(X).
+            count(0).
+            count(N) :- count(M), M < 10, N = M + 1.
+            ?- ancestor(1, 3), p(X), count(3).
+        "#,
+        );
+        assert_eq!(
+            modes.get("ancestor", &[Input, Input]),
+            Some(Determinism::Semidet)
+        );
+        assert_eq!(modes.get("p", &[Output]), Some(Determinism::Semidet));
+        assert_eq!(modes.get("p", &[Input]), Some(Determinism::Semidet));
+        // A fact proves the mode cannot fail; the recursion adds answers
+        assert_eq!(modes.get("count", &[Output]), Some(Determinism::Multi));
+        assert_eq!(modes.get("count", &[Input]), Some(Determinism::Semidet));
+
+        let source = r#"
+            rel parent: Int × Int
+            @mode(in, in, det)
+            rel ancestor: Int × Int
+            parent(1, 2).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let errors = crate::types::TypeChecker::new()
+            .check_program(&program)
+            .expect_err("expected errors");
+        assert_eq!(
+            errors.0[0].to_string(),
+            "Mode ancestor(in, in) is declared det but inferred semidet"
+        );
+    }
+
+    #[test]
+    fn test_declared_modes() {
+        let source = r#"
+            rel parent: String × String
+            @mode(in, out, det)
+            rel child: String × String
+            @mode(in, in, out)
+            rel broken: String × String
+            parent("alice", "bob").
+            parent("alice", "carol").
+            child(P, C) :- parent(P, C).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let errors = crate::types::TypeChecker::new()
+            .check_program(&program)
+            .expect_err("expected errors");
+        let messages: Vec<_> = errors.0.iter().map(|e| (e.code(), e.to_string())).collect();
+        assert_eq!(
+            messages,
+            vec![(
+                "E0601",
+                "Invalid mode declaration on broken: unknown determinism out".to_string()
+            )]
+        );
+
+        let source = source.replace("@mode(in, in, out)\n", "");
+        let program = parser::parse(&source).expect("Parse failed");
+        let errors = crate::types::TypeChecker::new()
+            .check_program(&program)
+            .expect_err("expected errors");
+        assert_eq!(errors.0[0].code(), "E0602");
+        assert_eq!(
+            errors.0[0].to_string(),
+            "Mode child(in, out) is declared det but inferred nondet"
+        );
+    }
+}
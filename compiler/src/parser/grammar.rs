@@ -3,6 +3,7 @@
 /// This module contains the parser implementation for qed syntax.
 use super::lexer::Token;
 use crate::ast::*;
+use chumsky::input::ValueInput;
 use chumsky::prelude::*;
 
 /// Convert chumsky span to our Span
@@ -15,7 +16,9 @@ fn to_span(span: SimpleSpan) -> Span {
 
 /// Parse a type expression
 /// Examples: Person, Int, String, Person × Person, List<Int>
-pub fn type_parser<'a>() -> impl Parser<'a, &'a [Token], Type, extra::Err<Simple<'a, Token>>> + Clone
+pub fn type_parser<'a, I>() -> impl Parser<'a, I, Type, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
     recursive(|ty| {
         // Named type: Person, Int, String
@@ -56,7 +59,9 @@ pub fn type_parser<'a>() -> impl Parser<'a, &'a [Token], Type, extra::Err<Simple
 
 /// Parse a term (with binary operators)
 /// Examples: X, 42, "hello", person("Alice", 45), X + Y
-pub fn term_parser<'a>() -> impl Parser<'a, &'a [Token], Term, extra::Err<Simple<'a, Token>>> + Clone
+pub fn term_parser<'a, I>() -> impl Parser<'a, I, Term, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
     recursive(|term| {
         // Variable: X, Y, Age
@@ -77,6 +82,24 @@ pub fn term_parser<'a>() -> impl Parser<'a, &'a [Token], Term, extra::Err<Simple
         }
         .map_with(|s, e| Term::String(s, to_span(e.span())));
 
+        // Temporal literals: 2026-12-31, 2026-12-31T10:00:00Z, P90D
+        let temporal = select! {
+            Token::Date(d) => (d, Term::Date as fn(i64, Span) -> Term),
+            Token::Timestamp(t) => (t, Term::Timestamp as fn(i64, Span) -> Term),
+            Token::Duration(d) => (d, Term::Duration as fn(i64, Span) -> Term),
+        }
+        .map_with(|(value, make), e| make(value, to_span(e.span())));
+
+        // Evaluation instant: now(), and its date: today()
+        let now = just(Token::LowerId("now".to_string()))
+            .then(just(Token::LParen))
+            .then(just(Token::RParen))
+            .map_with(|_, e| Term::Now(to_span(e.span())));
+        let today = just(Token::LowerId("today".to_string()))
+            .then(just(Token::LParen))
+            .then(just(Token::RParen))
+            .map_with(|_, e| Term::Today(to_span(e.span())));
+
         // Constructor: person("Alice", 45) or just a lowercase identifier
         let constructor = select! {
             Token::LowerId(name) => name,
@@ -111,7 +134,7 @@ pub fn term_parser<'a>() -> impl Parser<'a, &'a [Token], Term, extra::Err<Simple
             .delimited_by(just(Token::LParen), just(Token::RParen));
 
         // Atom (base term without operators)
-        let atom = choice((parens, constructor, var, int, string));
+        let atom = choice((parens, now, today, constructor, var, int, temporal, string));
 
         // Binary operators with precedence
         // Multiplicative: *, /, %
@@ -157,7 +180,9 @@ pub fn term_parser<'a>() -> impl Parser<'a, &'a [Token], Term, extra::Err<Simple
 }
 
 /// Parse an atom: parent(X, Y)
-pub fn atom_parser<'a>() -> impl Parser<'a, &'a [Token], Atom, extra::Err<Simple<'a, Token>>> + Clone
+pub fn atom_parser<'a, I>() -> impl Parser<'a, I, Atom, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
     select! {
         Token::LowerId(name) => name,
@@ -177,7 +202,9 @@ pub fn atom_parser<'a>() -> impl Parser<'a, &'a [Token], Atom, extra::Err<Simple
 
 /// Parse a goal in a rule body
 /// Examples: parent(X, Y), X = Y, Age > 18
-pub fn goal_parser<'a>() -> impl Parser<'a, &'a [Token], Goal, extra::Err<Simple<'a, Token>>> + Clone
+pub fn goal_parser<'a, I>() -> impl Parser<'a, I, Goal, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
     let term = term_parser();
 
@@ -202,17 +229,39 @@ pub fn goal_parser<'a>() -> impl Parser<'a, &'a [Token], Goal, extra::Err<Simple
         .then(term.clone())
         .map_with(|(left, right), e| Goal::Unify(left, right, to_span(e.span())));
 
+    // Regex match: matches(Path, "^/docs/")
+    let pattern = select! {
+        Token::String(s) => s,
+    }
+    .map_with(|s, e| (s, to_span(e.span())));
+
+    let matches = just(Token::LowerId("matches".to_string()))
+        .ignore_then(
+            term.clone()
+                .then_ignore(just(Token::Comma))
+                .then(pattern)
+                .delimited_by(just(Token::LParen), just(Token::RParen)),
+        )
+        .map_with(|(subject, (pattern, pattern_span)), e| Goal::Match {
+            subject,
+            pattern,
+            pattern_span,
+            span: to_span(e.span()),
+        });
+
     // Atom: parent(X, Y)
     let atom = atom_parser().map(Goal::Atom);
 
     // Try comparison and unify before atom (to avoid ambiguity)
-    choice((comparison, unify, atom))
+    choice((comparison, unify, matches, atom))
 }
 
 /// Parse a type definition
 /// Example: type Person = person(name: String, age: Int)
-pub fn type_def_parser<'a>(
-) -> impl Parser<'a, &'a [Token], TypeDef, extra::Err<Simple<'a, Token>>> + Clone {
+pub fn type_def_parser<'a, I>() -> impl Parser<'a, I, TypeDef, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
     let field = select! {
         Token::LowerId(name) => name,
     }
@@ -235,14 +284,18 @@ pub fn type_def_parser<'a>(
         fields,
     });
 
-    // Sum type: Public | Internal | Secret
-    let sum = select! {
-        Token::UpperId(variant) => variant,
-    }
-    .separated_by(just(Token::Pipe))
-    .at_least(1)
-    .collect()
-    .map(|variants| TypeDefKind::Sum { variants });
+    // Sum type: Public | Internal | Secret, optionally with a leading pipe
+    let sum = just(Token::Pipe)
+        .or_not()
+        .ignore_then(
+            select! {
+                Token::UpperId(variant) => variant,
+            }
+            .separated_by(just(Token::Pipe))
+            .at_least(1)
+            .collect(),
+        )
+        .map(|variants| TypeDefKind::Sum { variants });
 
     just(Token::Type)
         .ignore_then(select! {
@@ -257,26 +310,111 @@ pub fn type_def_parser<'a>(
         })
 }
 
+/// Parse the doc comments and attributes in front of a rule or relation
+/// Example: /// Owners can do anything
+///          @rule("owner-full-access")
+pub fn metadata_parser<'a, I>(
+) -> impl Parser<'a, I, (Option<String>, Vec<Attribute>), extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    let doc = select! {
+        Token::DocComment(text) => text,
+    };
+
+    let arg = select! {
+        Token::String(s) => s,
+        Token::LowerId(s) => s,
+        Token::Int(n) => n.to_string(),
+    };
+
+    let attribute = just(Token::At)
+        .ignore_then(select! {
+            Token::LowerId(name) => name,
+        })
+        .then(
+            arg.separated_by(just(Token::Comma))
+                .collect()
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .or_not(),
+        )
+        .map_with(|(name, args), e| Attribute {
+            name,
+            args: args.unwrap_or_default(),
+            span: to_span(e.span()),
+        });
+
+    choice((doc.map(Ok), attribute.map(Err)))
+        .repeated()
+        .collect::<Vec<_>>()
+        .map(|entries| {
+            let mut docs = Vec::new();
+            let mut attributes = Vec::new();
+            for entry in entries {
+                match entry {
+                    Ok(line) => docs.push(line),
+                    Err(attribute) => attributes.push(attribute),
+                }
+            }
+            let doc = (!docs.is_empty()).then(|| docs.join(" "));
+            (doc, attributes)
+        })
+}
+
 /// Parse a relation declaration
 /// Example: rel parent: Person × Person
-pub fn relation_decl_parser<'a>(
-) -> impl Parser<'a, &'a [Token], RelationDecl, extra::Err<Simple<'a, Token>>> + Clone {
-    just(Token::Rel)
+pub fn relation_decl_parser<'a, I>(
+) -> impl Parser<'a, I, RelationDecl, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    let decl = just(Token::Rel)
         .ignore_then(select! {
             Token::LowerId(name) => name,
         })
         .then_ignore(just(Token::Colon))
         .then(type_parser())
-        .map_with(|(name, signature), e| RelationDecl {
-            name,
-            signature,
-            span: to_span(e.span()),
-        })
+        .then(key_parser().repeated().collect())
+        .map_with(|((name, signature), keys), e| (name, signature, keys, to_span(e.span())));
+
+    metadata_parser()
+        .then(decl)
+        .map(
+            |((doc, attributes), (name, signature, keys, span))| RelationDecl {
+                name,
+                signature,
+                keys,
+                span,
+                doc,
+                attributes,
+            },
+        )
+}
+
+/// Parse a key declaration
+/// Example: key(0) or key(0, 1)
+fn key_parser<'a, I>() -> impl Parser<'a, I, Vec<usize>, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    let position = select! {
+        Token::Int(n) if n >= 0 => n as usize,
+    };
+
+    just(Token::LowerId("key".to_string())).ignore_then(
+        position
+            .separated_by(just(Token::Comma))
+            .at_least(1)
+            .collect()
+            .delimited_by(just(Token::LParen), just(Token::RParen)),
+    )
 }
 
 /// Parse a fact
 /// Example: parent(person("Alice", 45), person("Bob", 20)).
-pub fn fact_parser<'a>() -> impl Parser<'a, &'a [Token], Fact, extra::Err<Simple<'a, Token>>> + Clone
+pub fn fact_parser<'a, I>() -> impl Parser<'a, I, Fact, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
     select! {
         Token::LowerId(name) => name,
@@ -297,23 +435,106 @@ pub fn fact_parser<'a>() -> impl Parser<'a, &'a [Token], Fact, extra::Err<Simple
 
 /// Parse a rule
 /// Example: ancestor(X, Y) :- parent(X, Y).
-pub fn rule_parser<'a>() -> impl Parser<'a, &'a [Token], Rule, extra::Err<Simple<'a, Token>>> + Clone
+pub fn rule_parser<'a, I>() -> impl Parser<'a, I, Rule, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
-    atom_parser()
+    let rule = atom_parser()
         .then_ignore(just(Token::ColonDash))
         .then(goal_parser().separated_by(just(Token::Comma)).collect())
         .then_ignore(just(Token::Dot))
-        .map_with(|(head, body), e| Rule {
+        .map_with(|(head, body), e| (head, body, to_span(e.span())));
+
+    metadata_parser()
+        .then(rule)
+        .map(|((doc, attributes), (head, body, span))| Rule {
             head,
             body,
+            span,
+            doc,
+            attributes,
+        })
+}
+
+/// Parse an integrity constraint
+/// Example: :- has_role(U, Employee), has_role(U, Contractor).
+pub fn constraint_parser<'a, I>(
+) -> impl Parser<'a, I, Constraint, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    let constraint = just(Token::ColonDash)
+        .ignore_then(goal_parser().separated_by(just(Token::Comma)).collect())
+        .then_ignore(just(Token::Dot))
+        .map_with(|body, e| (body, to_span(e.span())));
+
+    metadata_parser()
+        .then(constraint)
+        .map(|((doc, attributes), (body, span))| Constraint {
+            body,
+            span,
+            doc,
+            attributes,
+        })
+}
+
+/// Parse a decision table
+/// Example: table classification(employee(_, _, Sal, Years), Level) {
+///     | Sal < 70000 | Years < 3 | Junior |
+/// }
+pub fn table_parser<'a, I>() -> impl Parser<'a, I, Table, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    // `-` first: no goal or term starts with a minus
+    let cell = choice((
+        just(Token::Minus).map_with(|_, e| TableCell::Any(to_span(e.span()))),
+        goal_parser()
+            .separated_by(just(Token::Comma))
+            .at_least(1)
+            .collect()
+            .map_with(|goals, e| TableCell::Goals(goals, to_span(e.span()))),
+        term_parser().map_with(|term, e| TableCell::Value(term, to_span(e.span()))),
+    ));
+
+    let row = just(Token::Pipe)
+        .ignore_then(
+            cell.then_ignore(just(Token::Pipe))
+                .repeated()
+                .at_least(1)
+                .collect(),
+        )
+        .map_with(|cells, e| TableRow {
+            cells,
             span: to_span(e.span()),
+        });
+
+    let table = just(Token::LowerId("table".to_string()))
+        .ignore_then(atom_parser())
+        .then(
+            row.repeated()
+                .collect()
+                .delimited_by(just(Token::LBrace), just(Token::RBrace)),
+        )
+        .map_with(|(head, rows), e| (head, rows, to_span(e.span())));
+
+    metadata_parser()
+        .then(table)
+        .map(|((doc, attributes), (head, rows, span))| Table {
+            head,
+            rows,
+            span,
+            doc,
+            attributes,
         })
 }
 
 /// Parse a query
 /// Example: ?- ancestor(person("Alice", 45), X).
-pub fn query_parser<'a>(
-) -> impl Parser<'a, &'a [Token], Query, extra::Err<Simple<'a, Token>>> + Clone {
+pub fn query_parser<'a, I>() -> impl Parser<'a, I, Query, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
     just(Token::QuestionDash)
         .ignore_then(goal_parser().separated_by(just(Token::Comma)).collect())
         .then_ignore(just(Token::Dot))
@@ -323,24 +544,110 @@ pub fn query_parser<'a>(
         })
 }
 
+/// Parse an explanation template
+/// Example: explain has_role(U, R) as "{U.name} has role {R}".
+pub fn explain_template_parser<'a, I>(
+) -> impl Parser<'a, I, ExplainTemplate, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    let relation = select! {
+        Token::LowerId(name) => name,
+    };
+    let param = select! {
+        Token::UpperId(name) => name,
+    };
+    let template = select! {
+        Token::String(s) => s,
+    }
+    .map_with(|s, e| (s, to_span(e.span())));
+
+    just(Token::LowerId("explain".to_string()))
+        .ignore_then(relation)
+        .then(
+            param
+                .separated_by(just(Token::Comma))
+                .collect()
+                .delimited_by(just(Token::LParen), just(Token::RParen)),
+        )
+        .then_ignore(just(Token::LowerId("as".to_string())))
+        .then(template)
+        .then_ignore(just(Token::Dot).or_not())
+        .map_with(
+            |((relation, params), (template, template_span)), e| ExplainTemplate {
+                relation,
+                params,
+                template,
+                template_span,
+                span: to_span(e.span()),
+            },
+        )
+}
+
+/// Facts and queries have nowhere to keep attributes, so any in front of
+/// one are reported rather than dropped
+fn reject_attributes<'a>(
+    attributes: Vec<Attribute>,
+    item: &str,
+    emitter: &mut chumsky::input::Emitter<Rich<'a, Token>>,
+) {
+    for attribute in attributes {
+        emitter.emit(Rich::custom(
+            SimpleSpan::from(attribute.span.start..attribute.span.end),
+            format!("attribute @{} cannot precede a {}", attribute.name, item),
+        ));
+    }
+}
+
 /// Parse a top-level item
-pub fn item_parser<'a>() -> impl Parser<'a, &'a [Token], Item, extra::Err<Simple<'a, Token>>> + Clone
+pub fn item_parser<'a, I>() -> impl Parser<'a, I, Item, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
 {
+    // Doc comments are kept on rules and relations and ignored elsewhere
+    let docs = select! {
+        Token::DocComment(_) => (),
+    }
+    .repeated();
+
     choice((
-        type_def_parser().map(Item::TypeDef),
+        docs.ignore_then(type_def_parser()).map(Item::TypeDef),
         relation_decl_parser().map(Item::RelationDecl),
-        query_parser().map(Item::Query),
+        metadata_parser()
+            .then(query_parser())
+            .validate(|((_, attributes), query), _, emitter| {
+                reject_attributes(attributes, "query", emitter);
+                Item::Query(query)
+            }),
+        docs.ignore_then(explain_template_parser())
+            .map(Item::Explain),
+        table_parser().map(Item::Table),
         rule_parser().map(Item::Rule),
-        fact_parser().map(Item::Fact),
+        constraint_parser().map(Item::Constraint),
+        metadata_parser()
+            .then(fact_parser())
+            .validate(|((_, attributes), fact), _, emitter| {
+                reject_attributes(attributes, "fact", emitter);
+                Item::Fact(fact)
+            }),
     ))
 }
 
 /// Parse a complete program
-pub fn program_parser<'a>(
-) -> impl Parser<'a, &'a [Token], Program, extra::Err<Simple<'a, Token>>> + Clone {
+pub fn program_parser<'a, I>() -> impl Parser<'a, I, Program, extra::Err<Rich<'a, Token>>> + Clone
+where
+    I: ValueInput<'a, Token = Token, Span = SimpleSpan>,
+{
+    // Doc comments at the end of the file document nothing and are dropped
+    let trailing_docs = select! {
+        Token::DocComment(_) => (),
+    }
+    .repeated();
+
     item_parser()
         .repeated()
         .collect()
+        .then_ignore(trailing_docs)
         .then_ignore(end())
         .map(|items| Program { items })
 }
@@ -357,7 +664,7 @@ mod tests {
     #[test]
     fn test_parse_type_named() {
         let tokens = lex("Person");
-        let result = type_parser().parse(&tokens).into_result();
+        let result = type_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), Type::Named("Person".to_string()));
     }
@@ -365,7 +672,7 @@ mod tests {
     #[test]
     fn test_parse_type_product() {
         let tokens = lex("Person × Int");
-        let result = type_parser().parse(&tokens).into_result();
+        let result = type_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         match result.unwrap() {
             Type::Product(types) => {
@@ -380,7 +687,7 @@ mod tests {
     #[test]
     fn test_parse_term_var() {
         let tokens = lex("X");
-        let result = term_parser().parse(&tokens).into_result();
+        let result = term_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         match result.unwrap() {
             Term::Var(name, _) => assert_eq!(name, "X"),
@@ -391,7 +698,7 @@ mod tests {
     #[test]
     fn test_parse_term_int() {
         let tokens = lex("42");
-        let result = term_parser().parse(&tokens).into_result();
+        let result = term_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         match result.unwrap() {
             Term::Int(n, _) => assert_eq!(n, 42),
@@ -402,7 +709,7 @@ mod tests {
     #[test]
     fn test_parse_term_constructor() {
         let tokens = lex(r#"person("Alice", 45)"#);
-        let result = term_parser().parse(&tokens).into_result();
+        let result = term_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         match result.unwrap() {
             Term::Construct {
@@ -418,7 +725,7 @@ mod tests {
     #[test]
     fn test_parse_term_binop() {
         let tokens = lex("X + Y");
-        let result = term_parser().parse(&tokens).into_result();
+        let result = term_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         match result.unwrap() {
             Term::BinOp { op, .. } => assert_eq!(op, BinOp::Add),
@@ -429,17 +736,148 @@ mod tests {
     #[test]
     fn test_parse_atom() {
         let tokens = lex("parent(X, Y)");
-        let result = atom_parser().parse(&tokens).into_result();
+        let result = atom_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         let atom = result.unwrap();
         assert_eq!(atom.relation, "parent");
         assert_eq!(atom.args.len(), 2);
     }
 
+    #[test]
+    fn test_parse_matches_goal() {
+        let tokens = lex(r#"matches(Path, "^/projects/[^/]+/docs/")"#);
+        let result = goal_parser().parse(tokens.as_slice()).into_result();
+        assert!(result.is_ok());
+        match result.unwrap() {
+            Goal::Match {
+                subject, pattern, ..
+            } => {
+                assert!(matches!(subject, Term::Var(ref name, _) if name == "Path"));
+                assert_eq!(pattern, "^/projects/[^/]+/docs/");
+            }
+            _ => panic!("Expected match goal"),
+        }
+    }
+
+    #[test]
+    fn test_parse_temporal_terms() {
+        let tokens = lex("D + P90D <= now()");
+        let result = goal_parser().parse(tokens.as_slice()).into_result();
+        match result.unwrap() {
+            Goal::Compare(CompareOp::Le, left, right, _) => {
+                assert!(matches!(
+                    left,
+                    Term::BinOp { ref right, .. } if matches!(**right, Term::Duration(7_776_000, _))
+                ));
+                assert!(matches!(right, Term::Now(_)));
+            }
+            _ => panic!("Expected comparison goal"),
+        }
+
+        let tokens = lex("Until >= today()");
+        let result = goal_parser().parse(tokens.as_slice()).into_result();
+        assert!(matches!(
+            result.unwrap(),
+            Goal::Compare(CompareOp::Ge, _, Term::Today(_), _)
+        ));
+    }
+
+    #[test]
+    fn test_parse_rule_metadata() {
+        let tokens = lex(r#"/// Owners can do anything
+            /// with their resources
+            @rule("owner-full-access")
+            can_access(U, R) :- owns(U, R)."#);
+        let rule = rule_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            rule.doc.as_deref(),
+            Some("Owners can do anything with their resources")
+        );
+        assert_eq!(rule.name(), Some("owner-full-access"));
+        assert_eq!(rule.label(), Some("owner-full-access"));
+    }
+
+    #[test]
+    fn test_parse_explain_template() {
+        let tokens = lex(r#"explain has_role(U, R) as "{U.name} has role {R} {{ok}}""#);
+        let template = explain_template_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        assert_eq!(template.relation, "has_role");
+        assert_eq!(template.params, vec!["U", "R"]);
+        assert_eq!(
+            template.parts().unwrap(),
+            vec![
+                TemplatePart::Hole {
+                    var: "U".to_string(),
+                    fields: vec!["name".to_string()]
+                },
+                TemplatePart::Text(" has role ".to_string()),
+                TemplatePart::Hole {
+                    var: "R".to_string(),
+                    fields: vec![]
+                },
+                TemplatePart::Text(" {ok}".to_string()),
+            ]
+        );
+
+        // A relation named `explain` is still an ordinary fact
+        let tokens = lex(r#"explain("x")."#);
+        let item = item_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        assert!(matches!(item, Item::Fact(_)));
+    }
+
+    #[test]
+    fn test_parse_constraint() {
+        let tokens = lex(r#"/// No contractor may also be an employee
+            :- has_role(U, "employee"), has_role(U, "contractor")."#);
+        let item = item_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        let Item::Constraint(constraint) = item else {
+            panic!("expected a constraint");
+        };
+        assert_eq!(constraint.body.len(), 2);
+        assert_eq!(
+            constraint.label(),
+            Some("No contractor may also be an employee")
+        );
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let tokens = lex(r#"table level(employee(_, Sal), L) {
+                | Sal < 100, Sal > 0 | Junior |
+                | -                  | Senior |
+            }"#);
+        let item = item_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        let Item::Table(table) = item else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.rows.len(), 2);
+        assert!(matches!(&table.rows[0].cells[0], TableCell::Goals(goals, _) if goals.len() == 2));
+        assert!(matches!(table.rows[1].cells[0], TableCell::Any(_)));
+        assert!(matches!(
+            &table.rows[1].cells[1],
+            TableCell::Value(Term::Var(name, _), _) if name == "Senior"
+        ));
+    }
+
     #[test]
     fn test_parse_type_def() {
         let tokens = lex("type Person = person(name: String, age: Int)");
-        let result = type_def_parser().parse(&tokens).into_result();
+        let result = type_def_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         let typedef = result.unwrap();
         assert_eq!(typedef.name, "Person");
@@ -458,16 +896,35 @@ mod tests {
     #[test]
     fn test_parse_relation_decl() {
         let tokens = lex("rel parent: Person × Person");
-        let result = relation_decl_parser().parse(&tokens).into_result();
+        let result = relation_decl_parser()
+            .parse(tokens.as_slice())
+            .into_result();
         assert!(result.is_ok());
         let decl = result.unwrap();
         assert_eq!(decl.name, "parent");
     }
 
+    #[test]
+    fn test_parse_relation_keys() {
+        let tokens = lex("rel salary_of: Employee × Int key(0)");
+        let decl = relation_decl_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        assert_eq!(decl.keys, vec![vec![0]]);
+
+        let tokens = lex("rel grade: String × Int × Int key(0, 1) key(2)");
+        let decl = relation_decl_parser()
+            .parse(tokens.as_slice())
+            .into_result()
+            .unwrap();
+        assert_eq!(decl.keys, vec![vec![0, 1], vec![2]]);
+    }
+
     #[test]
     fn test_parse_fact() {
         let tokens = lex(r#"parent(person("Alice", 45), person("Bob", 20))."#);
-        let result = fact_parser().parse(&tokens).into_result();
+        let result = fact_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         let fact = result.unwrap();
         assert_eq!(fact.relation, "parent");
@@ -477,7 +934,7 @@ mod tests {
     #[test]
     fn test_parse_rule() {
         let tokens = lex("ancestor(X, Y) :- parent(X, Y).");
-        let result = rule_parser().parse(&tokens).into_result();
+        let result = rule_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         let rule = result.unwrap();
         assert_eq!(rule.head.relation, "ancestor");
@@ -487,7 +944,7 @@ mod tests {
     #[test]
     fn test_parse_query() {
         let tokens = lex("?- ancestor(X, Y).");
-        let result = query_parser().parse(&tokens).into_result();
+        let result = query_parser().parse(tokens.as_slice()).into_result();
         assert!(result.is_ok());
         let query = result.unwrap();
         assert_eq!(query.goals.len(), 1);
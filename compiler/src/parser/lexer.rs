@@ -3,7 +3,23 @@
 /// Converts source text into tokens.
 use logos::Logos;
 
+/// Why a piece of source is not a token
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LexError {
+    /// No token starts here
+    #[default]
+    Unexpected,
+    /// Shaped like a literal of this kind but not one: `2025-02-30` is a
+    /// "date"
+    InvalidLiteral(&'static str),
+}
+
+fn literal(kind: &'static str, value: Option<i64>) -> Result<i64, LexError> {
+    value.ok_or(LexError::InvalidLiteral(kind))
+}
+
 #[derive(Logos, Debug, Clone, PartialEq, Eq, Hash)]
+#[logos(error = LexError)]
 #[logos(skip r"[ \t\n\f]+")]
 #[logos(skip r"//[^\n]*")]
 #[logos(skip r"/\*([^*]|\*[^/])*\*/")]
@@ -16,22 +32,42 @@ pub enum Token {
     Rel,
 
     // Literals
-    #[regex(r"[0-9]+", |lex| lex.slice().parse().ok())]
+    #[regex(r"[0-9]+", |lex| literal("integer", lex.slice().parse().ok()))]
     Int(i64),
 
-    #[regex(r#""([^"\\]|\\["\\bnfrt])*""#, |lex| {
-        let s = lex.slice();
-        s[1..s.len()-1].to_string()
+    #[regex(r"[0-9]{4}-[0-9]{2}-[0-9]{2}", |lex| {
+        literal("date", qed_runtime::temporal::parse_date(lex.slice()))
+    })]
+    Date(i64),
+
+    #[regex(r"[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}Z", |lex| {
+        literal("timestamp", qed_runtime::temporal::parse_timestamp(lex.slice()))
     })]
+    Timestamp(i64),
+
+    // ISO durations would otherwise lex as upper-case identifiers, so names
+    // of this shape (`P1D`, `PT2H`) are reserved and cannot be variables
+    #[regex(r"P([0-9]+[WD])+(T([0-9]+[HMS])+)?|PT([0-9]+[HMS])+", |lex| {
+        literal("duration", qed_runtime::temporal::parse_duration(lex.slice()))
+    }, priority = 10)]
+    Duration(i64),
+
+    // Unknown escapes such as `\d` stay as written, for regex patterns
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| unescape(lex.slice()))]
     String(String),
 
     // Identifiers
     #[regex(r"[a-z][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
     LowerId(String),
 
-    #[regex(r"[A-Z][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
+    // Variables, including the anonymous `_` and `_`-prefixed names
+    #[regex(r"[A-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
     UpperId(String),
 
+    // Doc comments attach to the following rule or relation
+    #[regex(r"///[^\n]*", |lex| lex.slice()[3..].trim().to_string(), priority = 10)]
+    DocComment(String),
+
     // Operators
     #[token(":-")]
     ColonDash,
@@ -108,12 +144,80 @@ pub enum Token {
 
     #[token("|")]
     Pipe,
+
+    #[token("@")]
+    At,
+}
+
+/// The value of a quoted string literal, with its escapes replaced
+fn unescape(literal: &str) -> String {
+    let mut value = String::new();
+    let mut chars = literal[1..literal.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some(quoted @ ('"' | '\\')) => quoted,
+            Some(other) => {
+                value.push('\\');
+                other
+            }
+            None => break,
+        };
+        value.push(escaped);
+    }
+    value
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_lex_temporal_literals() {
+        use qed_runtime::temporal::{parse_date, parse_duration, parse_timestamp};
+        let mut lex = Token::lexer("2026-12-31 2026-12-31T10:00:00Z P90D PT1H Pay");
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::Date(parse_date("2026-12-31").unwrap())))
+        );
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::Timestamp(
+                parse_timestamp("2026-12-31T10:00:00Z").unwrap()
+            )))
+        );
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::Duration(parse_duration("P90D").unwrap())))
+        );
+        assert_eq!(lex.next(), Some(Ok(Token::Duration(3600))));
+        assert_eq!(lex.next(), Some(Ok(Token::UpperId("Pay".to_string()))));
+
+        // Names shaped like durations are reserved for them
+        let mut lex = Token::lexer("P1D 2025-02-30 P1D2W");
+        assert_eq!(lex.next(), Some(Ok(Token::Duration(86400))));
+        assert_eq!(lex.next(), Some(Err(LexError::InvalidLiteral("date"))));
+        assert_eq!(lex.next(), Some(Err(LexError::InvalidLiteral("duration"))));
+    }
+
+    #[test]
+    fn test_lex_doc_comments() {
+        let mut lex = Token::lexer("// plain\n/// Owners can do anything\n@rule(\"owner\")");
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::DocComment("Owners can do anything".to_string())))
+        );
+        assert_eq!(lex.next(), Some(Ok(Token::At)));
+        assert_eq!(lex.next(), Some(Ok(Token::LowerId("rule".to_string()))));
+    }
+
     #[test]
     fn test_lex_keywords() {
         let mut lex = Token::lexer("type rel");
@@ -143,6 +247,24 @@ mod tests {
         assert_eq!(lex.next(), Some(Ok(Token::String("world".to_string()))));
     }
 
+    #[test]
+    fn test_lex_string_escapes() {
+        let mut lex = Token::lexer(r#""^/a\\.b$" "say \"hi\"\n""#);
+        assert_eq!(lex.next(), Some(Ok(Token::String("^/a\\.b$".to_string()))));
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::String("say \"hi\"\n".to_string())))
+        );
+        // Escapes a string does not define are kept for the regex engine
+        let mut lex = Token::lexer(r#""^\d+\.txt$" "\bword\b""#);
+        assert_eq!(
+            lex.next(),
+            Some(Ok(Token::String(r"^\d+\.txt$".to_string())))
+        );
+        // \b is a word boundary, not a backspace
+        assert_eq!(lex.next(), Some(Ok(Token::String(r"\bword\b".to_string()))));
+    }
+
     #[test]
     fn test_lex_operators() {
         let mut lex = Token::lexer(":- ?- = == != < <= > >=");
@@ -3,36 +3,198 @@
 /// Uses logos for lexing and chumsky for parsing.
 use crate::ast::*;
 use anyhow::Result;
+use chumsky::prelude::*;
+use std::collections::HashSet;
 
 pub mod grammar;
 pub mod lexer;
 
-/// Parse a qed source file into an AST
-pub fn parse(source: &str) -> Result<Program> {
-    use chumsky::prelude::*;
+/// Tokenize with logos, keeping byte spans so AST spans point into the source
+///
+/// Text shaped like a literal that is not a valid one, such as the date
+/// `2025-02-30`, and characters no token starts with are errors.
+fn lex(source: &str) -> Result<Vec<(lexer::Token, SimpleSpan)>> {
     use logos::Logos;
 
-    // 1. Tokenize with logos
-    let tokens: Vec<lexer::Token> = lexer::Token::lexer(source)
-        .filter_map(|tok| tok.ok())
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for (tok, span) in lexer::Token::lexer(source).spanned() {
+        match tok {
+            Ok(tok) => tokens.push((tok, SimpleSpan::from(span))),
+            Err(lexer::LexError::InvalidLiteral(kind)) => errors.push(format!(
+                "Parse error: invalid {} literal {} at {}..{}",
+                kind,
+                &source[span.clone()],
+                span.start,
+                span.end
+            )),
+            Err(lexer::LexError::Unexpected) => errors.push(format!(
+                "Parse error: unexpected {:?} at {}..{}",
+                &source[span.clone()],
+                span.start,
+                span.end
+            )),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("Parse errors:\n{}", errors.join("\n"));
+    }
+    Ok(tokens)
+}
+
+fn format_errors<T: std::fmt::Debug>(errors: Vec<T>) -> anyhow::Error {
+    let error_msgs: Vec<String> = errors
+        .iter()
+        .map(|e| format!("Parse error: {:?}", e))
         .collect();
+    anyhow::anyhow!("Parse errors:\n{}", error_msgs.join("\n"))
+}
+
+/// Parse a qed source file into an AST
+pub fn parse(source: &str) -> Result<Program> {
+    // 1. Tokenize with logos
+    let tokens = lex(source)?;
+    let eoi = SimpleSpan::from(source.len()..source.len());
 
     // 2. Parse with chumsky
     let program = grammar::program_parser()
-        .parse(&tokens)
+        .parse(tokens.as_slice().map(eoi, |(tok, span)| (tok, span)))
         .into_result()
-        .map_err(|errors| {
-            // Format parse errors nicely
-            let error_msgs: Vec<String> = errors
-                .iter()
-                .map(|e| format!("Parse error: {:?}", e))
-                .collect();
-            anyhow::anyhow!("Parse errors:\n{}", error_msgs.join("\n"))
-        })?;
+        .map_err(format_errors)?;
+
+    let mut program = program;
+    let variants = declared_variants(&program);
+    for item in &mut program.items {
+        match item {
+            Item::Fact(fact) => resolve_terms(&mut fact.args, &variants),
+            Item::Rule(rule) => {
+                resolve_terms(&mut rule.head.args, &variants);
+                resolve_goals(&mut rule.body, &variants);
+            }
+            Item::Query(query) => resolve_goals(&mut query.goals, &variants),
+            Item::Constraint(constraint) => resolve_goals(&mut constraint.body, &variants),
+            Item::Table(table) => {
+                resolve_terms(&mut table.head.args, &variants);
+                for cell in table.rows.iter_mut().flat_map(|row| &mut row.cells) {
+                    match cell {
+                        TableCell::Goals(goals, _) => resolve_goals(goals, &variants),
+                        TableCell::Value(term, _) => resolve_term(term, &variants),
+                        TableCell::Any(_) => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Each table row becomes a rule placed right after the table
+    let mut items = Vec::with_capacity(program.items.len());
+    for item in program.items {
+        let rules = match &item {
+            Item::Table(table) => table.rules().map_err(|e| anyhow::anyhow!(e))?,
+            _ => Vec::new(),
+        };
+        items.push(item);
+        items.extend(rules.into_iter().map(Item::Rule));
+    }
+    program.items = items;
 
     Ok(program)
 }
 
+/// A standalone query written out in full, with its `?-` and `.`;
+/// the spans of a parsed query point into this text
+pub fn query_text(source: &str) -> String {
+    let trimmed = source.trim();
+    let mut text = String::new();
+    if !trimmed.starts_with("?-") {
+        text.push_str("?- ");
+    }
+    text.push_str(trimmed);
+    if !trimmed.ends_with('.') {
+        text.push('.');
+    }
+    text
+}
+
+/// Parse a standalone query such as one given on the command line.
+/// The leading `?-` and trailing `.` are optional.
+pub fn parse_query(source: &str) -> Result<Query> {
+    let text = query_text(source);
+    let tokens = lex(&text)?;
+    let eoi = SimpleSpan::from(text.len()..text.len());
+    let query = grammar::query_parser()
+        .then_ignore(end())
+        .parse(tokens.as_slice().map(eoi, |(tok, span)| (tok, span)))
+        .into_result()
+        .map_err(format_errors)?;
+
+    Ok(query)
+}
+
+/// Parse a standalone query, resolving variant names declared in `program`
+pub fn parse_query_for(program: &Program, source: &str) -> Result<Query> {
+    let mut query = parse_query(source)?;
+    resolve_goals(&mut query.goals, &declared_variants(program));
+    Ok(query)
+}
+
+/// Names of all sum-type variants declared in a program
+fn declared_variants(program: &Program) -> HashSet<String> {
+    program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::TypeDef(TypeDef {
+                def: TypeDefKind::Sum { variants },
+                ..
+            }) => Some(variants.iter().cloned()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Nullary variants are written like variables (`Admin`), so once the whole
+/// program is parsed, upper-case names that are declared variants become
+/// zero-argument constructors.
+fn resolve_goals(goals: &mut [Goal], variants: &HashSet<String>) {
+    for goal in goals {
+        match goal {
+            Goal::Atom(atom) => resolve_terms(&mut atom.args, variants),
+            Goal::Unify(left, right, _) | Goal::Compare(_, left, right, _) => {
+                resolve_term(left, variants);
+                resolve_term(right, variants);
+            }
+            Goal::Match { subject, .. } => resolve_term(subject, variants),
+        }
+    }
+}
+
+fn resolve_terms(terms: &mut [Term], variants: &HashSet<String>) {
+    for term in terms {
+        resolve_term(term, variants);
+    }
+}
+
+fn resolve_term(term: &mut Term, variants: &HashSet<String>) {
+    match term {
+        Term::Var(name, span) if variants.contains(name.as_str()) => {
+            *term = Term::Construct {
+                constructor: std::mem::take(name),
+                args: vec![],
+                span: span.clone(),
+            };
+        }
+        Term::Construct { args, .. } => resolve_terms(args, variants),
+        Term::BinOp { left, right, .. } => {
+            resolve_term(left, variants);
+            resolve_term(right, variants);
+        }
+        _ => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,4 +233,132 @@ mod tests {
         let result = parse(source);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_parse_invalid_temporal_literals() {
+        let source = "rel due: Date\ndue(2025-02-30).";
+        let err = parse(source).unwrap_err().to_string();
+        assert!(
+            err.contains("invalid date literal 2025-02-30 at 18..28"),
+            "{}",
+            err
+        );
+
+        let source =
+            "rel at: Timestamp\nrel wait: Duration\nat(2025-01-01T25:00:00Z).\nwait(P1D2W).";
+        let err = parse(source).unwrap_err().to_string();
+        assert!(
+            err.contains("invalid timestamp literal 2025-01-01T25:00:00Z"),
+            "{}",
+            err
+        );
+        assert!(err.contains("invalid duration literal P1D2W"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_unexpected_character() {
+        let err = parse("rel p: Int\np(1) # note").unwrap_err().to_string();
+        assert!(err.contains("unexpected \"#\" at 16..17"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_doc_comment_before_query() {
+        let program = parse("rel p: Int\np(1).\n/// query doc\n?- p(1).").expect("Parse failed");
+        assert!(matches!(program.items.last(), Some(Item::Query(_))));
+    }
+
+    #[test]
+    fn test_parse_attribute_before_fact_or_query() {
+        let err = parse("rel p: Int\n@rule(\"x\")\np(1).")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("attribute @rule cannot precede a fact at 11..21"),
+            "{}",
+            err
+        );
+        let err = parse("rel p: Int\n@rule(\"x\")\n?- p(1).")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("attribute @rule cannot precede a query at 11..21"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_trailing_doc_comment() {
+        let program = parse("rel p: Int\np(1).\n/// Nothing follows").expect("Parse failed");
+        assert_eq!(program.items.len(), 2);
+        assert!(parse("/// Only a comment").is_ok());
+    }
+
+    #[test]
+    fn test_parse_query_without_prefix() {
+        let query = parse_query("ancestor(X, Y)").expect("Query parse failed");
+        assert_eq!(query.goals.len(), 1);
+        assert!(parse_query("?- ancestor(X, Y).").is_ok());
+    }
+
+    #[test]
+    fn test_table_rows_become_rules() {
+        let source = r#"
+            type Level = Junior | Senior
+            rel level: Int × Int × Level
+            table level(Sal, Years, L) {
+                | Sal < 100 | -          | Junior |
+                | -         | Years >= 5 | Senior |
+            }
+        "#;
+        let program = parse(source).expect("Parse failed");
+        assert!(matches!(program.items[2], Item::Table(_)));
+        let Item::Rule(rule) = &program.items[4] else {
+            panic!("Expected rule");
+        };
+        assert_eq!(rule.body.len(), 1);
+        assert_eq!(rule.doc.as_deref(), Some("level table row 2"));
+        // Sal is unconstrained in row 2 and the output is the resolved variant
+        assert!(matches!(&rule.head.args[0], Term::Var(name, _) if name == "_"));
+        assert!(matches!(&rule.head.args[1], Term::Var(name, _) if name == "Years"));
+        assert!(matches!(
+            &rule.head.args[2],
+            Term::Construct { constructor, .. } if constructor == "Senior"
+        ));
+
+        let ragged = "table t(X, Y) { | X > 1 | 2 | | 3 | }";
+        let err = parse(ragged).unwrap_err().to_string();
+        assert!(err.contains("row 2"), "{}", err);
+        let value_condition = "table t(X, Y) { | 1 | 2 | }";
+        assert!(parse(value_condition).is_err());
+    }
+
+    #[test]
+    fn test_resolve_nullary_variants() {
+        let source = r#"
+            type Level = | Public | Secret
+            rel level: String × Level
+            rel open: String
+            level("readme", Public).
+            open(Doc) :- level(Doc, L), L != Secret.
+        "#;
+        let program = parse(source).expect("Parse failed");
+        let Item::Rule(rule) = &program.items[4] else {
+            panic!("Expected rule");
+        };
+        let Goal::Compare(_, left, right, _) = &rule.body[1] else {
+            panic!("Expected comparison");
+        };
+        assert!(matches!(left, Term::Var(name, _) if name == "L"));
+        assert!(matches!(right, Term::Construct { constructor, args, .. }
+            if constructor == "Secret" && args.is_empty()));
+
+        let query = parse_query_for(&program, "level(D, Public)").unwrap();
+        let Goal::Atom(atom) = &query.goals[0] else {
+            panic!("Expected atom");
+        };
+        assert!(
+            matches!(&atom.args[1], Term::Construct { constructor, .. } if constructor == "Public")
+        );
+    }
 }
@@ -0,0 +1,717 @@
+/// Coverage checks for decision tables
+///
+/// Every variable that a table's conditions compare against constants is
+/// split into the intervals (or values) those constants delimit. Rows are
+/// evaluated on every combination: a combination no row matches is a gap,
+/// one that several rows match is an overlap. As with a DMN "unique" hit
+/// policy, both are reported as warnings. A column that makes the same
+/// relation calls and unifications in every row binds the inputs and is left
+/// out; a table whose other conditions are not all comparisons of a variable
+/// with a constant is reported as not analyzed rather than skipped silently.
+use crate::ast::{
+    CompareOp, Goal, Item, Program, Span, Table, TableCell, Term, TypeDef, TypeDefKind,
+};
+use crate::types::TypeWarning;
+use qed_runtime::temporal;
+use std::collections::BTreeMap;
+
+/// Combinations above which a table is not analyzed
+const MAX_CELLS: usize = 4096;
+
+/// Warnings for the gaps and overlaps of every table in a program
+pub fn check_tables(program: &Program) -> Vec<TypeWarning> {
+    let variants: Vec<&[String]> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::TypeDef(TypeDef {
+                def: TypeDefKind::Sum { variants },
+                ..
+            }) => Some(variants.as_slice()),
+            _ => None,
+        })
+        .collect();
+
+    program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Table(table) => Some(check_table(table, &variants)),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// A constant a condition compares with
+#[derive(Debug, Clone, PartialEq)]
+enum Constant {
+    Number(i64, NumberKind),
+    Text(String),
+    Variant(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumberKind {
+    Int,
+    Date,
+    Timestamp,
+    Duration,
+}
+
+impl NumberKind {
+    fn format(self, n: i64) -> String {
+        match self {
+            NumberKind::Int => n.to_string(),
+            NumberKind::Date => temporal::format_date(n),
+            NumberKind::Timestamp => temporal::format_timestamp(n),
+            NumberKind::Duration => temporal::format_duration(n),
+        }
+    }
+}
+
+/// `Var op constant`, with the variable on the left
+struct Condition {
+    var: String,
+    op: CompareOp,
+    value: Constant,
+}
+
+fn constant(term: &Term) -> Option<Constant> {
+    match term {
+        Term::Int(n, _) => Some(Constant::Number(*n, NumberKind::Int)),
+        Term::Date(n, _) => Some(Constant::Number(*n, NumberKind::Date)),
+        Term::Timestamp(n, _) => Some(Constant::Number(*n, NumberKind::Timestamp)),
+        Term::Duration(n, _) => Some(Constant::Number(*n, NumberKind::Duration)),
+        Term::String(s, _) => Some(Constant::Text(s.clone())),
+        Term::Construct {
+            constructor, args, ..
+        } if args.is_empty() => Some(Constant::Variant(constructor.clone())),
+        _ => None,
+    }
+}
+
+fn flip(op: CompareOp) -> CompareOp {
+    match op {
+        CompareOp::Lt => CompareOp::Gt,
+        CompareOp::Le => CompareOp::Ge,
+        CompareOp::Gt => CompareOp::Lt,
+        CompareOp::Ge => CompareOp::Le,
+        other => other,
+    }
+}
+
+fn condition(goal: &Goal) -> Option<Condition> {
+    let (op, left, right) = match goal {
+        Goal::Compare(op, left, right, _) => (*op, left, right),
+        Goal::Unify(left, right, _) => (CompareOp::Eq, left, right),
+        _ => return None,
+    };
+    let (op, var, value) = match (left, right) {
+        (Term::Var(var, _), other) => (op, var, constant(other)?),
+        (other, Term::Var(var, _)) => (flip(op), var, constant(other)?),
+        _ => return None,
+    };
+    if var == "_" {
+        return None;
+    }
+    // Strings and variants only compare for equality
+    if !matches!(value, Constant::Number(..)) && !matches!(op, CompareOp::Eq | CompareOp::Ne) {
+        return None;
+    }
+    Some(Condition {
+        var: var.clone(),
+        op,
+        value,
+    })
+}
+
+/// One of the pieces a variable's domain is split into
+#[derive(Debug, Clone)]
+enum Piece {
+    /// Numbers strictly or inclusively between two bounds; `None` is unbounded
+    Range {
+        lower: Option<(i64, bool)>,
+        upper: Option<(i64, bool)>,
+        sample: i64,
+    },
+    Value(Constant),
+    /// Any string that no condition mentions
+    Other,
+}
+
+/// A variable's domain, split at the constants its conditions use
+struct Dimension {
+    var: String,
+    pieces: Vec<Piece>,
+    kind: Option<NumberKind>,
+}
+
+impl Dimension {
+    fn new(var: &str, constants: &[&Constant], variants: &[&[String]]) -> Option<Self> {
+        let pieces = match constants[0] {
+            Constant::Number(_, kind) => {
+                let mut points = Vec::new();
+                for c in constants {
+                    match c {
+                        Constant::Number(n, k) if k == kind => points.push(*n),
+                        _ => return None,
+                    }
+                }
+                points.sort_unstable();
+                points.dedup();
+                let mut pieces = vec![Piece::Range {
+                    lower: None,
+                    upper: Some((points[0], false)),
+                    sample: points[0].checked_sub(1)?,
+                }];
+                for (i, &point) in points.iter().enumerate() {
+                    pieces.push(Piece::Range {
+                        lower: Some((point, true)),
+                        upper: Some((point, true)),
+                        sample: point,
+                    });
+                    let next = points.get(i + 1).copied();
+                    // No integer lies strictly between adjacent points
+                    if next.is_some_and(|next| next - point < 2) {
+                        continue;
+                    }
+                    pieces.push(Piece::Range {
+                        lower: Some((point, false)),
+                        upper: next.map(|next| (next, false)),
+                        sample: point.checked_add(1)?,
+                    });
+                }
+                return Some(Dimension {
+                    var: var.to_string(),
+                    pieces,
+                    kind: Some(*kind),
+                });
+            }
+            Constant::Text(_) => {
+                let mut pieces: Vec<Piece> = Vec::new();
+                for c in constants {
+                    if !matches!(c, Constant::Text(_)) {
+                        return None;
+                    }
+                    if !pieces
+                        .iter()
+                        .any(|p| matches!(p, Piece::Value(v) if v == *c))
+                    {
+                        pieces.push(Piece::Value((*c).clone()));
+                    }
+                }
+                pieces.push(Piece::Other);
+                pieces
+            }
+            Constant::Variant(_) => {
+                // The type declaring every variant compared with, as names
+                // can be shared between types
+                let all = variants.iter().find(|vs| {
+                    constants
+                        .iter()
+                        .all(|c| matches!(c, Constant::Variant(v) if vs.contains(v)))
+                })?;
+                all.iter()
+                    .map(|v| Piece::Value(Constant::Variant(v.clone())))
+                    .collect()
+            }
+        };
+        Some(Dimension {
+            var: var.to_string(),
+            pieces,
+            kind: None,
+        })
+    }
+
+    /// Whether `piece` satisfies `op value`
+    fn holds(&self, piece: &Piece, op: CompareOp, value: &Constant) -> bool {
+        match (piece, value) {
+            (Piece::Range { sample, .. }, Constant::Number(n, _)) => match op {
+                CompareOp::Eq => sample == n,
+                CompareOp::Ne => sample != n,
+                CompareOp::Lt => sample < n,
+                CompareOp::Le => sample <= n,
+                CompareOp::Gt => sample > n,
+                CompareOp::Ge => sample >= n,
+            },
+            (Piece::Value(v), _) => (v == value) == (op == CompareOp::Eq),
+            (Piece::Other, _) => op == CompareOp::Ne,
+            _ => false,
+        }
+    }
+
+    /// Describe a set of pieces, or `None` if it is the whole domain
+    fn describe(&self, set: &[usize]) -> Option<String> {
+        if set.len() == self.pieces.len() {
+            return None;
+        }
+        let var = &self.var;
+        if let Some(kind) = self.kind {
+            // Adjacent pieces join into one range
+            let mut runs: Vec<(usize, usize)> = Vec::new();
+            for &i in set {
+                match runs.last_mut() {
+                    Some((_, end)) if *end + 1 == i => *end = i,
+                    _ => runs.push((i, i)),
+                }
+            }
+            let ranges: Vec<String> = runs
+                .iter()
+                .map(|&(start, end)| {
+                    let lower = match &self.pieces[start] {
+                        Piece::Range { lower, .. } => *lower,
+                        _ => None,
+                    };
+                    let upper = match &self.pieces[end] {
+                        Piece::Range { upper, .. } => *upper,
+                        _ => None,
+                    };
+                    let bound = |(n, inclusive): (i64, bool), strict: &str, loose: &str| {
+                        let op = if inclusive { loose } else { strict };
+                        (op.to_string(), kind.format(n))
+                    };
+                    match (lower, upper) {
+                        (Some((a, true)), Some((b, true))) if a == b => {
+                            format!("{} = {}", var, kind.format(a))
+                        }
+                        (Some(lower), Some(upper)) => {
+                            let (lop, l) = bound(lower, "<", "<=");
+                            let (uop, u) = bound(upper, "<", "<=");
+                            format!("{} {} {} {} {}", l, lop, var, uop, u)
+                        }
+                        (Some(lower), None) => {
+                            let (op, l) = bound(lower, ">", ">=");
+                            format!("{} {} {}", var, op, l)
+                        }
+                        (None, Some(upper)) => {
+                            let (op, u) = bound(upper, "<", "<=");
+                            format!("{} {} {}", var, op, u)
+                        }
+                        (None, None) => var.to_string(),
+                    }
+                })
+                .collect();
+            return Some(ranges.join(" or "));
+        }
+
+        let name = |piece: &Piece| match piece {
+            Piece::Value(Constant::Text(s)) => format!("{:?}", s),
+            Piece::Value(Constant::Variant(v)) => v.clone(),
+            _ => String::new(),
+        };
+        if set.iter().any(|&i| matches!(self.pieces[i], Piece::Other)) {
+            let excluded: Vec<String> = (0..self.pieces.len())
+                .filter(|i| !set.contains(i))
+                .map(|i| name(&self.pieces[i]))
+                .collect();
+            return Some(match excluded.as_slice() {
+                [one] => format!("{} != {}", var, one),
+                _ => format!("{} not in {{{}}}", var, excluded.join(", ")),
+            });
+        }
+        let included: Vec<String> = set.iter().map(|&i| name(&self.pieces[i])).collect();
+        Some(match included.as_slice() {
+            [one] => format!("{} = {}", var, one),
+            _ => format!("{} in {{{}}}", var, included.join(", ")),
+        })
+    }
+}
+
+/// A set of combinations: the pieces allowed in each dimension
+type Region = Vec<Vec<usize>>;
+
+/// Relations a cell calls, with `=` for each unification that is not a
+/// condition, if it holds nothing else
+fn calls(cell: &TableCell) -> Option<Vec<&str>> {
+    match cell {
+        TableCell::Goals(goals, _) => goals
+            .iter()
+            .map(|goal| match goal {
+                Goal::Atom(atom) => Some(atom.relation.as_str()),
+                Goal::Unify(..) if condition(goal).is_none() => Some("="),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn goal_span(goal: &Goal) -> &Span {
+    match goal {
+        Goal::Atom(atom) => &atom.span,
+        Goal::Unify(_, _, span) | Goal::Compare(_, _, _, span) | Goal::Match { span, .. } => span,
+    }
+}
+
+fn check_table(table: &Table, variants: &[&[String]]) -> Vec<TypeWarning> {
+    let skipped = |reason: String, span: &Span| {
+        vec![TypeWarning::TableNotAnalyzed {
+            table: table.head.relation.clone(),
+            reason,
+            span: span.clone(),
+        }]
+    };
+
+    // A column making the same calls in every row binds the inputs rather
+    // than telling rows apart
+    let columns = table.rows.first().map_or(0, |row| row.cells.len());
+    let binding: Vec<bool> = (0..columns.saturating_sub(1))
+        .map(|i| {
+            let first = table.rows.first().and_then(|row| calls(&row.cells[i]));
+            first.is_some()
+                && table
+                    .rows
+                    .iter()
+                    .all(|row| row.cells.get(i).and_then(calls) == first)
+        })
+        .collect();
+
+    // Conditions of each row; any other goal stops the analysis
+    let mut rows: Vec<Vec<Condition>> = Vec::new();
+    for (n, row) in table.rows.iter().enumerate() {
+        let mut conditions = Vec::new();
+        for (column, (cell, _)) in row
+            .cells
+            .iter()
+            .zip(&binding)
+            .enumerate()
+            .filter(|(_, (_, binds))| !**binds)
+        {
+            if let TableCell::Goals(goals, _) = cell {
+                for goal in goals {
+                    match (condition(goal), goal) {
+                        (Some(c), _) => conditions.push(c),
+                        (None, Goal::Atom(_) | Goal::Unify(..)) if calls(cell).is_some() => {
+                            return skipped(
+                                format!(
+                                    "column {} does not bind the same way in every row",
+                                    column + 1
+                                ),
+                                goal_span(goal),
+                            )
+                        }
+                        (None, _) => {
+                            return skipped(
+                                format!(
+                                    "row {} has a condition other than comparing a variable with a constant",
+                                    n + 1
+                                ),
+                                goal_span(goal),
+                            )
+                        }
+                    }
+                }
+            }
+        }
+        rows.push(conditions);
+    }
+
+    // Dimensions in the order the table first mentions them
+    let mut constants: Vec<(&str, Vec<&Constant>)> = Vec::new();
+    for c in rows.iter().flatten() {
+        match constants.iter_mut().find(|(var, _)| *var == c.var) {
+            Some((_, values)) => values.push(&c.value),
+            None => constants.push((&c.var, vec![&c.value])),
+        }
+    }
+    let mut dims = Vec::new();
+    for (var, values) in &constants {
+        match Dimension::new(var, values, variants) {
+            Some(dim) => dims.push(dim),
+            None => {
+                return skipped(
+                    format!(
+                        "{} is compared with constants that do not split one type",
+                        var
+                    ),
+                    &table.head.span,
+                )
+            }
+        }
+    }
+    let cells = dims
+        .iter()
+        .try_fold(1usize, |n, d| n.checked_mul(d.pieces.len()));
+    if cells.is_none_or(|n| n > MAX_CELLS) {
+        return skipped(
+            format!(
+                "its conditions split the inputs into more than {} cases",
+                MAX_CELLS
+            ),
+            &table.head.span,
+        );
+    }
+
+    // Evaluate every row on every combination of pieces
+    let mut gaps: Vec<Region> = Vec::new();
+    let mut overlaps: BTreeMap<Vec<usize>, Vec<Region>> = BTreeMap::new();
+    let mut combination = vec![0; dims.len()];
+    loop {
+        let matching: Vec<usize> = rows
+            .iter()
+            .enumerate()
+            .filter(|(_, conditions)| {
+                conditions.iter().all(|c| {
+                    let d = dims.iter().position(|d| d.var == c.var).unwrap_or_default();
+                    dims[d].holds(&dims[d].pieces[combination[d]], c.op, &c.value)
+                })
+            })
+            .map(|(i, _)| i + 1)
+            .collect();
+        let region = combination.iter().map(|&i| vec![i]).collect();
+        match matching.len() {
+            0 => gaps.push(region),
+            1 => {}
+            _ => overlaps.entry(matching).or_default().push(region),
+        }
+
+        // Next combination, odometer style
+        let mut d = dims.len();
+        loop {
+            if d == 0 {
+                break;
+            }
+            d -= 1;
+            combination[d] += 1;
+            if combination[d] < dims[d].pieces.len() {
+                break;
+            }
+            combination[d] = 0;
+        }
+        if combination.iter().all(|&i| i == 0) {
+            break;
+        }
+    }
+
+    let name = &table.head.relation;
+    let describe = |region: &Region| {
+        let parts: Vec<String> = dims
+            .iter()
+            .zip(region)
+            .filter_map(|(dim, set)| dim.describe(set))
+            .collect();
+        if parts.is_empty() {
+            "any input".to_string()
+        } else {
+            parts.join(", ")
+        }
+    };
+
+    let mut warnings: Vec<TypeWarning> = merge(gaps)
+        .iter()
+        .map(|region| TypeWarning::TableGap {
+            table: name.clone(),
+            region: describe(region),
+            span: table.head.span.clone(),
+        })
+        .collect();
+    for (matching, regions) in overlaps {
+        let numbers: Vec<String> = matching.iter().map(|n| n.to_string()).collect();
+        let (last, rest) = numbers.split_last().expect("an overlap has two rows");
+        // Reported at the last of the overlapping rows
+        let span = &table.rows[matching[matching.len() - 1] - 1].span;
+        for region in merge(regions) {
+            warnings.push(TypeWarning::TableOverlap {
+                table: name.clone(),
+                rows: format!("{} and {}", rest.join(", "), last),
+                region: describe(&region),
+                span: span.clone(),
+            });
+        }
+    }
+    warnings
+}
+
+/// Join regions that differ in a single dimension until none do,
+/// starting from the last column so earlier columns read as ranges
+fn merge(mut regions: Vec<Region>) -> Vec<Region> {
+    let dims = regions.first().map_or(0, Vec::len);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for d in (0..dims).rev() {
+            let mut merged: Vec<Region> = Vec::new();
+            for region in regions {
+                let same = merged
+                    .iter_mut()
+                    .find(|m| (0..dims).all(|other| other == d || m[other] == region[other]));
+                match same {
+                    Some(m) => {
+                        m[d].extend(&region[d]);
+                        m[d].sort_unstable();
+                        m[d].dedup();
+                        changed = true;
+                    }
+                    None => merged.push(region),
+                }
+            }
+            regions = merged;
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn warnings(source: &str) -> Vec<String> {
+        check_tables(&parse(source).unwrap())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_table_gaps_and_overlaps() {
+        let source = r#"
+            type Level = Junior | Senior | Lead
+            rel staff: Int × Int
+            rel level: Int × Level
+            table level(Id, L) {
+                | staff(Id, Years) | Years < 3  | Junior |
+                | staff(Id, Years) | Years >= 3, Years < 8 | Senior |
+                | staff(Id, Years) | Years >= 6 | Lead |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec!["Rows 2 and 3 of table level overlap for 6 <= Years < 8"]
+        );
+
+        let source = r#"
+            type Level = Junior | Senior
+            rel staff: Int × Int × Int
+            rel level: Int × Level
+            table level(Id, L) {
+                | staff(Id, Years, Rank) | Years < 3  | -         | Junior |
+                | staff(Id, Years, Rank) | Years > 5  | Rank == 1 | Senior |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec![
+                "Table level has no row for 3 <= Years <= 5",
+                "Table level has no row for Years > 5, Rank < 1 or Rank > 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_binding_column() {
+        let source = r#"
+            type Level = Junior | Senior
+            rel staff: Int × Int
+            rel level: Int × Level
+            table level(Id, L) {
+                | staff(Id, Years) | Years < 3  | Junior |
+                | staff(Id, Years) | Years >= 5 | Senior |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(found, vec!["Table level has no row for 3 <= Years < 5"]);
+
+        // Unifications bind like calls
+        let source = r#"
+            type Person = person(name: String, years: Int)
+            type Level = Junior | Senior
+            rel staff: Person
+            rel level: Person × Level
+            table level(P, L) {
+                | staff(P), person(_, Years) = P | Years < 3  | Junior |
+                | staff(P), person(_, Years) = P | Years >= 3 | Senior |
+            }
+        "#;
+        assert!(warnings(source).is_empty());
+
+        // Different calls tell rows apart, which the analysis cannot follow
+        let source = r#"
+            type Level = Junior | Senior
+            rel staff: Int × Int
+            rel contractor: Int × Int
+            rel level: Int × Level
+            table level(Id, L) {
+                | staff(Id, Years)      | Years < 3  | Junior |
+                | contractor(Id, Years) | Years >= 5 | Senior |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec!["Table level was not checked for gaps or overlaps: column 1 does not bind the same way in every row"]
+        );
+    }
+
+    #[test]
+    fn test_table_discrete_columns() {
+        let source = r#"
+            type Tier = Gold | Silver | Bronze
+            rel customer: Int × Tier × String
+            rel discount: Int × Int
+            table discount(Id, D) {
+                | customer(Id, T, Region) | T == Gold   | -              | 20 |
+                | customer(Id, T, Region) | T == Silver | Region == "EU" | 10 |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec![
+                "Table discount has no row for T = Silver, Region != \"EU\"",
+                "Table discount has no row for T = Bronze",
+            ]
+        );
+
+        // Conditions other than comparisons with constants are not analyzed
+        let source = r#"
+            rel bounds: Int × Int × Int
+            rel limit: Int × Int
+            table limit(Id, L) {
+                | bounds(Id, A, B) | A < B | 1 |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec!["Table limit was not checked for gaps or overlaps: row 1 has a condition other than comparing a variable with a constant"]
+        );
+    }
+
+    #[test]
+    fn test_table_not_analyzed() {
+        let source = r#"
+            rel score: Int × Int
+            rel grade: Int × String
+            table grade(Id, G) {
+                | score(Id, S) | S == 1   | "a" |
+                | score(Id, S) | S == "b" | "b" |
+            }
+        "#;
+        let found = warnings(source);
+        assert_eq!(
+            found,
+            vec!["Table grade was not checked for gaps or overlaps: S is compared with constants that do not split one type"]
+        );
+
+        let columns: Vec<String> = (0..8).map(|i| format!("X{} < {}", i, i)).collect();
+        let source = format!(
+            r#"
+            rel wide: Int × Int × Int × Int × Int × Int × Int × Int × Int
+            rel out: Int × Int
+            table out(Id, O) {{
+                | wide(Id, X0, X1, X2, X3, X4, X5, X6, X7) | {} | 1 |
+            }}
+        "#,
+            columns.join(" | ")
+        );
+        let found = warnings(&source);
+        assert_eq!(
+            found,
+            vec!["Table out was not checked for gaps or overlaps: its conditions split the inputs into more than 4096 cases"]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+/// Termination warnings for recursion over unbounded domains
+///
+/// Relations that call each other form strongly connected components. A
+/// rule in a recursive component whose head argument is computed from the
+/// answers of a recursive call, by arithmetic or by wrapping them in a
+/// constructor, can produce a new value on every round: `path_exists`
+/// summing costs around a cycle never runs out of answers. Such rules are
+/// reported unless a comparison in the body bounds the computed value or
+/// every value it is computed from, as in a counter guarded by `N < 10`.
+use crate::ast::{Goal, Item, Program, Rule, Term};
+use crate::deps::DependencyGraph;
+use crate::types::TypeWarning;
+use std::collections::HashSet;
+
+/// Warnings for the recursive rules of a program that can grow forever
+pub fn check_termination(program: &Program) -> Vec<TypeWarning> {
+    let rules: Vec<&Rule> = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Rule(rule) => Some(rule),
+            _ => None,
+        })
+        .collect();
+
+    let graph = DependencyGraph::build(program);
+
+    let mut warnings = Vec::new();
+    for rule in rules {
+        let recursive: Vec<&Term> = rule
+            .body
+            .iter()
+            .filter_map(|goal| match goal {
+                Goal::Atom(atom) if graph.same_component(&atom.relation, &rule.head.relation) => {
+                    Some(&atom.args)
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        if recursive.is_empty() {
+            continue;
+        }
+        if let Some(position) = growing_argument(rule, &recursive) {
+            let argument = match &rule.head.args[position] {
+                Term::Var(name, _) => format!("argument {} ({})", position + 1, name),
+                _ => format!("argument {}", position + 1),
+            };
+            warnings.push(TypeWarning::UnboundedRecursion {
+                relation: rule.head.relation.clone(),
+                argument,
+                span: rule.span.clone(),
+            });
+        }
+    }
+    warnings
+}
+
+/// The first head argument computed from the answers of a recursive call
+fn growing_argument(rule: &Rule, recursive: &[&Term]) -> Option<usize> {
+    // Values a recursive call returns, whole or in part
+    let mut answers = HashSet::new();
+    for arg in recursive {
+        vars(arg, &mut answers);
+    }
+    let whole: HashSet<&str> = recursive
+        .iter()
+        .filter_map(|arg| match arg {
+            Term::Var(name, _) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let grows = |term: &Term, computed: &HashSet<&str>| match term {
+        Term::BinOp { .. } => {
+            let mut used = HashSet::new();
+            vars(term, &mut used);
+            used.iter()
+                .any(|v| answers.contains(v) || computed.contains(v))
+        }
+        Term::Construct { args, .. } => args.iter().any(|arg| {
+            let Term::Var(name, _) = arg else {
+                return false;
+            };
+            whole.contains(name.as_str()) || computed.contains(name.as_str())
+        }),
+        _ => false,
+    };
+
+    // A comparison with a constant keeps the value in a finite range
+    let mut bounded = HashSet::new();
+    for goal in &rule.body {
+        if let Goal::Compare(_, left, right, _) = goal {
+            for (var, other) in [(left, right), (right, left)] {
+                if let Term::Var(name, _) = var {
+                    let mut other_vars = HashSet::new();
+                    vars(other, &mut other_vars);
+                    if other_vars.is_empty() {
+                        bounded.insert(name.as_str());
+                    }
+                }
+            }
+        }
+    }
+
+    // Variables an equality defines by arithmetic or construction over answers
+    let mut bound = HashSet::new();
+    let mut computed = HashSet::new();
+    for goal in &rule.body {
+        match goal {
+            Goal::Atom(atom) => atom.args.iter().for_each(|arg| vars(arg, &mut bound)),
+            Goal::Unify(left, right, _) => {
+                for (target, value) in [(left, right), (right, left)] {
+                    if let Term::Var(name, _) = target {
+                        if !bound.contains(name.as_str()) && grows(value, &computed) {
+                            computed.insert(name.as_str());
+                            // Computed from bounded values only, it is bounded too
+                            let mut used = HashSet::new();
+                            vars(value, &mut used);
+                            if used.iter().all(|v| bounded.contains(v)) {
+                                bounded.insert(name.as_str());
+                            }
+                        }
+                    }
+                }
+                vars(left, &mut bound);
+                vars(right, &mut bound);
+            }
+            Goal::Compare(..) | Goal::Match { .. } => {}
+        }
+    }
+
+    rule.head.args.iter().position(|arg| match arg {
+        Term::Var(name, _) => computed.contains(name.as_str()) && !bounded.contains(name.as_str()),
+        _ => grows(arg, &computed),
+    })
+}
+
+fn vars<'t>(term: &'t Term, out: &mut HashSet<&'t str>) {
+    match term {
+        Term::Var(name, _) if name != "_" => {
+            out.insert(name);
+        }
+        Term::Construct { args, .. } => args.iter().for_each(|arg| vars(arg, out)),
+        Term::BinOp { left, right, .. } => {
+            vars(left, out);
+            vars(right, out);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let program = parser::parse(source).expect("Parse failed");
+        check_termination(&program)
+            .iter()
+            .map(|w| w.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_growing_cost() {
+        let source = r#"
+            rel edge: Int × Int × Int
+            rel route: Int × Int × Int
+            route(X, Y, C) :- edge(X, Y, C).
+            route(X, Z, Total) :- edge(X, Y, C1), route(Y, Z, C2), Total = C1 + C2.
+        "#;
+        assert_eq!(
+            warnings(source),
+            vec!["Recursive rule for route computes argument 3 (Total) from its own answers, so it may derive infinitely many tuples"]
+        );
+        // A bound keeps the cost finite
+        let bounded = source.replace("Total = C1 + C2.", "Total = C1 + C2, Total <= 100.");
+        assert!(warnings(&bounded).is_empty());
+
+        // So does a bound on the value the next one is computed from
+        let source = r#"
+            rel count: Int
+            count(0).
+            count(M) :- count(N), N < 10, M = N + 1.
+        "#;
+        assert!(warnings(source).is_empty());
+        let unguarded = source.replace("N < 10, ", "");
+        assert_eq!(warnings(&unguarded).len(), 1);
+    }
+
+    #[test]
+    fn test_mutual_recursion_and_construction() {
+        let source = r#"
+            type Nat = succ(prev: Nat)
+            rel zero: Nat
+            rel even: Nat
+            rel odd: Nat
+            rel ancestor: Int × Int
+            rel parent: Int × Int
+            even(N) :- zero(N).
+            even(succ(N)) :- odd(N).
+            odd(succ(N)) :- even(N).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+        "#;
+        let found = warnings(source);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].starts_with("Recursive rule for even computes argument 1 "));
+        assert!(found[1].starts_with("Recursive rule for odd computes argument 1 "));
+    }
+}
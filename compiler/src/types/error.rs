@@ -0,0 +1,748 @@
+/// Structured type errors
+///
+/// Every error carries a stable code, the span it is reported at, secondary
+/// labels (such as the other use in a type conflict) and explanatory notes,
+/// so tools can consume them as data and `qedc check` can render them.
+use crate::ast::{BinOp, Span, Type};
+use crate::ir::Determinism;
+use ariadne::{Config, Label, Report, ReportKind, Source};
+use std::fmt;
+use thiserror::Error;
+
+/// A use of a term that requires it to have a type
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeUse {
+    pub ty: Type,
+    /// What the term is used as: "argument 2 of age"
+    pub what: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} from {}", self.ty, self.what)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum TypeError {
+    #[error("Type {name} already defined")]
+    DuplicateType { name: String, span: Span },
+    #[error("Unknown relation {name}")]
+    UnknownRelation { name: String, span: Span },
+    #[error("Relation {name} already defined")]
+    DuplicateRelation { name: String, span: Span },
+    #[error("Unknown constructor {name}")]
+    UnknownConstructor { name: String, span: Span },
+    #[error("{what} has {found} arguments, expected {expected}")]
+    Arity {
+        what: String,
+        found: usize,
+        expected: usize,
+        span: Span,
+    },
+    #[error("Variant {name} is declared by {types}, and nothing here says which")]
+    AmbiguousVariant {
+        name: String,
+        types: String,
+        span: Span,
+    },
+
+    #[error("{what} has type {found}, expected {expected}")]
+    Mismatch {
+        what: String,
+        found: Type,
+        expected: Type,
+        span: Span,
+    },
+    #[error("Conflicting types for {subject}: {} vs {}", first.ty, second.ty)]
+    Conflict {
+        subject: String,
+        first: Box<TypeUse>,
+        second: Box<TypeUse>,
+        span: Span,
+    },
+    #[error("Cannot infer a type for variable {var}")]
+    CannotInfer { var: String, span: Span },
+    #[error("Cannot compare {comparison}: {ty} has no ordering")]
+    NoOrdering {
+        comparison: String,
+        ty: Type,
+        span: Span,
+    },
+    #[error("Operator {op:?} is not defined for {left} and {right}")]
+    UndefinedOperator {
+        op: BinOp,
+        left: Type,
+        right: Type,
+        span: Span,
+    },
+    #[error("Duration {duration} is not a whole number of days and cannot be added to a Date")]
+    PartialDays { duration: String, span: Span },
+    #[error("Duration added to a Date must be a constant whole number of days")]
+    UncheckedDays { span: Span },
+    #[error("Division by zero in a constant expression")]
+    DivisionByZero { span: Span },
+    #[error("Constant expression overflows a 64-bit integer")]
+    ArithmeticOverflow { span: Span },
+    #[error("Invalid regular expression \"{pattern}\": {error}")]
+    InvalidRegex {
+        pattern: String,
+        error: String,
+        span: Span,
+    },
+    #[error("The pattern of matches must be a string literal")]
+    NonLiteralPattern { span: Span },
+
+    #[error("Fact {relation} argument {position} contains variables")]
+    NonGroundFact {
+        relation: String,
+        position: usize,
+        span: Span,
+    },
+    #[error("Fact {relation} argument {position} uses {clock}")]
+    NowInFact {
+        relation: String,
+        position: usize,
+        /// `now()` or `today()`
+        clock: String,
+        span: Span,
+    },
+    #[error("Unsafe rule: variable {var} in head of {relation} is not bound by the body")]
+    UnsafeVariable {
+        var: String,
+        relation: String,
+        span: Span,
+    },
+    #[error("Variable {var} in {context} is not bound by an earlier positive goal or equality")]
+    UnboundVariable {
+        var: String,
+        /// What uses the variable: "comparison", "arithmetic expression"
+        context: &'static str,
+        span: Span,
+    },
+    #[error("Call to {relation} in {clause} has unbound arguments, but {relation} is answered top-down and cannot enumerate its tuples")]
+    UnboundCall {
+        relation: String,
+        /// The calling clause: "constraint 0", "rule 3 of grant"
+        clause: String,
+        span: Span,
+    },
+    #[error("Table {table} compares {var}, which no column binds")]
+    UnboundTableInput {
+        table: String,
+        var: String,
+        span: Span,
+    },
+    #[error("Integrity constraint violated{}", violation_details(label, bindings))]
+    ConstraintViolation {
+        /// `@rule` name or doc comment of the constraint
+        label: Option<String>,
+        /// The solution of the body: "U = \"a\""
+        bindings: Vec<String>,
+        /// Facts the solution was found from, and what each one says
+        facts: Vec<(Span, String)>,
+        span: Span,
+    },
+    #[error("Key position {position} of relation {relation} is out of range (arity {arity})")]
+    KeyOutOfRange {
+        relation: String,
+        position: usize,
+        arity: usize,
+        span: Span,
+    },
+    #[error("Key of relation {relation} lists position {position} twice")]
+    RepeatedKeyPosition {
+        relation: String,
+        position: usize,
+        span: Span,
+    },
+    #[error("Facts {} and {second} violate key({key}) of {relation}", first.0)]
+    KeyViolation {
+        relation: String,
+        key: String,
+        /// The earlier fact and where it is
+        first: Box<(String, Span)>,
+        second: String,
+        span: Span,
+    },
+
+    #[error("Relation {relation} already has an explanation template")]
+    DuplicateTemplate {
+        relation: String,
+        first: Span,
+        span: Span,
+    },
+    #[error("Explanation template for {relation} has {found} parameters, expected {expected}")]
+    TemplateArity {
+        relation: String,
+        found: usize,
+        expected: usize,
+        span: Span,
+    },
+    #[error("Explanation template for {relation} repeats parameter {param}")]
+    RepeatedParameter {
+        relation: String,
+        param: String,
+        span: Span,
+    },
+    #[error("Invalid explanation template for {relation}: {error}")]
+    InvalidTemplate {
+        relation: String,
+        error: String,
+        span: Span,
+    },
+    #[error("Explanation template for {relation} uses {{{var}}}, which is not a parameter")]
+    UnknownPlaceholder {
+        relation: String,
+        var: String,
+        span: Span,
+    },
+    #[error(
+        "Explanation template for {relation} uses {path}.{field}, but {ty} has no field {field}"
+    )]
+    UnknownField {
+        relation: String,
+        path: String,
+        field: String,
+        ty: Type,
+        span: Span,
+    },
+
+    #[error("Unknown combining algorithm \"{name}\" on relation {relation}")]
+    UnknownCombine {
+        name: String,
+        relation: String,
+        span: Span,
+    },
+    #[error("Relation {relation} uses {algorithm}, so its last argument must be a sum type with Deny and Permit (or Allow) variants, found {found}")]
+    CombineOutcome {
+        relation: String,
+        algorithm: String,
+        found: Type,
+        span: Span,
+    },
+    #[error("Rule name \"{name}\" is used by more than one rule")]
+    DuplicateRuleName {
+        name: String,
+        first: Span,
+        span: Span,
+    },
+    #[error("Rule overrides unknown rule \"{target}\"")]
+    UnknownOverride { target: String, span: Span },
+    #[error("Rule for {relation} cannot override \"{target}\", a rule for {other}")]
+    OverrideOtherRelation {
+        relation: String,
+        target: String,
+        other: String,
+        span: Span,
+    },
+    #[error("Rule \"{name}\" overrides itself through @overrides")]
+    OverrideCycle { name: String, span: Span },
+
+    #[error("Invalid mode declaration on {relation}: {reason}")]
+    InvalidMode {
+        relation: String,
+        reason: String,
+        span: Span,
+    },
+    #[error("Mode {mode} is declared {declared} but inferred {inferred}")]
+    ModeMismatch {
+        /// The call pattern: child(in, out)
+        mode: String,
+        declared: Determinism,
+        inferred: Determinism,
+        span: Span,
+    },
+}
+
+/// The label and bindings of a violated constraint: ": no-dual-roles with U = "a""
+fn violation_details(label: &Option<String>, bindings: &[String]) -> String {
+    let mut details = String::new();
+    if let Some(label) = label {
+        details.push_str(&format!(": {}", label));
+    }
+    if !bindings.is_empty() {
+        details.push_str(&format!(" with {}", bindings.join(", ")));
+    }
+    details
+}
+
+impl TypeError {
+    /// Stable code: E01xx names and arity, E02xx types, E03xx facts, rules
+    /// and keys, E04xx explanation templates, E05xx decision policies, E06xx
+    /// modes
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::DuplicateType { .. } => "E0101",
+            TypeError::UnknownRelation { .. } => "E0102",
+            TypeError::DuplicateRelation { .. } => "E0103",
+            TypeError::UnknownConstructor { .. } => "E0104",
+            TypeError::Arity { .. } => "E0105",
+            TypeError::AmbiguousVariant { .. } => "E0106",
+            TypeError::Mismatch { .. } => "E0201",
+            TypeError::Conflict { .. } => "E0202",
+            TypeError::CannotInfer { .. } => "E0203",
+            TypeError::NoOrdering { .. } => "E0204",
+            TypeError::UndefinedOperator { .. } => "E0205",
+            TypeError::PartialDays { .. } => "E0206",
+            TypeError::UncheckedDays { .. } => "E0210",
+            TypeError::InvalidRegex { .. } => "E0207",
+            TypeError::DivisionByZero { .. } => "E0208",
+            TypeError::ArithmeticOverflow { .. } => "E0209",
+            TypeError::NonLiteralPattern { .. } => "E0211",
+            TypeError::NonGroundFact { .. } => "E0301",
+            TypeError::NowInFact { .. } => "E0302",
+            TypeError::UnsafeVariable { .. } => "E0303",
+            TypeError::KeyOutOfRange { .. } => "E0304",
+            TypeError::RepeatedKeyPosition { .. } => "E0305",
+            TypeError::KeyViolation { .. } => "E0306",
+            TypeError::UnboundVariable { .. } => "E0307",
+            TypeError::UnboundCall { .. } => "E0308",
+            TypeError::UnboundTableInput { .. } => "E0309",
+            TypeError::ConstraintViolation { .. } => "E0310",
+            TypeError::DuplicateTemplate { .. } => "E0401",
+            TypeError::TemplateArity { .. } => "E0402",
+            TypeError::RepeatedParameter { .. } => "E0403",
+            TypeError::InvalidTemplate { .. } => "E0404",
+            TypeError::UnknownPlaceholder { .. } => "E0405",
+            TypeError::UnknownField { .. } => "E0406",
+            TypeError::UnknownCombine { .. } => "E0501",
+            TypeError::CombineOutcome { .. } => "E0502",
+            TypeError::DuplicateRuleName { .. } => "E0503",
+            TypeError::UnknownOverride { .. } => "E0504",
+            TypeError::OverrideOtherRelation { .. } => "E0505",
+            TypeError::OverrideCycle { .. } => "E0506",
+            TypeError::InvalidMode { .. } => "E0601",
+            TypeError::ModeMismatch { .. } => "E0602",
+        }
+    }
+
+    /// Where the error is reported
+    pub fn span(&self) -> &Span {
+        match self {
+            TypeError::DuplicateType { span, .. }
+            | TypeError::UnknownRelation { span, .. }
+            | TypeError::DuplicateRelation { span, .. }
+            | TypeError::UnknownConstructor { span, .. }
+            | TypeError::Arity { span, .. }
+            | TypeError::AmbiguousVariant { span, .. }
+            | TypeError::Mismatch { span, .. }
+            | TypeError::Conflict { span, .. }
+            | TypeError::CannotInfer { span, .. }
+            | TypeError::NoOrdering { span, .. }
+            | TypeError::UndefinedOperator { span, .. }
+            | TypeError::PartialDays { span, .. }
+            | TypeError::UncheckedDays { span }
+            | TypeError::InvalidRegex { span, .. }
+            | TypeError::DivisionByZero { span }
+            | TypeError::ArithmeticOverflow { span }
+            | TypeError::NonLiteralPattern { span }
+            | TypeError::NonGroundFact { span, .. }
+            | TypeError::NowInFact { span, .. }
+            | TypeError::UnsafeVariable { span, .. }
+            | TypeError::KeyOutOfRange { span, .. }
+            | TypeError::RepeatedKeyPosition { span, .. }
+            | TypeError::KeyViolation { span, .. }
+            | TypeError::UnboundVariable { span, .. }
+            | TypeError::UnboundCall { span, .. }
+            | TypeError::UnboundTableInput { span, .. }
+            | TypeError::ConstraintViolation { span, .. }
+            | TypeError::DuplicateTemplate { span, .. }
+            | TypeError::TemplateArity { span, .. }
+            | TypeError::RepeatedParameter { span, .. }
+            | TypeError::InvalidTemplate { span, .. }
+            | TypeError::UnknownPlaceholder { span, .. }
+            | TypeError::UnknownField { span, .. }
+            | TypeError::UnknownCombine { span, .. }
+            | TypeError::CombineOutcome { span, .. }
+            | TypeError::DuplicateRuleName { span, .. }
+            | TypeError::UnknownOverride { span, .. }
+            | TypeError::OverrideOtherRelation { span, .. }
+            | TypeError::OverrideCycle { span, .. }
+            | TypeError::InvalidMode { span, .. }
+            | TypeError::ModeMismatch { span, .. } => span,
+        }
+    }
+
+    /// Spans worth pointing at, the reported span first
+    pub fn labels(&self) -> Vec<(Span, String)> {
+        match self {
+            TypeError::Conflict { first, second, .. } => vec![
+                (second.span.clone(), second.to_string()),
+                (first.span.clone(), first.to_string()),
+            ],
+            TypeError::KeyViolation { first, .. } => vec![
+                (self.span().clone(), "this fact".to_string()),
+                (first.1.clone(), format!("conflicts with {}", first.0)),
+            ],
+            TypeError::DuplicateTemplate { first, .. } => vec![
+                (self.span().clone(), "second template".to_string()),
+                (first.clone(), "first template".to_string()),
+            ],
+            TypeError::ConstraintViolation { facts, .. } => {
+                let mut labels = vec![(self.span().clone(), self.to_string())];
+                labels.extend(
+                    facts
+                        .iter()
+                        .map(|(span, fact)| (span.clone(), format!("fact {}", fact))),
+                );
+                labels
+            }
+            TypeError::DuplicateRuleName { first, .. } => vec![
+                (self.span().clone(), "name used again".to_string()),
+                (first.clone(), "first used here".to_string()),
+            ],
+            _ => vec![(self.span().clone(), self.to_string())],
+        }
+    }
+
+    /// Explanations and hints shown below the labels
+    pub fn notes(&self) -> Vec<String> {
+        if let TypeError::ModeMismatch {
+            declared, inferred, ..
+        } = self
+        {
+            let mut notes = Vec::new();
+            if inferred.can_fail() && !declared.can_fail() {
+                notes.push("a call in this mode can have no answer".to_string());
+            }
+            if inferred.can_multi() && !declared.can_multi() {
+                notes.push(
+                    "overlapping clauses or a call with several answers can give more than one"
+                        .to_string(),
+                );
+            }
+            return notes;
+        }
+        let note = match self {
+            TypeError::AmbiguousVariant { .. } => {
+                "use it where a relation argument, a constructor field or a typed variable fixes its type"
+            }
+            TypeError::CannotInfer { .. } => {
+                "relate it to a relation argument, a constructor field or a literal"
+            }
+            TypeError::NoOrdering { .. } => {
+                "Int, String, Date, Timestamp, Duration and sum types are ordered"
+            }
+            TypeError::PartialDays { .. } => {
+                "Dates stay on midnight; use a Timestamp for shifts shorter than a day"
+            }
+            TypeError::UncheckedDays { .. } => {
+                "a Duration bound at run time may be shorter than a day; count days, as in P1D * N"
+            }
+            TypeError::NonLiteralPattern { .. } => {
+                "patterns are compiled when the program is checked; write one in quotes, as in matches(Path, \"^/docs/\")"
+            }
+            TypeError::NonGroundFact { .. } => "facts must be ground; derive tuples with a rule",
+            TypeError::NowInFact { .. } => "facts cannot depend on the evaluation time",
+            TypeError::UnsafeVariable { .. } => {
+                "bind it with a positive goal or an equality in the body"
+            }
+            TypeError::UnboundVariable { .. } => {
+                "goals run left to right; move a goal that binds it before this one"
+            }
+            TypeError::UnboundCall { .. } => {
+                "goals run left to right; bind its arguments with a goal before this call"
+            }
+            TypeError::UnboundTableInput { .. } => {
+                "start every row with a column that binds the inputs, such as | service(Emp, Sal, Years) |"
+            }
+            TypeError::ConstraintViolation { .. } => {
+                "the constraint's body must have no solution; remove or correct one of the facts"
+            }
+            TypeError::UnknownCombine { .. } => {
+                "expected deny-overrides, permit-overrides or first-applicable"
+            }
+            TypeError::OverrideCycle { .. } => "no rule in the cycle could ever win",
+            TypeError::InvalidMode { .. } => {
+                "write @mode(in, out, det) with one of det, semidet, multi or nondet"
+            }
+            _ => return Vec::new(),
+        };
+        vec![note.to_string()]
+    }
+
+    /// Render with ariadne against the source the spans point into
+    pub fn render(&self, path: &str, source: &str, color: bool) -> String {
+        let message = self.to_string();
+        render_report(
+            ReportKind::Error,
+            (self.code(), &message, self.span()),
+            &self.labels(),
+            &self.notes(),
+            (path, source, color),
+        )
+    }
+}
+
+/// Something legal but suspicious; `--deny-warnings` turns these into errors
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum TypeWarning {
+    #[error("Variable {var} occurs only once in this {clause}")]
+    SingletonVariable {
+        var: String,
+        clause: &'static str,
+        span: Span,
+    },
+    #[error("Relation {name} is declared but has no facts or rules")]
+    UndefinedRelation { name: String, span: Span },
+    #[error("Relation {name} is never used by a rule, query or constraint")]
+    UnusedRelation { name: String, span: Span },
+    #[error("Table {table} has no row for {region}")]
+    TableGap {
+        table: String,
+        region: String,
+        span: Span,
+    },
+    #[error("Rows {rows} of table {table} overlap for {region}")]
+    TableOverlap {
+        table: String,
+        rows: String,
+        region: String,
+        span: Span,
+    },
+    #[error("Table {table} was not checked for gaps or overlaps: {reason}")]
+    TableNotAnalyzed {
+        table: String,
+        reason: String,
+        span: Span,
+    },
+    #[error("Recursive rule for {relation} computes {argument} from its own answers, so it may derive infinitely many tuples")]
+    UnboundedRecursion {
+        relation: String,
+        /// Position and name of the head argument: "argument 3 (TotalCost)"
+        argument: String,
+        span: Span,
+    },
+    #[error(
+        "Rule {rule} of {relation} is overridden by a rule that depends on {relation} in turn"
+    )]
+    NegativeCycle {
+        rule: String,
+        relation: String,
+        span: Span,
+    },
+    #[error("Integrity constraint was only partly checked against the facts: the search reached its {limit}")]
+    UncheckedConstraint { limit: String, span: Span },
+}
+
+impl TypeWarning {
+    /// Stable code: W01xx variables and relations, W02xx decision tables,
+    /// W03xx termination
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeWarning::SingletonVariable { .. } => "W0101",
+            TypeWarning::UndefinedRelation { .. } => "W0102",
+            TypeWarning::UnusedRelation { .. } => "W0103",
+            TypeWarning::TableGap { .. } => "W0201",
+            TypeWarning::TableOverlap { .. } => "W0202",
+            TypeWarning::TableNotAnalyzed { .. } => "W0203",
+            TypeWarning::UnboundedRecursion { .. } => "W0301",
+            TypeWarning::UncheckedConstraint { .. } => "W0302",
+            TypeWarning::NegativeCycle { .. } => "W0303",
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            TypeWarning::SingletonVariable { span, .. }
+            | TypeWarning::UndefinedRelation { span, .. }
+            | TypeWarning::UnusedRelation { span, .. }
+            | TypeWarning::TableGap { span, .. }
+            | TypeWarning::TableOverlap { span, .. }
+            | TypeWarning::TableNotAnalyzed { span, .. }
+            | TypeWarning::UnboundedRecursion { span, .. }
+            | TypeWarning::NegativeCycle { span, .. }
+            | TypeWarning::UncheckedConstraint { span, .. } => span,
+        }
+    }
+
+    pub fn notes(&self) -> Vec<String> {
+        let note = match self {
+            TypeWarning::SingletonVariable { var, .. } => {
+                format!("if this is intended, write _{} or _", var)
+            }
+            TypeWarning::UndefinedRelation { .. } => "every query on it will fail".to_string(),
+            TypeWarning::TableOverlap { .. } => {
+                "each matching row derives its own output".to_string()
+            }
+            TypeWarning::TableNotAnalyzed { .. } => "only comparisons of one variable with constants of one type are analyzed, next to columns that bind the inputs the same way in every row".to_string(),
+            TypeWarning::UnboundedRecursion { relation, .. } => format!(
+                "table {} with subsumption to keep only the least (or greatest) value, or bound the argument with a comparison",
+                relation
+            ),
+            TypeWarning::UncheckedConstraint { .. } => {
+                "the compiled program checks it in full when it loads".to_string()
+            }
+            TypeWarning::NegativeCycle { .. } => {
+                "whether the rule applies then depends on its own answers, so no evaluation order decides it; the program is not stratified".to_string()
+            }
+            _ => return Vec::new(),
+        };
+        vec![note]
+    }
+
+    /// Render with ariadne against the source the spans point into
+    pub fn render(&self, path: &str, source: &str, color: bool) -> String {
+        let message = self.to_string();
+        let label = (self.span().clone(), message.clone());
+        render_report(
+            ReportKind::Warning,
+            (self.code(), &message, self.span()),
+            &[label],
+            &self.notes(),
+            (path, source, color),
+        )
+    }
+}
+
+/// Build and print an ariadne report for a coded diagnostic
+fn render_report(
+    kind: ReportKind,
+    (code, message, span): (&str, &str, &Span),
+    labels: &[(Span, String)],
+    notes: &[String],
+    (path, source, color): (&str, &str, bool),
+) -> String {
+    // ariadne counts characters; spans are byte offsets
+    let chars = |byte: usize| source[..byte.min(source.len())].chars().count();
+    let range = |span: &Span| chars(span.start)..chars(span.end);
+
+    let mut report = Report::build(kind, path, range(span).start)
+        .with_code(code)
+        .with_message(message)
+        .with_config(Config::default().with_color(color));
+    for (span, text) in labels {
+        report = report.with_label(Label::new((path, range(span))).with_message(text));
+    }
+    for note in notes {
+        report = report.with_note(note);
+    }
+
+    let mut out = Vec::new();
+    report
+        .finish()
+        .write((path, Source::from(source)), &mut out)
+        .expect("writing to a Vec cannot fail");
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Every type error found in a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeErrors(pub Vec<TypeError>);
+
+impl fmt::Display for TypeErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let span = error.span();
+            write!(
+                f,
+                "[{}] at {}..{}: {}",
+                error.code(),
+                span.start,
+                span.end,
+                error
+            )?;
+            // Secondary locations, such as the other side of a conflict
+            for (span, text) in error.labels().iter().skip(1) {
+                write!(f, "; {} at {}..{}", text, span.start, span.end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TypeErrors {
+    /// Like `Display`, with line:col positions in `source` and each error's notes
+    pub fn describe(&self, source: &str) -> String {
+        let at = |span: &Span| {
+            let (line, col) = span.line_col(source);
+            format!("{}:{}", line, col)
+        };
+        let mut out = String::new();
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            out += &format!("[{}] at {}: {}", error.code(), at(error.span()), error);
+            for (span, text) in error.labels().iter().skip(1) {
+                out += &format!("; {} at {}", text, at(span));
+            }
+            for note in error.notes() {
+                out += &format!("\n  note: {}", note);
+            }
+        }
+        out
+    }
+}
+
+impl std::error::Error for TypeErrors {}
+
+impl From<TypeError> for TypeErrors {
+    fn from(error: TypeError) -> Self {
+        TypeErrors(vec![error])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_type_error() {
+        let source = "rel p: Int\np(X) :- q(X).\n";
+        let start = source.find("q(X)").unwrap();
+        let error = TypeError::UnknownRelation {
+            name: "q".to_string(),
+            span: Span {
+                start,
+                end: start + 4,
+            },
+        };
+        assert_eq!(error.code(), "E0102");
+        let rendered = error.render("test.qed", source, false);
+        assert!(
+            rendered.contains("[E0102] Error: Unknown relation q"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("test.qed:2:9"), "{}", rendered);
+
+        let errors = TypeErrors(vec![error]);
+        assert_eq!(
+            errors.to_string(),
+            format!("[E0102] at {}..{}: Unknown relation q", start, start + 4)
+        );
+        assert_eq!(
+            errors.describe(source),
+            "[E0102] at 2:9: Unknown relation q"
+        );
+    }
+
+    #[test]
+    fn test_describe_type_errors_with_notes() {
+        let source = "rel p: Int\np(X) :- X > 1.\n";
+        let start = source.find("X > 1").unwrap();
+        let errors = TypeErrors(vec![TypeError::UnboundVariable {
+            var: "X".to_string(),
+            context: "comparison",
+            span: Span {
+                start,
+                end: start + 1,
+            },
+        }]);
+        let described = errors.describe(source);
+        assert!(described.starts_with("[E0307] at 2:9: "), "{}", described);
+        assert!(
+            described.ends_with(
+                "\n  note: goals run left to right; move a goal that binds it before this one"
+            ),
+            "{}",
+            described
+        );
+    }
+}
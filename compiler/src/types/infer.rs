@@ -0,0 +1,581 @@
+/// Constraint-based type inference for the variables of a clause
+///
+/// Each rule, query or constraint is inferred on its own. Walking its goals
+/// gives every term a type variable and records equalities between them;
+/// literals, constructor parameters and relation signatures pin variables
+/// to known types. Equalities are solved with union-find, so the result
+/// does not depend on the order of goals. Arithmetic and ordering are
+/// resolved once the types of their operands are known.
+use super::{
+    binop_result_type, check_whole_days, Checked, ClauseTypes, TypeChecker, TypeError, TypeUse,
+    VariantTypes,
+};
+use crate::ast::*;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// The use of a term that requires it to have a type
+#[derive(Debug, Clone)]
+struct Origin {
+    what: String,
+    span: Span,
+}
+
+/// `left op right = result`, solved once the operand types are known
+struct Arith<'a> {
+    result: usize,
+    left: usize,
+    right: usize,
+    op: BinOp,
+    term: &'a Term,
+}
+
+/// `left op right` with an ordering operator
+struct Ordered<'a> {
+    ty: usize,
+    op: CompareOp,
+    left: &'a Term,
+    right: &'a Term,
+}
+
+/// Types of arithmetic operands, tried when an operand has no other source
+const ARITH_TYPES: [&str; 4] = ["Int", "Date", "Timestamp", "Duration"];
+
+/// Type variables and the substitution solved for one clause
+pub(super) struct Inference<'a> {
+    checker: &'a TypeChecker,
+    /// Union-find parent of each type variable
+    parent: Vec<usize>,
+    /// Solved type of each root, with the use that required it
+    solved: Vec<Option<(Type, Origin)>>,
+    /// Type variable and first occurrence of each clause variable
+    vars: Vec<(String, usize, Span)>,
+    equalities: Vec<(usize, usize, String)>,
+    arith: Vec<Arith<'a>>,
+    ordered: Vec<Ordered<'a>>,
+    /// Variant names several types declare, typed by what they meet
+    shared: Vec<(usize, &'a str, &'a Span)>,
+}
+
+impl<'a> Inference<'a> {
+    pub(super) fn new(checker: &'a TypeChecker) -> Self {
+        Inference {
+            checker,
+            parent: Vec::new(),
+            solved: Vec::new(),
+            vars: Vec::new(),
+            equalities: Vec::new(),
+            arith: Vec::new(),
+            ordered: Vec::new(),
+            shared: Vec::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.solved.push(None);
+        self.parent.len() - 1
+    }
+
+    /// A type variable already solved to `ty`
+    fn known(&mut self, ty: Type, what: String, span: &Span) -> usize {
+        let v = self.fresh();
+        self.solved[v] = Some((
+            ty,
+            Origin {
+                what,
+                span: span.clone(),
+            },
+        ));
+        v
+    }
+
+    fn find(&mut self, v: usize) -> usize {
+        let parent = self.parent[v];
+        if parent == v {
+            return v;
+        }
+        let root = self.find(parent);
+        self.parent[v] = root;
+        root
+    }
+
+    fn solution(&mut self, v: usize) -> Option<Type> {
+        let root = self.find(v);
+        self.solved[root].as_ref().map(|(ty, _)| ty.clone())
+    }
+
+    /// Require the term to have the type of a signature position
+    fn expect(&mut self, term: &'a Term, ty: &Type, what: String) -> Checked<()> {
+        let actual = self.term(term)?;
+        let expected = self.known(ty.clone(), what, term.span());
+        self.equalities
+            .push((actual, expected, TypeChecker::format_term(term)));
+        Ok(())
+    }
+
+    /// Generate constraints for a term and return its type variable
+    fn term(&mut self, term: &'a Term) -> Checked<usize> {
+        let literal = |name: &str| Type::Named(name.to_string());
+        let text = || TypeChecker::format_term(term);
+        Ok(match term {
+            // Every `_` is a distinct variable
+            Term::Var(name, _) if name == "_" => self.fresh(),
+            Term::Var(name, span) => match self.vars.iter().find(|(n, _, _)| n == name) {
+                Some((_, v, _)) => *v,
+                None => {
+                    let v = self.fresh();
+                    self.vars.push((name.clone(), v, span.clone()));
+                    v
+                }
+            },
+            Term::Int(_, span) => self.known(literal("Int"), text(), span),
+            Term::String(_, span) => self.known(literal("String"), text(), span),
+            Term::Date(_, span) | Term::Today(span) => self.known(literal("Date"), text(), span),
+            Term::Timestamp(_, span) | Term::Now(span) => {
+                self.known(literal("Timestamp"), text(), span)
+            }
+            Term::Duration(_, span) => self.known(literal("Duration"), text(), span),
+            Term::Construct {
+                constructor,
+                args,
+                span,
+            } if args.is_empty() && self.checker.env.ambiguous_variant(constructor).is_some() => {
+                let v = self.fresh();
+                self.shared.push((v, constructor, span));
+                v
+            }
+            Term::Construct {
+                constructor,
+                args,
+                span,
+            } => {
+                let (param_types, result_ty) = self
+                    .checker
+                    .env
+                    .get_constructor_info(constructor)
+                    .cloned()
+                    .ok_or_else(|| TypeError::UnknownConstructor {
+                        name: constructor.clone(),
+                        span: span.clone(),
+                    })?;
+                if args.len() != param_types.len() {
+                    return Err(TypeError::Arity {
+                        what: format!("Constructor {}", constructor),
+                        found: args.len(),
+                        expected: param_types.len(),
+                        span: span.clone(),
+                    });
+                }
+                for (i, (arg, param_ty)) in args.iter().zip(param_types.iter()).enumerate() {
+                    self.expect(
+                        arg,
+                        param_ty,
+                        format!("argument {} of {}", i + 1, constructor),
+                    )?;
+                }
+                self.known(Type::Named(result_ty), text(), span)
+            }
+            Term::BinOp {
+                op, left, right, ..
+            } => {
+                let left = self.term(left)?;
+                let right = self.term(right)?;
+                let result = self.fresh();
+                self.arith.push(Arith {
+                    result,
+                    left,
+                    right,
+                    op: *op,
+                    term,
+                });
+                result
+            }
+        })
+    }
+
+    /// Arguments of a rule head or relation call
+    pub(super) fn atom(&mut self, atom: &'a Atom, what: &str) -> Checked<()> {
+        let expected_types = self
+            .checker
+            .get_relation_arg_types(&atom.relation, &atom.span)?;
+        if atom.args.len() != expected_types.len() {
+            return Err(TypeError::Arity {
+                what: format!("{} {}", what, atom.relation),
+                found: atom.args.len(),
+                expected: expected_types.len(),
+                span: atom.span.clone(),
+            });
+        }
+        for (i, (arg, ty)) in atom.args.iter().zip(expected_types.iter()).enumerate() {
+            self.expect(arg, ty, format!("argument {} of {}", i + 1, atom.relation))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn goal(&mut self, goal: &'a Goal) -> Checked<()> {
+        match goal {
+            Goal::Atom(atom) => self.atom(atom, "Goal")?,
+            Goal::Unify(left, right, _) => {
+                let l = self.term(left)?;
+                let r = self.term(right)?;
+                let text = format!(
+                    "{} = {}",
+                    TypeChecker::format_term(left),
+                    TypeChecker::format_term(right)
+                );
+                self.equalities.push((l, r, text));
+            }
+            Goal::Compare(op, left, right, _) => {
+                let l = self.term(left)?;
+                let r = self.term(right)?;
+                let text = format!(
+                    "{} {} {}",
+                    TypeChecker::format_term(left),
+                    op,
+                    TypeChecker::format_term(right)
+                );
+                self.equalities.push((l, r, text));
+                if !matches!(op, CompareOp::Eq | CompareOp::Ne) {
+                    self.ordered.push(Ordered {
+                        ty: l,
+                        op: *op,
+                        left,
+                        right,
+                    });
+                }
+            }
+            Goal::Match {
+                subject,
+                pattern,
+                pattern_span,
+                span,
+            } => {
+                let string_ty = Type::Named("String".to_string());
+                let actual = self.term(subject)?;
+                let expected = self.known(string_ty, "subject of matches".to_string(), span);
+                self.equalities
+                    .push((actual, expected, TypeChecker::format_term(subject)));
+
+                // Patterns are compiled once here so bad regexes never reach the runtime
+                if let Err(e) = Regex::new(pattern) {
+                    return Err(TypeError::InvalidRegex {
+                        pattern: pattern.clone(),
+                        error: e.to_string(),
+                        span: pattern_span.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge two type variables, failing if both are solved differently
+    fn unify(&mut self, a: usize, b: usize, text: &str) -> Checked<()> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return Ok(());
+        }
+        match (self.solved[a].take(), self.solved[b].take()) {
+            (Some((ta, oa)), Some((tb, ob))) if !TypeChecker::check_types_compatible(&ta, &tb) => {
+                // A literal or constructor meeting the type its position expects
+                if oa.span == ob.span {
+                    return Err(TypeError::Mismatch {
+                        what: oa.what,
+                        found: ta,
+                        expected: tb,
+                        span: ob.span,
+                    });
+                }
+                return Err(TypeError::Conflict {
+                    subject: text.to_string(),
+                    first: Box::new(TypeUse {
+                        ty: ta,
+                        what: oa.what,
+                        span: oa.span,
+                    }),
+                    second: Box::new(TypeUse {
+                        ty: tb,
+                        what: ob.what,
+                        span: ob.span.clone(),
+                    }),
+                    span: ob.span,
+                });
+            }
+            (sa, sb) => self.solved[b] = sa.or(sb),
+        }
+        self.parent[a] = b;
+        Ok(())
+    }
+
+    /// Solve a variable to `ty` because of the term at `origin`
+    fn assign(&mut self, v: usize, ty: Type, origin: Origin) -> Checked<()> {
+        let known = self.known(ty, origin.what.clone(), &origin.span);
+        self.unify(v, known, &origin.what)
+    }
+
+    /// Solve the constraints and return the principal type of each variable
+    pub(super) fn solve(mut self) -> Checked<ClauseTypes> {
+        for (a, b, text) in std::mem::take(&mut self.equalities) {
+            self.unify(a, b, &text)?;
+        }
+
+        let mut variants = VariantTypes::new();
+        for (v, name, span) in std::mem::take(&mut self.shared) {
+            let types = self.checker.env.ambiguous_variant(name).unwrap_or_default();
+            match self.solution(v) {
+                Some(Type::Named(ty)) if types.contains(&ty) => {
+                    variants.insert((span.start, span.end), ty);
+                }
+                Some(ty) => {
+                    return Err(TypeError::Mismatch {
+                        what: format!("Variant {}", name),
+                        found: Type::Named(types.join(" or ")),
+                        expected: ty,
+                        span: span.clone(),
+                    })
+                }
+                None => {
+                    return Err(TypeError::AmbiguousVariant {
+                        name: name.to_string(),
+                        types: types.join(" and "),
+                        span: span.clone(),
+                    })
+                }
+            }
+        }
+
+        let mut arith = std::mem::take(&mut self.arith);
+        while !arith.is_empty() {
+            let before = arith.len();
+            let mut pending = Vec::new();
+            for constraint in arith {
+                if !self.solve_arith(&constraint)? {
+                    pending.push(constraint);
+                }
+            }
+            arith = pending;
+            if arith.len() == before && !self.guess_operands(&arith)? {
+                // Nothing else says what the operands are
+                let int = Type::Named("Int".to_string());
+                let stuck = &arith[0];
+                for v in [stuck.left, stuck.right] {
+                    if self.solution(v).is_none() {
+                        let origin = Self::operand(stuck.term);
+                        self.assign(v, int.clone(), origin)?;
+                    }
+                }
+            }
+        }
+
+        for ordered in std::mem::take(&mut self.ordered) {
+            let ty = match self.solution(ordered.ty) {
+                Some(ty) => ty,
+                // Unconstrained comparisons default to Int
+                None => {
+                    let int = Type::Named("Int".to_string());
+                    let origin = Origin {
+                        what: "ordering comparison".to_string(),
+                        span: ordered.left.span().clone(),
+                    };
+                    self.assign(ordered.ty, int.clone(), origin)?;
+                    int
+                }
+            };
+            if !self.checker.is_ordered(&ty) {
+                return Err(TypeError::NoOrdering {
+                    comparison: format!(
+                        "{} {} {}",
+                        TypeChecker::format_term(ordered.left),
+                        ordered.op,
+                        TypeChecker::format_term(ordered.right)
+                    ),
+                    ty,
+                    span: Span {
+                        start: ordered.left.span().start,
+                        end: ordered.right.span().end,
+                    },
+                });
+            }
+        }
+
+        let mut types = HashMap::new();
+        for (name, v, span) in std::mem::take(&mut self.vars) {
+            let ty = self.solution(v).ok_or_else(|| TypeError::CannotInfer {
+                var: name.clone(),
+                span,
+            })?;
+            types.insert(name, ty);
+        }
+        Ok(ClauseTypes {
+            vars: types,
+            variants,
+        })
+    }
+
+    fn operand(term: &Term) -> Origin {
+        Origin {
+            what: format!("operand of {}", TypeChecker::format_term(term)),
+            span: term.span().clone(),
+        }
+    }
+
+    /// Type an operation whose operands are known; false if they are not yet
+    fn solve_arith(&mut self, arith: &Arith) -> Checked<bool> {
+        let (Some(left_ty), Some(right_ty)) =
+            (self.solution(arith.left), self.solution(arith.right))
+        else {
+            return Ok(false);
+        };
+        let Term::BinOp {
+            left, right, span, ..
+        } = arith.term
+        else {
+            unreachable!("arithmetic constraints come from BinOp terms");
+        };
+
+        check_whole_days(&left_ty, &right_ty, left, right)?;
+        let result = binop_result_type(arith.op, &left_ty, &right_ty).ok_or_else(|| {
+            TypeError::UndefinedOperator {
+                op: arith.op,
+                left: left_ty,
+                right: right_ty,
+                span: span.clone(),
+            }
+        })?;
+        let origin = Origin {
+            what: TypeChecker::format_term(arith.term),
+            span: span.clone(),
+        };
+        self.assign(arith.result, result, origin)?;
+        Ok(true)
+    }
+
+    /// Solve an unknown operand when only one type fits what is known
+    fn guess_operands(&mut self, arith: &[Arith]) -> Checked<bool> {
+        for constraint in arith {
+            let known = [
+                self.solution(constraint.left),
+                self.solution(constraint.right),
+                self.solution(constraint.result),
+            ];
+            if known.iter().all(Option::is_none) {
+                continue;
+            }
+            let mut fits = Vec::new();
+            for l in ARITH_TYPES {
+                for r in ARITH_TYPES {
+                    let (l, r) = (Type::Named(l.to_string()), Type::Named(r.to_string()));
+                    let Some(result) = binop_result_type(constraint.op, &l, &r) else {
+                        continue;
+                    };
+                    let candidate = [l, r, result];
+                    if candidate
+                        .iter()
+                        .zip(&known)
+                        .all(|(c, k)| k.as_ref().is_none_or(|k| k == c))
+                    {
+                        fits.push(candidate);
+                    }
+                }
+            }
+            if let [[l, r, _]] = fits.as_slice() {
+                let (l, r) = (l.clone(), r.clone());
+                for (v, ty) in [(constraint.left, l), (constraint.right, r)] {
+                    if self.solution(v).is_none() {
+                        self.assign(v, ty, Self::operand(constraint.term))?;
+                    }
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use anyhow::Result;
+
+    fn checker(source: &str) -> Result<(TypeChecker, Program)> {
+        let program = parser::parse(source)?;
+        let mut checker = TypeChecker::new();
+        checker.check_program(&program)?;
+        Ok((checker, program))
+    }
+
+    #[test]
+    fn test_infer_independent_of_goal_order() {
+        // Y is used before anything says X is a Date. Safety rejects this
+        // order in a program, so the rule is inferred on its own.
+        let decls = r#"
+            rel due: String × Date
+            rel reminder: String × Date
+        "#;
+        let (checker, _) = checker(decls).expect("Type check failed");
+        let source = format!("{}\nreminder(C, Y) :- Y = X - P7D, due(C, X).", decls);
+        let program = parser::parse(&source).expect("Parse failed");
+        let Item::Rule(rule) = &program.items[2] else {
+            panic!("Expected rule");
+        };
+        let types = checker
+            .infer_clause(Some(&rule.head), &rule.body)
+            .expect("Inference failed");
+        assert_eq!(types["X"], Type::Named("Date".to_string()));
+        assert_eq!(types["Y"], Type::Named("Date".to_string()));
+        assert_eq!(types["C"], Type::Named("String".to_string()));
+    }
+
+    #[test]
+    fn test_infer_requires_a_type_for_every_variable() {
+        let source = r#"
+            rel p: Int
+            ?- p(A), X = Y.
+        "#;
+        let err = checker(source)
+            .err()
+            .expect("Type check passed")
+            .to_string();
+        assert!(
+            err.contains("Cannot infer a type for variable X"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_infer_reports_both_conflicting_uses() {
+        let source = r#"
+            rel age: String × Int
+            rel name: String
+            odd(N) :- age(N, A), X = A, name(X).
+            rel odd: String
+        "#;
+        let err = checker(source)
+            .err()
+            .expect("Type check passed")
+            .to_string();
+        let first = source.find("A)").unwrap();
+        let second = source.find("X)").unwrap();
+        assert!(
+            err.contains(&format!(
+                "[E0202] at {}..{}: Conflicting types for X: Int vs String",
+                second,
+                second + 1
+            )),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains(&format!(
+                "Int from argument 2 of age at {}..{}",
+                first,
+                first + 1
+            )),
+            "{}",
+            err
+        );
+    }
+}
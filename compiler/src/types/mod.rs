@@ -7,7 +7,15 @@
 /// - Mode analysis (input/output pattern detection)
 use crate::ast::*;
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use qed_runtime::temporal;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+mod error;
+mod infer;
+mod safety;
+
+pub use error::{TypeError, TypeErrors, TypeUse, TypeWarning};
 
 /// Type environment tracks type definitions and relation signatures
 #[derive(Debug, Clone)]
@@ -20,6 +28,9 @@ pub struct TypeEnv {
 
     /// Constructor signatures: person -> (String, Int) -> Person
     pub constructors: HashMap<String, (Vec<Type>, String)>,
+
+    /// Sum types declaring each variant, in declaration order
+    pub variants: HashMap<String, Vec<String>>,
 }
 
 impl TypeEnv {
@@ -28,6 +39,7 @@ impl TypeEnv {
             types: HashMap::new(),
             relations: HashMap::new(),
             constructors: HashMap::new(),
+            variants: HashMap::new(),
         };
 
         // Add built-in types
@@ -59,6 +71,10 @@ impl TypeEnv {
                 for variant in variants {
                     self.constructors
                         .insert(variant.clone(), (vec![], name.clone()));
+                    self.variants
+                        .entry(variant.clone())
+                        .or_default()
+                        .push(name.clone());
                 }
             }
         }
@@ -82,287 +98,691 @@ impl TypeEnv {
     pub fn get_constructor_info(&self, name: &str) -> Option<&(Vec<Type>, String)> {
         self.constructors.get(name)
     }
+
+    /// The types declaring a variant name, when more than one does
+    pub fn ambiguous_variant(&self, name: &str) -> Option<&[String]> {
+        self.variants
+            .get(name)
+            .filter(|types| types.len() > 1)
+            .map(Vec::as_slice)
+    }
+}
+
+/// Type of each variant name that several types declare, by the span of
+/// its occurrence
+pub type VariantTypes = HashMap<(usize, usize), String>;
+
+/// Types solved for a rule, query or constraint
+#[derive(Debug, Clone, Default)]
+pub struct ClauseTypes {
+    /// Principal type of each variable
+    pub vars: HashMap<String, Type>,
+    pub variants: VariantTypes,
 }
 
 /// Type checker for qed programs
 pub struct TypeChecker {
     env: TypeEnv,
+    /// Warnings from the last `check_program`
+    warnings: Vec<TypeWarning>,
 }
 
+/// Result of checking one item
+type Checked<T> = std::result::Result<T, TypeError>;
+
 impl TypeChecker {
     pub fn new() -> Self {
         TypeChecker {
             env: TypeEnv::new(),
+            warnings: Vec::new(),
         }
     }
 
-    pub fn check_program(&mut self, program: &Program) -> Result<()> {
+    /// Warnings found by the last `check_program`, whether or not it failed
+    pub fn warnings(&self) -> &[TypeWarning] {
+        &self.warnings
+    }
+
+    /// Check a whole program, reporting every error rather than the first
+    pub fn check_program(&mut self, program: &Program) -> std::result::Result<(), TypeErrors> {
+        let mut errors = Vec::new();
+
         // First pass: collect type and relation definitions
         for item in &program.items {
             match item {
                 Item::TypeDef(typedef) => {
-                    self.env
-                        .add_type(typedef.name.clone(), typedef.def.clone())?;
+                    let added = self.env.add_type(typedef.name.clone(), typedef.def.clone());
+                    if added.is_err() {
+                        errors.push(TypeError::DuplicateType {
+                            name: typedef.name.clone(),
+                            span: typedef.span.clone(),
+                        });
+                    }
                 }
                 Item::RelationDecl(rel) => {
-                    self.env
-                        .add_relation(rel.name.clone(), rel.signature.clone())?;
+                    if self
+                        .env
+                        .add_relation(rel.name.clone(), rel.signature.clone())
+                        .is_err()
+                    {
+                        errors.push(TypeError::DuplicateRelation {
+                            name: rel.name.clone(),
+                            span: rel.span.clone(),
+                        });
+                    } else {
+                        errors.extend(self.check_keys(rel));
+                        errors.extend(crate::modes::declarations(rel).err());
+                    }
                 }
                 _ => {}
             }
         }
 
         // Second pass: type check facts and rules
+        let mut explained: HashMap<&str, &Span> = HashMap::new();
+        // Rows of a table with unbound inputs are reported once, for the table
+        let mut rejected_rows = HashSet::new();
         for item in &program.items {
-            match item {
-                Item::Fact(fact) => self.check_fact(fact)?,
-                Item::Rule(rule) => self.check_rule(rule)?,
-                Item::Query(query) => self.check_query(query)?,
-                _ => {}
-            }
+            let result = match item {
+                Item::Fact(fact) => self.check_fact(fact),
+                Item::Table(table) => {
+                    let result = Self::check_table_inputs(table);
+                    if result.is_err() {
+                        rejected_rows
+                            .extend(table.rows.iter().map(|row| (row.span.start, row.span.end)));
+                    }
+                    result
+                }
+                Item::Rule(rule) if rejected_rows.contains(&(rule.span.start, rule.span.end)) => {
+                    Ok(())
+                }
+                Item::Rule(rule) => self.check_rule(rule),
+                Item::Query(query) => self
+                    .infer_clause(None, &query.goals)
+                    .and_then(|_| safety::check_clause(None, &query.goals)),
+                Item::Constraint(constraint) => self
+                    .infer_clause(None, &constraint.body)
+                    .and_then(|_| safety::check_clause(None, &constraint.body)),
+                Item::Explain(template) => match explained.get(template.relation.as_str()) {
+                    Some(first) => Err(TypeError::DuplicateTemplate {
+                        relation: template.relation.clone(),
+                        first: (*first).clone(),
+                        span: template.span.clone(),
+                    }),
+                    None => {
+                        explained.insert(&template.relation, &template.span);
+                        errors.extend(self.check_explain_template(template));
+                        Ok(())
+                    }
+                },
+                _ => Ok(()),
+            };
+            errors.extend(result.err());
         }
 
-        Ok(())
-    }
+        // Keys compare facts by value, after their arithmetic is evaluated
+        let (folded, fold_errors) = crate::fold::fold_program(program);
+        errors.extend(fold_errors);
+        errors.extend(self.check_fact_keys(&folded));
+        errors.extend(self.check_policies(program));
 
-    fn check_fact(&self, fact: &Fact) -> Result<()> {
-        // 1. Look up relation signature
-        let expected_types = self.get_relation_arg_types(&fact.relation)?;
+        // Determinism is only meaningful for a program that is otherwise sound
+        if errors.is_empty() {
+            let modes = crate::modes::infer(program);
+            errors.extend(crate::modes::check_declarations(program, &modes));
+        }
 
-        // 2. Check arity
-        if fact.args.len() != expected_types.len() {
-            return Err(anyhow!(
-                "Fact {} has {} arguments, expected {}",
-                fact.relation,
-                fact.args.len(),
-                expected_types.len()
-            ));
+        self.warnings = Self::lint(program);
+        self.warnings.extend(crate::tables::check_tables(program));
+        self.warnings
+            .extend(crate::termination::check_termination(program));
+        self.warnings
+            .extend(crate::deps::check_negative_cycles(program));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(TypeErrors(errors))
         }
+    }
 
-        // 3. Check each argument is ground (no variables in facts)
-        for (i, arg) in fact.args.iter().enumerate() {
-            if !self.is_ground(arg) {
-                return Err(anyhow!(
-                    "Fact {} argument {} contains variables (facts must be ground)",
-                    fact.relation,
-                    i + 1
-                ));
+    /// Singleton variables, and relations that are never defined or never used
+    fn lint(program: &Program) -> Vec<TypeWarning> {
+        let mut warnings = Vec::new();
+        let mut defined = HashSet::new();
+        let mut used = HashSet::new();
+
+        // A table row may leave a variable its binding column shares with
+        // other rows unused, as a row with `-` does
+        let mut table_vars: HashMap<(usize, usize), HashSet<&str>> = HashMap::new();
+        for item in &program.items {
+            let Item::Table(table) = item else { continue };
+            let rows: Vec<Vec<(&str, &Span)>> = table
+                .rows
+                .iter()
+                .map(|row| {
+                    let mut occurrences = Vec::new();
+                    for cell in &row.cells {
+                        match cell {
+                            TableCell::Goals(goals, _) => goals
+                                .iter()
+                                .for_each(|goal| Self::goal_occurrences(goal, &mut occurrences)),
+                            TableCell::Value(term, _) => {
+                                Self::var_occurrences(term, &mut occurrences)
+                            }
+                            TableCell::Any(_) => {}
+                        }
+                    }
+                    occurrences
+                })
+                .collect();
+            for (n, row) in table.rows.iter().enumerate() {
+                let shared = rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != n)
+                    .flat_map(|(_, vars)| vars.iter().map(|(name, _)| *name))
+                    .collect();
+                table_vars.insert((row.span.start, row.span.end), shared);
             }
         }
 
-        // 4. Type check each argument
-        let empty_env = HashMap::new();
-        for (i, (arg, expected_ty)) in fact.args.iter().zip(expected_types.iter()).enumerate() {
-            let actual_ty = self.infer_term_type(arg, &empty_env)?;
-            if !self.types_compatible(&actual_ty, expected_ty) {
-                return Err(anyhow!(
-                    "Fact {} argument {} has type {}, expected {}",
-                    fact.relation,
-                    i + 1,
-                    actual_ty,
-                    expected_ty
-                ));
+        for item in &program.items {
+            let (clause, head, body) = match item {
+                Item::Fact(fact) => {
+                    defined.insert(fact.relation.as_str());
+                    continue;
+                }
+                Item::Rule(rule) => {
+                    defined.insert(rule.head.relation.as_str());
+                    ("rule", Some(&rule.head), &rule.body)
+                }
+                Item::Constraint(constraint) => ("constraint", None, &constraint.body),
+                Item::Query(query) => {
+                    used.extend(query.goals.iter().filter_map(|goal| match goal {
+                        Goal::Atom(atom) => Some(atom.relation.as_str()),
+                        _ => None,
+                    }));
+                    continue;
+                }
+                _ => continue,
+            };
+
+            // A relation's own rules do not count as a use of it
+            for goal in body {
+                if let Goal::Atom(atom) = goal {
+                    if head.is_none_or(|head| head.relation != atom.relation) {
+                        used.insert(atom.relation.as_str());
+                    }
+                }
+            }
+
+            let mut occurrences: Vec<(&str, &Span)> = Vec::new();
+            for arg in head.iter().flat_map(|head| &head.args) {
+                Self::var_occurrences(arg, &mut occurrences);
+            }
+            for goal in body {
+                Self::goal_occurrences(goal, &mut occurrences);
+            }
+            let shared = match item {
+                Item::Rule(rule) => table_vars.get(&(rule.span.start, rule.span.end)),
+                _ => None,
+            };
+            for (name, span) in &occurrences {
+                let count = occurrences
+                    .iter()
+                    .filter(|(other, _)| other == name)
+                    .count();
+                let in_other_rows = shared.is_some_and(|vars| vars.contains(name));
+                if count == 1 && !name.starts_with('_') && !in_other_rows {
+                    warnings.push(TypeWarning::SingletonVariable {
+                        var: name.to_string(),
+                        clause,
+                        span: (*span).clone(),
+                    });
+                }
             }
         }
 
-        Ok(())
+        for item in &program.items {
+            let Item::RelationDecl(rel) = item else {
+                continue;
+            };
+            if !defined.contains(rel.name.as_str()) {
+                warnings.push(TypeWarning::UndefinedRelation {
+                    name: rel.name.clone(),
+                    span: rel.span.clone(),
+                });
+            } else if !used.contains(rel.name.as_str()) {
+                warnings.push(TypeWarning::UnusedRelation {
+                    name: rel.name.clone(),
+                    span: rel.span.clone(),
+                });
+            }
+        }
+        warnings
     }
 
-    fn check_rule(&self, rule: &Rule) -> Result<()> {
-        // 1. Get expected types for head relation
-        let head_types = self.get_relation_arg_types(&rule.head.relation)?;
+    /// Every occurrence of a named variable in a goal
+    fn goal_occurrences<'t>(goal: &'t Goal, out: &mut Vec<(&'t str, &'t Span)>) {
+        match goal {
+            Goal::Atom(atom) => atom
+                .args
+                .iter()
+                .for_each(|arg| Self::var_occurrences(arg, out)),
+            Goal::Unify(left, right, _) | Goal::Compare(_, left, right, _) => {
+                Self::var_occurrences(left, out);
+                Self::var_occurrences(right, out);
+            }
+            Goal::Match { subject, .. } => Self::var_occurrences(subject, out),
+        }
+    }
 
-        if rule.head.args.len() != head_types.len() {
-            return Err(anyhow!(
-                "Rule head {} has {} arguments, expected {}",
-                rule.head.relation,
-                rule.head.args.len(),
-                head_types.len()
-            ));
+    /// Every occurrence of a named variable in a term
+    fn var_occurrences<'t>(term: &'t Term, out: &mut Vec<(&'t str, &'t Span)>) {
+        match term {
+            Term::Var(name, span) => out.push((name, span)),
+            Term::Construct { args, .. } => {
+                for arg in args {
+                    Self::var_occurrences(arg, out);
+                }
+            }
+            Term::BinOp { left, right, .. } => {
+                Self::var_occurrences(left, out);
+                Self::var_occurrences(right, out);
+            }
+            _ => {}
         }
+    }
 
-        // 2. Build variable type environment from head pattern
-        let mut var_env: HashMap<String, Type> = HashMap::new();
-        for (arg, expected_ty) in rule.head.args.iter().zip(head_types.iter()) {
-            self.collect_var_types(arg, expected_ty, &mut var_env)?;
+    /// Check `@combine` on relations and `@overrides` between named rules
+    fn check_policies(&self, program: &Program) -> Vec<TypeError> {
+        let mut errors = Vec::new();
+        for item in &program.items {
+            let Item::RelationDecl(rel) = item else {
+                continue;
+            };
+            let Some(name) = rel.combine() else { continue };
+            let span = rel
+                .attributes
+                .iter()
+                .find(|a| a.name == "combine")
+                .map_or(&rel.span, |a| &a.span);
+            let Some(combine) = Combine::from_name(name) else {
+                errors.push(TypeError::UnknownCombine {
+                    name: name.to_string(),
+                    relation: rel.name.clone(),
+                    span: span.clone(),
+                });
+                continue;
+            };
+            let Ok(arg_types) = self.get_relation_arg_types(&rel.name, &rel.span) else {
+                continue;
+            };
+            if combine == Combine::FirstApplicable {
+                continue;
+            }
+            // The outcome is the last argument and must be able to say Deny and Permit
+            let outcome = arg_types.last().expect("relations have arguments");
+            let variants = match outcome {
+                Type::Named(ty) => match self.env.types.get(ty) {
+                    Some(TypeDefKind::Sum { variants }) => variants.as_slice(),
+                    _ => &[],
+                },
+                _ => &[],
+            };
+            let has = |v: &str| variants.iter().any(|x| x == v);
+            if !has("Deny") || !(has("Permit") || has("Allow")) {
+                errors.push(TypeError::CombineOutcome {
+                    relation: rel.name.clone(),
+                    algorithm: combine.to_string(),
+                    found: outcome.clone(),
+                    span: span.clone(),
+                });
+            }
         }
 
-        // 3. Check each goal in body and extend var_env
-        for goal in &rule.body {
-            self.check_goal(goal, &mut var_env)?;
+        // Rule names must be unique for overrides to refer to them
+        let mut named: HashMap<&str, &Rule> = HashMap::new();
+        for item in &program.items {
+            if let Item::Rule(rule) = item {
+                if let Some(name) = rule.name() {
+                    match named.get(name) {
+                        Some(first) => errors.push(TypeError::DuplicateRuleName {
+                            name: name.to_string(),
+                            first: first.span.clone(),
+                            span: rule.span.clone(),
+                        }),
+                        None => {
+                            named.insert(name, rule);
+                        }
+                    }
+                }
+            }
         }
 
-        // 4. Safety check: all variables in head must appear in body
-        let head_vars = self.collect_vars_in_terms(&rule.head.args);
-        let body_vars = self.collect_vars_in_goals(&rule.body);
+        let mut edges: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for item in &program.items {
+            let Item::Rule(rule) = item else { continue };
+            for target in rule.overrides() {
+                let span = rule
+                    .attributes
+                    .iter()
+                    .find(|a| a.name == "overrides" && a.args.iter().any(|t| t == target))
+                    .map_or(&rule.span, |a| &a.span);
+                let Some(overridden) = named.get(target) else {
+                    errors.push(TypeError::UnknownOverride {
+                        target: target.to_string(),
+                        span: span.clone(),
+                    });
+                    continue;
+                };
+                if overridden.head.relation != rule.head.relation {
+                    errors.push(TypeError::OverrideOtherRelation {
+                        relation: rule.head.relation.clone(),
+                        target: target.to_string(),
+                        other: overridden.head.relation.clone(),
+                        span: span.clone(),
+                    });
+                    continue;
+                }
+                if let Some(name) = rule.name() {
+                    edges.entry(name).or_default().push(target);
+                }
+            }
+        }
 
-        for var in &head_vars {
-            if !body_vars.contains(var) {
-                return Err(anyhow!(
-                    "Unsafe rule: variable {} in head of {} does not appear in body",
-                    var,
-                    rule.head.relation
-                ));
+        // Overrides must not form a cycle, or no rule in it could win
+        for start in edges.keys() {
+            let mut stack: Vec<&str> = edges[start].clone();
+            let mut seen = HashSet::new();
+            while let Some(name) = stack.pop() {
+                if name == *start {
+                    errors.push(TypeError::OverrideCycle {
+                        name: start.to_string(),
+                        span: named[start].span.clone(),
+                    });
+                    break;
+                }
+                if seen.insert(name) {
+                    stack.extend(edges.get(name).into_iter().flatten());
+                }
             }
         }
 
-        Ok(())
+        errors
     }
 
-    fn check_query(&self, query: &Query) -> Result<()> {
-        let mut var_env: HashMap<String, Type> = HashMap::new();
-        for goal in &query.goals {
-            self.check_goal(goal, &mut var_env)?;
+    /// Key positions must be in range and listed once
+    fn check_keys(&self, rel: &RelationDecl) -> Vec<TypeError> {
+        let Ok(arg_types) = self.get_relation_arg_types(&rel.name, &rel.span) else {
+            return Vec::new();
+        };
+        let arity = arg_types.len();
+        let mut errors = Vec::new();
+        for key in &rel.keys {
+            for (i, &position) in key.iter().enumerate() {
+                if position >= arity {
+                    errors.push(TypeError::KeyOutOfRange {
+                        relation: rel.name.clone(),
+                        position,
+                        arity,
+                        span: rel.span.clone(),
+                    });
+                } else if key[..i].contains(&position) {
+                    errors.push(TypeError::RepeatedKeyPosition {
+                        relation: rel.name.clone(),
+                        position,
+                        span: rel.span.clone(),
+                    });
+                }
+            }
         }
-        Ok(())
+        errors
     }
 
-    /// Check a goal and update variable environment
-    fn check_goal(&self, goal: &Goal, var_env: &mut HashMap<String, Type>) -> Result<()> {
-        match goal {
-            Goal::Atom(atom) => {
-                let expected_types = self.get_relation_arg_types(&atom.relation)?;
-
-                if atom.args.len() != expected_types.len() {
-                    return Err(anyhow!(
-                        "Goal {} has {} arguments, expected {}",
-                        atom.relation,
-                        atom.args.len(),
-                        expected_types.len()
-                    ));
-                }
-
-                // Check/infer types for each argument
-                for (arg, expected_ty) in atom.args.iter().zip(expected_types.iter()) {
-                    self.check_term_against_type(arg, expected_ty, var_env)?;
-                }
-            }
-            Goal::Unify(left, right, _) => {
-                // For unification, infer types of both sides
-                // If one side has known type, propagate to other
-                let left_ty = self.try_infer_term_type(left, var_env);
-                let right_ty = self.try_infer_term_type(right, var_env);
-
-                match (left_ty, right_ty) {
-                    (Some(lt), Some(rt)) => {
-                        if !self.types_compatible(&lt, &rt) {
-                            return Err(anyhow!(
-                                "Cannot unify {} with {} (type mismatch: {} vs {})",
-                                self.term_to_string(left),
-                                self.term_to_string(right),
-                                lt,
-                                rt
-                            ));
-                        }
-                    }
-                    (Some(ty), None) => {
-                        self.collect_var_types(right, &ty, var_env)?;
-                    }
-                    (None, Some(ty)) => {
-                        self.collect_var_types(left, &ty, var_env)?;
+    /// Reject facts that agree on a key but differ elsewhere
+    fn check_fact_keys(&self, program: &Program) -> Vec<TypeError> {
+        let mut errors = Vec::new();
+        for item in &program.items {
+            let Item::RelationDecl(rel) = item else {
+                continue;
+            };
+            let arity = match self.get_relation_arg_types(&rel.name, &rel.span) {
+                Ok(types) => types.len(),
+                Err(_) => continue,
+            };
+            for key in rel.keys.iter().filter(|key| key.iter().all(|&i| i < arity)) {
+                let mut seen: HashMap<Vec<String>, (Vec<String>, &Span)> = HashMap::new();
+                for other in &program.items {
+                    let Item::Fact(fact) = other else { continue };
+                    if fact.relation != rel.name || fact.args.len() != arity {
+                        continue;
                     }
-                    (None, None) => {
-                        // Both sides are variables with unknown types - allowed for now
+                    let args: Vec<String> = fact.args.iter().map(Self::format_term).collect();
+                    let key_args: Vec<String> = key.iter().map(|&i| args[i].clone()).collect();
+                    match seen.get(&key_args) {
+                        Some((first, span)) if *first != args => {
+                            errors.push(TypeError::KeyViolation {
+                                relation: rel.name.clone(),
+                                key: key
+                                    .iter()
+                                    .map(|i| i.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", "),
+                                first: Box::new((
+                                    format!("{}({})", rel.name, first.join(", ")),
+                                    (*span).clone(),
+                                )),
+                                second: format!("{}({})", rel.name, args.join(", ")),
+                                span: fact.span.clone(),
+                            });
+                        }
+                        Some(_) => {}
+                        None => {
+                            seen.insert(key_args, (args, &fact.span));
+                        }
                     }
                 }
             }
-            Goal::Compare(op, left, right, _) => {
-                // Comparisons require compatible types
-                let int_ty = Type::Named("Int".to_string());
+        }
+        errors
+    }
+
+    fn check_fact(&self, fact: &Fact) -> Checked<()> {
+        self.fact_types(fact).map(|_| ())
+    }
+
+    /// Which type each shared variant name in a checked fact belongs to
+    pub fn fact_variants(&self, fact: &Fact) -> VariantTypes {
+        self.fact_types(fact).unwrap_or_default()
+    }
+
+    fn fact_types(&self, fact: &Fact) -> Checked<VariantTypes> {
+        // 1. Look up relation signature
+        let expected_types = self.get_relation_arg_types(&fact.relation, &fact.span)?;
+
+        // 2. Check arity
+        if fact.args.len() != expected_types.len() {
+            return Err(TypeError::Arity {
+                what: format!("Fact {}", fact.relation),
+                found: fact.args.len(),
+                expected: expected_types.len(),
+                span: fact.span.clone(),
+            });
+        }
+
+        // 3. Check each argument is ground (no variables in facts)
+        for (i, arg) in fact.args.iter().enumerate() {
+            if let Term::Now(span) | Term::Today(span) = arg {
+                return Err(TypeError::NowInFact {
+                    relation: fact.relation.clone(),
+                    position: i + 1,
+                    clock: Self::format_term(arg),
+                    span: span.clone(),
+                });
+            }
+            if !self.is_ground(arg) {
+                return Err(TypeError::NonGroundFact {
+                    relation: fact.relation.clone(),
+                    position: i + 1,
+                    span: arg.span().clone(),
+                });
+            }
+        }
 
-                // For now, assume comparisons are on Int
-                self.check_term_against_type(left, &int_ty, var_env)?;
-                self.check_term_against_type(right, &int_ty, var_env)?;
+        // 4. Type check each argument
+        let mut variants = VariantTypes::new();
+        for (i, (arg, expected_ty)) in fact.args.iter().zip(expected_types.iter()).enumerate() {
+            let actual_ty = self.infer_term_type(arg, Some(expected_ty), &mut variants)?;
+            if !self.types_compatible(&actual_ty, expected_ty) {
+                return Err(TypeError::Mismatch {
+                    what: format!("Fact {} argument {}", fact.relation, i + 1),
+                    found: actual_ty,
+                    expected: expected_ty.clone(),
+                    span: arg.span().clone(),
+                });
             }
         }
-        Ok(())
+
+        Ok(variants)
     }
 
-    /// Check a term against an expected type, updating var_env for any variables
-    fn check_term_against_type(
-        &self,
-        term: &Term,
-        expected_ty: &Type,
-        var_env: &mut HashMap<String, Type>,
-    ) -> Result<()> {
-        match term {
-            Term::Var(name, _) => {
-                if let Some(existing_ty) = var_env.get(name) {
-                    if !self.types_compatible(existing_ty, expected_ty) {
-                        return Err(anyhow!(
-                            "Variable {} has conflicting types: {} vs {}",
-                            name,
-                            existing_ty,
-                            expected_ty
-                        ));
-                    }
-                } else {
-                    var_env.insert(name.clone(), expected_ty.clone());
-                }
+    /// Check that every placeholder names a parameter and valid fields of
+    /// its type, reporting each bad placeholder
+    fn check_explain_template(&self, template: &ExplainTemplate) -> Vec<TypeError> {
+        let arg_types = match self.get_relation_arg_types(&template.relation, &template.span) {
+            Ok(types) => types,
+            Err(error) => return vec![error],
+        };
+        if template.params.len() != arg_types.len() {
+            return vec![TypeError::TemplateArity {
+                relation: template.relation.clone(),
+                found: template.params.len(),
+                expected: arg_types.len(),
+                span: template.span.clone(),
+            }];
+        }
+
+        let mut params: HashMap<&str, &Type> = HashMap::new();
+        for (param, ty) in template.params.iter().zip(arg_types.iter()) {
+            if params.insert(param, ty).is_some() {
+                return vec![TypeError::RepeatedParameter {
+                    relation: template.relation.clone(),
+                    param: param.clone(),
+                    span: template.span.clone(),
+                }];
             }
-            _ => {
-                let actual_ty = self.infer_term_type(term, var_env)?;
-                if !self.types_compatible(&actual_ty, expected_ty) {
-                    return Err(anyhow!(
-                        "Type mismatch: {} has type {}, expected {}",
-                        self.term_to_string(term),
-                        actual_ty,
-                        expected_ty
-                    ));
-                }
+        }
+
+        let span = &template.template_span;
+        let parts = match template.parts() {
+            Ok(parts) => parts,
+            Err(error) => {
+                return vec![TypeError::InvalidTemplate {
+                    relation: template.relation.clone(),
+                    error,
+                    span: span.clone(),
+                }]
+            }
+        };
+
+        let mut errors = Vec::new();
+        for part in &parts {
+            let TemplatePart::Hole { var, fields } = part else {
+                continue;
+            };
+            let Some(mut ty) = params.get(var.as_str()).copied() else {
+                errors.push(TypeError::UnknownPlaceholder {
+                    relation: template.relation.clone(),
+                    var: var.clone(),
+                    span: span.clone(),
+                });
+                continue;
+            };
+            let mut path = var.clone();
+            for field in fields {
+                let found = match ty {
+                    Type::Named(name) => match self.env.types.get(name) {
+                        Some(TypeDefKind::Product { fields: defs, .. }) => {
+                            defs.iter().find(|f| &f.name == field).map(|f| &f.ty)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let Some(found) = found else {
+                    errors.push(TypeError::UnknownField {
+                        relation: template.relation.clone(),
+                        path: path.clone(),
+                        field: field.clone(),
+                        ty: ty.clone(),
+                        span: span.clone(),
+                    });
+                    break;
+                };
+                ty = found;
+                path = format!("{}.{}", path, field);
             }
         }
-        Ok(())
+        errors
     }
 
-    /// Try to infer term type, returning None if variables are unbound
-    fn try_infer_term_type(&self, term: &Term, var_env: &HashMap<String, Type>) -> Option<Type> {
-        match term {
-            Term::Var(name, _) => var_env.get(name).cloned(),
-            Term::Int(_, _) => Some(Type::Named("Int".to_string())),
-            Term::String(_, _) => Some(Type::Named("String".to_string())),
-            Term::Construct { constructor, .. } => self
-                .env
-                .get_constructor_info(constructor)
-                .map(|(_, result_ty)| Type::Named(result_ty.clone())),
-            Term::BinOp { .. } => Some(Type::Named("Int".to_string())),
+    /// A table's conditions compare its inputs, so a column has to bind
+    /// them first, as `| service(Emp, Sal, Years) |` does
+    fn check_table_inputs(table: &Table) -> Checked<()> {
+        // Malformed tables are rejected by the parser
+        let Ok(rules) = table.rules() else {
+            return Ok(());
+        };
+        for rule in &rules {
+            if let Err(TypeError::UnboundVariable { var, span, .. }) =
+                safety::check_clause(None, &rule.body)
+            {
+                return Err(TypeError::UnboundTableInput {
+                    table: table.head.relation.clone(),
+                    var,
+                    span,
+                });
+            }
         }
+        Ok(())
+    }
+
+    fn check_rule(&self, rule: &Rule) -> Checked<()> {
+        // 1. Infer a type for every variable from the head and body together
+        self.infer_clause(Some(&rule.head), &rule.body)?;
+
+        // 2. Range restriction: every variable is bound before it is used
+        safety::check_clause(Some(&rule.head), &rule.body)
     }
 
-    /// Collect variable types from a pattern
-    fn collect_var_types(
+    /// Check a query against the declarations collected by `check_program`
+    pub fn check_query(&self, query: &Query) -> std::result::Result<(), TypeErrors> {
+        self.infer_clause(None, &query.goals)?;
+        safety::check_clause(None, &query.goals)?;
+        Ok(())
+    }
+
+    /// Infer the principal type of each variable of a rule, query or constraint
+    pub fn infer_clause(
         &self,
-        term: &Term,
-        expected_ty: &Type,
-        var_env: &mut HashMap<String, Type>,
-    ) -> Result<()> {
-        match term {
-            Term::Var(name, _) => {
-                if let Some(existing_ty) = var_env.get(name) {
-                    if !self.types_compatible(existing_ty, expected_ty) {
-                        return Err(anyhow!(
-                            "Variable {} has conflicting types: {} vs {}",
-                            name,
-                            existing_ty,
-                            expected_ty
-                        ));
-                    }
-                } else {
-                    var_env.insert(name.clone(), expected_ty.clone());
-                }
-            }
-            Term::Construct {
-                constructor, args, ..
-            } => {
-                if let Some((param_types, _)) = self.env.get_constructor_info(constructor) {
-                    for (arg, param_ty) in args.iter().zip(param_types.iter()) {
-                        self.collect_var_types(arg, param_ty, var_env)?;
-                    }
-                }
-            }
-            _ => {}
+        head: Option<&Atom>,
+        body: &[Goal],
+    ) -> Checked<HashMap<String, Type>> {
+        self.resolve_clause(head, body).map(|types| types.vars)
+    }
+
+    /// Like `infer_clause`, also saying which type each shared variant name is
+    pub fn resolve_clause(&self, head: Option<&Atom>, body: &[Goal]) -> Checked<ClauseTypes> {
+        let mut inference = infer::Inference::new(self);
+        if let Some(head) = head {
+            inference.atom(head, "Rule head")?;
         }
-        Ok(())
+        for goal in body {
+            inference.goal(goal)?;
+        }
+        inference.solve()
     }
 
     /// Collect all variable names from terms
@@ -376,10 +796,8 @@ impl TypeChecker {
 
     fn collect_vars_in_term(term: &Term, vars: &mut Vec<String>) {
         match term {
-            Term::Var(name, _) => {
-                if !vars.contains(name) {
-                    vars.push(name.clone());
-                }
+            Term::Var(name, _) if name != "_" && !vars.contains(name) => {
+                vars.push(name.clone());
             }
             Term::Construct { args, .. } => {
                 for arg in args {
@@ -412,6 +830,9 @@ impl TypeChecker {
                     Self::collect_vars_in_term(left, &mut vars);
                     Self::collect_vars_in_term(right, &mut vars);
                 }
+                Goal::Match { subject, .. } => {
+                    Self::collect_vars_in_term(subject, &mut vars);
+                }
             }
         }
         vars
@@ -426,7 +847,12 @@ impl TypeChecker {
         match term {
             Term::Var(name, _) => name.clone(),
             Term::Int(n, _) => n.to_string(),
-            Term::String(s, _) => format!("\"{}\"", s),
+            Term::String(s, _) => format!("{:?}", s),
+            Term::Date(d, _) => temporal::format_date(*d),
+            Term::Timestamp(t, _) => temporal::format_timestamp(*t),
+            Term::Duration(d, _) => temporal::format_duration(*d),
+            Term::Now(_) => "now()".to_string(),
+            Term::Today(_) => "today()".to_string(),
             Term::Construct {
                 constructor, args, ..
             } => {
@@ -456,14 +882,48 @@ impl TypeChecker {
         }
     }
 
-    fn infer_term_type(&self, term: &Term, var_env: &HashMap<String, Type>) -> Result<Type> {
+    /// Type of a ground term, as found in facts; `expected` decides which
+    /// type a variant name several types declare belongs to
+    fn infer_term_type(
+        &self,
+        term: &Term,
+        expected: Option<&Type>,
+        variants: &mut VariantTypes,
+    ) -> Checked<Type> {
         match term {
-            Term::Var(name, _) => var_env
-                .get(name)
-                .cloned()
-                .ok_or_else(|| anyhow!("Unbound variable: {}", name)),
+            Term::Var(name, span) => Err(TypeError::CannotInfer {
+                var: name.clone(),
+                span: span.clone(),
+            }),
             Term::Int(_, _) => Ok(Type::Named("Int".to_string())),
             Term::String(_, _) => Ok(Type::Named("String".to_string())),
+            Term::Date(_, _) | Term::Today(_) => Ok(Type::Named("Date".to_string())),
+            Term::Timestamp(_, _) | Term::Now(_) => Ok(Type::Named("Timestamp".to_string())),
+            Term::Duration(_, _) => Ok(Type::Named("Duration".to_string())),
+            Term::Construct {
+                constructor,
+                args,
+                span,
+            } if args.is_empty() && self.env.ambiguous_variant(constructor).is_some() => {
+                let types = self.env.ambiguous_variant(constructor).unwrap_or_default();
+                match expected {
+                    Some(Type::Named(ty)) if types.contains(ty) => {
+                        variants.insert((span.start, span.end), ty.clone());
+                        Ok(Type::Named(ty.clone()))
+                    }
+                    Some(ty) => Err(TypeError::Mismatch {
+                        what: format!("Variant {}", constructor),
+                        found: Type::Named(types.join(" or ")),
+                        expected: ty.clone(),
+                        span: span.clone(),
+                    }),
+                    None => Err(TypeError::AmbiguousVariant {
+                        name: constructor.clone(),
+                        types: types.join(" and "),
+                        span: span.clone(),
+                    }),
+                }
+            }
             Term::Construct {
                 constructor,
                 args,
@@ -473,29 +933,34 @@ impl TypeChecker {
                 let (param_types, result_type) = self
                     .env
                     .get_constructor_info(constructor)
-                    .ok_or_else(|| anyhow!("Unknown constructor: {}", constructor))?;
+                    .ok_or_else(|| TypeError::UnknownConstructor {
+                        name: constructor.clone(),
+                        span: span.clone(),
+                    })?;
 
                 // Check arity
                 if args.len() != param_types.len() {
-                    return Err(anyhow!(
-                        "Constructor {} expects {} arguments, got {}",
-                        constructor,
-                        param_types.len(),
-                        args.len()
-                    ));
+                    return Err(TypeError::Arity {
+                        what: format!("Constructor {}", constructor),
+                        found: args.len(),
+                        expected: param_types.len(),
+                        span: span.clone(),
+                    });
                 }
 
                 // Check each argument type
                 for (i, (arg, expected_ty)) in args.iter().zip(param_types.iter()).enumerate() {
-                    let actual_ty = self.infer_term_type(arg, var_env)?;
+                    if matches!(arg, Term::Var(name, _) if name == "_") {
+                        continue;
+                    }
+                    let actual_ty = self.infer_term_type(arg, Some(expected_ty), variants)?;
                     if !self.types_compatible(&actual_ty, expected_ty) {
-                        return Err(anyhow!(
-                            "Argument {} of {} has type {}, expected {}",
-                            i + 1,
-                            constructor,
-                            actual_ty,
-                            expected_ty
-                        ));
+                        return Err(TypeError::Mismatch {
+                            what: format!("Argument {} of {}", i + 1, constructor),
+                            found: actual_ty,
+                            expected: expected_ty.clone(),
+                            span: arg.span().clone(),
+                        });
                     }
                 }
 
@@ -507,28 +972,30 @@ impl TypeChecker {
                 right,
                 span,
             } => {
-                // Check both operands are Int
-                let left_ty = self.infer_term_type(left, var_env)?;
-                let right_ty = self.infer_term_type(right, var_env)?;
-
-                let int_ty = Type::Named("Int".to_string());
-                if !self.types_compatible(&left_ty, &int_ty) {
-                    return Err(anyhow!(
-                        "Left operand of {:?} has type {}, expected Int",
-                        op,
-                        left_ty
-                    ));
-                }
-                if !self.types_compatible(&right_ty, &int_ty) {
-                    return Err(anyhow!(
-                        "Right operand of {:?} has type {}, expected Int",
-                        op,
-                        right_ty
-                    ));
-                }
+                let left_ty = self.infer_term_type(left, None, variants)?;
+                let right_ty = self.infer_term_type(right, None, variants)?;
+                check_whole_days(&left_ty, &right_ty, left, right)?;
+                binop_result_type(*op, &left_ty, &right_ty).ok_or_else(|| {
+                    TypeError::UndefinedOperator {
+                        op: *op,
+                        left: left_ty,
+                        right: right_ty,
+                        span: span.clone(),
+                    }
+                })
+            }
+        }
+    }
 
-                Ok(int_ty)
+    /// Types that support `<`, `<=`, `>` and `>=`; enums order by declaration
+    fn is_ordered(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Named(name) => {
+                matches!(name.as_str(), "Int" | "String")
+                    || is_temporal(ty)
+                    || matches!(self.env.types.get(name), Some(TypeDefKind::Sum { .. }))
             }
+            _ => false,
         }
     }
 
@@ -567,6 +1034,9 @@ impl TypeChecker {
             Term::Var(_, _) => false,
             Term::Int(_, _) => true,
             Term::String(_, _) => true,
+            Term::Date(_, _) | Term::Timestamp(_, _) | Term::Duration(_, _) => true,
+            // now() and today() are only known when a query runs
+            Term::Now(_) | Term::Today(_) => false,
             Term::Construct { args, .. } => args.iter().all(Self::check_is_ground),
             Term::BinOp { left, right, .. } => {
                 Self::check_is_ground(left) && Self::check_is_ground(right)
@@ -575,11 +1045,18 @@ impl TypeChecker {
     }
 
     /// Get the expected types for a relation's arguments
-    fn get_relation_arg_types(&self, name: &str) -> Result<Vec<Type>> {
-        let sig = self
-            .env
-            .get_relation_signature(name)
-            .ok_or_else(|| anyhow!("Unknown relation: {}", name))?;
+    fn get_relation_arg_types(&self, name: &str, span: &Span) -> Checked<Vec<Type>> {
+        let sig = self.env.get_relation_signature(name).ok_or_else(|| {
+            // The parser reads matches(S, P) as a call unless P is a string
+            if name == "matches" {
+                TypeError::NonLiteralPattern { span: span.clone() }
+            } else {
+                TypeError::UnknownRelation {
+                    name: name.to_string(),
+                    span: span.clone(),
+                }
+            }
+        })?;
 
         match sig {
             Type::Product(types) => Ok(types.clone()),
@@ -588,6 +1065,82 @@ impl TypeChecker {
     }
 }
 
+/// Built-in types that support ordering and calendar arithmetic
+fn is_temporal(ty: &Type) -> bool {
+    matches!(ty, Type::Named(n) if n == "Date" || n == "Timestamp" || n == "Duration")
+}
+
+/// Dates stay on midnight only when shifted by whole days
+///
+/// The shift must be known to be whole days here: a literal, or a sum or
+/// multiple of whole-day literals. A Duration bound at run time could be shorter.
+fn check_whole_days(left_ty: &Type, right_ty: &Type, left: &Term, right: &Term) -> Checked<()> {
+    let is_date = |ty: &Type| matches!(ty, Type::Named(n) if n == "Date");
+    for (date_ty, other_ty, other) in [(left_ty, right_ty, right), (right_ty, left_ty, left)] {
+        if !is_date(date_ty) || !matches!(other_ty, Type::Named(n) if n == "Duration") {
+            continue;
+        }
+        match crate::fold::fold_term(other, &mut Vec::new()) {
+            Term::Duration(d, _) if d % temporal::SECONDS_PER_DAY != 0 => {
+                return Err(TypeError::PartialDays {
+                    duration: temporal::format_duration(d),
+                    span: other.span().clone(),
+                });
+            }
+            folded if !is_whole_days(&folded) => {
+                return Err(TypeError::UncheckedDays {
+                    span: other.span().clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether a folded Duration term is a whole number of days whatever its variables
+fn is_whole_days(term: &Term) -> bool {
+    match term {
+        Term::Duration(d, _) => d % temporal::SECONDS_PER_DAY == 0,
+        Term::BinOp {
+            op: BinOp::Add | BinOp::Sub,
+            left,
+            right,
+            ..
+        } => is_whole_days(left) && is_whole_days(right),
+        Term::BinOp {
+            op: BinOp::Mul,
+            left,
+            right,
+            ..
+        } => is_whole_days(left) || is_whole_days(right),
+        _ => false,
+    }
+}
+
+/// Result type of an arithmetic operator, or None if it is not defined
+///
+/// Besides Int arithmetic, instants can be shifted by durations, subtracted
+/// to give a duration, and durations can be scaled by an Int.
+fn binop_result_type(op: BinOp, left: &Type, right: &Type) -> Option<Type> {
+    let (Type::Named(l), Type::Named(r)) = (left, right) else {
+        return None;
+    };
+    let result = match (op, l.as_str(), r.as_str()) {
+        (_, "Int", "Int") => "Int",
+        (BinOp::Add | BinOp::Sub, "Date", "Duration") => "Date",
+        (BinOp::Add, "Duration", "Date") => "Date",
+        (BinOp::Add | BinOp::Sub, "Timestamp", "Duration") => "Timestamp",
+        (BinOp::Add, "Duration", "Timestamp") => "Timestamp",
+        (BinOp::Sub, "Date", "Date") | (BinOp::Sub, "Timestamp", "Timestamp") => "Duration",
+        (BinOp::Add | BinOp::Sub, "Duration", "Duration") => "Duration",
+        (BinOp::Mul, "Duration", "Int") | (BinOp::Mul, "Int", "Duration") => "Duration",
+        (BinOp::Div, "Duration", "Int") => "Duration",
+        _ => return None,
+    };
+    Some(Type::Named(result.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -752,4 +1305,502 @@ mod tests {
         // This should work - A1 and A2 are Int from the person constructor
         assert!(checker.check_program(&program).is_ok());
     }
+
+    #[test]
+    fn test_check_matches() {
+        let source = r#"
+            rel path: String
+            rel doc_path: String
+            doc_path(P) :- path(P), matches(P, "^/projects/[^/]+/docs/").
+        "#;
+
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        assert!(checker.check_program(&program).is_ok());
+    }
+
+    #[test]
+    fn test_check_shared_variant_names() {
+        // Each use of Admin takes the type its position expects
+        let source = r#"
+            type Action = Read | Admin
+            type Role = Admin | Guest
+            rel act: Action
+            rel role: Role
+            rel admin_action: Action
+            act(Admin).
+            role(Admin).
+            admin_action(A) :- act(A), A = Admin.
+            ?- act(Admin).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        checker.check_program(&program).expect("Type check failed");
+        let Item::Fact(fact) = &program.items[5] else {
+            panic!("expected a fact");
+        };
+        let types: Vec<_> = checker.fact_variants(fact).into_values().collect();
+        assert_eq!(types, vec!["Action".to_string()]);
+
+        // Nothing says which Admin this is
+        let source = r#"
+            type Action = Read | Admin
+            type Role = Admin | Guest
+            ?- X = Admin.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert_eq!(err.0[0].code(), "E0106");
+        assert_eq!(
+            err.0[0].to_string(),
+            "Variant Admin is declared by Action and Role, and nothing here says which"
+        );
+
+        // Neither type fits
+        let source = r#"
+            type Action = Read | Admin
+            type Role = Admin | Guest
+            rel count: Int
+            count(Admin).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert_eq!(
+            err.0[0].to_string(),
+            "Variant Admin has type Action or Role, expected Int"
+        );
+    }
+
+    #[test]
+    fn test_check_invalid_regex() {
+        let source = r#"
+            rel path: String
+            rel doc_path: String
+            doc_path(P) :- path(P), matches(P, "^/projects/[").
+        "#;
+
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        let err = checker.check_program(&program).unwrap_err().to_string();
+        assert!(err.contains("Invalid regular expression"));
+        // The span points at the pattern literal in the source
+        let start = source.find("\"^/projects/[\"").unwrap();
+        assert!(err.contains(&format!("at {}..{}", start, start + 14)));
+
+        // A pattern held in a variable is not a call to an unknown relation
+        let source = r#"
+            rel path: String
+            rel pattern: String
+            rel doc_path: String
+            doc_path(P) :- path(P), pattern(R), matches(P, R).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        let err = checker.check_program(&program).unwrap_err().to_string();
+        assert!(err.contains("[E0211]"));
+        assert!(err.contains("The pattern of matches must be a string literal"));
+        assert!(!err.contains("Unknown relation"));
+    }
+
+    #[test]
+    fn test_check_temporal_arithmetic() {
+        let source = r#"
+            rel contract: String × Date × Int
+            rel renewal_due: String × Date
+            rel overdue: String
+            rel seen: String × Timestamp
+            rel stale: String
+            renewal_due(C, D) :- contract(C, Start, Days), D = Start + P1D * Days - P30D.
+            overdue(C) :- renewal_due(C, D), D - P1W < 2026-12-31.
+            stale(C) :- seen(C, At), now() - At > P1D.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        assert!(checker.check_program(&program).is_ok());
+
+        // today() is the Date to compare Date columns with
+        let source = r#"
+            rel offer: String × Date
+            rel valid: String
+            rel started: String × Date
+            rel probation_over: String
+            valid(O) :- offer(O, Until), Until >= today().
+            probation_over(E) :- started(E, Start), Start + P90D <= today().
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        assert!(TypeChecker::new().check_program(&program).is_ok());
+    }
+
+    #[test]
+    fn test_check_temporal_errors() {
+        // Comparing a Date with a Timestamp is a type error
+        let source = r#"
+            rel due: Date
+            rel late: Date
+            late(D) :- due(D), D < now().
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert!(err.to_string().contains("Timestamp"));
+
+        // Dates only move by whole days
+        let source = r#"
+            rel due: Date
+            rel soon: Date
+            soon(D) :- due(E), D = E + PT12H.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert!(err.to_string().contains("whole number of days"));
+
+        // A Duration known only at run time could be shorter than a day
+        let source = r#"
+            rel due: Date
+            rel grace: Duration
+            rel extended: Date
+            extended(D) :- due(E), grace(G), D = E + G.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert_eq!(err.0[0].code(), "E0210");
+
+        // Multiples of whole days are whole days
+        let source = r#"
+            rel due: Date
+            rel weeks: Int
+            rel extended: Date
+            extended(D) :- due(E), weeks(N), D = E + P7D * N - P1D.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        assert!(TypeChecker::new().check_program(&program).is_ok());
+
+        // Facts cannot depend on the evaluation instant
+        let program = parser::parse("rel seen: Timestamp\nseen(now()).").expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert!(err.to_string().contains("now()"));
+        let program = parser::parse("rel seen: Date\nseen(today()).").expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert!(err.to_string().contains("uses today()"));
+    }
+
+    #[test]
+    fn test_check_comparisons_on_any_type() {
+        let source = r#"
+            type Node = node(id: String)
+            type Level = | Public | Internal | Secret
+            rel edge: Node × Node
+            rel level: String × Level
+            rel distinct: Node × Node
+            rel readable: String
+            rel before: String × String
+            distinct(X, Y) :- edge(X, Y), X != Y.
+            readable(D) :- level(D, L), L <= Internal.
+            before(A, B) :- level(A, _), level(B, _), A < B.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        assert!(checker.check_program(&program).is_ok());
+    }
+
+    #[test]
+    fn test_check_comparison_errors() {
+        // Products support equality but not ordering
+        let source = r#"
+            type Node = node(id: String)
+            rel edge: Node × Node
+            rel lower: Node × Node
+            lower(X, Y) :- edge(X, Y), X < Y.
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let err = TypeChecker::new().check_program(&program).unwrap_err();
+        assert!(err.to_string().contains("Node has no ordering"));
+
+        // Operands must agree
+        let source = r#"
+            type Level = | Public | Secret
+            rel level: String × Level
+            rel odd: String
+            odd(D) :- level(D, L), L == "Public".
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        assert!(TypeChecker::new().check_program(&program).is_err());
+    }
+
+    #[test]
+    fn test_check_explain_templates() {
+        let base = r#"
+            type User = user(name: String, age: Int)
+            rel has_role: User × String
+        "#;
+        let check = |template: &str| {
+            let program = parser::parse(&format!("{}{}", base, template)).unwrap();
+            TypeChecker::new().check_program(&program)
+        };
+
+        assert!(check(r#"explain has_role(U, R) as "{U.name} has role {R}""#).is_ok());
+
+        let err = check(r#"explain has_role(U, R) as "{V} has role {R}""#).unwrap_err();
+        assert!(err.to_string().contains("{V}, which is not a parameter"));
+
+        let err = check(r#"explain has_role(U, R) as "{U.email}""#).unwrap_err();
+        assert!(err.to_string().contains("User has no field email"));
+
+        let err = check(r#"explain has_role(U, R) as "{R.name}""#).unwrap_err();
+        assert!(err.to_string().contains("String has no field name"));
+
+        let err = check(r#"explain has_role(U) as "{U}""#).unwrap_err();
+        assert!(err.to_string().contains("has 1 parameters, expected 2"));
+
+        let err = check(r#"explain has_role(U, R) as "{U.name""#).unwrap_err();
+        assert!(err.to_string().contains("unclosed placeholder"));
+
+        // Every bad placeholder is reported, not just the first
+        let err = check(r#"explain has_role(U, R) as "{U.nam} {Q} {R}""#).unwrap_err();
+        let found: Vec<_> = err.0.iter().map(|e| e.code()).collect();
+        assert_eq!(found, vec!["E0406", "E0405"]);
+    }
+
+    #[test]
+    fn test_check_table_needs_binding_column() {
+        let base = r#"
+            type Employee = employee(id: Int, salary: Int, years: Int)
+            type Level = | Junior | Senior
+            rel service: Employee × Int × Int
+            rel classification: Employee × Level
+            service(employee(1, 50, 2), 50, 2).
+        "#;
+        let check = |table: &str| {
+            let program = parser::parse(&format!("{}{}", base, table)).unwrap();
+            TypeChecker::new().check_program(&program)
+        };
+
+        let bound = r#"
+            table classification(Emp, Level) {
+                | service(Emp, Sal, Years) | Sal < 100 | Years < 3  | Junior |
+                | service(Emp, Sal, Years) | Sal < 100 | Years >= 3 | Senior |
+            }
+        "#;
+        assert!(check(bound).is_ok(), "{:?}", check(bound));
+
+        // Without a column binding them, the conditions compare nothing
+        let unbound = r#"
+            table classification(Emp, Level) {
+                | Sal < 100 | Years < 3  | Junior |
+                | Sal < 100 | Years >= 3 | Senior |
+            }
+        "#;
+        let errors = check(unbound).unwrap_err();
+        assert_eq!(errors.0.len(), 1, "{}", errors);
+        assert_eq!(errors.0[0].code(), "E0309");
+        assert_eq!(
+            errors.0[0].to_string(),
+            "Table classification compares Sal, which no column binds"
+        );
+    }
+
+    #[test]
+    fn test_check_relation_keys() {
+        let check = |source: &str| {
+            let program = parser::parse(source).unwrap();
+            TypeChecker::new().check_program(&program)
+        };
+
+        let source = r#"
+            rel salary_of: String × Int key(0)
+            salary_of("alice", 100).
+            salary_of("bob", 100).
+            salary_of("alice", 100).
+        "#;
+        assert!(check(source).is_ok());
+
+        let source = r#"
+            rel salary_of: String × Int key(0)
+            salary_of("alice", 100).
+            salary_of("alice", 120).
+        "#;
+        let err = check(source).unwrap_err().to_string();
+        assert!(err.contains("salary_of(\"alice\", 100)"));
+        assert!(err.contains("salary_of(\"alice\", 120)"));
+        assert!(err.contains("violate key(0) of salary_of"));
+
+        let err = check("rel salary_of: String × Int key(2)").unwrap_err();
+        assert!(err.to_string().contains("out of range"));
+    }
+
+    #[test]
+    fn test_check_policies() {
+        let base = r#"
+            type Effect = | Permit | Deny
+            rel contractor: String
+            rel manager: String
+        "#;
+        let check = |source: &str| {
+            let program = parser::parse(&format!("{}{}", base, source)).unwrap();
+            TypeChecker::new().check_program(&program)
+        };
+
+        let valid = r#"
+            @combine("deny-overrides")
+            rel decision: String × Effect
+            @rule("deny-contractors")
+            decision(U, Deny) :- contractor(U).
+            @rule("manager-exception")
+            @overrides("deny-contractors")
+            decision(U, Permit) :- manager(U).
+        "#;
+        assert!(check(valid).is_ok());
+
+        let err = check(r#"@combine("majority") rel decision: String × Effect"#).unwrap_err();
+        assert!(err.to_string().contains("Unknown combining algorithm"));
+
+        let err = check(r#"@combine("deny-overrides") rel decision: String × Int"#).unwrap_err();
+        assert!(err.to_string().contains("Deny and Permit"));
+
+        let unknown = r#"
+            rel decision: String × Effect
+            @overrides("missing")
+            decision(U, Permit) :- manager(U).
+        "#;
+        let err = check(unknown).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("overrides unknown rule \"missing\""));
+
+        let cycle = r#"
+            rel decision: String × Effect
+            @rule("a") @overrides("b")
+            decision(U, Permit) :- manager(U).
+            @rule("b") @overrides("a")
+            decision(U, Deny) :- contractor(U).
+        "#;
+        let err = check(cycle).unwrap_err();
+        assert!(err.to_string().contains("overrides itself"));
+    }
+
+    #[test]
+    fn test_check_collects_every_error() {
+        let source = r#"
+            rel age: String × Int
+            rel adult: String
+            age("alice", "old").
+            adult(N) :- age(N, A), A > "18".
+            minor(N) :- age(N, _).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let errors = TypeChecker::new().check_program(&program).unwrap_err().0;
+        let codes: Vec<_> = errors.iter().map(TypeError::code).collect();
+        assert_eq!(codes, vec!["E0201", "E0202", "E0102"]);
+
+        // Every error points into the source
+        let fact = source.find("\"old\"").unwrap();
+        assert_eq!(errors[0].span().start, fact);
+        assert!(errors[1].notes().is_empty());
+        assert_eq!(errors[2].span().start, source.find("minor").unwrap());
+    }
+
+    #[test]
+    fn test_check_warnings() {
+        let source = r#"
+            rel owns: String × String
+            rel grants: String × String
+            rel can_edit: String × String
+            rel audited: String
+            owns("alice", "doc1").
+            can_edit(Owner, Doc) :- owns(Onwer, Doc), grants(_Admin, Doc).
+            ?- can_edit(U, D).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        // Owner is unsafe as well as a singleton; warnings are kept on errors
+        assert!(checker.check_program(&program).is_err());
+        let found: Vec<_> = checker
+            .warnings()
+            .iter()
+            .map(|w| (w.code(), w.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "W0101",
+                    "Variable Owner occurs only once in this rule".to_string()
+                ),
+                (
+                    "W0101",
+                    "Variable Onwer occurs only once in this rule".to_string()
+                ),
+                (
+                    "W0102",
+                    "Relation grants is declared but has no facts or rules".to_string()
+                ),
+                (
+                    "W0102",
+                    "Relation audited is declared but has no facts or rules".to_string()
+                ),
+            ]
+        );
+        let typo = source.find("Onwer").unwrap();
+        assert_eq!(checker.warnings()[1].span().start, typo);
+
+        let source = r#"
+            rel edge: Int × Int
+            rel path: Int × Int
+            edge(1, 2).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        checker.check_program(&program).expect("Type check failed");
+        // Recursion through its own rules is not a use of path
+        let found: Vec<_> = checker.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            found,
+            vec!["Relation path is never used by a rule, query or constraint"]
+        );
+
+        // Table rows share the variables of their binding column
+        let source = r#"
+            type Level = Junior | Senior
+            rel staff: Int × Int × Int
+            rel level: Int × Level
+            table level(Id, L) {
+                | staff(Id, Years, Rank) | Years < 3  | -          | Junior |
+                | staff(Id, Years, Rank) | Years >= 3 | Rank >= 0  | Senior |
+                | staff(Id, Years, Rnak) | Years >= 3 | Rank < 0   | Senior |
+            }
+            ?- level(1, L).
+        "#;
+        let program = parser::parse(source).expect("Parse failed");
+        let mut checker = TypeChecker::new();
+        let _ = checker.check_program(&program);
+        let found: Vec<_> = checker
+            .warnings()
+            .iter()
+            .filter(|w| w.code() == "W0101")
+            .map(|w| w.to_string())
+            .collect();
+        assert_eq!(found, vec!["Variable Rnak occurs only once in this rule"]);
+    }
+
+    #[test]
+    fn test_examples_type_check() {
+        let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/../examples");
+        for name in ["family", "graph", "access_control", "business_rules"] {
+            let source = std::fs::read_to_string(format!("{}/{}.qed", examples, name)).unwrap();
+            let program = parser::parse(&source).expect("Parse failed");
+            let mut checker = TypeChecker::new();
+            let result = checker.check_program(&program);
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+            // CI checks these examples with --deny-warnings; the others
+            // show what the warnings find
+            let warned = ["graph", "business_rules"];
+            assert!(
+                warned.contains(&name) || checker.warnings().is_empty(),
+                "{}: {:?}",
+                name,
+                checker.warnings()
+            );
+        }
+    }
 }
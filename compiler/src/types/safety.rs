@@ -0,0 +1,262 @@
+/// Range restriction for rules, queries and constraints
+///
+/// Goals are solved left to right, so a variable is bound once a positive
+/// atom or an equality with a bound side has mentioned it. Comparisons,
+/// regex matches and arithmetic only test or compute with values; every
+/// variable they use must already be bound, and so must every head variable
+/// once the whole body has been solved. A head cannot use `_`, which no goal
+/// binds, so every answer is ground.
+use super::{Checked, TypeError};
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Check that every variable of a clause is bound before it is used
+pub(super) fn check_clause(head: Option<&Atom>, body: &[Goal]) -> Checked<()> {
+    let mut bound = HashSet::new();
+    for goal in body {
+        match goal {
+            Goal::Atom(atom) => {
+                for arg in &atom.args {
+                    require_operands(arg, &bound)?;
+                }
+                for arg in &atom.args {
+                    bind(arg, &mut bound);
+                }
+            }
+            Goal::Unify(left, right, _) => {
+                require_operands(left, &bound)?;
+                require_operands(right, &bound)?;
+                unify(left, right, &mut bound)?;
+            }
+            Goal::Compare(_, left, right, _) => {
+                require(left, "comparison", &bound)?;
+                require(right, "comparison", &bound)?;
+            }
+            Goal::Match { subject, .. } => require(subject, "regex match", &bound)?,
+        }
+    }
+
+    if let Some(head) = head {
+        if let Some((var, span)) = head
+            .args
+            .iter()
+            .find_map(|arg| unbound_in_head(arg, &bound))
+        {
+            return Err(TypeError::UnsafeVariable {
+                var: var.to_string(),
+                relation: head.relation.clone(),
+                span: span.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Bind what an equality determines, pairing up constructor arguments
+fn unify(left: &Term, right: &Term, bound: &mut HashSet<String>) -> Checked<()> {
+    match (left, right) {
+        _ if first_unbound(right, bound).is_none() => bind(left, bound),
+        _ if first_unbound(left, bound).is_none() => bind(right, bound),
+        (
+            Term::Construct {
+                constructor: lc,
+                args: la,
+                ..
+            },
+            Term::Construct {
+                constructor: rc,
+                args: ra,
+                ..
+            },
+        ) if lc == rc && la.len() == ra.len() => {
+            // Arguments are solved together, so retry until nothing new is bound
+            let mut pending: Vec<_> = la.iter().zip(ra).collect();
+            while !pending.is_empty() {
+                let before = pending.len();
+                let mut stuck = Vec::new();
+                for (l, r) in pending {
+                    if first_unbound(l, bound).is_some() && first_unbound(r, bound).is_some() {
+                        stuck.push((l, r));
+                    } else {
+                        unify(l, r, bound)?;
+                    }
+                }
+                if stuck.len() == before {
+                    let (l, r) = stuck[0];
+                    return unify(l, r, bound);
+                }
+                pending = stuck;
+            }
+        }
+        _ => {
+            let (var, span) = first_unbound(right, bound)
+                .or_else(|| first_unbound(left, bound))
+                .expect("neither side is bound");
+            return Err(unbound(var, "equality", span));
+        }
+    }
+    Ok(())
+}
+
+/// Every variable of `term` must already be bound
+fn require(term: &Term, context: &'static str, bound: &HashSet<String>) -> Checked<()> {
+    require_operands(term, bound)?;
+    match first_unbound(term, bound) {
+        Some((var, span)) => Err(unbound(var, context, span)),
+        None => Ok(()),
+    }
+}
+
+/// Variables under arithmetic must already be bound, even inside patterns
+fn require_operands(term: &Term, bound: &HashSet<String>) -> Checked<()> {
+    match term {
+        Term::BinOp { .. } => match first_unbound(term, bound) {
+            Some((var, span)) => Err(unbound(var, "arithmetic expression", span)),
+            None => Ok(()),
+        },
+        Term::Construct { args, .. } => args.iter().try_for_each(|a| require_operands(a, bound)),
+        _ => Ok(()),
+    }
+}
+
+fn bind(term: &Term, bound: &mut HashSet<String>) {
+    match term {
+        Term::Var(name, _) if name != "_" => {
+            bound.insert(name.clone());
+        }
+        Term::Construct { args, .. } => args.iter().for_each(|a| bind(a, bound)),
+        _ => {}
+    }
+}
+
+fn first_unbound<'t>(term: &'t Term, bound: &HashSet<String>) -> Option<(&'t str, &'t Span)> {
+    match term {
+        Term::Var(name, span) if name != "_" && !bound.contains(name) => Some((name, span)),
+        Term::Construct { args, .. } => args.iter().find_map(|a| first_unbound(a, bound)),
+        Term::BinOp { left, right, .. } => {
+            first_unbound(left, bound).or_else(|| first_unbound(right, bound))
+        }
+        _ => None,
+    }
+}
+
+/// Like `first_unbound`, counting `_` as unbound
+fn unbound_in_head<'t>(term: &'t Term, bound: &HashSet<String>) -> Option<(&'t str, &'t Span)> {
+    match term {
+        Term::Var(name, span) if name == "_" || !bound.contains(name) => Some((name, span)),
+        Term::Construct { args, .. } => args.iter().find_map(|a| unbound_in_head(a, bound)),
+        Term::BinOp { left, right, .. } => {
+            unbound_in_head(left, bound).or_else(|| unbound_in_head(right, bound))
+        }
+        _ => None,
+    }
+}
+
+fn unbound(var: &str, context: &'static str, span: &Span) -> TypeError {
+    TypeError::UnboundVariable {
+        var: var.to_string(),
+        context,
+        span: span.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use crate::types::TypeChecker;
+
+    const DECLS: &str = r#"
+        rel item: String × Int
+        rel big: String
+        rel next: String × Int
+        rel same: Int × Int
+    "#;
+
+    fn check(rule: &str) -> Result<(), String> {
+        let source = format!("{}\n{}", DECLS, rule);
+        let program = parser::parse(&source).expect("Parse failed");
+        TypeChecker::new()
+            .check_program(&program)
+            .map_err(|e| e.0[0].to_string())
+    }
+
+    #[test]
+    fn test_safe_rules() {
+        assert!(check(r#"big(N) :- item(N, P), P > 3."#).is_ok());
+        assert!(check(r#"next(N, Q) :- item(N, P), Q = P + 1."#).is_ok());
+        assert!(check(r#"next(N, Q) :- item(N, P), P + 1 = Q."#).is_ok());
+        assert!(check(r#"same(X, Y) :- item(_, X), Y = X."#).is_ok());
+        assert!(check(r#"?- item(N, P), P > 3."#).is_ok());
+    }
+
+    #[test]
+    fn test_comparison_needs_a_binding() {
+        assert_eq!(
+            check(r#"big(N) :- item(N, _), P > 3."#),
+            Err(
+                "Variable P in comparison is not bound by an earlier positive goal or equality"
+                    .to_string()
+            )
+        );
+        // Goals are solved left to right
+        assert_eq!(
+            check(r#"big(N) :- P > 3, item(N, P)."#),
+            Err(
+                "Variable P in comparison is not bound by an earlier positive goal or equality"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_needs_bound_operands() {
+        assert_eq!(
+            check(r#"next(N, Q) :- item(N, _), Q = P + 1."#),
+            Err(
+                "Variable P in arithmetic expression is not bound by an earlier positive goal or equality"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(r#"same(X, Y) :- X = Y, item(_, X)."#),
+            Err(
+                "Variable Y in equality is not bound by an earlier positive goal or equality"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_head_variables_need_a_binding() {
+        let source = format!("{}\n{}", DECLS, "next(N, P) :- big(N), P = P.");
+        let program = parser::parse(&source).expect("Parse failed");
+        let errors = TypeChecker::new().check_program(&program).unwrap_err();
+        assert_eq!(errors.0[0].code(), "E0307");
+
+        let source = format!("{}\n{}", DECLS, "next(N, P) :- big(N).");
+        let program = parser::parse(&source).expect("Parse failed");
+        let errors = TypeChecker::new().check_program(&program).unwrap_err();
+        let head_p = source.find("P)").unwrap();
+        assert_eq!(errors.0[0].code(), "E0303");
+        assert_eq!(errors.0[0].span().start, head_p);
+        assert_eq!(
+            errors.0[0].to_string(),
+            "Unsafe rule: variable P in head of next is not bound by the body"
+        );
+
+        // `_` in a head would answer with an unbound value, even in a pattern
+        assert_eq!(
+            check(r#"next(N, _) :- big(N)."#),
+            Err("Unsafe rule: variable _ in head of next is not bound by the body".to_string())
+        );
+        let source = format!(
+            "{}\n{}",
+            "type Pair = pair(a: String, b: Int)\nrel pairs: Pair\nrel big: String",
+            "pairs(pair(N, _)) :- big(N)."
+        );
+        let program = parser::parse(&source).expect("Parse failed");
+        let errors = TypeChecker::new().check_program(&program).unwrap_err();
+        assert_eq!(errors.0[0].code(), "E0303");
+        assert_eq!(errors.0[0].span().start, source.find("_)").unwrap());
+    }
+}
@@ -4,8 +4,18 @@
 ///
 /// This module contains runtime data structures and functions that
 /// compiled qed programs link against.
+use regex::Regex;
 use std::alloc::{alloc, dealloc, Layout};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
 use std::ptr;
+use std::sync::{Mutex, OnceLock};
+
+pub mod relation;
+pub mod temporal;
+
+use relation::TupleSet;
 
 /// Arena allocator for query execution
 ///
@@ -68,6 +78,39 @@ pub struct ProofNode {
     pub children_count: usize,
 }
 
+impl ProofNode {
+    /// Label of the rule that produced this node, if the program gave one
+    pub fn label(&self) -> Option<String> {
+        rule_label(self.rule_id)
+    }
+}
+
+/// Rule labels registered by a compiled program, indexed by rule id
+fn rule_labels() -> &'static Mutex<Vec<Option<String>>> {
+    static LABELS: OnceLock<Mutex<Vec<Option<String>>>> = OnceLock::new();
+    LABELS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Record the `@rule` name or doc comment for a rule id
+pub fn register_rule(rule_id: u32, label: &str) {
+    let mut labels = rule_labels().lock().unwrap();
+    let index = rule_id as usize;
+    if labels.len() <= index {
+        labels.resize(index + 1, None);
+    }
+    labels[index] = Some(label.to_string());
+}
+
+/// Label registered for a rule id
+pub fn rule_label(rule_id: u32) -> Option<String> {
+    rule_labels()
+        .lock()
+        .unwrap()
+        .get(rule_id as usize)
+        .cloned()
+        .flatten()
+}
+
 /// Query context holds arena and proof tracking
 #[repr(C)]
 pub struct QueryContext {
@@ -186,6 +229,77 @@ impl Drop for Table {
     }
 }
 
+/// Compiled regex cache keyed by pattern text
+///
+/// Patterns are validated by the compiler, so compilation here only fails
+/// if a program was built against a different regex dialect.
+fn regex_cache() -> &'static Mutex<HashMap<String, Regex>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Match a string against a regex pattern, compiling each pattern once
+pub fn regex_match(subject: &str, pattern: &str) -> bool {
+    let mut cache = regex_cache().lock().unwrap();
+    if !cache.contains_key(pattern) {
+        match Regex::new(pattern) {
+            Ok(re) => {
+                cache.insert(pattern.to_string(), re);
+            }
+            Err(_) => return false,
+        }
+    }
+    cache[pattern].is_match(subject)
+}
+
+/// Starting value for key hashes (FNV-1a offset basis)
+pub const HASH_SEED: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Fold bytes into a key hash (FNV-1a)
+///
+/// The compiler hashes fact keys with this when it builds static indexes,
+/// and generated lookups hash their arguments the same way at run time.
+pub fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// Fold a scalar key component into a key hash
+pub fn hash_i64(hash: u64, value: i64) -> u64 {
+    hash_bytes(hash, &value.to_le_bytes())
+}
+
+/// The kinds of flattened scalar `qed_show` renders
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Int,
+    /// Pointer to a NUL-terminated string
+    Str,
+    Date,
+    Timestamp,
+    Duration,
+}
+
+/// Render a flattened scalar the way explanations print values:
+/// strings quoted, temporal values as ISO 8601
+pub fn show(kind: Scalar, value: i64) -> String {
+    match kind {
+        Scalar::Int => value.to_string(),
+        Scalar::Str => {
+            let text = value as *const c_char;
+            if text.is_null() {
+                return "\"\"".to_string();
+            }
+            format!("{:?}", unsafe { CStr::from_ptr(text) }.to_string_lossy())
+        }
+        Scalar::Date => temporal::format_date(value),
+        Scalar::Timestamp => temporal::format_timestamp(value),
+        Scalar::Duration => temporal::format_duration(value),
+    }
+}
+
 // C-compatible exports for LLVM-generated code
 
 #[no_mangle]
@@ -224,6 +338,195 @@ pub extern "C" fn qed_table_insert(table: *mut Table, key_hash: u64, key: *mut u
     }
 }
 
+#[no_mangle]
+pub extern "C" fn qed_rel_new(width: i64) -> *mut TupleSet {
+    Box::into_raw(Box::new(TupleSet::new(width as usize)))
+}
+
+#[no_mangle]
+pub extern "C" fn qed_rel_free(rel: *mut TupleSet) {
+    if !rel.is_null() {
+        unsafe { drop(Box::from_raw(rel)) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn qed_rel_len(rel: *mut TupleSet) -> i64 {
+    unsafe { (*rel).len() as i64 }
+}
+
+/// Pointer to the components of a row, valid until rows are added
+#[no_mangle]
+pub extern "C" fn qed_rel_row(rel: *mut TupleSet, index: i64) -> *const i64 {
+    unsafe { (*rel).row(index as usize).as_ptr() }
+}
+
+/// Whether the set holds the tuple at `tuple` (as many components as its width)
+#[no_mangle]
+pub extern "C" fn qed_rel_contains(rel: *mut TupleSet, tuple: *const i64) -> i32 {
+    unsafe {
+        let rel = &*rel;
+        let tuple = std::slice::from_raw_parts(tuple, rel.width());
+        rel.contains(tuple) as i32
+    }
+}
+
+/// Add a derived tuple to `new` unless `full` already has it; 1 if added
+#[no_mangle]
+pub extern "C" fn qed_rel_derive(
+    full: *mut TupleSet,
+    new: *mut TupleSet,
+    tuple: *const i64,
+) -> i32 {
+    unsafe {
+        let tuple = std::slice::from_raw_parts(tuple, (*full).width());
+        if (*full).contains(tuple) {
+            return 0;
+        }
+        (*new).insert(tuple) as i32
+    }
+}
+
+/// Rows matching `key` on the columns in `mask`: a count, then row numbers
+#[no_mangle]
+pub extern "C" fn qed_rel_probe(rel: *mut TupleSet, mask: i64, key: *const i64) -> *const i64 {
+    unsafe {
+        let columns = (mask as u64).count_ones() as usize;
+        let key = std::slice::from_raw_parts(key, columns);
+        (*rel).probe(mask as u64, key).as_ptr()
+    }
+}
+
+/// End a semi-naive round, returning how many tuples it found
+#[no_mangle]
+pub extern "C" fn qed_rel_swap(
+    full: *mut TupleSet,
+    delta: *mut TupleSet,
+    new: *mut TupleSet,
+) -> i64 {
+    unsafe { TupleSet::advance(&mut *full, &mut *delta, &mut *new) as i64 }
+}
+
+/// Match a NUL-terminated subject against a NUL-terminated pattern.
+/// Strings are passed as i64 pointers, matching the flattened value layout.
+#[no_mangle]
+pub extern "C" fn qed_regex_match(subject: i64, pattern: i64) -> i32 {
+    let subject = subject as *const c_char;
+    let pattern = pattern as *const c_char;
+    if subject.is_null() || pattern.is_null() {
+        return 0;
+    }
+
+    let (subject, pattern) = unsafe { (CStr::from_ptr(subject), CStr::from_ptr(pattern)) };
+    match (subject.to_str(), pattern.to_str()) {
+        (Ok(subject), Ok(pattern)) => regex_match(subject, pattern) as i32,
+        _ => 0,
+    }
+}
+
+/// Compare two NUL-terminated strings byte-wise, returning -1, 0 or 1.
+/// A null pointer sorts before every string.
+#[no_mangle]
+pub extern "C" fn qed_str_cmp(left: i64, right: i64) -> i32 {
+    let (left, right) = (left as *const c_char, right as *const c_char);
+    let ordering = match (left.is_null(), right.is_null()) {
+        (true, true) => std::cmp::Ordering::Equal,
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        (false, false) => unsafe { CStr::from_ptr(left).cmp(CStr::from_ptr(right)) },
+    };
+    ordering as i32
+}
+
+/// Fold a scalar key component into a key hash
+#[no_mangle]
+pub extern "C" fn qed_hash_i64(hash: i64, value: i64) -> i64 {
+    hash_i64(hash as u64, value) as i64
+}
+
+/// Fold a NUL-terminated string key component into a key hash by content.
+/// A null pointer hashes like the empty string.
+#[no_mangle]
+pub extern "C" fn qed_hash_str(hash: i64, value: i64) -> i64 {
+    let value = value as *const c_char;
+    let bytes = if value.is_null() {
+        &[][..]
+    } else {
+        unsafe { CStr::from_ptr(value) }.to_bytes()
+    };
+    hash_bytes(hash as u64, bytes) as i64
+}
+
+/// Render a flattened scalar of the given `Scalar` kind as a NUL-terminated
+/// string, which stays valid until the next call on the same thread
+#[no_mangle]
+pub extern "C" fn qed_show(kind: i32, value: i64) -> *const c_char {
+    thread_local! {
+        static SHOWN: RefCell<CString> = RefCell::default();
+    }
+    let kind = match kind {
+        0 => Scalar::Int,
+        1 => Scalar::Str,
+        2 => Scalar::Date,
+        3 => Scalar::Timestamp,
+        4 => Scalar::Duration,
+        _ => Scalar::Int,
+    };
+    let text = CString::new(show(kind, value)).unwrap_or_default();
+    SHOWN.with(|shown| {
+        *shown.borrow_mut() = text;
+        shown.borrow().as_ptr()
+    })
+}
+
+/// Register a NUL-terminated label for a rule id
+#[no_mangle]
+pub extern "C" fn qed_register_rule(rule_id: u32, label: *const c_char) {
+    if label.is_null() {
+        return;
+    }
+    if let Ok(label) = unsafe { CStr::from_ptr(label) }.to_str() {
+        register_rule(rule_id, label);
+    }
+}
+
+/// Instant that `now()` evaluates to in compiled rules
+#[no_mangle]
+pub extern "C" fn qed_now() -> i64 {
+    temporal::now()
+}
+
+/// Date that `today()` evaluates to in compiled rules
+#[no_mangle]
+pub extern "C" fn qed_today() -> i64 {
+    temporal::today()
+}
+
+/// Supply `now()` from an embedding host
+#[no_mangle]
+pub extern "C" fn qed_set_now(seconds: i64) {
+    temporal::set_now(seconds);
+}
+
+/// Fix `now()` once at program startup: `QED_NOW` if set, otherwise the
+/// wall clock. Returns 0 if `QED_NOW` is set but unparseable.
+#[no_mangle]
+pub extern "C" fn qed_now_init() -> i32 {
+    match std::env::var("QED_NOW") {
+        Ok(value) => match temporal::parse_instant(&value) {
+            Some(seconds) => {
+                temporal::set_now(seconds);
+                1
+            }
+            None => 0,
+        },
+        Err(_) => {
+            temporal::set_now(temporal::wall_clock());
+            1
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,4 +579,90 @@ mod tests {
         assert!(result.is_some());
         assert_eq!(result.unwrap(), value);
     }
+
+    #[test]
+    fn test_regex_match() {
+        assert!(regex_match(
+            "/projects/qed/docs/intro",
+            "^/projects/[^/]+/docs/"
+        ));
+        assert!(!regex_match(
+            "/projects/qed/src/main.rs",
+            "^/projects/[^/]+/docs/"
+        ));
+    }
+
+    #[test]
+    fn test_regex_match_ffi() {
+        let subject = c"/projects/qed/docs/intro";
+        let pattern = c"^/projects/[^/]+/docs/";
+        assert_eq!(
+            qed_regex_match(subject.as_ptr() as i64, pattern.as_ptr() as i64),
+            1
+        );
+        assert_eq!(qed_regex_match(0, pattern.as_ptr() as i64), 0);
+    }
+
+    #[test]
+    fn test_str_cmp() {
+        let (a, b) = (c"alice", c"bob");
+        assert_eq!(qed_str_cmp(a.as_ptr() as i64, b.as_ptr() as i64), -1);
+        assert_eq!(qed_str_cmp(b.as_ptr() as i64, a.as_ptr() as i64), 1);
+        assert_eq!(qed_str_cmp(a.as_ptr() as i64, c"alice".as_ptr() as i64), 0);
+        assert_eq!(qed_str_cmp(0, a.as_ptr() as i64), -1);
+    }
+
+    #[test]
+    fn test_show_scalars() {
+        let show = |kind: Scalar, value: i64| {
+            let text = qed_show(kind as i32, value);
+            unsafe { CStr::from_ptr(text) }
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(show(Scalar::Int, -7), "-7");
+        assert_eq!(
+            show(Scalar::Str, c"say \"hi\"".as_ptr() as i64),
+            r#""say \"hi\"""#
+        );
+        assert_eq!(show(Scalar::Str, 0), r#""""#);
+        assert_eq!(show(Scalar::Date, 86400), "1970-01-02");
+        assert_eq!(show(Scalar::Duration, 90 * 60), "PT1H30M");
+    }
+
+    #[test]
+    fn test_key_hash() {
+        let seed = HASH_SEED as i64;
+        let name = std::ffi::CString::new("alice").unwrap();
+        assert_eq!(
+            qed_hash_str(seed, name.as_ptr() as i64) as u64,
+            hash_bytes(HASH_SEED, b"alice")
+        );
+        assert_eq!(qed_hash_i64(seed, 42) as u64, hash_i64(HASH_SEED, 42));
+        assert_ne!(hash_i64(HASH_SEED, 1), hash_i64(HASH_SEED, 2));
+    }
+
+    #[test]
+    fn test_rule_labels() {
+        qed_register_rule(3, c"owner-full-access".as_ptr());
+        let node = ProofNode {
+            rule_id: 3,
+            relation_name: ptr::null(),
+            relation_name_len: 0,
+            arguments: ptr::null_mut(),
+            arg_count: 0,
+            children: ptr::null_mut(),
+            children_count: 0,
+        };
+        assert_eq!(node.label().as_deref(), Some("owner-full-access"));
+        assert_eq!(rule_label(99), None);
+    }
+
+    #[test]
+    fn test_set_now() {
+        qed_set_now(1_700_000_000);
+        assert_eq!(qed_now(), 1_700_000_000);
+        assert_eq!(qed_today(), 1_699_920_000);
+    }
 }
@@ -0,0 +1,154 @@
+/// Tuple sets for bottom-up evaluation
+///
+/// A recursive component is evaluated semi-naively with three sets per
+/// relation: `full` holds every tuple derived so far, `delta` the tuples
+/// found in the last round and `new` those being found in this round.
+/// Tuples are fixed-width rows of flattened i64 components. Joins probe
+/// hash indexes on the bound columns, built on first use and extended as
+/// rows are added.
+use std::collections::{HashMap, HashSet};
+
+/// Probe result for a key with no matching rows
+static NO_MATCHES: [i64; 1] = [0];
+
+pub struct TupleSet {
+    width: usize,
+    len: usize,
+    rows: Vec<i64>,
+    members: HashSet<Box<[i64]>>,
+    /// Indexes by bitmask of key columns
+    indexes: HashMap<u64, TupleIndex>,
+}
+
+/// Rows by the values of some columns
+struct TupleIndex {
+    /// Rows indexed so far
+    indexed: usize,
+    /// Count followed by row numbers, per key
+    matches: HashMap<Box<[i64]>, Vec<i64>>,
+}
+
+impl TupleSet {
+    pub fn new(width: usize) -> Self {
+        TupleSet {
+            width,
+            len: 0,
+            rows: Vec::new(),
+            members: HashSet::new(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Components per tuple
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, tuple: &[i64]) -> bool {
+        self.members.contains(tuple)
+    }
+
+    /// Add a tuple, returning whether it was new
+    pub fn insert(&mut self, tuple: &[i64]) -> bool {
+        if self.members.contains(tuple) {
+            return false;
+        }
+        self.members.insert(tuple.into());
+        self.rows.extend_from_slice(tuple);
+        self.len += 1;
+        true
+    }
+
+    pub fn row(&self, index: usize) -> &[i64] {
+        &self.rows[index * self.width..(index + 1) * self.width]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.rows.clear();
+        self.members.clear();
+        self.indexes.clear();
+    }
+
+    /// Rows whose columns in `mask` equal `key`, as a count followed by row
+    /// numbers. The slice stays valid until rows are added to the set.
+    pub fn probe(&mut self, mask: u64, key: &[i64]) -> &[i64] {
+        let columns: Vec<usize> = (0..self.width).filter(|c| mask & (1 << c) != 0).collect();
+        let index = self.indexes.entry(mask).or_insert_with(|| TupleIndex {
+            indexed: 0,
+            matches: HashMap::new(),
+        });
+        for row in index.indexed..self.len {
+            let start = row * self.width;
+            let values: Box<[i64]> = columns.iter().map(|c| self.rows[start + c]).collect();
+            let rows = index.matches.entry(values).or_insert_with(|| vec![0]);
+            rows.push(row as i64);
+            rows[0] += 1;
+        }
+        index.indexed = self.len;
+        match index.matches.get(key) {
+            Some(rows) => rows,
+            None => &NO_MATCHES,
+        }
+    }
+
+    /// End a round: tuples found in it join `full` and become the delta
+    pub fn advance(full: &mut TupleSet, delta: &mut TupleSet, new: &mut TupleSet) -> usize {
+        delta.clear();
+        for row in 0..new.len {
+            let tuple = new.row(row);
+            full.insert(tuple);
+            delta.insert(tuple);
+        }
+        new.clear();
+        delta.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_deduplicates() {
+        let mut set = TupleSet::new(2);
+        assert!(set.insert(&[1, 2]));
+        assert!(set.insert(&[2, 3]));
+        assert!(!set.insert(&[1, 2]));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.row(1), &[2, 3]);
+        assert!(set.contains(&[2, 3]) && !set.contains(&[3, 2]));
+    }
+
+    #[test]
+    fn test_probe_extends_index() {
+        let mut set = TupleSet::new(2);
+        set.insert(&[1, 2]);
+        set.insert(&[1, 3]);
+        assert_eq!(set.probe(0b01, &[1]), &[2, 0, 1]);
+        assert_eq!(set.probe(0b10, &[3]), &[1, 1]);
+        set.insert(&[1, 4]);
+        assert_eq!(set.probe(0b01, &[1]), &[3, 0, 1, 2]);
+        assert_eq!(set.probe(0b01, &[9]), &[0]);
+    }
+
+    #[test]
+    fn test_advance() {
+        let (mut full, mut delta, mut new) = (TupleSet::new(1), TupleSet::new(1), TupleSet::new(1));
+        full.insert(&[1]);
+        new.insert(&[2]);
+        assert_eq!(TupleSet::advance(&mut full, &mut delta, &mut new), 1);
+        assert!(full.contains(&[1]) && full.contains(&[2]));
+        assert!(delta.contains(&[2]) && !delta.contains(&[1]));
+        assert!(new.is_empty());
+        assert_eq!(TupleSet::advance(&mut full, &mut delta, &mut new), 0);
+    }
+}
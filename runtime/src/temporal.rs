@@ -0,0 +1,244 @@
+/// Calendar support for the built-in `Date`, `Timestamp` and `Duration` types
+///
+/// All three are represented as seconds: dates and timestamps count from
+/// the Unix epoch in UTC (a date is its midnight), durations are a plain
+/// number of seconds. Only fixed-length units (weeks, days, hours, minutes,
+/// seconds) are accepted in durations so arithmetic stays exact.
+///
+/// `now()` never reads the clock from inside rules. The host sets it once
+/// with `set_now`, or a compiled binary calls `qed_now_init` at startup.
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Calendar date for a day count since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        _ if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        _ => 28,
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str, digits: usize) -> Option<T> {
+    if text.len() == digits && text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parse an ISO date `YYYY-MM-DD` into seconds since the epoch
+pub fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.split('-');
+    let year: i64 = number(parts.next()?, 4)?;
+    let month: u32 = number(parts.next()?, 2)?;
+    let day: u32 = number(parts.next()?, 2)?;
+    if parts.next().is_some() || !(1..=12).contains(&month) {
+        return None;
+    }
+    if day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * SECONDS_PER_DAY)
+}
+
+/// Parse an ISO UTC timestamp `YYYY-MM-DDTHH:MM:SSZ` into seconds since the epoch
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once('T')?;
+    let time = time.strip_suffix('Z')?;
+    let mut parts = time.split(':');
+    let hour: i64 = number(parts.next()?, 2)?;
+    let minute: i64 = number(parts.next()?, 2)?;
+    let second: i64 = number(parts.next()?, 2)?;
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(parse_date(date)? + hour * 3600 + minute * 60 + second)
+}
+
+/// Parse an ISO duration such as `P90D`, `P2W` or `P1DT12H30M` into seconds
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let rest = text.strip_prefix('P')?;
+    let (date_part, time_part) = match rest.split_once('T') {
+        Some((d, t)) if !t.is_empty() => (d, Some(t)),
+        Some(_) => return None,
+        None => (rest, None),
+    };
+
+    let mut total: i64 = 0;
+    let mut components = 0;
+    for (part, units) in [
+        (
+            Some(date_part),
+            &[('W', 7 * SECONDS_PER_DAY), ('D', SECONDS_PER_DAY)][..],
+        ),
+        (time_part, &[('H', 3600), ('M', 60), ('S', 1)][..]),
+    ] {
+        let Some(mut part) = part else { continue };
+        // Units must appear in order, each at most once
+        for &(unit, seconds) in units {
+            if let Some(pos) = part.find(unit) {
+                let amount: i64 = number(&part[..pos], pos)?;
+                total = total.checked_add(amount.checked_mul(seconds)?)?;
+                part = &part[pos + 1..];
+                components += 1;
+            }
+        }
+        if !part.is_empty() {
+            return None;
+        }
+    }
+
+    (components > 0).then_some(total)
+}
+
+/// Format seconds since the epoch as an ISO date
+pub fn format_date(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Format seconds since the epoch as an ISO UTC timestamp
+pub fn format_timestamp(seconds: i64) -> String {
+    let secs = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(seconds),
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Format a number of seconds as an ISO duration
+pub fn format_duration(seconds: i64) -> String {
+    let sign = if seconds < 0 { "-" } else { "" };
+    let seconds = seconds.unsigned_abs();
+    let days = seconds / SECONDS_PER_DAY as u64;
+    let rest = seconds % SECONDS_PER_DAY as u64;
+
+    let mut text = format!("{}P", sign);
+    if days > 0 || rest == 0 {
+        text.push_str(&format!("{}D", days));
+    }
+    if rest > 0 {
+        text.push('T');
+        for (amount, unit) in [
+            (rest / 3600, 'H'),
+            (rest % 3600 / 60, 'M'),
+            (rest % 60, 'S'),
+        ] {
+            if amount > 0 {
+                text.push_str(&format!("{}{}", amount, unit));
+            }
+        }
+    }
+    text
+}
+
+static NOW: AtomicI64 = AtomicI64::new(0);
+
+/// Supply the instant that `now()` evaluates to
+pub fn set_now(seconds: i64) {
+    NOW.store(seconds, Ordering::SeqCst);
+}
+
+/// The instant last supplied with `set_now`
+pub fn now() -> i64 {
+    NOW.load(Ordering::SeqCst)
+}
+
+/// The UTC date of `now()`, which `today()` evaluates to
+pub fn today() -> i64 {
+    now().div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY
+}
+
+/// Read the current wall-clock time; used only by hosts, never by rules
+pub fn wall_clock() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Parse a `QED_NOW`-style value: an ISO timestamp, an ISO date or epoch seconds
+pub fn parse_instant(text: &str) -> Option<i64> {
+    let text = text.trim();
+    parse_timestamp(text)
+        .or_else(|| parse_date(text))
+        .or_else(|| text.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_round_trip() {
+        let date = parse_date("2026-12-31").unwrap();
+        assert_eq!(format_date(date), "2026-12-31");
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(
+            parse_date("2024-02-29").map(format_date).as_deref(),
+            Some("2024-02-29")
+        );
+        assert_eq!(parse_date("2025-02-29"), None);
+        assert_eq!(parse_date("2025-13-01"), None);
+    }
+
+    #[test]
+    fn test_timestamp_parse() {
+        let ts = parse_timestamp("2026-01-02T03:04:05Z").unwrap();
+        assert_eq!(
+            ts - parse_date("2026-01-02").unwrap(),
+            3 * 3600 + 4 * 60 + 5
+        );
+        assert_eq!(format_timestamp(ts), "2026-01-02T03:04:05Z");
+        assert_eq!(parse_timestamp("2026-01-02T24:00:00Z"), None);
+    }
+
+    #[test]
+    fn test_duration_parse() {
+        assert_eq!(parse_duration("P90D"), Some(90 * SECONDS_PER_DAY));
+        assert_eq!(parse_duration("P2W"), Some(14 * SECONDS_PER_DAY));
+        assert_eq!(parse_duration("PT1H30M"), Some(5400));
+        assert_eq!(parse_duration("P1DT12H"), Some(SECONDS_PER_DAY + 12 * 3600));
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("PT"), None);
+        assert_eq!(parse_duration("P1Y"), None);
+        assert_eq!(format_duration(SECONDS_PER_DAY + 5400), "P1DT1H30M");
+    }
+
+    #[test]
+    fn test_parse_instant() {
+        assert_eq!(parse_instant("2026-01-01"), parse_date("2026-01-01"));
+        assert_eq!(parse_instant("1700000000"), Some(1_700_000_000));
+    }
+}